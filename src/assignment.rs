@@ -0,0 +1,312 @@
+// Variable values by name.
+//
+// Builds from pairs of any string type, so `&[("x", 8.0), ("y", 4.0)].into()`
+// is an assignment. Clones share the values until one of them is modified,
+// so keeping the previous assignment around to see what changed is cheap.
+
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::sync::Arc;
+
+// A variable the assignment has no value for, with the names it does have
+// and those among them that look like a typo of the missing one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingVariable {
+    pub name: String,
+    pub provided: Vec<String>,
+    pub suggestions: Vec<String>,
+}
+
+impl fmt::Display for MissingVariable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "variable {} is not assigned", self.name)?;
+        if !self.suggestions.is_empty() {
+            write!(f, "; did you mean {}?", self.suggestions.join(" or "))?;
+        }
+        if self.provided.is_empty() {
+            write!(f, " (the assignment is empty)")
+        } else {
+            write!(f, " (assigned: {})", self.provided.join(", "))
+        }
+    }
+}
+
+impl Error for MissingVariable {}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Assignment {
+    values: Arc<HashMap<String, f32>>,
+}
+
+impl Assignment {
+    pub fn new() -> Self {
+        Assignment::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Assignment {
+            values: Arc::new(HashMap::with_capacity(capacity)),
+        }
+    }
+
+    // the previous value of the variable, if any
+    pub fn insert<S: Into<String>>(&mut self, name: S, value: f32) -> Option<f32> {
+        Arc::make_mut(&mut self.values).insert(name.into(), value)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&f32> {
+        self.values.get(name)
+    }
+
+    // The value of `name`, or which variables there are instead.
+    pub fn lookup(&self, name: &str) -> Result<f32, MissingVariable> {
+        if let Some(&value) = self.values.get(name) {
+            return Ok(value);
+        }
+        let mut provided: Vec<String> = self.values.keys().cloned().collect();
+        provided.sort();
+        let threshold = (name.chars().count() / 3).max(1);
+        let mut near: Vec<(usize, &String)> = provided
+            .iter()
+            .map(|p| (edit_distance(name, p), p))
+            .filter(|&(distance, _)| distance <= threshold)
+            .collect();
+        near.sort();
+        let suggestions = near.into_iter().map(|(_, p)| p.clone()).collect();
+        Err(MissingVariable {
+            name: name.to_string(),
+            provided,
+            suggestions,
+        })
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut f32> {
+        Arc::make_mut(&mut self.values).get_mut(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<f32> {
+        Arc::make_mut(&mut self.values).remove(name)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // Sets `name`, returning whether its value changed. A new variable counts
+    // as a change.
+    pub fn update<S: Into<String>>(&mut self, name: S, value: f32) -> bool {
+        let name = name.into();
+        if self.values.get(&name).map(|v| v.to_bits()) == Some(value.to_bits()) {
+            return false;
+        }
+        self.insert(name, value);
+        true
+    }
+
+    // Like `update` for several variables; returns the names that changed.
+    pub fn update_all<'b>(&mut self, changes: &[(&'b str, f32)]) -> Vec<&'b str> {
+        changes
+            .iter()
+            .filter(|&&(name, value)| self.update(name, value))
+            .map(|&(name, _)| name)
+            .collect()
+    }
+
+    // Names assigned differently by the two, including names only one of
+    // them assigns, in sorted order.
+    pub fn diff(&self, other: &Assignment) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .values
+            .keys()
+            .chain(other.values.keys())
+            .filter(|name| {
+                self.values.get(*name).map(|v| v.to_bits())
+                    != other.values.get(*name).map(|v| v.to_bits())
+            })
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // The values of `other` that differ from this assignment, in the form
+    // `Incremental::update` takes.
+    pub fn changes_to<'b>(&self, other: &'b Assignment) -> Vec<(&'b str, f32)> {
+        let mut changes: Vec<(&str, f32)> = other
+            .values
+            .iter()
+            .filter(|&(name, value)| {
+                self.values.get(name).map(|v| v.to_bits()) != Some(value.to_bits())
+            })
+            .map(|(name, &value)| (name.as_str(), value))
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(b.0));
+        changes
+    }
+
+    pub fn keys(&self) -> hash_map::Keys<'_, String, f32> {
+        self.values.keys()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, String, f32> {
+        self.values.iter()
+    }
+}
+
+// panics if the variable is not assigned
+impl Index<&str> for Assignment {
+    type Output = f32;
+
+    fn index(&self, name: &str) -> &f32 {
+        match self.values.get(name) {
+            Some(value) => value,
+            None => panic!("{}", self.lookup(name).unwrap_err()),
+        }
+    }
+}
+
+impl<S: Into<String>> FromIterator<(S, f32)> for Assignment {
+    fn from_iter<I: IntoIterator<Item = (S, f32)>>(iter: I) -> Self {
+        Assignment {
+            values: Arc::new(
+                iter.into_iter()
+                    .map(|(name, value)| (name.into(), value))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<S: Into<String>> Extend<(S, f32)> for Assignment {
+    fn extend<I: IntoIterator<Item = (S, f32)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl<'a, const N: usize> From<[(&'a str, f32); N]> for Assignment {
+    fn from(pairs: [(&'a str, f32); N]) -> Self {
+        pairs.iter().cloned().collect()
+    }
+}
+
+impl<'a, 'b> From<&'b [(&'a str, f32)]> for Assignment {
+    fn from(pairs: &'b [(&'a str, f32)]) -> Self {
+        pairs.iter().cloned().collect()
+    }
+}
+
+impl<'a> From<Vec<(&'a str, f32)>> for Assignment {
+    fn from(pairs: Vec<(&'a str, f32)>) -> Self {
+        pairs.into_iter().collect()
+    }
+}
+
+impl From<HashMap<String, f32>> for Assignment {
+    fn from(values: HashMap<String, f32>) -> Self {
+        Assignment {
+            values: Arc::new(values),
+        }
+    }
+}
+
+impl From<Assignment> for HashMap<String, f32> {
+    fn from(assignment: Assignment) -> Self {
+        Arc::try_unwrap(assignment.values).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl<'a> IntoIterator for &'a Assignment {
+    type Item = (&'a String, &'a f32);
+    type IntoIter = hash_map::Iter<'a, String, f32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.iter()
+    }
+}
+
+impl IntoIterator for Assignment {
+    type Item = (String, f32);
+    type IntoIter = hash_map::IntoIter<String, f32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        HashMap::from(self).into_iter()
+    }
+}
+
+#[test]
+fn assignments_from_pairs() {
+    let at: Assignment = [("x", 8.0), ("y", 4.0)].into();
+    assert_eq!(at["x"], 8.0);
+    assert_eq!(at.len(), 2);
+
+    let pairs: &[(&str, f32)] = &[("x", 8.0), ("y", 4.0)];
+    assert_eq!(Assignment::from(pairs), at);
+    let collected: Assignment = vec![("x".to_string(), 8.0), ("y".to_string(), 4.0)]
+        .into_iter()
+        .collect();
+    assert_eq!(collected, at);
+
+    let mut map: HashMap<String, f32> = at.clone().into();
+    map.insert("z".to_string(), 1.0);
+    assert_eq!(Assignment::from(map).get("z"), Some(&1.0));
+}
+
+#[test]
+fn missing_variables_suggest_near_misses() {
+    let at: Assignment = [("velocity", 1.0), ("mass", 2.0), ("x", 0.0)].into();
+    let missing = at.lookup("velocty").unwrap_err();
+    assert_eq!(missing.suggestions, vec!["velocity".to_string()]);
+    assert_eq!(
+        missing.to_string(),
+        "variable velocty is not assigned; did you mean velocity? (assigned: mass, velocity, x)"
+    );
+    assert!(at.lookup("y").unwrap_err().suggestions == vec!["x".to_string()]);
+    assert!(at.lookup("time").unwrap_err().suggestions.is_empty());
+    assert_eq!(at.lookup("mass"), Ok(2.0));
+}
+
+#[test]
+fn change_tracking() {
+    let before: Assignment = [("x", 1.0), ("y", 2.0)].into();
+    let mut after = before.clone();
+    assert!(Arc::ptr_eq(&before.values, &after.values));
+
+    assert!(!after.update("x", 1.0));
+    assert!(after.update("y", 3.0));
+    assert_eq!(after.update_all(&[("x", 1.0), ("z", 0.0)]), vec!["z"]);
+    assert_eq!(before.get("y"), Some(&2.0));
+
+    assert_eq!(before.diff(&after), vec!["y".to_string(), "z".to_string()]);
+    assert_eq!(before.changes_to(&after), vec![("y", 3.0), ("z", 0.0)]);
+    assert!(after.diff(&after.clone()).is_empty());
+}
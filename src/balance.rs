@@ -0,0 +1,102 @@
+// Weights for combining several loss terms, for use with `grad_weighted`.
+
+use {backward_multi, Node};
+
+// `weights` scaled to sum to 1. All-zero weights are returned unchanged.
+pub fn normalize_weights(weights: &[f32]) -> Vec<f32> {
+    let total: f32 = weights.iter().sum();
+    if total == 0f32 {
+        return weights.to_vec();
+    }
+    weights.iter().map(|w| w / total).collect()
+}
+
+// Weights that give every term the same gradient norm, at the current
+// variable values. They average to 1; terms with a zero gradient get 1.
+pub fn gradient_norm_weights(terms: &[Node], variables: &[&str]) -> Vec<f32> {
+    let norms: Vec<f32> = backward_multi(terms, variables)
+        .iter()
+        .map(|grads| grads.values().iter().map(|g| g * g).sum::<f32>().sqrt())
+        .collect();
+    let nonzero: Vec<f32> = norms.iter().cloned().filter(|&n| n > 0f32).collect();
+    if nonzero.is_empty() {
+        return vec![1f32; terms.len()];
+    }
+    let mean = nonzero.iter().sum::<f32>() / nonzero.len() as f32;
+    norms
+        .iter()
+        .map(|&n| if n > 0f32 { mean / n } else { 1f32 })
+        .collect()
+}
+
+// Gradient norm balancing across training steps. The weights move towards
+// `gradient_norm_weights` by `smoothing` per update, so that noisy steps do
+// not swing them around.
+#[derive(Debug, Clone)]
+pub struct LossBalancer {
+    pub smoothing: f32,
+    weights: Vec<f32>,
+}
+
+impl LossBalancer {
+    pub fn new(terms: usize, smoothing: f32) -> Self {
+        LossBalancer {
+            smoothing,
+            weights: vec![1f32; terms],
+        }
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    pub fn update(&mut self, terms: &[Node], variables: &[&str]) -> &[f32] {
+        assert_eq!(terms.len(), self.weights.len(), "one weight per term");
+        let target = gradient_norm_weights(terms, variables);
+        for (weight, target) in self.weights.iter_mut().zip(target) {
+            *weight += self.smoothing * (target - *weight);
+        }
+        &self.weights
+    }
+
+    // the terms paired with their weights, ready for `grad_weighted`
+    pub fn weighted<'a>(&self, terms: &[Node<'a>]) -> Vec<(Node<'a>, f32)> {
+        terms
+            .iter()
+            .cloned()
+            .zip(self.weights.iter().cloned())
+            .collect()
+    }
+}
+
+#[test]
+fn balanced_weights() {
+    use {constant, grad_weighted, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let fit = mul(
+        arena,
+        constant(arena, 10f32),
+        sub(arena, x, constant(arena, 1f32)),
+    );
+    let penalty = mul(arena, x, x);
+    x.set_value(2f32);
+
+    let (value, grads) = grad_weighted(&[(fit, 0.5), (penalty, 2f32)], &["x"]);
+    assert_eq!(value, 0.5 * 10f32 + 2f32 * 4f32);
+    assert_eq!(grads["x"], 0.5 * 10f32 + 2f32 * 4f32);
+
+    // gradient norms 10 and 4
+    let weights = gradient_norm_weights(&[fit, penalty], &["x"]);
+    assert_eq!(weights, vec![0.7, 1.75]);
+    assert_eq!(normalize_weights(&[1f32, 3f32]), vec![0.25, 0.75]);
+
+    let mut balancer = LossBalancer::new(2, 0.5);
+    balancer.update(&[fit, penalty], &["x"]);
+    assert_eq!(balancer.weights(), &[0.85, 1.375]);
+    let (_, grads) = grad_weighted(&balancer.weighted(&[fit, penalty]), &["x"]);
+    assert_eq!(grads["x"], 0.85 * 10f32 + 1.375 * 4f32);
+}
@@ -0,0 +1,220 @@
+// Evaluation over many rows of variable values, without building an
+// `Assignment` per row.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+
+use {node_key, topological_order, Assignment, Node, NodeType};
+
+// The graph of a root flattened into topological order, with children
+// referred to by position.
+struct Plan<'a> {
+    nodes: Vec<Node<'a>>,
+    args: Vec<Vec<usize>>,
+    // the column read by each variable node, if any
+    columns: Vec<Option<usize>>,
+}
+
+impl<'a> Plan<'a> {
+    fn new(root: Node<'a>, variables: &[&str]) -> Self {
+        let nodes = topological_order(&[root]);
+        let positions: HashMap<_, _> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, &node)| (node_key(node), i))
+            .collect();
+        let args = nodes
+            .iter()
+            .map(|node| {
+                node.children()
+                    .iter()
+                    .map(|&child| positions[&node_key(child)])
+                    .collect()
+            })
+            .collect();
+        let columns = nodes
+            .iter()
+            .map(|node| match node.type_ {
+                NodeType::Var(ref name) => variables.iter().position(|v| v == name),
+                _ => None,
+            })
+            .collect();
+
+        Plan {
+            nodes,
+            args,
+            columns,
+        }
+    }
+
+    // Evaluates every node into `values`. Variables read `input` for their
+    // column, or their cell if they have none.
+    fn eval<F: Fn(usize) -> f32>(&self, values: &mut Vec<f32>, input: F) {
+        values.clear();
+        let mut inputs = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let value = match self.columns[i] {
+                Some(column) => input(column),
+                None => {
+                    inputs.clear();
+                    inputs.extend(self.args[i].iter().map(|&arg| values[arg]));
+                    node.eval(&inputs)
+                }
+            };
+            values.push(value);
+        }
+    }
+
+    // Gradient of the root with respect to the columns, after `eval`.
+    fn grad(&self, values: &[f32], adjoints: &mut Vec<f32>, grad: &mut [f32]) {
+        adjoints.clear();
+        adjoints.resize(self.nodes.len(), 0f32);
+        *adjoints.last_mut().unwrap() = 1f32;
+        for g in grad.iter_mut() {
+            *g = 0f32;
+        }
+
+        let mut inputs = vec![];
+        for (i, node) in self.nodes.iter().enumerate().rev() {
+            let adjoint = adjoints[i];
+            if adjoint == 0f32 {
+                continue;
+            }
+            if let Some(column) = self.columns[i] {
+                grad[column] += adjoint;
+                continue;
+            }
+            inputs.clear();
+            inputs.extend(self.args[i].iter().map(|&arg| values[arg]));
+            for (&arg, partial) in self.args[i].iter().zip(node.partials(&inputs)) {
+                adjoints[arg] += adjoint * partial;
+            }
+        }
+    }
+}
+
+fn rows(columns: &[&[f32]]) -> usize {
+    let rows = columns.first().map(|column| column.len()).unwrap_or(0);
+    assert!(
+        columns.iter().all(|column| column.len() == rows),
+        "columns of different lengths"
+    );
+    rows
+}
+
+// Value of `node` for every row, where `columns[j][i]` is the value of
+// `variables[j]` in row i. Variables without a column keep their current
+// values.
+pub fn forward_batch(node: Node, variables: &[&str], columns: &[&[f32]]) -> Vec<f32> {
+    assert_eq!(variables.len(), columns.len(), "one column per variable");
+    let plan = Plan::new(node, variables);
+    let mut values = Vec::with_capacity(plan.nodes.len());
+
+    (0..rows(columns))
+        .map(|row| {
+            plan.eval(&mut values, |column| columns[column][row]);
+            *values.last().unwrap()
+        })
+        .collect()
+}
+
+// Value and gradient with respect to `variables` for every row, as in
+// `forward_batch`. Returns the values and one gradient row per input row.
+pub fn grad_batch(node: Node, variables: &[&str], columns: &[&[f32]]) -> (Vec<f32>, Vec<Vec<f32>>) {
+    assert_eq!(variables.len(), columns.len(), "one column per variable");
+    let plan = Plan::new(node, variables);
+    let mut values = Vec::with_capacity(plan.nodes.len());
+    let mut adjoints = Vec::with_capacity(plan.nodes.len());
+
+    (0..rows(columns))
+        .map(|row| {
+            plan.eval(&mut values, |column| columns[column][row]);
+            let mut grad = vec![0f32; variables.len()];
+            plan.grad(&values, &mut adjoints, &mut grad);
+            (*values.last().unwrap(), grad)
+        })
+        .unzip()
+}
+
+// Value of `node` for every assignment of the iterator, computed lazily
+// with one reused buffer, so memory does not grow with the stream.
+// Variables missing from an assignment keep their current values.
+pub fn forward_stream<'a, I>(node: Node<'a>, assignments: I) -> impl Iterator<Item = f32> + 'a
+where
+    I: IntoIterator,
+    I::IntoIter: 'a,
+    I::Item: Borrow<Assignment>,
+{
+    let mut names: Vec<&'a str> = vec![];
+    let mut defaults = vec![];
+    for node in topological_order(&[node]) {
+        if let Some(name) = node.var_name() {
+            if !names.contains(&name) {
+                names.push(name);
+                defaults.push(node.value());
+            }
+        }
+    }
+    let plan = Plan::new(node, &names);
+    let mut values = Vec::with_capacity(plan.nodes.len());
+    let mut row = vec![0f32; names.len()];
+
+    assignments.into_iter().map(move |assignment| {
+        let assignment = assignment.borrow();
+        for ((value, name), default) in row.iter_mut().zip(&names).zip(&defaults) {
+            *value = assignment.get(name).cloned().unwrap_or(*default);
+        }
+        plan.eval(&mut values, |column| row[column]);
+        *values.last().unwrap()
+    })
+}
+
+#[test]
+fn batch_matches_single_evaluation() {
+    use {add, mul, sin, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let y = var(&arena, "y".to_string());
+    let c = var(&arena, "c".to_string());
+    c.set_value(2.0);
+    let f = add(&arena, mul(&arena, x, y), mul(&arena, c, sin(&arena, x)));
+
+    let xs = [0.0f32, 0.5, 1.0];
+    let ys = [1.0f32, -1.0, 3.0];
+    let values = forward_batch(f, &["x", "y"], &[&xs, &ys]);
+    let (same, grads) = grad_batch(f, &["y", "x"], &[&ys, &xs]);
+    assert_eq!(values, same);
+
+    for i in 0..3 {
+        x.set_value(xs[i]);
+        y.set_value(ys[i]);
+        f.forward();
+        f.backward_ad(&["x", "y"]);
+        assert_eq!(values[i], f.value());
+        assert!((grads[i][0] - f.grad("y")).abs() < 1e-6);
+        assert!((grads[i][1] - f.grad("x")).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn stream_of_assignments() {
+    use {mul, pow, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let y = var(&arena, "y".to_string());
+    y.set_value(10.0);
+    let f = mul(&arena, pow(&arena, x, 2.0), y);
+
+    let assignments = (0..4).map(|i| {
+        let mut assignment = Assignment::new();
+        assignment.insert("x".to_string(), i as f32);
+        if i == 3 {
+            assignment.insert("y".to_string(), 1.0);
+        }
+        assignment
+    });
+    let values: Vec<f32> = forward_stream(f, assignments).collect();
+    assert_eq!(values, vec![0.0, 10.0, 40.0, 9.0]);
+}
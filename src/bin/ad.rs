@@ -0,0 +1,374 @@
+// Evaluates and differentiates an infix expression from the command line:
+//
+//     ad 'sin(x) * y + x^2' x=0.5 y=3 --grad x,y
+//
+// Built with `--features cli`.
+
+extern crate ad;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use ad::canonical::canonicalize;
+use ad::dump::to_dot;
+use ad::rpn::{from_rpn, Operation, Token};
+use ad::sympy::to_sympy;
+use ad::{try_eval_with_grad, Arena, Assignment};
+
+const USAGE: &str = "usage: ad EXPRESSION [NAME=VALUE]... [--grad x,y] [--simplify] [--dot FILE]";
+
+struct Options {
+    expression: String,
+    assignment: Assignment,
+    grad: Vec<String>,
+    simplify: bool,
+    dot: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut expression = None;
+    let mut assignment = Assignment::new();
+    let mut grad = vec![];
+    let mut simplify = false;
+    let mut dot = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--grad" => {
+                let names = args.next().ok_or("--grad needs variable names")?;
+                grad.extend(names.split(',').map(|name| name.trim().to_string()));
+            }
+            "--simplify" => simplify = true,
+            "--dot" => dot = Some(args.next().ok_or("--dot needs a file name")?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => match arg.find('=') {
+                Some(at) => {
+                    let value = arg[at + 1..]
+                        .parse()
+                        .map_err(|_| format!("{} is not a number", &arg[at + 1..]))?;
+                    assignment.insert(arg[..at].to_string(), value);
+                }
+                None if expression.is_none() => expression = Some(arg.clone()),
+                None => return Err(format!("unexpected argument {}", arg)),
+            },
+        }
+    }
+
+    Ok(Options {
+        expression: expression.ok_or("no expression given")?,
+        assignment,
+        grad,
+        simplify,
+        dot,
+    })
+}
+
+// Translates infix text into the tokens of `from_rpn`. `^` binds tighter
+// than unary minus and groups to the right, so `-x^2` is `-(x^2)`.
+struct Parser<'s> {
+    chars: Vec<char>,
+    at: usize,
+    text: &'s str,
+    out: Vec<Token>,
+}
+
+impl<'s> Parser<'s> {
+    fn new(text: &'s str) -> Self {
+        Parser {
+            chars: text.chars().collect(),
+            at: 0,
+            text,
+            out: vec![],
+        }
+    }
+
+    fn parse(mut self) -> Result<Vec<Token>, String> {
+        self.expression()?;
+        match self.peek() {
+            None => Ok(self.out),
+            Some(c) => Err(self.error(&format!("unexpected {:?}", c))),
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at column {} of {:?}", message, self.at + 1, self.text)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+        self.chars.get(self.at).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self) -> Result<(), String> {
+        self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Operation::Plus,
+                Some('-') => Operation::Minus,
+                _ => return Ok(()),
+            };
+            self.at += 1;
+            self.term()?;
+            self.out.push(Token::Binary(op));
+        }
+    }
+
+    fn term(&mut self) -> Result<(), String> {
+        self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => Operation::Times,
+                Some('/') => Operation::Div,
+                _ => return Ok(()),
+            };
+            self.at += 1;
+            self.unary()?;
+            self.out.push(Token::Binary(op));
+        }
+    }
+
+    fn unary(&mut self) -> Result<(), String> {
+        let op = match self.peek() {
+            Some('+') => Operation::Plus,
+            Some('-') => Operation::Minus,
+            _ => return self.power(),
+        };
+        self.at += 1;
+        self.unary()?;
+        self.out.push(Token::Unary(op));
+        Ok(())
+    }
+
+    fn power(&mut self) -> Result<(), String> {
+        self.atom()?;
+        if self.eat('^') {
+            self.unary()?;
+            self.out.push(Token::Binary(Operation::Pow));
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some('(') => {
+                self.at += 1;
+                self.expression()?;
+                if !self.eat(')') {
+                    return Err(self.error("expected )"));
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.at;
+                while self.at < self.chars.len() {
+                    let c = self.chars[self.at];
+                    let exponent_sign = (c == '+' || c == '-')
+                        && (self.chars[self.at - 1] == 'e' || self.chars[self.at - 1] == 'E');
+                    if !(c.is_ascii_alphanumeric() || c == '.' || exponent_sign) {
+                        break;
+                    }
+                    self.at += 1;
+                }
+                let number: String = self.chars[start..self.at].iter().collect();
+                let value = number.parse().map_err(|_| {
+                    self.at = start;
+                    self.error(&format!("bad number {}", number))
+                })?;
+                self.out.push(Token::Number(value));
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let start = self.at;
+                while self
+                    .chars
+                    .get(self.at)
+                    .is_some_and(|&c| c.is_alphanumeric() || c == '_')
+                {
+                    self.at += 1;
+                }
+                let name: String = self.chars[start..self.at].iter().collect();
+                if self.eat('(') {
+                    let mut arity = 0;
+                    if !self.eat(')') {
+                        loop {
+                            self.expression()?;
+                            arity += 1;
+                            if self.eat(')') {
+                                break;
+                            }
+                            if !self.eat(',') {
+                                return Err(self.error("expected , or )"));
+                            }
+                        }
+                    }
+                    self.out.push(Token::Func(name, arity));
+                } else {
+                    self.out.push(Token::Var(name));
+                }
+            }
+            Some(c) => return Err(self.error(&format!("unexpected {:?}", c))),
+            None => return Err(self.error("unexpected end")),
+        }
+        Ok(())
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let tokens = Parser::new(&options.expression).parse()?;
+    let arena = Arena::new();
+    let mut node = from_rpn(&arena, &tokens).map_err(|e| e.to_string())?;
+    if options.simplify {
+        node = canonicalize(&arena, node);
+        println!("expression = {}", to_sympy(node));
+    }
+
+    let names: Vec<&str> = options.grad.iter().map(|name| name.as_str()).collect();
+    let (value, grads) =
+        try_eval_with_grad(node, &options.assignment, &names).map_err(|e| e.to_string())?;
+    println!("value = {}", value);
+    for name in &names {
+        println!("d/d{} = {}", name, grads[*name]);
+    }
+
+    if let Some(ref path) = options.dot {
+        // the graph is written with the values stored in its nodes
+        node.assign(&options.assignment);
+        node.forward();
+        fs::write(path, to_dot(node)).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("ad: {}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(message) = run(&options) {
+        eprintln!("ad: {}", message);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+fn eval(text: &str, at: &[(&str, f32)]) -> Result<f32, String> {
+    use ad::forward_multi;
+
+    let tokens = Parser::new(text).parse()?;
+    let arena = Arena::new();
+    let node = from_rpn(&arena, &tokens).map_err(|e| e.to_string())?;
+    Ok(forward_multi(&[node], &at.iter().cloned().collect())[0])
+}
+
+#[test]
+fn infix_precedence() {
+    use self::Operation::*;
+
+    assert_eq!(
+        Parser::new("-x^2").parse(),
+        Ok(vec![
+            Token::Var("x".to_string()),
+            Token::Number(2.0),
+            Token::Binary(Pow),
+            Token::Unary(Minus),
+        ])
+    );
+    let at = [("x", 3f32), ("y", 2f32)];
+    assert_eq!(eval("-x^2", &at), Ok(-9.0));
+    assert_eq!(eval("2^3^2", &at), Ok(512.0));
+    assert_eq!(eval("2^-1", &at), Ok(0.5));
+    assert_eq!(eval("1 - x - y * x / y", &at), Ok(-5.0));
+    assert_eq!(eval("(1 - x) * -(y + 1)", &at), Ok(6.0));
+    assert_eq!(eval(" sin( x ) * cos(0)", &at), Ok(3f32.sin()));
+}
+
+#[test]
+fn infix_numbers_and_errors() {
+    let at = [("x", 1000f32)];
+    assert_eq!(eval("1e-3 * x", &at), Ok(1.0));
+    assert_eq!(eval("2.5E+1 + .5", &at), Ok(25.5));
+
+    assert_eq!(
+        eval("2x", &at),
+        Err("bad number 2x at column 1 of \"2x\"".to_string())
+    );
+    assert_eq!(
+        eval("sin(x", &at),
+        Err("expected , or ) at column 6 of \"sin(x\"".to_string())
+    );
+    assert_eq!(
+        eval("x + * 2", &at),
+        Err("unexpected '*' at column 5 of \"x + * 2\"".to_string())
+    );
+    assert_eq!(
+        eval("x 2", &at),
+        Err("unexpected '2' at column 3 of \"x 2\"".to_string())
+    );
+    assert_eq!(
+        eval("x +", &at),
+        Err("unexpected end at column 4 of \"x +\"".to_string())
+    );
+}
+
+#[test]
+fn command_line_arguments() {
+    let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+
+    let options = parse_args(&args(&[
+        "x * y",
+        "x=2",
+        "--grad",
+        "x, y",
+        "y=-1.5",
+        "--simplify",
+        "--dot",
+        "out.dot",
+    ]))
+    .unwrap();
+    assert_eq!(options.expression, "x * y");
+    assert_eq!(options.assignment, [("x", 2.0), ("y", -1.5)].into());
+    assert_eq!(options.grad, vec!["x", "y"]);
+    assert!(options.simplify);
+    assert_eq!(options.dot, Some("out.dot".to_string()));
+
+    assert_eq!(
+        parse_args(&args(&["x=1"])).err(),
+        Some("no expression given".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["x", "--hessian"])).err(),
+        Some("unknown option --hessian".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["x", "x=one"])).err(),
+        Some("one is not a number".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["x", "y"])).err(),
+        Some("unexpected argument y".to_string())
+    );
+    assert_eq!(
+        parse_args(&args(&["x", "--grad"])).err(),
+        Some("--grad needs variable names".to_string())
+    );
+}
@@ -0,0 +1,219 @@
+// A compact, versioned binary format for owned graphs.
+//
+// After the magic and a version byte come the number of nodes and then
+// every node in order: an op code, its payload (f32 constants and
+// exponents, length-prefixed variable names, operand counts of n-ary ops)
+// and its operands. Operands are written as the distance back to them, as
+// LEB128 varints, which is usually a single byte since operands tend to be
+// close to their users. Node ids survive a round trip; labels are not kept.
+
+use std::error::Error;
+use std::fmt;
+
+use graph::{Graph, NodeId, Op};
+use reader::{Malformed, Reader};
+
+const MAGIC: &[u8] = b"ADGR";
+pub const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    // custom ops are native code and cannot be written out
+    CustomOp(String),
+    UnsupportedVersion(u8),
+    // the bytes are not a graph written by `to_bytes`
+    Malformed,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::CustomOp(ref name) => write!(f, "custom op {} cannot be serialized", name),
+            FormatError::UnsupportedVersion(version) => {
+                write!(f, "unsupported graph format version {}", version)
+            }
+            FormatError::Malformed => write!(f, "malformed graph"),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+impl From<Malformed> for FormatError {
+    fn from(_: Malformed) -> Self {
+        FormatError::Malformed
+    }
+}
+
+impl Graph {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FormatError> {
+        let mut out = MAGIC.to_vec();
+        out.push(FORMAT_VERSION);
+        put_varint(&mut out, self.len() as u64);
+
+        for index in 0..self.len() {
+            let id = NodeId(index);
+            let args = self.args(id);
+            match *self.op(id) {
+                Op::Const(value) => {
+                    out.push(0);
+                    out.extend_from_slice(&value.to_bits().to_le_bytes());
+                }
+                Op::Var(ref name) => {
+                    out.push(1);
+                    put_varint(&mut out, name.len() as u64);
+                    out.extend_from_slice(name.as_bytes());
+                }
+                Op::Pow(exponent) => {
+                    out.push(7);
+                    out.extend_from_slice(&exponent.to_bits().to_le_bytes());
+                }
+                Op::AddN | Op::MulN => {
+                    out.push(if let Op::AddN = *self.op(id) { 14 } else { 15 });
+                    put_varint(&mut out, args.len() as u64);
+                }
+                Op::Custom(ref op) => return Err(FormatError::CustomOp(op.name().to_string())),
+                ref op => out.push(fixed_arity_code(op)),
+            }
+            for arg in args {
+                put_varint(&mut out, (index - arg.index()) as u64);
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Graph, FormatError> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(FormatError::Malformed);
+        }
+        let version = reader.byte()?;
+        if version != FORMAT_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+
+        let len = reader.varint()? as usize;
+        // every node takes at least one byte
+        if len > bytes.len() {
+            return Err(FormatError::Malformed);
+        }
+        let mut graph = Graph::with_capacity(len);
+        for index in 0..len {
+            let (op, arity) = match reader.byte()? {
+                0 => (Op::Const(reader.f32()?), 0),
+                1 => {
+                    let len = reader.varint()? as usize;
+                    (Op::Var(reader.utf8(len)?), 0)
+                }
+                2 => (Op::Neg, 1),
+                3 => (Op::Add, 2),
+                4 => (Op::Sub, 2),
+                5 => (Op::Mul, 2),
+                6 => (Op::Div, 2),
+                7 => (Op::Pow(reader.f32()?), 1),
+                8 => (Op::Sin, 1),
+                9 => (Op::Cos, 1),
+                10 => (Op::ExpM1, 1),
+                11 => (Op::Ln1p, 1),
+                12 => (Op::StopGradient, 1),
+                13 => (Op::Select, 3),
+                code @ 14..=15 => {
+                    let arity = reader.varint()? as usize;
+                    (if code == 14 { Op::AddN } else { Op::MulN }, arity)
+                }
+                _ => return Err(FormatError::Malformed),
+            };
+
+            let args = (0..arity)
+                .map(|_| {
+                    let distance = reader.varint()? as usize;
+                    if distance == 0 || distance > index {
+                        return Err(FormatError::Malformed);
+                    }
+                    Ok(NodeId(index - distance))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            graph.push(op, &args);
+        }
+
+        if reader.is_at_end() {
+            Ok(graph)
+        } else {
+            Err(FormatError::Malformed)
+        }
+    }
+}
+
+fn fixed_arity_code(op: &Op) -> u8 {
+    match *op {
+        Op::Neg => 2,
+        Op::Add => 3,
+        Op::Sub => 4,
+        Op::Mul => 5,
+        Op::Div => 6,
+        Op::Sin => 8,
+        Op::Cos => 9,
+        Op::ExpM1 => 10,
+        Op::Ln1p => 11,
+        Op::StopGradient => 12,
+        Op::Select => 13,
+        _ => unreachable!("{} has a payload", op.name()),
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[test]
+fn graph_round_trip() {
+    use testgen::{generate_random, variable_names, OpWeights};
+    use {add_n, select, sub, var, Arena, Assignment};
+
+    let arena = Arena::new();
+    let f = generate_random(&arena, 3, 10, 3, &OpWeights::default());
+    let names = variable_names(3);
+    let x0 = var(&arena, names[0].clone());
+    let f = select(&arena, sub(&arena, f, x0), f, add_n(&arena, &[f, f]));
+    let (graph, root) = Graph::from_node(f);
+
+    let bytes = graph.to_bytes().unwrap();
+    // a byte for the op and about one per operand
+    assert!(bytes.len() < 4 * graph.len());
+    let loaded = Graph::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.len(), graph.len());
+
+    let at: Assignment = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.clone(), 0.2 + i as f32))
+        .collect();
+    assert_eq!(
+        loaded.eval(root, &at).to_bits(),
+        graph.eval(root, &at).to_bits()
+    );
+
+    assert_eq!(
+        Graph::from_bytes(&bytes[..bytes.len() - 1]).err(),
+        Some(FormatError::Malformed)
+    );
+    let mut future = bytes.clone();
+    future[4] = 2;
+    assert_eq!(
+        Graph::from_bytes(&future).err(),
+        Some(FormatError::UnsupportedVersion(2))
+    );
+
+    let mut custom = Graph::new();
+    let x = custom.var("x").id();
+    custom.custom(::ops::BceWithLogits, &[x, x]);
+    assert_eq!(
+        custom.to_bytes(),
+        Err(FormatError::CustomOp("BceWithLogits".to_string()))
+    );
+}
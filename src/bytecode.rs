@@ -0,0 +1,537 @@
+// Compact register-based bytecode for evaluating an expression repeatedly.
+//
+// Compiling flattens the graph into a list of instructions over a small set
+// of registers, reusing a register once its value is no longer needed.
+// Programs without custom ops can be written to and read from bytes.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use reader::{Malformed, Reader};
+use {node_key, topological_order, CustomOp, Node, NodeData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Sin,
+    Cos,
+    ExpM1,
+    Ln1p,
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone)]
+pub enum Instr {
+    Const {
+        dst: u32,
+        value: f32,
+    },
+    Input {
+        dst: u32,
+        slot: u32,
+    },
+    Unary {
+        op: UnaryOp,
+        dst: u32,
+        src: u32,
+    },
+    Binary {
+        op: BinaryOp,
+        dst: u32,
+        lhs: u32,
+        rhs: u32,
+    },
+    Pow {
+        dst: u32,
+        src: u32,
+        exponent: f32,
+    },
+    Select {
+        dst: u32,
+        cond: u32,
+        then: u32,
+        else_: u32,
+    },
+    Sum {
+        dst: u32,
+        args: Vec<u32>,
+    },
+    Product {
+        dst: u32,
+        args: Vec<u32>,
+    },
+    Custom {
+        dst: u32,
+        op: Arc<dyn CustomOp>,
+        args: Vec<u32>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    // custom ops are native code and cannot be written out
+    CustomOp(String),
+    // the bytes are not a program written by `to_bytes`
+    Malformed,
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BytecodeError::CustomOp(ref name) => {
+                write!(f, "custom op {} cannot be serialized", name)
+            }
+            BytecodeError::Malformed => write!(f, "malformed bytecode"),
+        }
+    }
+}
+
+impl Error for BytecodeError {}
+
+impl From<Malformed> for BytecodeError {
+    fn from(_: Malformed) -> Self {
+        BytecodeError::Malformed
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    inputs: Vec<String>,
+    code: Vec<Instr>,
+    registers: usize,
+    output: u32,
+}
+
+impl Program {
+    // Every variable of `node` becomes an input, in order of first
+    // appearance; see `inputs`.
+    pub fn compile(node: Node) -> Program {
+        use NodeType::*;
+
+        let order = topological_order(&[node]);
+        let positions: HashMap<*const NodeData, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(i, &node)| (node_key(node), i))
+            .collect();
+        let mut last_use: Vec<usize> = (0..order.len()).collect();
+        for (i, node) in order.iter().enumerate() {
+            for child in node.children() {
+                last_use[positions[&node_key(child)]] = i;
+            }
+        }
+        // the output must survive to the end
+        last_use[order.len() - 1] = order.len();
+
+        let mut inputs: Vec<String> = vec![];
+        let mut code = vec![];
+        let mut register = vec![0u32; order.len()];
+        let mut free: Vec<u32> = vec![];
+        let mut registers = 0;
+
+        for (i, &node) in order.iter().enumerate() {
+            let children = node.children();
+            let args: Vec<u32> = children
+                .iter()
+                .map(|&child| register[positions[&node_key(child)]])
+                .collect();
+            // operands are read before the result is written, so registers
+            // of values dying here can be reused for the result at once
+            for child in &children {
+                let c = positions[&node_key(child)];
+                if last_use[c] == i && !free.contains(&register[c]) {
+                    free.push(register[c]);
+                }
+            }
+            let dst = free.pop().unwrap_or_else(|| {
+                registers += 1;
+                registers as u32 - 1
+            });
+            register[i] = dst;
+
+            let unary = |op| Instr::Unary {
+                op,
+                dst,
+                src: args[0],
+            };
+            let binary = |op| Instr::Binary {
+                op,
+                dst,
+                lhs: args[0],
+                rhs: args[1],
+            };
+            code.push(match node.type_ {
+                Const(value) => Instr::Const { dst, value },
+                Var(ref name) => {
+                    let slot = match inputs.iter().position(|input| input == name) {
+                        Some(slot) => slot,
+                        None => {
+                            inputs.push(name.clone());
+                            inputs.len() - 1
+                        }
+                    };
+                    Instr::Input {
+                        dst,
+                        slot: slot as u32,
+                    }
+                }
+                Neg(_) => unary(UnaryOp::Neg),
+                Sin(_) => unary(UnaryOp::Sin),
+                Cos(_) => unary(UnaryOp::Cos),
+                ExpM1(_) => unary(UnaryOp::ExpM1),
+                Ln1p(_) => unary(UnaryOp::Ln1p),
+                StopGradient(_) => unary(UnaryOp::Copy),
+                Add(_, _) => binary(BinaryOp::Add),
+                Sub(_, _) => binary(BinaryOp::Sub),
+                Mul(_, _) => binary(BinaryOp::Mul),
+                Div(_, _) => binary(BinaryOp::Div),
+                Pow(_, exponent) => Instr::Pow {
+                    dst,
+                    src: args[0],
+                    exponent,
+                },
+                Select(_, _, _) => Instr::Select {
+                    dst,
+                    cond: args[0],
+                    then: args[1],
+                    else_: args[2],
+                },
+                AddN(_) => Instr::Sum { dst, args },
+                MulN(_) => Instr::Product { dst, args },
+                Custom(ref op, _) => Instr::Custom {
+                    dst,
+                    op: op.clone(),
+                    args,
+                },
+            });
+        }
+
+        Program {
+            inputs,
+            code,
+            registers,
+            output: register[order.len() - 1],
+        }
+    }
+
+    // names of the inputs expected by `run`, in order
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    pub fn code(&self) -> &[Instr] {
+        &self.code
+    }
+
+    pub fn registers(&self) -> usize {
+        self.registers
+    }
+
+    pub fn run(&self, inputs: &[f32]) -> f32 {
+        self.run_with(&mut vec![], inputs)
+    }
+
+    // Like `run`, reusing `registers` between calls.
+    pub fn run_with(&self, registers: &mut Vec<f32>, inputs: &[f32]) -> f32 {
+        assert_eq!(inputs.len(), self.inputs.len(), "one value per input");
+        registers.clear();
+        registers.resize(self.registers, 0f32);
+        let r = registers;
+
+        let mut operands = vec![];
+        for instr in &self.code {
+            let (dst, value) = match *instr {
+                Instr::Const { dst, value } => (dst, value),
+                Instr::Input { dst, slot } => (dst, inputs[slot as usize]),
+                Instr::Unary { op, dst, src } => {
+                    let x = r[src as usize];
+                    let value = match op {
+                        UnaryOp::Neg => -x,
+                        UnaryOp::Sin => x.sin(),
+                        UnaryOp::Cos => x.cos(),
+                        UnaryOp::ExpM1 => x.exp_m1(),
+                        UnaryOp::Ln1p => x.ln_1p(),
+                        UnaryOp::Copy => x,
+                    };
+                    (dst, value)
+                }
+                Instr::Binary { op, dst, lhs, rhs } => {
+                    let (l, r) = (r[lhs as usize], r[rhs as usize]);
+                    let value = match op {
+                        BinaryOp::Add => l + r,
+                        BinaryOp::Sub => l - r,
+                        BinaryOp::Mul => l * r,
+                        BinaryOp::Div => l / r,
+                    };
+                    (dst, value)
+                }
+                Instr::Pow { dst, src, exponent } => (dst, r[src as usize].powf(exponent)),
+                Instr::Select {
+                    dst,
+                    cond,
+                    then,
+                    else_,
+                } => {
+                    let branch = if r[cond as usize] > 0f32 { then } else { else_ };
+                    (dst, r[branch as usize])
+                }
+                Instr::Sum { dst, ref args } => (dst, args.iter().map(|&a| r[a as usize]).sum()),
+                Instr::Product { dst, ref args } => {
+                    (dst, args.iter().map(|&a| r[a as usize]).product())
+                }
+                Instr::Custom {
+                    dst,
+                    ref op,
+                    ref args,
+                } => {
+                    operands.clear();
+                    operands.extend(args.iter().map(|&a| r[a as usize]));
+                    (dst, op.forward(&operands))
+                }
+            };
+            r[dst as usize] = value;
+        }
+
+        r[self.output as usize]
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BytecodeError> {
+        let mut out = b"ADBC".to_vec();
+        put(&mut out, self.inputs.len() as u32);
+        for input in &self.inputs {
+            put(&mut out, input.len() as u32);
+            out.extend_from_slice(input.as_bytes());
+        }
+        put(&mut out, self.registers as u32);
+        put(&mut out, self.output);
+        put(&mut out, self.code.len() as u32);
+
+        for instr in &self.code {
+            match *instr {
+                Instr::Const { dst, value } => {
+                    out.push(0);
+                    put(&mut out, dst);
+                    put(&mut out, value.to_bits());
+                }
+                Instr::Input { dst, slot } => {
+                    out.push(1);
+                    put(&mut out, dst);
+                    put(&mut out, slot);
+                }
+                Instr::Unary { op, dst, src } => {
+                    out.push(2);
+                    out.push(op as u8);
+                    put(&mut out, dst);
+                    put(&mut out, src);
+                }
+                Instr::Binary { op, dst, lhs, rhs } => {
+                    out.push(3);
+                    out.push(op as u8);
+                    put(&mut out, dst);
+                    put(&mut out, lhs);
+                    put(&mut out, rhs);
+                }
+                Instr::Pow { dst, src, exponent } => {
+                    out.push(4);
+                    put(&mut out, dst);
+                    put(&mut out, src);
+                    put(&mut out, exponent.to_bits());
+                }
+                Instr::Select {
+                    dst,
+                    cond,
+                    then,
+                    else_,
+                } => {
+                    out.push(5);
+                    for &reg in &[dst, cond, then, else_] {
+                        put(&mut out, reg);
+                    }
+                }
+                Instr::Sum { dst, ref args } | Instr::Product { dst, ref args } => {
+                    out.push(if let Instr::Sum { .. } = *instr { 6 } else { 7 });
+                    put(&mut out, dst);
+                    put(&mut out, args.len() as u32);
+                    for &arg in args {
+                        put(&mut out, arg);
+                    }
+                }
+                Instr::Custom { ref op, .. } => {
+                    return Err(BytecodeError::CustomOp(op.name().to_string()))
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, BytecodeError> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(4)? != b"ADBC" {
+            return Err(BytecodeError::Malformed);
+        }
+        let inputs = (0..reader.u32()?)
+            .map(|_| {
+                let len = reader.u32()? as usize;
+                reader.utf8(len)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let registers = reader.u32()? as usize;
+        let output = reader.u32()?;
+
+        let mut code = vec![];
+        for _ in 0..reader.u32()? {
+            let instr = match reader.byte()? {
+                0 => Instr::Const {
+                    dst: reader.u32()?,
+                    value: reader.f32()?,
+                },
+                1 => Instr::Input {
+                    dst: reader.u32()?,
+                    slot: reader.u32()?,
+                },
+                2 => {
+                    let op = match reader.byte()? {
+                        0 => UnaryOp::Neg,
+                        1 => UnaryOp::Sin,
+                        2 => UnaryOp::Cos,
+                        3 => UnaryOp::ExpM1,
+                        4 => UnaryOp::Ln1p,
+                        5 => UnaryOp::Copy,
+                        _ => return Err(BytecodeError::Malformed),
+                    };
+                    Instr::Unary {
+                        op,
+                        dst: reader.u32()?,
+                        src: reader.u32()?,
+                    }
+                }
+                3 => {
+                    let op = match reader.byte()? {
+                        0 => BinaryOp::Add,
+                        1 => BinaryOp::Sub,
+                        2 => BinaryOp::Mul,
+                        3 => BinaryOp::Div,
+                        _ => return Err(BytecodeError::Malformed),
+                    };
+                    Instr::Binary {
+                        op,
+                        dst: reader.u32()?,
+                        lhs: reader.u32()?,
+                        rhs: reader.u32()?,
+                    }
+                }
+                4 => Instr::Pow {
+                    dst: reader.u32()?,
+                    src: reader.u32()?,
+                    exponent: reader.f32()?,
+                },
+                5 => Instr::Select {
+                    dst: reader.u32()?,
+                    cond: reader.u32()?,
+                    then: reader.u32()?,
+                    else_: reader.u32()?,
+                },
+                tag @ 6..=7 => {
+                    let dst = reader.u32()?;
+                    let args = (0..reader.u32()?)
+                        .map(|_| reader.u32())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if tag == 6 {
+                        Instr::Sum { dst, args }
+                    } else {
+                        Instr::Product { dst, args }
+                    }
+                }
+                _ => return Err(BytecodeError::Malformed),
+            };
+            code.push(instr);
+        }
+
+        let program = Program {
+            inputs,
+            code,
+            registers,
+            output,
+        };
+        if program.is_valid() && reader.is_at_end() {
+            Ok(program)
+        } else {
+            Err(BytecodeError::Malformed)
+        }
+    }
+
+    // every register and input slot is in range, and there are no more
+    // registers than `compile` gives out, one per instruction at most
+    fn is_valid(&self) -> bool {
+        let reg = |r: u32| (r as usize) < self.registers;
+        self.registers <= self.code.len()
+            && reg(self.output)
+            && self.code.iter().all(|instr| match *instr {
+                Instr::Const { dst, .. } => reg(dst),
+                Instr::Input { dst, slot } => reg(dst) && (slot as usize) < self.inputs.len(),
+                Instr::Unary { dst, src, .. } | Instr::Pow { dst, src, .. } => reg(dst) && reg(src),
+                Instr::Binary { dst, lhs, rhs, .. } => reg(dst) && reg(lhs) && reg(rhs),
+                Instr::Select {
+                    dst,
+                    cond,
+                    then,
+                    else_,
+                } => reg(dst) && reg(cond) && reg(then) && reg(else_),
+                Instr::Sum { dst, ref args }
+                | Instr::Product { dst, ref args }
+                | Instr::Custom { dst, ref args, .. } => reg(dst) && args.iter().all(|&a| reg(a)),
+            })
+    }
+}
+
+fn put(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+#[test]
+fn compile_run_and_round_trip() {
+    use testgen::{generate_random, variable_names, OpWeights};
+    use {constant, forward_multi, Arena, Assignment};
+
+    let arena = Arena::new();
+    let f = generate_random(&arena, 7, 10, 3, &OpWeights::default());
+    let program = Program::compile(f);
+    assert!(program.registers() < program.code().len());
+
+    let mut at = Assignment::new();
+    for (i, name) in variable_names(3).into_iter().enumerate() {
+        at.insert(name, 0.3 + i as f32);
+    }
+    let inputs: Vec<f32> = program.inputs().iter().map(|name| at[name]).collect();
+    let expected = forward_multi(&[f], &at)[0];
+    assert_eq!(program.run(&inputs).to_bits(), expected.to_bits());
+
+    let bytes = program.to_bytes().unwrap();
+    let loaded = Program::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.run(&inputs).to_bits(), expected.to_bits());
+    assert_eq!(
+        Program::from_bytes(&bytes[..bytes.len() - 1]).err(),
+        Some(BytecodeError::Malformed)
+    );
+
+    // a register count that `run` would have to allocate blindly
+    let constant = Program::compile(constant(&arena, 1f32));
+    let mut bytes = constant.to_bytes().unwrap();
+    assert_eq!(bytes[8..12], 1u32.to_le_bytes());
+    bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        Program::from_bytes(&bytes).err(),
+        Some(BytecodeError::Malformed)
+    );
+}
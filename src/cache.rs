@@ -0,0 +1,159 @@
+// Least-recently-used cache of values and gradients keyed by the values of
+// the variables, for callers that revisit (nearly) the same points, such as
+// line searches.
+
+use std::collections::HashMap;
+
+use {adjoints, evaluate_all, node_key, topological_order, variable_gradients, Gradients, Node};
+
+pub struct GradCache<'a> {
+    root: Node<'a>,
+    order: Vec<Node<'a>>,
+    variables: Vec<String>,
+    // every node of each variable
+    nodes: Vec<Vec<Node<'a>>>,
+    capacity: usize,
+    quantum: f32,
+    entries: HashMap<Vec<i64>, (f32, Gradients, u64)>,
+    tick: u64,
+    hits: usize,
+    misses: usize,
+}
+
+impl<'a> GradCache<'a> {
+    // Caches up to `capacity` points of `root` as a function of
+    // `variables`. Other variables are read when a point is first computed,
+    // so `clear` the cache after changing them.
+    pub fn new(root: Node<'a>, variables: &[&str], capacity: usize) -> Self {
+        let order = topological_order(&[root]);
+        let nodes = variables
+            .iter()
+            .map(|name| {
+                order
+                    .iter()
+                    .cloned()
+                    .filter(|node| node.var_name() == Some(name))
+                    .collect()
+            })
+            .collect();
+
+        GradCache {
+            root,
+            order,
+            variables: variables.iter().map(|name| name.to_string()).collect(),
+            nodes,
+            capacity,
+            quantum: 0f32,
+            entries: HashMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // Points whose coordinates round to the same multiple of `quantum` share
+    // an entry. Zero, the default, matches points exactly.
+    pub fn quantum(mut self, quantum: f32) -> Self {
+        self.quantum = quantum;
+        self
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn key(&self, values: &[f32]) -> Vec<i64> {
+        values
+            .iter()
+            .map(|&v| {
+                if self.quantum > 0f32 {
+                    (v / self.quantum).round() as i64
+                } else {
+                    v.to_bits() as i64
+                }
+            })
+            .collect()
+    }
+
+    // Value and gradient at `values`, one per variable, from the cache if
+    // possible. Variables are left at `values` either way.
+    pub fn eval(&mut self, values: &[f32]) -> (f32, Gradients) {
+        assert_eq!(values.len(), self.variables.len(), "one value per variable");
+        for (nodes, &value) in self.nodes.iter().zip(values) {
+            for node in nodes {
+                node.set_value(value);
+            }
+        }
+
+        self.tick += 1;
+        let key = self.key(values);
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.2 = self.tick;
+            self.hits += 1;
+            return (entry.0, entry.1.clone());
+        }
+        self.misses += 1;
+
+        let computed = evaluate_all(&self.order);
+        let adjoints = adjoints(&self.order, &computed, &[(self.root, 1f32)]);
+        let names: Vec<&str> = self.variables.iter().map(|name| name.as_str()).collect();
+        let value = computed[&node_key(self.root)];
+        let grads = variable_gradients(&self.order, &adjoints, &names);
+
+        if self.capacity > 0 {
+            if self.entries.len() >= self.capacity {
+                let oldest = self
+                    .entries
+                    .iter()
+                    .min_by_key(|&(_, &(_, _, tick))| tick)
+                    .map(|(key, _)| key.clone())
+                    .unwrap();
+                self.entries.remove(&oldest);
+            }
+            self.entries.insert(key, (value, grads.clone(), self.tick));
+        }
+
+        (value, grads)
+    }
+}
+
+#[test]
+fn cache_hits_and_eviction() {
+    use {mul, sin, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let f = sin(&arena, mul(&arena, x, x));
+    let mut cache = GradCache::new(f, &["x"], 2).quantum(1e-4);
+
+    let (value, grads) = cache.eval(&[1.0]);
+    assert_eq!(value, 1f32.sin());
+    assert_eq!(grads["x"], 2.0 * 1f32.cos());
+
+    // within the quantum of 1.0
+    assert_eq!(cache.eval(&[1.00001]).0, value);
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+    cache.eval(&[2.0]);
+    cache.eval(&[3.0]);
+    // 1.0 was least recently used
+    assert_eq!(cache.len(), 2);
+    cache.eval(&[1.0]);
+    assert_eq!((cache.hits(), cache.misses()), (1, 4));
+}
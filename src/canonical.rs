@@ -0,0 +1,251 @@
+// A normal form for expressions.
+//
+// Subtraction becomes addition of a negation, division becomes
+// multiplication by a power of -1, nested sums and products are flattened
+// into single n-ary nodes with their operands sorted, and structurally equal
+// subexpressions become the same node. Two expressions that differ only in
+// these respects canonicalize to the same node when canonicalized together.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use {node_key, topological_order, Arena, Node, NodeData, NodeType};
+
+// what a node is without its operands, used for hash consing
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Head {
+    Const(u32),
+    Var(String),
+    Neg,
+    Pow(u32),
+    Sin,
+    Cos,
+    ExpM1,
+    Ln1p,
+    StopGradient,
+    // custom ops are told apart by identity, their names only order them
+    Custom(String, usize),
+    Select,
+    AddN,
+    MulN,
+}
+
+struct Canonicalizer<'a> {
+    arena: &'a Arena<'a>,
+    // canonical node of every node seen so far
+    canonical: HashMap<*const NodeData<'a>, Node<'a>>,
+    // canonical nodes by head and operands
+    interned: HashMap<(Head, Vec<*const NodeData<'a>>), Node<'a>>,
+    // structural hash of every canonical node, which orders operands
+    hashes: HashMap<*const NodeData<'a>, u64>,
+}
+
+impl<'a> Canonicalizer<'a> {
+    fn make(&mut self, head: Head, args: Vec<Node<'a>>) -> Node<'a> {
+        let key = (head, args.iter().map(|&arg| node_key(arg)).collect());
+        if let Some(&node) = self.interned.get(&key) {
+            return node;
+        }
+
+        let type_ = match key.0 {
+            Head::Const(bits) => NodeType::Const(f32::from_bits(bits)),
+            Head::Var(ref name) => NodeType::Var(name.clone()),
+            Head::Neg => NodeType::Neg(args[0]),
+            Head::Pow(bits) => NodeType::Pow(args[0], f32::from_bits(bits)),
+            Head::Sin => NodeType::Sin(args[0]),
+            Head::Cos => NodeType::Cos(args[0]),
+            Head::ExpM1 => NodeType::ExpM1(args[0]),
+            Head::Ln1p => NodeType::Ln1p(args[0]),
+            Head::StopGradient => NodeType::StopGradient(args[0]),
+            Head::Custom(..) => unreachable!("custom ops are built by the caller"),
+            Head::Select => NodeType::Select(args[0], args[1], args[2]),
+            Head::AddN => NodeType::AddN(args.clone()),
+            Head::MulN => NodeType::MulN(args.clone()),
+        };
+        let node: Node<'a> = self.arena.alloc(type_.into());
+        self.finish(key, node)
+    }
+
+    fn finish(&mut self, key: (Head, Vec<*const NodeData<'a>>), node: Node<'a>) -> Node<'a> {
+        let mut hasher = DefaultHasher::new();
+        match key.0 {
+            Head::Custom(ref name, _) => name.hash(&mut hasher),
+            ref head => head.hash(&mut hasher),
+        }
+        for arg in &key.1 {
+            self.hashes[arg].hash(&mut hasher);
+        }
+        self.hashes.insert(node_key(node), hasher.finish());
+        self.interned.insert(key, node);
+        node
+    }
+
+    // a flattened, sorted n-ary node; a single operand stands for itself
+    fn n_ary(&mut self, head: Head, operands: &[Node<'a>]) -> Node<'a> {
+        let mut flat = vec![];
+        for &operand in operands {
+            match operand.type_ {
+                NodeType::AddN(ref terms) if head == Head::AddN => flat.extend_from_slice(terms),
+                NodeType::MulN(ref factors) if head == Head::MulN => {
+                    flat.extend_from_slice(factors)
+                }
+                _ => flat.push(operand),
+            }
+        }
+        if flat.len() == 1 {
+            return flat[0];
+        }
+        flat.sort_by_key(|&node| self.hashes[&node_key(node)]);
+        self.make(head, flat)
+    }
+
+    fn visit(&mut self, node: Node<'a>) -> Node<'a> {
+        use NodeType::*;
+
+        let c = |n: &Node<'a>| self.canonical[&node_key(n)];
+        let canonical = match node.type_ {
+            Const(v) => self.make(Head::Const(v.to_bits()), vec![]),
+            Var(ref name) => self.make(Head::Var(name.clone()), vec![]),
+            Neg(x) => {
+                let x = c(&x);
+                self.make(Head::Neg, vec![x])
+            }
+            Add(l, r) => {
+                let operands = [c(&l), c(&r)];
+                self.n_ary(Head::AddN, &operands)
+            }
+            Sub(l, r) => {
+                let (l, r) = (c(&l), c(&r));
+                let negated = self.make(Head::Neg, vec![r]);
+                self.n_ary(Head::AddN, &[l, negated])
+            }
+            Mul(l, r) => {
+                let operands = [c(&l), c(&r)];
+                self.n_ary(Head::MulN, &operands)
+            }
+            Div(l, r) => {
+                let (l, r) = (c(&l), c(&r));
+                let reciprocal = self.make(Head::Pow((-1f32).to_bits()), vec![r]);
+                self.n_ary(Head::MulN, &[l, reciprocal])
+            }
+            Pow(x, p) => {
+                let x = c(&x);
+                self.make(Head::Pow(p.to_bits()), vec![x])
+            }
+            Sin(x) => {
+                let x = c(&x);
+                self.make(Head::Sin, vec![x])
+            }
+            Cos(x) => {
+                let x = c(&x);
+                self.make(Head::Cos, vec![x])
+            }
+            ExpM1(x) => {
+                let x = c(&x);
+                self.make(Head::ExpM1, vec![x])
+            }
+            Ln1p(x) => {
+                let x = c(&x);
+                self.make(Head::Ln1p, vec![x])
+            }
+            StopGradient(x) => {
+                let x = c(&x);
+                self.make(Head::StopGradient, vec![x])
+            }
+            Custom(ref op, ref args) => {
+                let args: Vec<Node<'a>> = args.iter().map(c).collect();
+                let head =
+                    Head::Custom(op.name().to_string(), Arc::as_ptr(op) as *const () as usize);
+                let key = (head, args.iter().map(|&arg| node_key(arg)).collect());
+                match self.interned.get(&key) {
+                    Some(&node) => node,
+                    None => {
+                        let node: Node<'a> = self.arena.alloc(Custom(op.clone(), args).into());
+                        self.finish(key, node)
+                    }
+                }
+            }
+            Select(cond, then, else_) => {
+                let args = vec![c(&cond), c(&then), c(&else_)];
+                self.make(Head::Select, args)
+            }
+            AddN(ref terms) => {
+                let terms: Vec<Node<'a>> = terms.iter().map(c).collect();
+                self.n_ary(Head::AddN, &terms)
+            }
+            MulN(ref factors) => {
+                let factors: Vec<Node<'a>> = factors.iter().map(c).collect();
+                self.n_ary(Head::MulN, &factors)
+            }
+        };
+        self.canonical.insert(node_key(node), canonical);
+        canonical
+    }
+}
+
+// The normal form of `node`, built in `arena`. Variables of the result are
+// new nodes starting at zero.
+pub fn canonicalize<'a>(arena: &'a Arena<'a>, node: Node<'a>) -> Node<'a> {
+    canonicalize_all(arena, &[node])[0]
+}
+
+// Normal forms of several roots sharing one table, so structurally equal
+// roots (and subexpressions) come out as the same node.
+pub fn canonicalize_all<'a>(arena: &'a Arena<'a>, roots: &[Node<'a>]) -> Vec<Node<'a>> {
+    let mut canonicalizer = Canonicalizer {
+        arena,
+        canonical: HashMap::new(),
+        interned: HashMap::new(),
+        hashes: HashMap::new(),
+    };
+    for node in topological_order(roots) {
+        canonicalizer.visit(node);
+    }
+    roots
+        .iter()
+        .map(|&root| canonicalizer.canonical[&node_key(root)])
+        .collect()
+}
+
+#[test]
+fn equivalent_forms_meet() {
+    use {add, constant, div, eval_with_grad, mul, neg, pow, sin, sub, var, Assignment};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let z = var(arena, "z".to_string());
+    // separately created variables with the same name
+    let x2 = var(arena, "x".to_string());
+
+    // (x - y) / z + sin(x) and sin(x) + (-y + x) * z^-1
+    let f = add(arena, div(arena, sub(arena, x, y), z), sin(arena, x));
+    let g = add(
+        arena,
+        sin(arena, x2),
+        mul(arena, add(arena, neg(arena, y), x2), pow(arena, z, -1f32)),
+    );
+    // ((x + y) + z) and (z + (y + x)) flatten to one sum
+    let h = add(arena, add(arena, x, y), z);
+    let k = add(arena, z, add(arena, y, constant(arena, 0f32)));
+
+    let canonical = canonicalize_all(arena, &[f, g, h, k]);
+    assert!(::std::ptr::eq(canonical[0], canonical[1]));
+    match canonical[2].type_ {
+        NodeType::AddN(ref terms) => assert_eq!(terms.len(), 3),
+        ref other => panic!("expected a flat sum, got {:?}", other),
+    }
+    assert!(!::std::ptr::eq(canonical[2], canonical[3]));
+
+    // values and gradients are unchanged
+    let at: Assignment = [("x", 0.3), ("y", -1.2), ("z", 2.5)].into();
+    let (value, grads) = eval_with_grad(f, &at, &["x", "y", "z"]);
+    let (canonical_value, canonical_grads) = eval_with_grad(canonical[0], &at, &["x", "y", "z"]);
+    assert!((value - canonical_value).abs() < 1e-6);
+    for name in &["x", "y", "z"] {
+        assert!((grads[*name] - canonical_grads[*name]).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,193 @@
+// Reverse-mode gradients that keep node values only at checkpoints.
+//
+// `backward_ad` stores a gradient map in every node. Here only the adjoints
+// of nodes that still have unprocessed parents are alive, and values of
+// nodes between checkpoints are recomputed when the backward sweep reaches
+// them.
+
+use std::collections::HashMap;
+
+use {node_key, topological_order, Gradients, Node, NodeData};
+
+// Gradients of `root` with respect to `variables`, storing the value of
+// every `stride`-th node (in evaluation order) during the forward sweep.
+pub fn backward_checkpointed(root: Node, variables: &[&str], stride: usize) -> Gradients {
+    assert!(stride > 0, "checkpoint stride must be positive");
+
+    let order = topological_order(&[root]);
+    let position: HashMap<*const NodeData, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(i, &node)| (node_key(node), i))
+        .collect();
+    let is_checkpoint = |i: usize| i.is_multiple_of(stride) || i == order.len() - 1;
+
+    // number of parents that have not consumed a node's value/adjoint yet
+    let mut remaining = vec![0usize; order.len()];
+    for node in &order {
+        for child in node.children() {
+            remaining[position[&node_key(child)]] += 1;
+        }
+    }
+
+    // forward sweep, dropping values once every parent has read them
+    let mut checkpoints = HashMap::new();
+    {
+        let mut live: HashMap<usize, f32> = HashMap::new();
+        let mut uses = remaining.clone();
+        for (i, node) in order.iter().enumerate() {
+            let children = node.children();
+            let inputs: Vec<f32> = children
+                .iter()
+                .map(|child| live[&position[&node_key(child)]])
+                .collect();
+            let value = node.eval(&inputs);
+
+            for child in children {
+                let j = position[&node_key(child)];
+                uses[j] -= 1;
+                if uses[j] == 0 {
+                    live.remove(&j);
+                }
+            }
+            if is_checkpoint(i) {
+                checkpoints.insert(i, value);
+            }
+            if uses[i] > 0 {
+                live.insert(i, value);
+            }
+        }
+    }
+
+    // backward sweep, one segment between checkpoints at a time
+    let mut grads: HashMap<String, f32> = variables.iter().map(|v| (v.to_string(), 0f32)).collect();
+    let mut adjoints: HashMap<usize, f32> = HashMap::new();
+    adjoints.insert(order.len() - 1, 1f32);
+
+    let mut segment_cache: HashMap<usize, f32> = HashMap::new();
+    for i in (0..order.len()).rev() {
+        if is_checkpoint(i) {
+            segment_cache.clear();
+        }
+
+        let adjoint = match adjoints.remove(&i) {
+            Some(adjoint) => adjoint,
+            None => continue,
+        };
+        let node = order[i];
+        if let Some(name) = node.var_name() {
+            if let Some(grad) = grads.get_mut(name) {
+                *grad += adjoint;
+            }
+            continue;
+        }
+
+        let children = node.children();
+        let inputs: Vec<f32> = children
+            .iter()
+            .map(|child| {
+                recompute(
+                    &order,
+                    &position,
+                    &checkpoints,
+                    &mut segment_cache,
+                    position[&node_key(child)],
+                )
+            })
+            .collect();
+        for (child, partial) in children.iter().zip(node.partials(&inputs)) {
+            *adjoints.entry(position[&node_key(child)]).or_insert(0f32) += adjoint * partial;
+        }
+    }
+
+    let values = variables.iter().map(|v| grads[*v]).collect();
+    Gradients::new(variables, values)
+}
+
+fn recompute(
+    order: &[Node],
+    position: &HashMap<*const NodeData, usize>,
+    checkpoints: &HashMap<usize, f32>,
+    cache: &mut HashMap<usize, f32>,
+    i: usize,
+) -> f32 {
+    let known = |cache: &HashMap<usize, f32>, i: usize| {
+        checkpoints.get(&i).or_else(|| cache.get(&i)).cloned()
+    };
+
+    // explicit stack, as segments of long unrolled graphs are deep
+    let mut stack = vec![(i, false)];
+    while let Some((i, expanded)) = stack.pop() {
+        if known(cache, i).is_some() {
+            continue;
+        }
+        let children = order[i].children();
+        if expanded {
+            let inputs: Vec<f32> = children
+                .iter()
+                .map(|child| known(cache, position[&node_key(child)]).unwrap())
+                .collect();
+            cache.insert(i, order[i].eval(&inputs));
+        } else {
+            stack.push((i, true));
+            for child in children {
+                let c = position[&node_key(child)];
+                if known(cache, c).is_none() {
+                    stack.push((c, false));
+                }
+            }
+        }
+    }
+    known(cache, i).unwrap()
+}
+
+#[test]
+fn checkpointed_matches_backward_ad() {
+    use {add, constant, cos, div, mul, sin, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    // an unrolled recurrence h <- sin(h * x) + cos(h) / y
+    let mut h = constant(arena, 0.5);
+    for _ in 0..8 {
+        h = add(
+            arena,
+            sin(arena, mul(arena, h, x)),
+            div(arena, cos(arena, h), y),
+        );
+    }
+
+    x.set_value(0.7);
+    y.set_value(2.5);
+    h.forward();
+    h.backward_ad(&["x", "y"]);
+
+    for &stride in &[1, 3, 16, 1000] {
+        let grads = backward_checkpointed(h, &["x", "y"], stride);
+        assert!((grads["x"] - h.grad("x")).abs() < 1e-5);
+        assert!((grads["y"] - h.grad("y")).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn deep_segments_are_recomputed_without_recursion() {
+    use {add, constant, mul, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let half = constant(arena, 0.5);
+
+    // h <- h / 2 + x, far deeper than the stack would allow recursively
+    let mut h = x;
+    for _ in 0..50_000 {
+        h = add(arena, mul(arena, h, half), x);
+    }
+    x.set_value(1.0);
+    let grads = backward_checkpointed(h, &["x"], 1_000_000);
+    assert!((grads["x"] - 2.0).abs() < 1e-5);
+}
@@ -0,0 +1,87 @@
+// Discrete convolution of node sequences.
+
+use {add_n, constant, mul, Arena, Node};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvMode {
+    // only outputs where the kernel lies entirely inside the signal,
+    // signal.len() - kernel.len() + 1 of them
+    Valid,
+    // as many outputs as the signal, centered, with zeros outside it
+    Same,
+}
+
+// The convolution of `signal` and `kernel` (the kernel is flipped, as in
+// numpy's `convolve`). Outputs only reference the products they need, so
+// gradients reach both the signal and the kernel.
+pub fn conv1d<'a>(
+    arena: &'a Arena<'a>,
+    signal: &[Node<'a>],
+    kernel: &[Node<'a>],
+    mode: ConvMode,
+) -> Vec<Node<'a>> {
+    let (n, k) = (signal.len(), kernel.len());
+    assert!(k > 0, "empty convolution kernel");
+    // output t takes signal[t + offset - j] * kernel[j]
+    let (offset, outputs) = match mode {
+        ConvMode::Valid => {
+            assert!(n >= k, "kernel longer than the signal");
+            (k - 1, n - k + 1)
+        }
+        ConvMode::Same => ((k - 1) / 2, n),
+    };
+
+    (0..outputs)
+        .map(|t| {
+            let position = t + offset;
+            let terms: Vec<Node<'a>> = (0..k)
+                .filter(|&j| j <= position && position - j < n)
+                .map(|j| mul(arena, signal[position - j], kernel[j]))
+                .collect();
+            add_n(arena, &terms)
+        })
+        .collect()
+}
+
+// Means over every window of `width` consecutive elements.
+pub fn moving_average<'a>(
+    arena: &'a Arena<'a>,
+    signal: &[Node<'a>],
+    width: usize,
+) -> Vec<Node<'a>> {
+    let weight = constant(arena, 1f32 / width as f32);
+    conv1d(arena, signal, &vec![weight; width], ConvMode::Valid)
+}
+
+#[test]
+fn convolution_modes_and_gradients() {
+    use {eval_with_grad, forward_multi, var, Assignment};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let c = |v: f32| constant(arena, v);
+
+    let signal: Vec<Node> = (0..5).map(|i| var(arena, format!("s{}", i))).collect();
+    let kernel = [var(arena, "k0".to_string()), c(2f32), c(3f32)];
+    let mut at = Assignment::new();
+    for (i, v) in [1f32, 2f32, 3f32, 4f32, 5f32].iter().enumerate() {
+        at.insert(format!("s{}", i), *v);
+    }
+    at.insert("k0", 1f32);
+
+    // numpy.convolve([1, 2, 3, 4, 5], [1, 2, 3], mode)
+    let valid = conv1d(arena, &signal, &kernel, ConvMode::Valid);
+    assert_eq!(forward_multi(&valid, &at), vec![10f32, 16f32, 22f32]);
+    let same = conv1d(arena, &signal, &kernel, ConvMode::Same);
+    assert_eq!(
+        forward_multi(&same, &at),
+        vec![4f32, 10f32, 16f32, 22f32, 22f32]
+    );
+
+    // the first valid output is s2 k0 + s1 k1 + s0 k2
+    let (_, grads) = eval_with_grad(valid[0], &at, &["s0", "s2", "s4", "k0"]);
+    assert_eq!(grads.values(), &[3f32, 1f32, 0f32, 3f32]);
+
+    let averages = moving_average(arena, &signal, 2);
+    assert_eq!(forward_multi(&averages, &at), vec![1.5, 2.5, 3.5, 4.5]);
+}
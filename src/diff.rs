@@ -0,0 +1,233 @@
+// Structural differences between two expressions.
+//
+// The expressions are walked together from their roots. Where the
+// operations match, their operands are compared position by position;
+// where they differ, the whole subexpression is reported as changed.
+// Operands are identified by paths of operand positions from the root,
+// written like `$.1.0`. A differing subexpression shared by several paths
+// is reported once, at the first path that reaches it. Subexpressions are
+// shown as SymPy source unless that would be long, since shared
+// subexpressions are written out at every use.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use sympy::to_sympy;
+use {node_key, Node, NodeData};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    // an operand only the second expression has
+    Added {
+        path: Vec<usize>,
+        node: String,
+    },
+    // an operand only the first expression has
+    Removed {
+        path: Vec<usize>,
+        node: String,
+    },
+    Changed {
+        path: Vec<usize>,
+        from: String,
+        to: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphDiff {
+    pub changes: Vec<Change>,
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+}
+
+fn path_string(path: &[usize]) -> String {
+    let mut s = "$".to_string();
+    for i in path {
+        s.push_str(&format!(".{}", i));
+    }
+    s
+}
+
+// one line per change: `+` added, `-` removed, `~` changed
+impl fmt::Display for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.changes {
+            match *change {
+                Change::Added { ref path, ref node } => {
+                    writeln!(f, "+ {}: {}", path_string(path), node)?
+                }
+                Change::Removed { ref path, ref node } => {
+                    writeln!(f, "- {}: {}", path_string(path), node)?
+                }
+                Change::Changed {
+                    ref path,
+                    ref from,
+                    ref to,
+                } => writeln!(f, "~ {}: {} -> {}", path_string(path), from, to)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// more nodes than this written out are summarized instead
+const MAX_SHOWN: usize = 64;
+
+fn show(node: Node) -> String {
+    // count the nodes as `to_sympy` would write them, giving up early
+    let mut stack = vec![node];
+    let mut count = 0;
+    while let Some(n) = stack.pop() {
+        count += 1;
+        if count > MAX_SHOWN {
+            return format!("{} (over {} nodes written out)", node.describe(), MAX_SHOWN);
+        }
+        stack.extend(n.children());
+    }
+    to_sympy(node)
+}
+
+struct Differ<'a> {
+    // pairs already compared and whether they were equal, so shared
+    // subexpressions are compared once
+    compared: HashMap<(*const NodeData<'a>, *const NodeData<'a>), bool>,
+    changes: Vec<Change>,
+}
+
+impl<'a> Differ<'a> {
+    // whether the subexpressions are equal; records the differences if not
+    fn compare(&mut self, a: Node<'a>, b: Node<'a>, path: &mut Vec<usize>) -> bool {
+        let key = (node_key(a), node_key(b));
+        if key.0 == key.1 {
+            return true;
+        }
+        if let Some(&same) = self.compared.get(&key) {
+            return same;
+        }
+        // the op carries constants, names and exponents, but not operands
+        if a.type_.op() != b.type_.op() {
+            self.changes.push(Change::Changed {
+                path: path.clone(),
+                from: show(a),
+                to: show(b),
+            });
+            self.compared.insert(key, false);
+            return false;
+        }
+
+        let (a_args, b_args) = (a.children(), b.children());
+        let mut same = a_args.len() == b_args.len();
+        for i in 0..a_args.len().max(b_args.len()) {
+            path.push(i);
+            match (a_args.get(i), b_args.get(i)) {
+                (Some(&a), Some(&b)) => same &= self.compare(a, b, path),
+                (Some(&a), None) => self.changes.push(Change::Removed {
+                    path: path.clone(),
+                    node: show(a),
+                }),
+                (None, Some(&b)) => self.changes.push(Change::Added {
+                    path: path.clone(),
+                    node: show(b),
+                }),
+                (None, None) => unreachable!(),
+            }
+            path.pop();
+        }
+        self.compared.insert(key, same);
+        same
+    }
+}
+
+pub fn diff_graphs<'a>(a: Node<'a>, b: Node<'a>) -> GraphDiff {
+    let mut differ = Differ {
+        compared: HashMap::new(),
+        changes: vec![],
+    };
+    differ.compare(a, b, &mut vec![]);
+    GraphDiff {
+        changes: differ.changes,
+    }
+}
+
+#[test]
+fn differences_are_located() {
+    use {add, add_n, constant, cos, mul, sin, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    // x * sin(y) + (x + y + 1)
+    let old = add(
+        arena,
+        mul(arena, x, sin(arena, y)),
+        add_n(arena, &[x, y, constant(arena, 1f32)]),
+    );
+    // x * cos(y) + (x + y), built from fresh nodes
+    let x2 = var(arena, "x".to_string());
+    let y2 = var(arena, "y".to_string());
+    let new = add(
+        arena,
+        mul(arena, x2, cos(arena, y2)),
+        add_n(arena, &[x2, y2]),
+    );
+
+    let diff = diff_graphs(old, new);
+    assert_eq!(
+        diff.changes,
+        vec![
+            Change::Changed {
+                path: vec![0, 1],
+                from: "sin(y)".to_string(),
+                to: "cos(y)".to_string(),
+            },
+            Change::Removed {
+                path: vec![1, 2],
+                node: "1".to_string(),
+            },
+        ]
+    );
+    assert_eq!(diff.to_string(), "~ $.0.1: sin(y) -> cos(y)\n- $.1.2: 1\n");
+    assert!(diff_graphs(new, new).is_empty());
+}
+
+#[test]
+fn shared_differences_are_reported_once() {
+    use {add, mul, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    // 2^40 paths lead to the leaf of each chain
+    let mut a = var(arena, "x".to_string());
+    let mut b = var(arena, "y".to_string());
+    for _ in 0..40 {
+        a = add(arena, a, a);
+        b = add(arena, b, b);
+    }
+
+    let diff = diff_graphs(a, b);
+    assert_eq!(diff.len(), 1);
+    match diff.changes[0] {
+        Change::Changed { ref path, .. } => assert_eq!(path, &vec![0; 40]),
+        ref change => panic!("{:?}", change),
+    }
+
+    // large subexpressions are summarized rather than written out
+    let args = a.children();
+    let diff = diff_graphs(a, mul(arena, args[0], args[1]));
+    assert_eq!(
+        diff.to_string(),
+        "~ $: Add (over 64 nodes written out) -> Mul (over 64 nodes written out)\n"
+    );
+}
@@ -0,0 +1,144 @@
+// Views of a graph with the values and gradients currently stored in the
+// nodes, for debugging failing tests: an indented tree and Graphviz source.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use {node_key, topological_order, Node, NodeData, NodeType};
+
+// One line per node, children indented below their parent. A node with
+// several parents is written out once, tagged `#n`, and referred to by its
+// tag afterwards.
+pub fn dump(node: Node) -> String {
+    let mut parents: HashMap<*const NodeData, usize> = HashMap::new();
+    for n in topological_order(&[node]) {
+        for child in n.children() {
+            *parents.entry(node_key(child)).or_insert(0) += 1;
+        }
+    }
+
+    let mut tags: HashMap<*const NodeData, usize> = HashMap::new();
+    let mut out = String::new();
+    let mut stack = vec![(node, 0)];
+    while let Some((n, depth)) = stack.pop() {
+        let indent = "  ".repeat(depth);
+        let key = node_key(n);
+        if let Some(tag) = tags.get(&key) {
+            writeln!(out, "{}#{}", indent, tag).unwrap();
+            continue;
+        }
+
+        let mut line = indent;
+        if parents.get(&key).cloned().unwrap_or(0) > 1 {
+            let tag = tags.len() + 1;
+            tags.insert(key, tag);
+            write!(line, "#{} ", tag).unwrap();
+        }
+        line.push_str(&n.describe());
+        match n.type_ {
+            NodeType::Var(ref name) => write!(line, " {}", name).unwrap(),
+            NodeType::Const(v) => write!(line, " {}", v).unwrap(),
+            NodeType::Pow(_, p) => write!(line, " {}", p).unwrap(),
+            _ => {}
+        }
+        write!(line, " = {}", n.value()).unwrap();
+        let grads = n.grads();
+        if !grads.is_empty() {
+            let mut grads: Vec<(&str, f32)> = grads.iter().collect();
+            grads.sort_by(|a, b| a.0.cmp(b.0));
+            let grads: Vec<String> = grads
+                .iter()
+                .map(|&(name, g)| format!("d{}: {}", name, g))
+                .collect();
+            write!(line, "  [{}]", grads.join(", ")).unwrap();
+        }
+        writeln!(out, "{}", line).unwrap();
+
+        for child in n.children().into_iter().rev() {
+            stack.push((child, depth + 1));
+        }
+    }
+    out
+}
+
+// Graphviz source for the graph below `node`, one box per node with its
+// current value and an edge to every operand, e.g. for `dot -Tsvg`.
+pub fn to_dot(node: Node) -> String {
+    let order = topological_order(&[node]);
+    let ids: HashMap<*const NodeData, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (node_key(n), i))
+        .collect();
+
+    let mut out = "digraph {\n    node [shape=box];\n".to_string();
+    for (i, n) in order.iter().enumerate() {
+        let mut text = n.describe();
+        match n.type_ {
+            NodeType::Var(ref name) => write!(text, " {}", name).unwrap(),
+            NodeType::Const(v) => write!(text, " {}", v).unwrap(),
+            NodeType::Pow(_, p) => write!(text, " {}", p).unwrap(),
+            _ => {}
+        }
+        write!(text, "\\n= {}", n.value()).unwrap();
+        writeln!(out, "    n{} [label=\"{}\"];", i, text.replace('"', "\\\"")).unwrap();
+        for (position, child) in n.children().into_iter().enumerate() {
+            writeln!(
+                out,
+                "    n{} -> n{} [label=\"{}\"];",
+                i,
+                ids[&node_key(child)],
+                position
+            )
+            .unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[test]
+fn dump_shows_tree_values_and_gradients() {
+    use {add, labelled, mul, sin, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let y = var(&arena, "y".to_string());
+    x.set_value(2.0);
+    y.set_value(3.0);
+    let xy = labelled(mul(&arena, x, y), "xy");
+    let f = add(&arena, xy, sin(&arena, xy));
+    f.forward();
+
+    let lines: Vec<String> = dump(f).lines().map(|l| l.to_string()).collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], format!("Add = {}", 6f32 + 6f32.sin()));
+    assert!(lines[1].starts_with("  #1 Mul xy ("));
+    assert!(lines[1].ends_with(" = 6"));
+    assert_eq!(lines[2], "    Var x = 2");
+    assert_eq!(lines[5], "    #1");
+
+    f.backward_ad(&["x", "y"]);
+    // each node shows its own derivatives
+    let lines: Vec<String> = dump(f).lines().map(|l| l.to_string()).collect();
+    assert!(lines[0].contains(&format!("dx: {}", 3.0 * (1.0 + 6f32.cos()))));
+    assert_eq!(lines[2], "    Var x = 2  [dx: 1, dy: 0]");
+}
+
+#[test]
+fn dot_lists_every_node_and_operand() {
+    use {add, mul, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    x.set_value(3.0);
+    let f = add(&arena, mul(&arena, x, x), x);
+    f.forward();
+
+    let dot = to_dot(f);
+    assert!(dot.starts_with("digraph {"));
+    assert!(dot.contains("n0 [label=\"Var x\\n= 3\"];"));
+    assert!(dot.contains("n2 [label=\"Add\\n= 12\"];"));
+    // x is shared: one node, three edges into it
+    assert_eq!(dot.matches("-> n0 ").count(), 3);
+}
@@ -0,0 +1,170 @@
+// Arithmetic operators on nodes.
+//
+// An `Expr` is a node together with the arena new nodes go to, so that
+// `x * y + 2.0` builds the graph the constructor functions would. Plain
+// numbers on either side of an operator become constants.
+
+use std::fmt;
+use std::ops::{Add, Deref, Div, Mul, Neg, Sub};
+
+use num::ln_by_select;
+use {
+    add, constant, cos, div, exp_m1, ln_1p, mul, neg, pow, select, sin, sub, var, Arena, Node,
+    NodeData,
+};
+
+#[derive(Clone, Copy)]
+pub struct Expr<'a> {
+    arena: &'a Arena<'a>,
+    node: Node<'a>,
+}
+
+impl<'a> Expr<'a> {
+    pub fn new(arena: &'a Arena<'a>, node: Node<'a>) -> Self {
+        Expr { arena, node }
+    }
+
+    pub fn var(arena: &'a Arena<'a>, name: &str) -> Self {
+        Expr::new(arena, var(arena, name.to_string()))
+    }
+
+    pub fn constant(arena: &'a Arena<'a>, value: f32) -> Self {
+        Expr::new(arena, constant(arena, value))
+    }
+
+    pub fn node(self) -> Node<'a> {
+        self.node
+    }
+
+    pub fn arena(self) -> &'a Arena<'a> {
+        self.arena
+    }
+
+    // a node built by `f` in the same arena
+    fn map<F: FnOnce(&'a Arena<'a>, Node<'a>) -> Node<'a>>(self, f: F) -> Self {
+        Expr::new(self.arena, f(self.arena, self.node))
+    }
+
+    pub fn pow(self, rhs: f32) -> Self {
+        self.map(|arena, node| pow(arena, node, rhs))
+    }
+
+    pub fn sin(self) -> Self {
+        self.map(sin)
+    }
+
+    pub fn cos(self) -> Self {
+        self.map(cos)
+    }
+
+    pub fn exp_m1(self) -> Self {
+        self.map(exp_m1)
+    }
+
+    pub fn ln_1p(self) -> Self {
+        self.map(ln_1p)
+    }
+
+    pub fn ln(self) -> Self {
+        let arena = self.arena;
+        ln_by_select(self, Expr::constant(arena, 0.5), |cond, then, else_| {
+            Expr::new(arena, select(arena, cond.node, then.node, else_.node))
+        })
+    }
+}
+
+impl<'a> Deref for Expr<'a> {
+    type Target = NodeData<'a>;
+
+    fn deref(&self) -> &NodeData<'a> {
+        self.node
+    }
+}
+
+impl<'a> From<Expr<'a>> for Node<'a> {
+    fn from(expr: Expr<'a>) -> Node<'a> {
+        expr.node
+    }
+}
+
+impl<'a> fmt::Debug for Expr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expr({:?})", self.node)
+    }
+}
+
+macro_rules! binary_op {
+    ($trait_:ident, $method:ident, $build:ident) => {
+        impl<'a> $trait_ for Expr<'a> {
+            type Output = Expr<'a>;
+
+            fn $method(self, rhs: Expr<'a>) -> Expr<'a> {
+                Expr::new(self.arena, $build(self.arena, self.node, rhs.node))
+            }
+        }
+
+        impl<'a> $trait_<f32> for Expr<'a> {
+            type Output = Expr<'a>;
+
+            fn $method(self, rhs: f32) -> Expr<'a> {
+                let rhs = constant(self.arena, rhs);
+                Expr::new(self.arena, $build(self.arena, self.node, rhs))
+            }
+        }
+
+        impl<'a> $trait_<Expr<'a>> for f32 {
+            type Output = Expr<'a>;
+
+            fn $method(self, rhs: Expr<'a>) -> Expr<'a> {
+                let lhs = constant(rhs.arena, self);
+                Expr::new(rhs.arena, $build(rhs.arena, lhs, rhs.node))
+            }
+        }
+    };
+}
+
+binary_op!(Add, add, add);
+binary_op!(Sub, sub, sub);
+binary_op!(Mul, mul, mul);
+binary_op!(Div, div, div);
+
+impl<'a> Neg for Expr<'a> {
+    type Output = Expr<'a>;
+
+    fn neg(self) -> Expr<'a> {
+        self.map(neg)
+    }
+}
+
+#[test]
+fn operators_build_the_same_graph() {
+    let arena = Arena::new();
+    let x = Expr::var(&arena, "x");
+    let y = Expr::var(&arena, "y");
+    x.set_value(3.0);
+    y.set_value(4.0);
+
+    let f = (x * y + 2.0) / (1.0 - x.sin()) - 0.5 * y.pow(2.0);
+    f.forward();
+    let (xv, yv) = (3f32, 4f32);
+    let expected = (xv * yv + 2.0) / (1.0 - xv.sin()) - 0.5 * yv * yv;
+    assert!((f.value() - expected).abs() < 1e-5);
+
+    f.backward_ad(&["x", "y"]);
+    let dy = xv / (1.0 - xv.sin()) - yv;
+    assert!((f.grad("y") - dy).abs() < 1e-5);
+}
+
+#[test]
+fn negation_operator() {
+    let arena = Arena::new();
+    let x = Expr::var(&arena, "x");
+    x.set_value(3.0);
+
+    let f = -x * x + -(2.0 * x);
+    f.forward();
+    assert_eq!(f.value(), -15.0);
+
+    f.backward_ad(&["x"]);
+    assert_eq!(f.grad("x"), -8.0);
+}
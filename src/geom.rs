@@ -0,0 +1,203 @@
+// Rotations over node-valued components.
+//
+// Vectors are arrays of nodes and quaternions are `Quat`s of four nodes,
+// w + xi + yj + zk. Rotating with a quaternion assumes it has unit norm;
+// `Quat::normalize` keeps free parameters on the unit sphere.
+
+use ops::normalize;
+use {add_n, constant, cos, mul, neg, sin, sub, Arena, Node};
+
+// `v` rotated counterclockwise by `angle` radians.
+pub fn rotate2<'a>(arena: &'a Arena<'a>, angle: Node<'a>, v: [Node<'a>; 2]) -> [Node<'a>; 2] {
+    let (c, s) = (cos(arena, angle), sin(arena, angle));
+    [
+        sub(arena, mul(arena, c, v[0]), mul(arena, s, v[1])),
+        add_n(arena, &[mul(arena, s, v[0]), mul(arena, c, v[1])]),
+    ]
+}
+
+// `m v` for a 3x3 matrix, e.g. one from `Quat::to_matrix`.
+pub fn apply3<'a>(arena: &'a Arena<'a>, m: &[[Node<'a>; 3]; 3], v: [Node<'a>; 3]) -> [Node<'a>; 3] {
+    let row = |r: &[Node<'a>; 3]| {
+        add_n(
+            arena,
+            &[
+                mul(arena, r[0], v[0]),
+                mul(arena, r[1], v[1]),
+                mul(arena, r[2], v[2]),
+            ],
+        )
+    };
+    [row(&m[0]), row(&m[1]), row(&m[2])]
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quat<'a> {
+    pub w: Node<'a>,
+    pub x: Node<'a>,
+    pub y: Node<'a>,
+    pub z: Node<'a>,
+}
+
+impl<'a> Quat<'a> {
+    pub fn new(w: Node<'a>, x: Node<'a>, y: Node<'a>, z: Node<'a>) -> Self {
+        Quat { w, x, y, z }
+    }
+
+    pub fn identity(arena: &'a Arena<'a>) -> Self {
+        let zero = constant(arena, 0f32);
+        Quat::new(constant(arena, 1f32), zero, zero, zero)
+    }
+
+    // rotation by `angle` about the unit vector `axis`
+    pub fn from_axis_angle(arena: &'a Arena<'a>, axis: [Node<'a>; 3], angle: Node<'a>) -> Self {
+        let half = mul(arena, constant(arena, 0.5), angle);
+        let s = sin(arena, half);
+        Quat::new(
+            cos(arena, half),
+            mul(arena, s, axis[0]),
+            mul(arena, s, axis[1]),
+            mul(arena, s, axis[2]),
+        )
+    }
+
+    // the quaternion divided by its norm; a zero quaternion stays zero
+    pub fn normalize(self, arena: &'a Arena<'a>) -> Self {
+        let unit = normalize(arena, &[self.w, self.x, self.y, self.z], 1e-12);
+        Quat::new(unit[0], unit[1], unit[2], unit[3])
+    }
+
+    pub fn conjugate(self, arena: &'a Arena<'a>) -> Self {
+        Quat::new(
+            self.w,
+            neg(arena, self.x),
+            neg(arena, self.y),
+            neg(arena, self.z),
+        )
+    }
+
+    // Hamilton product: the rotation `rhs` followed by `self`
+    pub fn compose(self, arena: &'a Arena<'a>, rhs: Quat<'a>) -> Self {
+        let (a, b) = (self, rhs);
+        let term = |l: Node<'a>, r: Node<'a>| mul(arena, l, r);
+        let minus = |l: Node<'a>, r: Node<'a>| neg(arena, mul(arena, l, r));
+        Quat::new(
+            add_n(
+                arena,
+                &[
+                    term(a.w, b.w),
+                    minus(a.x, b.x),
+                    minus(a.y, b.y),
+                    minus(a.z, b.z),
+                ],
+            ),
+            add_n(
+                arena,
+                &[
+                    term(a.w, b.x),
+                    term(a.x, b.w),
+                    term(a.y, b.z),
+                    minus(a.z, b.y),
+                ],
+            ),
+            add_n(
+                arena,
+                &[
+                    term(a.w, b.y),
+                    minus(a.x, b.z),
+                    term(a.y, b.w),
+                    term(a.z, b.x),
+                ],
+            ),
+            add_n(
+                arena,
+                &[
+                    term(a.w, b.z),
+                    term(a.x, b.y),
+                    minus(a.y, b.x),
+                    term(a.z, b.w),
+                ],
+            ),
+        )
+    }
+
+    // The rotation matrix of a unit quaternion. Rotating many vectors
+    // through the matrix shares its nodes.
+    pub fn to_matrix(self, arena: &'a Arena<'a>) -> [[Node<'a>; 3]; 3] {
+        let (w, x, y, z) = (self.w, self.x, self.y, self.z);
+        let two = constant(arena, 2f32);
+        let one = constant(arena, 1f32);
+        // 2 (p q + r s) and 1 - 2 (p^2 + q^2)
+        let off = |p: Node<'a>, q: Node<'a>, r: Node<'a>, s: Node<'a>, sign: f32| {
+            let rs = mul(arena, r, s);
+            let rs = if sign < 0f32 { neg(arena, rs) } else { rs };
+            mul(arena, two, add_n(arena, &[mul(arena, p, q), rs]))
+        };
+        let diag = |p: Node<'a>, q: Node<'a>| {
+            let squares = add_n(arena, &[mul(arena, p, p), mul(arena, q, q)]);
+            sub(arena, one, mul(arena, two, squares))
+        };
+        [
+            [diag(y, z), off(x, y, w, z, -1f32), off(x, z, w, y, 1f32)],
+            [off(x, y, w, z, 1f32), diag(x, z), off(y, z, w, x, -1f32)],
+            [off(x, z, w, y, -1f32), off(y, z, w, x, 1f32), diag(x, y)],
+        ]
+    }
+
+    // `v` rotated by this unit quaternion
+    pub fn rotate(self, arena: &'a Arena<'a>, v: [Node<'a>; 3]) -> [Node<'a>; 3] {
+        apply3(arena, &self.to_matrix(arena), v)
+    }
+}
+
+#[test]
+fn rotations_agree() {
+    use {eval_with_grad, forward_multi, var, Assignment};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let c = |v: f32| constant(arena, v);
+
+    let theta = var(arena, "theta".to_string());
+    let phi = var(arena, "phi".to_string());
+    let v = [c(1f32), c(2f32), c(0.5)];
+    let z_axis = [c(0f32), c(0f32), c(1f32)];
+    let at: Assignment = [("theta", 0.4), ("phi", -1.1)].into();
+
+    // about z, a quaternion rotation is the planar one
+    let planar = rotate2(arena, theta, [v[0], v[1]]);
+    let q = Quat::from_axis_angle(arena, z_axis, theta);
+    let spatial = q.rotate(arena, v);
+    let values = forward_multi(
+        &[planar[0], planar[1], spatial[0], spatial[1], spatial[2]],
+        &at,
+    );
+    assert!((values[0] - values[2]).abs() < 1e-6);
+    assert!((values[1] - values[3]).abs() < 1e-6);
+    assert!((values[4] - 0.5).abs() < 1e-6);
+
+    // d/dtheta of the rotated x is minus the rotated y
+    let (_, grads) = eval_with_grad(spatial[0], &at, &["theta"]);
+    assert!((grads["theta"] + values[3]).abs() < 1e-5);
+
+    // composing rotations about one axis adds the angles
+    let composed = q.compose(arena, Quat::from_axis_angle(arena, z_axis, phi));
+    let sum = Quat::from_axis_angle(arena, z_axis, add_n(arena, &[theta, phi]));
+    let both = forward_multi(
+        &[composed.rotate(arena, v)[0], sum.rotate(arena, v)[0]],
+        &at,
+    );
+    assert!((both[0] - both[1]).abs() < 1e-5);
+
+    // a scaled quaternion rotates like the normalized one
+    let scaled = Quat::new(c(2f32), c(0f32), c(0f32), c(2f32)).normalize(arena);
+    let inverse = scaled.conjugate(arena);
+    let round_trip = inverse.compose(arena, scaled);
+    let parts = forward_multi(
+        &[round_trip.w, round_trip.z, scaled.rotate(arena, v)[0]],
+        &at,
+    );
+    assert!((parts[0] - 1f32).abs() < 1e-6 && parts[1].abs() < 1e-6);
+    // a quarter turn about z
+    assert!((parts[2] + 2f32).abs() < 1e-5);
+}
@@ -0,0 +1,287 @@
+// GLSL expressions of a graph and of its partial derivatives, to paste into
+// shaders. Variables are replaced by GLSL expressions such as `uv.x`; unbound
+// variables are baked in at their current values. Shared subexpressions are
+// written out at every use.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use {node_key, topological_order, Node, NodeData};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GlslError {
+    // custom ops only exist on the CPU
+    UnsupportedOp(String),
+}
+
+impl fmt::Display for GlslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GlslError::UnsupportedOp(ref name) => write!(f, "{} cannot be compiled to GLSL", name),
+        }
+    }
+}
+
+impl Error for GlslError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlslGradient {
+    pub value: String,
+    // one expression per requested variable
+    pub gradient: Vec<String>,
+}
+
+fn literal(v: f32) -> String {
+    if v.is_nan() {
+        "(0.0 / 0.0)".to_string()
+    } else if v.is_infinite() {
+        format!("({}1.0 / 0.0)", if v < 0f32 { "-" } else { "" })
+    } else if v < 0f32 {
+        format!("({:?})", v)
+    } else {
+        format!("{:?}", v)
+    }
+}
+
+// x^p, by multiplication for small integer powers since GLSL's `pow` is
+// undefined for negative bases
+fn power(x: &str, p: f32) -> String {
+    if p.fract() == 0f32 && p.abs() <= 4f32 {
+        let n = p.abs() as usize;
+        let product = if n == 0 {
+            "1.0".to_string()
+        } else {
+            vec![x; n].join(" * ")
+        };
+        if p < 0f32 {
+            format!("(1.0 / ({}))", product)
+        } else {
+            format!("({})", product)
+        }
+    } else {
+        format!("pow({}, {})", x, literal(p))
+    }
+}
+
+// derivatives that are known to vanish are None
+fn product(factor: &str, d: &Option<String>) -> Option<String> {
+    d.as_ref().map(|d| {
+        if d == "1.0" {
+            factor.to_string()
+        } else {
+            format!("({} * {})", factor, d)
+        }
+    })
+}
+
+fn total(terms: Vec<Option<String>>) -> Option<String> {
+    let mut terms: Vec<String> = terms.into_iter().flatten().collect();
+    if terms.len() <= 1 {
+        terms.pop()
+    } else {
+        Some(format!("({})", terms.join(" + ")))
+    }
+}
+
+fn or_zero(d: &Option<String>) -> String {
+    d.clone().unwrap_or_else(|| "0.0".to_string())
+}
+
+// The value of `node` as a GLSL expression.
+pub fn to_glsl(node: Node, bindings: &HashMap<&str, &str>) -> Result<String, GlslError> {
+    to_glsl_with_gradient(node, bindings, &[]).map(|glsl| glsl.value)
+}
+
+// The value of `node` and its partial derivatives with respect to
+// `variables` as GLSL expressions.
+pub fn to_glsl_with_gradient<'a>(
+    node: Node<'a>,
+    bindings: &HashMap<&str, &str>,
+    variables: &[&str],
+) -> Result<GlslGradient, GlslError> {
+    use NodeType::*;
+
+    type Entry = (String, Vec<Option<String>>);
+    let mut exprs: HashMap<*const NodeData<'a>, Entry> = HashMap::new();
+    for n in topological_order(&[node]) {
+        let entry = {
+            let of = |child: Node<'a>| &exprs[&node_key(child)].0;
+            let d = |child: Node<'a>, i: usize| &exprs[&node_key(child)].1[i];
+            let each = |f: &dyn Fn(usize) -> Option<String>| (0..variables.len()).map(f).collect();
+            match n.type_ {
+                Const(v) => (literal(v), vec![None; variables.len()]),
+                Var(ref name) => match bindings.get(name.as_str()) {
+                    Some(expr) => (
+                        format!("({})", expr),
+                        variables
+                            .iter()
+                            .map(|v| {
+                                if v == name {
+                                    Some("1.0".to_string())
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect(),
+                    ),
+                    None => (literal(n.value()), vec![None; variables.len()]),
+                },
+                Neg(x) => (
+                    format!("(-{})", of(x)),
+                    each(&|i| d(x, i).as_ref().map(|d| format!("(-{})", d))),
+                ),
+                Add(l, r) => (
+                    format!("({} + {})", of(l), of(r)),
+                    each(&|i| total(vec![d(l, i).clone(), d(r, i).clone()])),
+                ),
+                Sub(l, r) => (
+                    format!("({} - {})", of(l), of(r)),
+                    each(&|i| match (d(l, i), d(r, i)) {
+                        (dl, Some(dr)) => Some(format!("({} - {})", or_zero(dl), dr)),
+                        (dl, None) => dl.clone(),
+                    }),
+                ),
+                Mul(l, r) => (
+                    format!("({} * {})", of(l), of(r)),
+                    each(&|i| total(vec![product(of(r), d(l, i)), product(of(l), d(r, i))])),
+                ),
+                Div(l, r) => (
+                    format!("({} / {})", of(l), of(r)),
+                    each(&|i| {
+                        // (dl - f dr) / r
+                        let f = format!("({} / {})", of(l), of(r));
+                        match (d(l, i), d(r, i)) {
+                            (&None, &None) => None,
+                            (dl, dr) => Some(format!(
+                                "(({} - {} * {}) / {})",
+                                or_zero(dl),
+                                f,
+                                or_zero(dr),
+                                of(r)
+                            )),
+                        }
+                    }),
+                ),
+                Pow(x, p) => (
+                    power(of(x), p),
+                    each(&|i| {
+                        product(
+                            &format!("({} * {})", literal(p), power(of(x), p - 1f32)),
+                            d(x, i),
+                        )
+                    }),
+                ),
+                Sin(x) => (
+                    format!("sin({})", of(x)),
+                    each(&|i| product(&format!("cos({})", of(x)), d(x, i))),
+                ),
+                Cos(x) => (
+                    format!("cos({})", of(x)),
+                    each(&|i| product(&format!("(-sin({}))", of(x)), d(x, i))),
+                ),
+                ExpM1(x) => (
+                    format!("(exp({}) - 1.0)", of(x)),
+                    each(&|i| product(&format!("exp({})", of(x)), d(x, i))),
+                ),
+                Ln1p(x) => (
+                    format!("log(1.0 + {})", of(x)),
+                    each(&|i| product(&format!("(1.0 / (1.0 + {}))", of(x)), d(x, i))),
+                ),
+                StopGradient(x) => (of(x).clone(), vec![None; variables.len()]),
+                Custom(ref op, _) => return Err(GlslError::UnsupportedOp(op.name().to_string())),
+                Select(c, t, e) => (
+                    format!("({} > 0.0 ? {} : {})", of(c), of(t), of(e)),
+                    each(&|i| match (d(t, i), d(e, i)) {
+                        (&None, &None) => None,
+                        (dt, de) => Some(format!(
+                            "({} > 0.0 ? {} : {})",
+                            of(c),
+                            or_zero(dt),
+                            or_zero(de)
+                        )),
+                    }),
+                ),
+                AddN(ref args) if args.is_empty() => {
+                    ("0.0".to_string(), vec![None; variables.len()])
+                }
+                MulN(ref args) if args.is_empty() => {
+                    ("1.0".to_string(), vec![None; variables.len()])
+                }
+                AddN(ref args) => (
+                    format!(
+                        "({})",
+                        args.iter()
+                            .map(|&a| of(a).clone())
+                            .collect::<Vec<_>>()
+                            .join(" + ")
+                    ),
+                    each(&|i| total(args.iter().map(|&a| d(a, i).clone()).collect())),
+                ),
+                MulN(ref args) => (
+                    format!(
+                        "({})",
+                        args.iter()
+                            .map(|&a| of(a).clone())
+                            .collect::<Vec<_>>()
+                            .join(" * ")
+                    ),
+                    each(&|i| {
+                        total(
+                            args.iter()
+                                .enumerate()
+                                .map(|(j, &a)| {
+                                    let others: Vec<String> = args
+                                        .iter()
+                                        .enumerate()
+                                        .filter(|&(k, _)| k != j)
+                                        .map(|(_, &b)| of(b).clone())
+                                        .collect();
+                                    if others.is_empty() {
+                                        d(a, i).clone()
+                                    } else {
+                                        product(&format!("({})", others.join(" * ")), d(a, i))
+                                    }
+                                })
+                                .collect(),
+                        )
+                    }),
+                ),
+            }
+        };
+        exprs.insert(node_key(n), entry);
+    }
+
+    let (value, gradient) = exprs.remove(&node_key(node)).unwrap();
+    Ok(GlslGradient {
+        value,
+        gradient: gradient.iter().map(or_zero).collect(),
+    })
+}
+
+#[test]
+fn glsl_height_field() {
+    use {add, mul, pow, sin, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let k = var(arena, "k".to_string());
+    k.set_value(2.0);
+    // sin(k x) + y^2
+    let h = add(arena, sin(arena, mul(arena, k, x)), pow(arena, y, 2.0));
+
+    let mut bindings = HashMap::new();
+    bindings.insert("x", "uv.x");
+    bindings.insert("y", "uv.y");
+    assert_eq!(
+        to_glsl(h, &bindings).unwrap(),
+        "(sin((2.0 * (uv.x))) + ((uv.y) * (uv.y)))"
+    );
+
+    let glsl = to_glsl_with_gradient(h, &bindings, &["x", "y", "z"]).unwrap();
+    assert_eq!(glsl.gradient[0], "(cos((2.0 * (uv.x))) * 2.0)");
+    assert_eq!(glsl.gradient[1], "(2.0 * ((uv.y)))");
+    assert_eq!(glsl.gradient[2], "0.0");
+}
@@ -0,0 +1,190 @@
+// Per-node gradient storage of `backward_ad`.
+//
+// Differentiating with respect to a few variables is the common case, so up
+// to `INLINE` gradients are kept in an array next to a list of names shared
+// by every node of the pass, with no hashing or allocation per node. More
+// variables fall back to a map.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::ops::Index;
+use std::rc::Rc;
+
+use GradientValues;
+
+pub const INLINE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub enum GradMap {
+    Inline {
+        names: Rc<[String]>,
+        values: [f32; INLINE],
+    },
+    Map(HashMap<String, f32>),
+}
+
+impl Default for GradMap {
+    fn default() -> Self {
+        GradMap::Map(HashMap::new())
+    }
+}
+
+impl GradMap {
+    // Storage for exactly `names`, all zero, if they fit inline.
+    pub(crate) fn for_names(names: Option<&Rc<[String]>>) -> Self {
+        match names {
+            Some(names) => GradMap::Inline {
+                names: names.clone(),
+                values: [0f32; INLINE],
+            },
+            None => GradMap::default(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, value: f32) {
+        match *self {
+            GradMap::Inline {
+                ref names,
+                ref mut values,
+            } => {
+                let i = names
+                    .iter()
+                    .position(|n| n == name)
+                    .expect("variable not in this pass");
+                values[i] = value;
+            }
+            GradMap::Map(ref mut map) => {
+                map.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = GradMap::default();
+    }
+
+    pub fn get(&self, name: &str) -> Option<f32> {
+        match *self {
+            GradMap::Inline {
+                ref names,
+                ref values,
+            } => names.iter().position(|n| n == name).map(|i| values[i]),
+            GradMap::Map(ref map) => map.get(name).cloned(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            GradMap::Inline { ref names, .. } => names.len(),
+            GradMap::Map(ref map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&str, f32)> + '_> {
+        match *self {
+            GradMap::Inline {
+                ref names,
+                ref values,
+            } => Box::new(
+                names
+                    .iter()
+                    .map(|name| name.as_str())
+                    .zip(values.iter().cloned()),
+            ),
+            GradMap::Map(ref map) => Box::new(map.iter().map(|(name, &g)| (name.as_str(), g))),
+        }
+    }
+
+    pub(crate) fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut f32> + '_> {
+        match *self {
+            GradMap::Inline {
+                ref names,
+                ref mut values,
+            } => Box::new(values[..names.len()].iter_mut()),
+            GradMap::Map(ref mut map) => Box::new(map.values_mut()),
+        }
+    }
+
+    pub fn to_map(&self) -> HashMap<String, f32> {
+        self.iter().map(|(name, g)| (name.to_string(), g)).collect()
+    }
+
+    // bytes allocated by this node alone; inline names are shared
+    pub(crate) fn heap_bytes(&self) -> usize {
+        match *self {
+            GradMap::Inline { .. } => 0,
+            // one control byte per bucket besides the entry itself
+            GradMap::Map(ref map) => {
+                map.capacity() * (size_of::<(String, f32)>() + 1)
+                    + map.keys().map(|key| key.capacity()).sum::<usize>()
+            }
+        }
+    }
+}
+
+impl Index<&str> for GradMap {
+    type Output = f32;
+
+    fn index(&self, name: &str) -> &f32 {
+        match *self {
+            GradMap::Inline {
+                ref names,
+                ref values,
+            } => {
+                let i = names
+                    .iter()
+                    .position(|n| n == name)
+                    .expect("no gradient for variable");
+                &values[i]
+            }
+            GradMap::Map(ref map) => &map[name],
+        }
+    }
+}
+
+impl GradientValues for GradMap {
+    fn get(&self, name: &str) -> Option<f32> {
+        GradMap::get(self, name)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, f32)> + '_> {
+        GradMap::iter(self)
+    }
+
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut f32> + '_> {
+        GradMap::values_mut(self)
+    }
+}
+
+impl From<HashMap<String, f32>> for GradMap {
+    fn from(map: HashMap<String, f32>) -> Self {
+        GradMap::Map(map)
+    }
+}
+
+impl PartialEq for GradMap {
+    fn eq(&self, other: &GradMap) -> bool {
+        self.len() == other.len() && self.iter().all(|(name, g)| other.get(name) == Some(g))
+    }
+}
+
+#[test]
+fn inline_and_map_agree() {
+    let names: Rc<[String]> = vec!["x".to_string(), "y".to_string()].into();
+    let mut inline = GradMap::for_names(Some(&names));
+    let mut map = GradMap::for_names(None);
+    for grads in [&mut inline, &mut map].iter_mut() {
+        grads.insert("x", 1.5);
+        grads.insert("y", -2.0);
+    }
+
+    assert_eq!(inline, map);
+    assert_eq!(inline["y"], -2.0);
+    assert_eq!(map.get("z"), None);
+    assert_eq!(inline.to_map(), map.to_map());
+    assert_eq!(inline.heap_bytes(), 0);
+}
@@ -0,0 +1,166 @@
+// Gradients returned by value from the gradient APIs, in the order the
+// variables were requested.
+
+use std::collections::HashMap;
+use std::iter::Zip;
+use std::ops::Index;
+use std::slice;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Gradients {
+    names: Vec<String>,
+    values: Vec<f32>,
+}
+
+impl Gradients {
+    pub fn new(names: &[&str], values: Vec<f32>) -> Self {
+        assert_eq!(names.len(), values.len());
+        Gradients {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            values,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .map(|i| self.values[i])
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            inner: self.names.iter().zip(self.values.iter()),
+        }
+    }
+
+    // values in the given order; names that were not requested get 0
+    pub fn to_vec(&self, order: &[&str]) -> Vec<f32> {
+        order.iter().map(|name| self[*name]).collect()
+    }
+
+    pub fn into_map(self) -> HashMap<String, f32> {
+        self.names.into_iter().zip(self.values).collect()
+    }
+}
+
+static ZERO: f32 = 0f32;
+
+// like `NodeData::grad`, unknown variables have zero gradient
+impl Index<&str> for Gradients {
+    type Output = f32;
+
+    fn index(&self, name: &str) -> &f32 {
+        match self.names.iter().position(|n| n == name) {
+            Some(i) => &self.values[i],
+            None => &ZERO,
+        }
+    }
+}
+
+impl Index<usize> for Gradients {
+    type Output = f32;
+
+    fn index(&self, index: usize) -> &f32 {
+        &self.values[index]
+    }
+}
+
+pub struct Iter<'a> {
+    inner: Zip<slice::Iter<'a, String>, slice::Iter<'a, f32>>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|(name, value)| (name.as_str(), *value))
+    }
+}
+
+impl<'a> IntoIterator for &'a Gradients {
+    type Item = (&'a str, f32);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+// The gradient containers of the crate: the `Gradients` returned by value,
+// the maps left in nodes by `backward_ad` and plain maps. Optimizers and
+// clipping take any of them.
+pub trait GradientValues {
+    fn get(&self, name: &str) -> Option<f32>;
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, f32)> + '_>;
+
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut f32> + '_>;
+}
+
+impl GradientValues for Gradients {
+    fn get(&self, name: &str) -> Option<f32> {
+        Gradients::get(self, name)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, f32)> + '_> {
+        Box::new(Gradients::iter(self))
+    }
+
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut f32> + '_> {
+        Box::new(self.values.iter_mut())
+    }
+}
+
+impl GradientValues for HashMap<String, f32> {
+    fn get(&self, name: &str) -> Option<f32> {
+        HashMap::get(self, name).cloned()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, f32)> + '_> {
+        Box::new(HashMap::iter(self).map(|(name, &g)| (name.as_str(), g)))
+    }
+
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut f32> + '_> {
+        Box::new(HashMap::values_mut(self))
+    }
+}
+
+impl From<Gradients> for HashMap<String, f32> {
+    fn from(grads: Gradients) -> Self {
+        grads.into_map()
+    }
+}
+
+#[test]
+fn gradients_lookup() {
+    let grads = Gradients::new(&["x", "y"], vec![1.0, -2.0]);
+
+    assert_eq!(grads["y"], -2.0);
+    assert_eq!(grads["z"], 0.0);
+    assert_eq!(grads[0], 1.0);
+    assert_eq!(grads.get("z"), None);
+    assert_eq!(grads.to_vec(&["y", "z", "x"]), vec![-2.0, 0.0, 1.0]);
+    assert_eq!(
+        grads.iter().collect::<Vec<_>>(),
+        vec![("x", 1.0), ("y", -2.0)]
+    );
+}
@@ -0,0 +1,851 @@
+// Owned expression graphs.
+//
+// Nodes live in a `Graph` and are referred to by `NodeId`, so a graph has no
+// lifetime and can be stored anywhere. Operands are always added before the
+// nodes using them, which keeps the node list in topological order.
+
+use std::collections::HashMap;
+use std::mem::{self, size_of};
+use std::ops::Deref;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use memory::MemoryUsage;
+use {topological_order, Arena, Assignment, CustomOp, Gradients, Label, Node, NodeData, NodeType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub(crate) usize);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+// An operation without its operands. This is where the local evaluation and
+// differentiation rules of every node kind live.
+#[derive(Debug, Clone)]
+pub enum Op {
+    Const(f32),
+    Var(String),
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow(f32),
+    Sin,
+    Cos,
+    ExpM1,
+    Ln1p,
+    StopGradient,
+    Custom(Arc<dyn CustomOp>),
+    Select,
+    AddN,
+    MulN,
+}
+
+// Constants and exponents compare by bits, so NaN equals itself, and
+// custom ops are equal only when they are the same instance.
+impl PartialEq for Op {
+    fn eq(&self, other: &Op) -> bool {
+        use self::Op::*;
+
+        match (self, other) {
+            (&Const(a), &Const(b)) | (&Pow(a), &Pow(b)) => a.to_bits() == b.to_bits(),
+            (Var(a), Var(b)) => a == b,
+            (Custom(a), Custom(b)) => Arc::ptr_eq(a, b),
+            _ => mem::discriminant(self) == mem::discriminant(other),
+        }
+    }
+}
+
+impl Op {
+    pub fn name(&self) -> &str {
+        use self::Op::*;
+
+        match *self {
+            Const(_) => "Const",
+            Var(_) => "Var",
+            Neg => "Neg",
+            Add => "Add",
+            Sub => "Sub",
+            Mul => "Mul",
+            Div => "Div",
+            Pow(_) => "Pow",
+            Sin => "Sin",
+            Cos => "Cos",
+            ExpM1 => "ExpM1",
+            Ln1p => "Ln1p",
+            StopGradient => "StopGradient",
+            Custom(ref op) => op.name(),
+            Select => "Select",
+            AddN => "AddN",
+            MulN => "MulN",
+        }
+    }
+
+    // value given the values of the operands
+    pub fn eval(&self, inputs: &[f32]) -> f32 {
+        use self::Op::*;
+
+        match *self {
+            Const(v) => v,
+            Var(ref name) => panic!("variable {} has no local rule", name),
+            Neg => -inputs[0],
+            Add => inputs[0] + inputs[1],
+            Sub => inputs[0] - inputs[1],
+            Mul => inputs[0] * inputs[1],
+            Div => inputs[0] / inputs[1],
+            Pow(rhs) => inputs[0].powf(rhs),
+            Sin => inputs[0].sin(),
+            Cos => inputs[0].cos(),
+            ExpM1 => inputs[0].exp_m1(),
+            Ln1p => inputs[0].ln_1p(),
+            StopGradient => inputs[0],
+            Custom(ref op) => op.forward(inputs),
+            Select => {
+                if inputs[0] > 0f32 {
+                    inputs[1]
+                } else {
+                    inputs[2]
+                }
+            }
+            AddN => inputs.iter().sum(),
+            MulN => inputs.iter().product(),
+        }
+    }
+
+    // partial derivatives with respect to each operand
+    pub fn partials(&self, inputs: &[f32]) -> Vec<f32> {
+        use self::Op::*;
+
+        match *self {
+            Const(_) | Var(_) => vec![],
+            Neg => vec![-1f32],
+            Add => vec![1f32, 1f32],
+            Sub => vec![1f32, -1f32],
+            Mul => vec![inputs[1], inputs[0]],
+            Div => vec![1f32 / inputs[1], -inputs[0] / inputs[1].powi(2)],
+            Pow(rhs) => vec![rhs * inputs[0].powf(rhs - 1f32)],
+            Sin => vec![inputs[0].cos()],
+            Cos => vec![-inputs[0].sin()],
+            ExpM1 => vec![inputs[0].exp()],
+            Ln1p => vec![1f32 / (1f32 + inputs[0])],
+            StopGradient => vec![0f32],
+            Custom(ref op) => op.backward(inputs, 1f32),
+            Select => {
+                if inputs[0] > 0f32 {
+                    vec![0f32, 1f32, 0f32]
+                } else {
+                    vec![0f32, 0f32, 1f32]
+                }
+            }
+            AddN => vec![1f32; inputs.len()],
+            // products of the inputs before and after each one, which stays
+            // exact when some inputs are zero
+            MulN => {
+                let mut partials = vec![1f32; inputs.len()];
+                let mut before = 1f32;
+                for (partial, input) in partials.iter_mut().zip(inputs) {
+                    *partial = before;
+                    before *= input;
+                }
+                let mut after = 1f32;
+                for (partial, input) in partials.iter_mut().zip(inputs).rev() {
+                    *partial *= after;
+                    after *= input;
+                }
+                partials
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    op: Op,
+    args: Vec<NodeId>,
+    label: Option<Label>,
+}
+
+// Mutable evaluation state, kept apart from the graph so that one graph can
+// be evaluated from several threads, each with its own workspace.
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    values: Vec<f32>,
+    adjoints: Vec<f32>,
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Workspace::default()
+    }
+
+    // value of `id` in the last evaluation, if it was computed
+    pub fn value(&self, id: NodeId) -> Option<f32> {
+        self.values.get(id.0).cloned()
+    }
+}
+
+// A variable of a graph that carries its own value. Handles of the same name
+// share the value, as do clones of the graph they came from.
+#[derive(Debug, Clone)]
+pub struct VarHandle {
+    id: NodeId,
+    name: String,
+    value: Arc<AtomicU32>,
+}
+
+impl VarHandle {
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.value.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// so that handles go straight into the builders as `*x`
+impl Deref for VarHandle {
+    type Target = NodeId;
+
+    fn deref(&self) -> &NodeId {
+        &self.id
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    nodes: Vec<Entry>,
+    // the value of every variable name, starting at zero like `var`
+    cells: HashMap<String, Arc<AtomicU32>>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph::default()
+    }
+
+    // room for `nodes` nodes before reallocating
+    pub fn with_capacity(nodes: usize) -> Self {
+        Graph {
+            nodes: Vec::with_capacity(nodes),
+            cells: HashMap::new(),
+        }
+    }
+
+    // Bytes held by the graph. An owned graph has no gradient maps.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage {
+            nodes: self.nodes.capacity() * size_of::<Entry>(),
+            ..MemoryUsage::default()
+        };
+        for entry in &self.nodes {
+            usage.operands += entry.args.capacity() * size_of::<NodeId>();
+            if let Op::Var(ref name) = entry.op {
+                usage.operands += name.capacity();
+            }
+        }
+        usage
+    }
+
+    // Copies the arena graph rooted at `node`.
+    pub fn from_node(node: Node) -> (Self, NodeId) {
+        let mut graph = Graph::new();
+        let root = graph.import(node);
+        (graph, root)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn op(&self, id: NodeId) -> &Op {
+        &self.nodes[id.0].op
+    }
+
+    pub fn args(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.0].args
+    }
+
+    pub fn label(&self, id: NodeId) -> Option<&Label> {
+        self.nodes[id.0].label.as_ref()
+    }
+
+    // Same as `labelled` for arena nodes.
+    #[track_caller]
+    pub fn labelled(&mut self, id: NodeId, name: &str) -> NodeId {
+        self.nodes[id.0].label = Some(Label {
+            name: name.to_string(),
+            location: Location::caller(),
+        });
+        id
+    }
+
+    pub fn push(&mut self, op: Op, args: &[NodeId]) -> NodeId {
+        assert!(
+            args.iter().all(|arg| arg.0 < self.nodes.len()),
+            "operands must belong to this graph"
+        );
+        if let Op::Var(ref name) = op {
+            self.cells
+                .entry(name.clone())
+                .or_insert_with(|| Arc::new(AtomicU32::new(0f32.to_bits())));
+        }
+        self.nodes.push(Entry {
+            op,
+            args: args.to_vec(),
+            label: None,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn constant(&mut self, value: f32) -> NodeId {
+        self.push(Op::Const(value), &[])
+    }
+
+    pub fn var<S: Into<String>>(&mut self, name: S) -> VarHandle {
+        let name = name.into();
+        let id = self.push(Op::Var(name.clone()), &[]);
+        VarHandle {
+            id,
+            value: self.cells[&name].clone(),
+            name,
+        }
+    }
+
+    // the values set through the handles, for `eval` and `grad`
+    pub fn values(&self) -> Assignment {
+        self.cells
+            .iter()
+            .map(|(name, cell)| (name.as_str(), f32::from_bits(cell.load(Ordering::Relaxed))))
+            .collect()
+    }
+
+    pub fn neg(&mut self, value: NodeId) -> NodeId {
+        self.push(Op::Neg, &[value])
+    }
+
+    pub fn add(&mut self, lhs: NodeId, rhs: NodeId) -> NodeId {
+        self.push(Op::Add, &[lhs, rhs])
+    }
+
+    pub fn sub(&mut self, lhs: NodeId, rhs: NodeId) -> NodeId {
+        self.push(Op::Sub, &[lhs, rhs])
+    }
+
+    pub fn mul(&mut self, lhs: NodeId, rhs: NodeId) -> NodeId {
+        self.push(Op::Mul, &[lhs, rhs])
+    }
+
+    pub fn div(&mut self, lhs: NodeId, rhs: NodeId) -> NodeId {
+        self.push(Op::Div, &[lhs, rhs])
+    }
+
+    pub fn pow(&mut self, lhs: NodeId, rhs: f32) -> NodeId {
+        self.push(Op::Pow(rhs), &[lhs])
+    }
+
+    pub fn sin(&mut self, value: NodeId) -> NodeId {
+        self.push(Op::Sin, &[value])
+    }
+
+    pub fn cos(&mut self, value: NodeId) -> NodeId {
+        self.push(Op::Cos, &[value])
+    }
+
+    pub fn exp_m1(&mut self, value: NodeId) -> NodeId {
+        self.push(Op::ExpM1, &[value])
+    }
+
+    pub fn ln_1p(&mut self, value: NodeId) -> NodeId {
+        self.push(Op::Ln1p, &[value])
+    }
+
+    pub fn custom<O: CustomOp + 'static>(&mut self, op: O, args: &[NodeId]) -> NodeId {
+        self.push(Op::Custom(Arc::new(op)), args)
+    }
+
+    pub fn select(&mut self, cond: NodeId, then: NodeId, else_: NodeId) -> NodeId {
+        self.push(Op::Select, &[cond, then, else_])
+    }
+
+    // like the arena `gather`
+    pub fn gather(&mut self, values: &[NodeId], index: NodeId) -> NodeId {
+        assert!(!values.is_empty(), "gather from no values");
+        if let Op::Const(index) = *self.op(index) {
+            let i = index.round().max(0f32).min((values.len() - 1) as f32);
+            return values[i as usize];
+        }
+        self.pick(values, 0, index)
+    }
+
+    fn pick(&mut self, values: &[NodeId], first: usize, index: NodeId) -> NodeId {
+        if values.len() == 1 {
+            return values[0];
+        }
+        let mid = values.len() / 2;
+        let boundary = self.constant((first + mid) as f32 - 0.5);
+        let cond = self.sub(index, boundary);
+        let above = self.pick(&values[mid..], first + mid, index);
+        let below = self.pick(&values[..mid], first, index);
+        self.select(cond, above, below)
+    }
+
+    pub fn add_n(&mut self, terms: &[NodeId]) -> NodeId {
+        self.push(Op::AddN, terms)
+    }
+
+    pub fn mul_n(&mut self, factors: &[NodeId]) -> NodeId {
+        self.push(Op::MulN, factors)
+    }
+
+    pub fn stop_gradient(&mut self, value: NodeId) -> NodeId {
+        self.push(Op::StopGradient, &[value])
+    }
+
+    // Copies the arena graph rooted at `node` into this graph. Shared
+    // subexpressions stay shared.
+    pub fn import(&mut self, node: Node) -> NodeId {
+        let mut ids: HashMap<*const NodeData, NodeId> = HashMap::new();
+        for node in topological_order(&[node]) {
+            let args: Vec<NodeId> = node
+                .children()
+                .iter()
+                .map(|child| ids[&(*child as *const NodeData)])
+                .collect();
+            let id = self.push(node.type_.op(), &args);
+            self.nodes[id.0].label = node.label();
+            ids.insert(node as *const NodeData, id);
+        }
+        ids[&(node as *const NodeData)]
+    }
+
+    // Builds `root` in `arena`. Variables start at zero like `var` does.
+    pub fn to_arena<'a>(&self, arena: &'a Arena<'a>, root: NodeId) -> Node<'a> {
+        self.build_in(arena, root, &HashMap::new())
+    }
+
+    // Builds `root` in `arena`, using the bound nodes in place of the
+    // variables with those names.
+    pub(crate) fn build_in<'a>(
+        &self,
+        arena: &'a Arena<'a>,
+        root: NodeId,
+        bindings: &HashMap<&str, Node<'a>>,
+    ) -> Node<'a> {
+        let mut nodes: Vec<Option<Node<'a>>> = vec![None; root.0 + 1];
+        for index in self.reachable(root) {
+            let entry = &self.nodes[index];
+            let arg = |i: usize| nodes[entry.args[i].0].unwrap();
+            let all = || entry.args.iter().map(|a| nodes[a.0].unwrap()).collect();
+            let type_ = match entry.op {
+                Op::Var(ref name) if bindings.contains_key(name.as_str()) => {
+                    nodes[index] = Some(bindings[name.as_str()]);
+                    continue;
+                }
+                Op::Const(v) => NodeType::Const(v),
+                Op::Var(ref name) => NodeType::Var(name.clone()),
+                Op::Neg => NodeType::Neg(arg(0)),
+                Op::Add => NodeType::Add(arg(0), arg(1)),
+                Op::Sub => NodeType::Sub(arg(0), arg(1)),
+                Op::Mul => NodeType::Mul(arg(0), arg(1)),
+                Op::Div => NodeType::Div(arg(0), arg(1)),
+                Op::Pow(rhs) => NodeType::Pow(arg(0), rhs),
+                Op::Sin => NodeType::Sin(arg(0)),
+                Op::Cos => NodeType::Cos(arg(0)),
+                Op::ExpM1 => NodeType::ExpM1(arg(0)),
+                Op::Ln1p => NodeType::Ln1p(arg(0)),
+                Op::StopGradient => NodeType::StopGradient(arg(0)),
+                Op::Custom(ref op) => NodeType::Custom(op.clone(), all()),
+                Op::Select => NodeType::Select(arg(0), arg(1), arg(2)),
+                Op::AddN => NodeType::AddN(all()),
+                Op::MulN => NodeType::MulN(all()),
+            };
+            let node: Node<'a> = arena.alloc(type_.into());
+            *node.label.borrow_mut() = entry.label.clone();
+            nodes[index] = Some(node);
+        }
+        nodes[root.0].unwrap()
+    }
+
+    // Copies `root` of this graph into `target` with the same substitution
+    // as `build_in`.
+    pub(crate) fn copy_into(
+        &self,
+        target: &mut Graph,
+        root: NodeId,
+        bindings: &HashMap<&str, NodeId>,
+    ) -> NodeId {
+        let mut ids = vec![NodeId(0); root.0 + 1];
+        for index in self.reachable(root) {
+            let entry = &self.nodes[index];
+            ids[index] = match entry.op {
+                Op::Var(ref name) if bindings.contains_key(name.as_str()) => {
+                    bindings[name.as_str()]
+                }
+                ref op => {
+                    let args: Vec<NodeId> = entry.args.iter().map(|arg| ids[arg.0]).collect();
+                    target.push(op.clone(), &args)
+                }
+            };
+        }
+        ids[root.0]
+    }
+
+    pub fn eval(&self, root: NodeId, assignment: &Assignment) -> f32 {
+        self.eval_in(&mut Workspace::new(), root, assignment)
+    }
+
+    // Like `eval`, but reuses the buffers of `workspace`.
+    pub fn eval_in(&self, workspace: &mut Workspace, root: NodeId, assignment: &Assignment) -> f32 {
+        self.forward(workspace, root, assignment);
+        workspace.values[root.0]
+    }
+
+    // Gradient of `root` with respect to the named variables by one reverse
+    // sweep. Variables not in the graph get zero.
+    pub fn grad(&self, root: NodeId, assignment: &Assignment, variables: &[&str]) -> Gradients {
+        self.grad_in(&mut Workspace::new(), root, assignment, variables)
+    }
+
+    pub fn grad_in(
+        &self,
+        workspace: &mut Workspace,
+        root: NodeId,
+        assignment: &Assignment,
+        variables: &[&str],
+    ) -> Gradients {
+        let order = self.forward(workspace, root, assignment);
+        let Workspace {
+            ref values,
+            ref mut adjoints,
+        } = *workspace;
+        adjoints.clear();
+        adjoints.resize(root.0 + 1, 0f32);
+        adjoints[root.0] = 1f32;
+
+        let mut grads: HashMap<&str, f32> = HashMap::new();
+        for index in order.into_iter().rev() {
+            let adjoint = adjoints[index];
+            if adjoint == 0f32 {
+                continue;
+            }
+            let entry = &self.nodes[index];
+            if let Op::Var(ref name) = entry.op {
+                *grads.entry(name).or_insert(0f32) += adjoint;
+                continue;
+            }
+            let inputs: Vec<f32> = entry.args.iter().map(|arg| values[arg.0]).collect();
+            for (arg, partial) in entry.args.iter().zip(entry.op.partials(&inputs)) {
+                adjoints[arg.0] += adjoint * partial;
+            }
+        }
+
+        let values = variables
+            .iter()
+            .map(|name| grads.get(name).cloned().unwrap_or(0f32))
+            .collect();
+        Gradients::new(variables, values)
+    }
+
+    // Value and gradient of `root` in one pass.
+    pub fn eval_with_grad(
+        &self,
+        root: NodeId,
+        assignment: &Assignment,
+        variables: &[&str],
+    ) -> (f32, Gradients) {
+        let mut workspace = Workspace::new();
+        let grads = self.grad_in(&mut workspace, root, assignment, variables);
+        (workspace.values[root.0], grads)
+    }
+
+    // Indices of the nodes `root` depends on, in increasing order.
+    fn reachable(&self, root: NodeId) -> Vec<usize> {
+        let mut needed = vec![false; root.0 + 1];
+        needed[root.0] = true;
+        for index in (0..=root.0).rev() {
+            if needed[index] {
+                for arg in &self.nodes[index].args {
+                    needed[arg.0] = true;
+                }
+            }
+        }
+        (0..=root.0).filter(|&index| needed[index]).collect()
+    }
+
+    // Fills the values of the nodes up to `root` (unreachable ones are left
+    // at zero) and returns the reachable ones in order.
+    fn forward(
+        &self,
+        workspace: &mut Workspace,
+        root: NodeId,
+        assignment: &Assignment,
+    ) -> Vec<usize> {
+        let order = self.reachable(root);
+        let values = &mut workspace.values;
+        values.clear();
+        values.resize(root.0 + 1, 0f32);
+        for &index in &order {
+            values[index] = self.eval_node(index, values, assignment);
+        }
+        order
+    }
+
+    fn eval_node(&self, index: usize, values: &[f32], assignment: &Assignment) -> f32 {
+        let entry = &self.nodes[index];
+        match entry.op {
+            Op::Var(ref name) => assignment.lookup(name).unwrap_or_else(|e| panic!("{}", e)),
+            ref op => {
+                let inputs: Vec<f32> = entry.args.iter().map(|arg| values[arg.0]).collect();
+                op.eval(&inputs)
+            }
+        }
+    }
+
+    // Like `eval`, spreading the work over `threads` threads. Nodes are
+    // grouped into levels by their distance from the leaves; nodes of one
+    // level are independent and evaluated in parallel, and every level
+    // waits for the previous one. Narrow levels are evaluated on the
+    // calling thread.
+    pub fn eval_parallel(&self, root: NodeId, assignment: &Assignment, threads: usize) -> f32 {
+        const MIN_PER_THREAD: usize = 16;
+
+        let mut levels: Vec<Vec<usize>> = vec![];
+        let mut level_of = vec![0; root.0 + 1];
+        for index in self.reachable(root) {
+            let level = self.nodes[index]
+                .args
+                .iter()
+                .map(|arg| level_of[arg.0] + 1)
+                .max()
+                .unwrap_or(0);
+            level_of[index] = level;
+            if levels.len() <= level {
+                levels.push(vec![]);
+            }
+            levels[level].push(index);
+        }
+
+        let mut values = vec![0f32; root.0 + 1];
+        for level in &levels {
+            let chunk = level.len().div_ceil(threads.max(1)).max(MIN_PER_THREAD);
+            if chunk >= level.len() {
+                for &index in level {
+                    values[index] = self.eval_node(index, &values, assignment);
+                }
+                continue;
+            }
+
+            let results: Vec<Vec<f32>> = {
+                let values = &values;
+                thread::scope(|scope| {
+                    let handles: Vec<_> = level
+                        .chunks(chunk)
+                        .map(|indices| {
+                            scope.spawn(move || {
+                                indices
+                                    .iter()
+                                    .map(|&index| self.eval_node(index, values, assignment))
+                                    .collect()
+                            })
+                        })
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|handle| handle.join().unwrap())
+                        .collect()
+                })
+            };
+            for (indices, results) in level.chunks(chunk).zip(results) {
+                for (&index, value) in indices.iter().zip(results) {
+                    values[index] = value;
+                }
+            }
+        }
+
+        values[root.0]
+    }
+}
+
+#[test]
+fn owned_graph_eval_and_grad() {
+    fn build() -> (Graph, NodeId) {
+        let mut graph = Graph::new();
+        let x = graph.var("x").id();
+        let y = graph.var("y").id();
+        let xy = graph.mul(x, y);
+        let s = graph.sin(x);
+        let root = graph.add(xy, s);
+        (graph, root)
+    }
+
+    let (graph, root) = build();
+    let mut assignment = Assignment::new();
+    assignment.insert("x".to_string(), 1.5);
+    assignment.insert("y".to_string(), -2.0);
+
+    assert_eq!(graph.eval(root, &assignment), 1.5 * -2.0 + 1.5f32.sin());
+    let grads = graph.grad(root, &assignment, &["x", "y", "z"]);
+    assert_eq!(grads.values(), &[-2.0 + 1.5f32.cos(), 1.5, 0.0]);
+}
+
+#[test]
+fn owned_graph_round_trip() {
+    use {add, labelled, mul, var};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let xx = mul(&arena, x, x);
+    let node = add(&arena, xx, x);
+
+    let node = labelled(node, "root");
+
+    let (graph, root) = Graph::from_node(node);
+    // x is shared, so it is imported only once
+    assert_eq!(graph.len(), 3);
+    assert_eq!(graph.label(root).unwrap().name, "root");
+
+    let arena2 = Arena::new();
+    let rebuilt = graph.to_arena(&arena2, root);
+    let mut assignment = Assignment::new();
+    assignment.insert("x".to_string(), 3.0);
+    rebuilt.assign(&assignment);
+    rebuilt.forward();
+    assert_eq!(rebuilt.value(), 12.0);
+    assert_eq!(graph.grad(root, &assignment, &["x"])["x"], 7.0);
+}
+
+#[test]
+fn graph_is_shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Graph>();
+
+    let mut graph = Graph::new();
+    let x = graph.var("x").id();
+    let root = graph.pow(x, 2.0);
+
+    let results: Vec<(f32, Gradients)> = ::std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let graph = &graph;
+                scope.spawn(move || {
+                    let mut workspace = Workspace::new();
+                    let mut assignment = Assignment::new();
+                    assignment.insert("x".to_string(), i as f32);
+                    let value = graph.eval_in(&mut workspace, root, &assignment);
+                    let grad = graph.grad_in(&mut workspace, root, &assignment, &["x"]);
+                    (value, grad)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    for (i, (value, grad)) in results.into_iter().enumerate() {
+        let x = i as f32;
+        assert_eq!(value, x * x);
+        assert_eq!(grad[0], 2.0 * x);
+    }
+}
+
+#[test]
+fn graph_capacity_and_memory() {
+    let mut graph = Graph::with_capacity(4);
+    let before = graph.memory_usage();
+    assert_eq!(before.nodes, 4 * size_of::<Entry>());
+
+    let x = graph.var("x").id();
+    let y = graph.var("y").id();
+    let xy = graph.mul(x, y);
+    graph.sin(xy);
+    let after = graph.memory_usage();
+    // no reallocation happened
+    assert_eq!(after.nodes, before.nodes);
+    assert_eq!(after.operands, 3 * size_of::<NodeId>() + 2);
+}
+
+#[test]
+fn parallel_eval_matches_sequential() {
+    let mut graph = Graph::new();
+    let x = graph.var("x").id();
+    let terms: Vec<NodeId> = (0..500)
+        .map(|i| {
+            let c = graph.constant(i as f32 * 0.01);
+            let shifted = graph.add(x, c);
+            let s = graph.sin(shifted);
+            graph.pow(s, 2.0)
+        })
+        .collect();
+    let root = graph.add_n(&terms);
+
+    let mut assignment = Assignment::new();
+    assignment.insert("x".to_string(), 0.3);
+    assert_eq!(
+        graph.eval_parallel(root, &assignment, 4),
+        graph.eval(root, &assignment)
+    );
+}
+
+#[test]
+fn var_handles_carry_values() {
+    let mut graph = Graph::new();
+    let x = graph.var("x");
+    let y = graph.var("y");
+    let xy = graph.mul(*x, *y);
+    let root = graph.add(xy, *x);
+
+    x.set(8.0);
+    y.set(4.0);
+    assert_eq!(x.get(), 8.0);
+    assert_eq!(graph.eval(root, &graph.values()), 40.0);
+
+    // another node of the same name shares the value
+    let again = graph.var("x");
+    assert_eq!(again.get(), 8.0);
+    again.set(1.0);
+    let grads = graph.grad(root, &graph.values(), &["x", "y"]);
+    assert_eq!((grads["x"], grads["y"]), (5.0, 1.0));
+}
+
+#[test]
+fn negation_in_owned_graph() {
+    let mut graph = Graph::new();
+    let x = graph.var("x");
+    let minus_x = graph.neg(*x);
+    let root = graph.mul(minus_x, *x);
+
+    let at = [("x", 3f32)].into();
+    assert_eq!(graph.eval(root, &at), -9.0);
+    assert_eq!(graph.grad(root, &at, &["x"])["x"], -6.0);
+}
+
+#[test]
+fn gather_in_owned_graph() {
+    let mut graph = Graph::new();
+    let values: Vec<NodeId> = (0..3).map(|i| graph.constant(i as f32 * 10f32)).collect();
+    let index = graph.var("i").id();
+    let picked = graph.gather(&values, index);
+
+    assert_eq!(graph.eval(picked, &[("i", 1f32)].into()), 10f32);
+    assert_eq!(graph.eval(picked, &[("i", 7f32)].into()), 20f32);
+    let fixed = graph.constant(0f32);
+    assert_eq!(graph.gather(&values, fixed), values[0]);
+}
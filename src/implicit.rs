@@ -0,0 +1,59 @@
+use {Assignment, Node};
+
+// Derivatives of y(x) defined implicitly by f(x, y) = 0, where y is the
+// variable `solve_for`, with respect to every variable in `wrt`:
+// dy/dx = -(df/dx) / (df/dy). `assignment` must be a point on the curve.
+// Returns None where df/dy vanishes and y is not locally a function of x.
+pub fn implicit_grad(
+    f: Node,
+    solve_for: &str,
+    wrt: &[&str],
+    assignment: &Assignment,
+) -> Option<Vec<f32>> {
+    f.assign(assignment);
+    f.forward();
+
+    let mut variables = vec![solve_for];
+    variables.extend_from_slice(wrt);
+    f.backward_ad(&variables);
+
+    let dy = f.grad(solve_for);
+    if dy == 0f32 {
+        return None;
+    }
+
+    Some(wrt.iter().map(|x| -f.grad(x) / dy).collect())
+}
+
+#[test]
+fn circle_slope() {
+    use {add, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let r = var(arena, "r".to_string());
+    // x^2 + y^2 - r^2 = 0
+    let f = sub(
+        arena,
+        add(arena, mul(arena, x, x), mul(arena, y, y)),
+        mul(arena, r, r),
+    );
+
+    let mut assignment = Assignment::new();
+    assignment.insert("x".to_string(), 3f32);
+    assignment.insert("y".to_string(), 4f32);
+    assignment.insert("r".to_string(), 5f32);
+
+    // dy/dx = -x / y, dy/dr = r / y
+    assert_eq!(
+        implicit_grad(f, "y", &["x", "r"], &assignment),
+        Some(vec![-0.75, 1.25])
+    );
+
+    assignment.insert("x".to_string(), 5f32);
+    assignment.insert("y".to_string(), 0f32);
+    assert_eq!(implicit_grad(f, "y", &["x"], &assignment), None);
+}
@@ -0,0 +1,134 @@
+// Re-evaluation of only the part of a graph affected by changed variables.
+
+use std::collections::HashMap;
+
+use {node_key, topological_order, Node, NodeData};
+
+pub struct Incremental<'a> {
+    order: Vec<Node<'a>>,
+    positions: HashMap<*const NodeData<'a>, usize>,
+    args: Vec<Vec<usize>>,
+    parents: Vec<Vec<usize>>,
+    values: Vec<f32>,
+    recomputed: usize,
+}
+
+impl<'a> Incremental<'a> {
+    // Evaluates the whole graph of `root` at the current variable values.
+    pub fn new(root: Node<'a>) -> Self {
+        let order = topological_order(&[root]);
+        let positions: HashMap<_, _> = order
+            .iter()
+            .enumerate()
+            .map(|(i, &node)| (node_key(node), i))
+            .collect();
+        let args: Vec<Vec<usize>> = order
+            .iter()
+            .map(|node| {
+                node.children()
+                    .iter()
+                    .map(|&child| positions[&node_key(child)])
+                    .collect()
+            })
+            .collect();
+        let mut parents = vec![vec![]; order.len()];
+        for (i, args) in args.iter().enumerate() {
+            for &arg in args {
+                parents[arg].push(i);
+            }
+        }
+
+        let mut incremental = Incremental {
+            values: vec![0f32; order.len()],
+            order,
+            positions,
+            args,
+            parents,
+            recomputed: 0,
+        };
+        let all: Vec<bool> = vec![true; incremental.order.len()];
+        incremental.recompute(&all);
+        incremental
+    }
+
+    pub fn value(&self) -> f32 {
+        *self.values.last().unwrap()
+    }
+
+    // value of any node of the graph as of the last update
+    pub fn value_of(&self, node: Node<'a>) -> Option<f32> {
+        self.positions.get(&node_key(node)).map(|&i| self.values[i])
+    }
+
+    // number of nodes evaluated by the last update
+    pub fn recomputed(&self) -> usize {
+        self.recomputed
+    }
+
+    // Sets the named variables and re-evaluates the nodes depending on them.
+    // Returns the new value of the root.
+    pub fn update(&mut self, changes: &[(&str, f32)]) -> f32 {
+        let mut dirty = vec![false; self.order.len()];
+        for (i, node) in self.order.iter().enumerate() {
+            if let Some(name) = node.var_name() {
+                if let Some(&(_, value)) = changes.iter().find(|&&(n, _)| n == name) {
+                    node.set_value(value);
+                    dirty[i] = true;
+                }
+            }
+        }
+        // parents come later in topological order, so one pass marks all
+        for i in 0..dirty.len() {
+            if dirty[i] {
+                for &parent in &self.parents[i] {
+                    dirty[parent] = true;
+                }
+            }
+        }
+
+        self.recompute(&dirty);
+        self.value()
+    }
+
+    fn recompute(&mut self, dirty: &[bool]) {
+        self.recomputed = 0;
+        let mut inputs = vec![];
+        for (i, node) in self.order.iter().enumerate() {
+            if !dirty[i] {
+                continue;
+            }
+            inputs.clear();
+            inputs.extend(self.args[i].iter().map(|&arg| self.values[arg]));
+            self.values[i] = node.eval(&inputs);
+            node.set_value(self.values[i]);
+            self.recomputed += 1;
+        }
+    }
+}
+
+#[test]
+fn update_recomputes_downstream_only() {
+    use {add, mul, sin, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let y = var(&arena, "y".to_string());
+    x.set_value(1.0);
+    y.set_value(2.0);
+    // heavy(y) does not depend on x
+    let heavy = sin(&arena, mul(&arena, y, y));
+    let f = add(&arena, mul(&arena, x, x), heavy);
+
+    let mut incremental = Incremental::new(f);
+    assert_eq!(incremental.recomputed(), 6);
+    assert_eq!(incremental.value(), 1.0 + 4f32.sin());
+
+    let value = incremental.update(&[("x", 3.0)]);
+    assert_eq!(value, 9.0 + 4f32.sin());
+    // x, x * x and the sum
+    assert_eq!(incremental.recomputed(), 3);
+    assert_eq!(incremental.value_of(heavy), Some(4f32.sin()));
+
+    f.forward();
+    assert_eq!(f.value(), value);
+}
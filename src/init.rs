@@ -0,0 +1,116 @@
+// Reproducible random initial values for parameters.
+//
+// The generator is a small splitmix64, so a seed gives the same values on
+// every platform without pulling in a random number crate. For the same
+// reason these are always available rather than behind a `rand` feature.
+
+use std::f32::consts::PI;
+
+use {Assignment, Node};
+
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    pub fn uniform(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // uniform in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // standard normal by Box-Muller
+    pub fn normal(&mut self) -> f32 {
+        let u = 1f32 - self.uniform();
+        let v = self.uniform();
+        (-2f32 * u.ln()).sqrt() * (2f32 * PI * v).cos()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Init {
+    Uniform { lo: f32, hi: f32 },
+    Normal { mu: f32, sigma: f32 },
+    // Glorot uniform for a layer with the given fan-in and fan-out
+    Xavier { fan_in: usize, fan_out: usize },
+}
+
+pub fn uniform(lo: f32, hi: f32) -> Init {
+    Init::Uniform { lo, hi }
+}
+
+pub fn normal(mu: f32, sigma: f32) -> Init {
+    Init::Normal { mu, sigma }
+}
+
+pub fn xavier(fan_in: usize, fan_out: usize) -> Init {
+    Init::Xavier { fan_in, fan_out }
+}
+
+impl Init {
+    pub fn sample(&self, rng: &mut Rng) -> f32 {
+        match *self {
+            Init::Uniform { lo, hi } => lo + (hi - lo) * rng.uniform(),
+            Init::Normal { mu, sigma } => mu + sigma * rng.normal(),
+            Init::Xavier { fan_in, fan_out } => {
+                let limit = (6f32 / (fan_in + fan_out) as f32).sqrt();
+                limit * (2f32 * rng.uniform() - 1f32)
+            }
+        }
+    }
+
+    // Sets every parameter node to a fresh sample, in order.
+    pub fn initialize(&self, params: &[Node], rng: &mut Rng) {
+        for param in params {
+            param.set_value(self.sample(rng));
+        }
+    }
+
+    // An assignment of a fresh sample to every name, in order.
+    pub fn assignment(&self, names: &[&str], rng: &mut Rng) -> Assignment {
+        names.iter().map(|&name| (name, self.sample(rng))).collect()
+    }
+}
+
+#[test]
+fn initialization_is_reproducible() {
+    use var;
+    use Arena;
+
+    let arena = Arena::new();
+    let params: Vec<Node> = (0..64).map(|i| var(&arena, format!("w{}", i))).collect();
+    xavier(16, 48).initialize(&params, &mut Rng::new(7));
+    let limit = (6f32 / 64f32).sqrt();
+    assert!(params.iter().all(|p| p.value().abs() <= limit));
+
+    let names: Vec<String> = (0..64).map(|i| format!("w{}", i)).collect();
+    let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+    let at = xavier(16, 48).assignment(&names, &mut Rng::new(7));
+    assert!(params.iter().zip(&names).all(|(p, n)| at[*n] == p.value()));
+
+    let mut rng = Rng::new(1);
+    let samples: Vec<f32> = (0..4000)
+        .map(|_| normal(2.0, 0.5).sample(&mut rng))
+        .collect();
+    let mean = samples.iter().sum::<f32>() / 4000.0;
+    assert!((mean - 2.0).abs() < 0.05);
+    assert!((0..100).all(|_| {
+        let u = uniform(-1.0, 3.0).sample(&mut rng);
+        (-1.0..3.0).contains(&u)
+    }));
+}
@@ -0,0 +1,110 @@
+// Equality-constrained problems through their first-order (KKT) conditions.
+//
+// For min f(x) subject to c_i(x) = 0, the Lagrangian is
+// L = f + sum_i lambda_i c_i, and a constrained stationary point solves the
+// square system dL/dx = 0, c(x) = 0 in x and the multipliers lambda.
+
+use std::collections::HashSet;
+
+use symbolic::grad_graph;
+use {add_n, mul, topological_order, var, Arena, Node};
+
+#[derive(Debug, Clone)]
+pub struct Lagrangian<'a> {
+    pub lagrangian: Node<'a>,
+    // the variables of the objective and the constraints, in order of first
+    // appearance
+    pub vars: Vec<Node<'a>>,
+    // one new variable per constraint, named `lambda0`, `lambda1`, ...
+    // (prefixed with underscores if the problem already uses those names)
+    pub multipliers: Vec<Node<'a>>,
+    // dL/dx for every variable followed by the constraints
+    pub system: Vec<Node<'a>>,
+}
+
+impl<'a> Lagrangian<'a> {
+    // the unknowns of `system`: the variables, then the multipliers
+    pub fn unknowns(&self) -> Vec<Node<'a>> {
+        self.vars.iter().chain(&self.multipliers).cloned().collect()
+    }
+}
+
+// The Lagrangian of minimizing `objective` subject to every constraint node
+// being zero, with its stationarity system ready for `find_root_multi`.
+pub fn lagrangian<'a>(
+    arena: &'a Arena<'a>,
+    objective: Node<'a>,
+    constraints: &[Node<'a>],
+) -> Lagrangian<'a> {
+    let mut roots = vec![objective];
+    roots.extend_from_slice(constraints);
+    let mut names = HashSet::new();
+    let vars: Vec<Node<'a>> = topological_order(&roots)
+        .into_iter()
+        .filter(|node| match node.var_name() {
+            Some(name) => names.insert(name.to_string()),
+            None => false,
+        })
+        .collect();
+
+    let mut prefix = String::new();
+    while (0..constraints.len()).any(|i| names.contains(&format!("{}lambda{}", prefix, i))) {
+        prefix.push('_');
+    }
+    let multipliers: Vec<Node<'a>> = (0..constraints.len())
+        .map(|i| var(arena, format!("{}lambda{}", prefix, i)))
+        .collect();
+
+    let mut terms = vec![objective];
+    terms.extend(
+        multipliers
+            .iter()
+            .zip(constraints)
+            .map(|(&lambda, &constraint)| mul(arena, lambda, constraint)),
+    );
+    let lagrangian = add_n(arena, &terms);
+
+    let mut system = grad_graph(arena, lagrangian, &vars);
+    system.extend_from_slice(constraints);
+    Lagrangian {
+        lagrangian,
+        vars,
+        multipliers,
+        system,
+    }
+}
+
+#[test]
+fn kkt_system_is_solved_by_newton() {
+    use roots::find_root_multi;
+    use {add, constant, sub};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    // min x^2 + 2 y^2 subject to x + y = 3
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let objective = add(
+        arena,
+        mul(arena, x, x),
+        mul(arena, constant(arena, 2f32), mul(arena, y, y)),
+    );
+    let constraint = sub(arena, add(arena, x, y), constant(arena, 3f32));
+
+    let kkt = lagrangian(arena, objective, &[constraint]);
+    assert_eq!(kkt.system.len(), 3);
+    assert_eq!(kkt.multipliers[0].var_name(), Some("lambda0"));
+
+    let solution = find_root_multi(&kkt.system, &kkt.unknowns(), &[0f32, 0f32, 0f32]).unwrap();
+    let names: Vec<&str> = kkt.vars.iter().map(|v| v.var_name().unwrap()).collect();
+    let (x, y) = if names[0] == "x" {
+        (solution[0], solution[1])
+    } else {
+        (solution[1], solution[0])
+    };
+    // 2x = 4y = -lambda
+    assert!((x - 2f32).abs() < 1e-4);
+    assert!((y - 1f32).abs() < 1e-4);
+    assert!((solution[2] + 4f32).abs() < 1e-4);
+}
@@ -1,8 +1,163 @@
 extern crate typed_arena;
 
 use std::cell::Cell;
+use std::cell::Ref;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::panic::Location;
+use std::rc::Rc;
+use std::sync::Arc;
+
+pub use assignment::{Assignment, MissingVariable};
+pub use grad_map::GradMap;
+pub use gradients::{GradientValues, Gradients};
+use graph::Op;
+use observe::Observer;
+use units::Dimension;
+
+pub mod assignment;
+pub mod balance;
+pub mod batch;
+pub mod binary;
+pub mod bytecode;
+pub mod cache;
+pub mod canonical;
+pub mod checkpoint;
+pub mod conv;
+pub mod diff;
+pub mod dump;
+pub mod expr;
+pub mod geom;
+pub mod glsl;
+pub mod grad_map;
+pub mod gradients;
+pub mod graph;
+pub mod implicit;
+pub mod incremental;
+pub mod init;
+pub mod lagrange;
+mod linalg;
+pub mod matrix;
+pub mod memory;
+pub mod num;
+pub mod observe;
+pub mod ode;
+pub mod ops;
+pub mod optim;
+pub mod piecewise;
+pub mod prelude;
+pub mod profile;
+pub mod quadrature;
+mod reader;
+pub mod registry;
+pub mod roots;
+pub mod rpn;
+pub mod sample;
+pub mod scan;
+pub mod scope;
+pub mod smoothness;
+pub mod sparse;
+pub mod sweep;
+pub mod symbolic;
+pub mod sympy;
+pub mod tape;
+pub mod taylor;
+pub mod template;
+pub mod testgen;
+#[macro_use]
+pub mod typed;
+pub mod units;
+pub mod wgsl;
+
+// An operation outside the built-in set, given by its value and the
+// partial derivatives with respect to each of its inputs. Ops are shared
+// between threads by `graph::Graph`, hence the bounds.
+pub trait CustomOp: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn forward(&self, inputs: &[f32]) -> f32;
+
+    // upstream times the partial derivative with respect to each input
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32>;
+
+    // Whether `inputs` are within `eps` of a point where the op is not
+    // differentiable, for `smoothness::analyze_smoothness`.
+    fn is_kink(&self, _inputs: &[f32], _eps: f32) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for dyn CustomOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CustomOp({})", self.name())
+    }
+}
+
+// `backward` by central differences of `op.forward`, for ops without an
+// analytic derivative; call it from `CustomOp::backward`. Each input is
+// perturbed by `step` relative to its magnitude (absolute below 1), so the
+// op is evaluated twice per input.
+pub fn finite_difference_backward<O: CustomOp + ?Sized>(
+    op: &O,
+    inputs: &[f32],
+    upstream: f32,
+    step: f32,
+) -> Vec<f32> {
+    let mut shifted = inputs.to_vec();
+    (0..inputs.len())
+        .map(|i| {
+            let x = inputs[i];
+            let h = step * x.abs().max(1f32);
+            shifted[i] = x + h;
+            let above = op.forward(&shifted);
+            shifted[i] = x - h;
+            let below = op.forward(&shifted);
+            // the perturbation that was actually representable
+            let width = (x + h) - (x - h);
+            shifted[i] = x;
+            upstream * (above - below) / width
+        })
+        .collect()
+}
+
+// A custom op from a plain function, differentiated by central differences.
+pub struct FiniteDifference<F> {
+    name: String,
+    function: F,
+    step: f32,
+}
+
+impl<F: Fn(&[f32]) -> f32 + Send + Sync> FiniteDifference<F> {
+    // the default relative step, about the cube root of f32 epsilon
+    pub const STEP: f32 = 5e-3;
+
+    pub fn new(name: &str, function: F) -> Self {
+        FiniteDifference {
+            name: name.to_string(),
+            function,
+            step: Self::STEP,
+        }
+    }
+
+    pub fn step(self, step: f32) -> Self {
+        FiniteDifference { step, ..self }
+    }
+}
+
+impl<F: Fn(&[f32]) -> f32 + Send + Sync> CustomOp for FiniteDifference<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        (self.function)(inputs)
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        finite_difference_backward(self, inputs, upstream, self.step)
+    }
+}
 
 #[derive(Debug)]
 pub enum NodeType<'a> {
@@ -16,13 +171,65 @@ pub enum NodeType<'a> {
     Pow(Node<'a>, f32),
     Sin(Node<'a>),
     Cos(Node<'a>),
+    // exp(x) - 1 and ln(1 + x), accurate for small x
+    ExpM1(Node<'a>),
+    Ln1p(Node<'a>),
+    StopGradient(Node<'a>),
+    Custom(Arc<dyn CustomOp>, Vec<Node<'a>>),
+    // the second operand if the first one is positive, otherwise the third
+    Select(Node<'a>, Node<'a>, Node<'a>),
+    // sum and product of any number of operands as a single node
+    AddN(Vec<Node<'a>>),
+    MulN(Vec<Node<'a>>),
+}
+
+// A name given to a node and where it was given, for diagnostics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub location: &'static Location<'static>,
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.location)
+    }
 }
 
 #[derive(Debug)]
 pub struct NodeData<'a> {
     type_: NodeType<'a>,
     value: Cell<f32>,
-    grads: RefCell<HashMap<String, f32>>,
+    grads: RefCell<GradMap>,
+    label: RefCell<Option<Label>>,
+    unit: Cell<Option<Dimension>>,
+}
+
+impl<'a> NodeType<'a> {
+    // the operation without its operands
+    pub(crate) fn op(&self) -> Op {
+        use NodeType::*;
+
+        match *self {
+            Const(v) => Op::Const(v),
+            Var(ref name) => Op::Var(name.clone()),
+            Neg(_) => Op::Neg,
+            Add(_, _) => Op::Add,
+            Sub(_, _) => Op::Sub,
+            Mul(_, _) => Op::Mul,
+            Div(_, _) => Op::Div,
+            Pow(_, rhs) => Op::Pow(rhs),
+            Sin(_) => Op::Sin,
+            Cos(_) => Op::Cos,
+            ExpM1(_) => Op::ExpM1,
+            Ln1p(_) => Op::Ln1p,
+            StopGradient(_) => Op::StopGradient,
+            Custom(ref op, _) => Op::Custom(op.clone()),
+            Select(_, _, _) => Op::Select,
+            AddN(_) => Op::AddN,
+            MulN(_) => Op::MulN,
+        }
+    }
 }
 
 impl<'a> std::convert::From<NodeType<'a>> for NodeData<'a> {
@@ -30,23 +237,124 @@ impl<'a> std::convert::From<NodeType<'a>> for NodeData<'a> {
         NodeData {
             type_,
             value: Cell::new(0f32), // bad idea
-            grads: RefCell::new(HashMap::new()),
+            grads: RefCell::new(GradMap::default()),
+            label: RefCell::new(None),
+            unit: Cell::new(None),
         }
     }
 }
 
 impl<'a> NodeData<'a> {
+    pub fn value(&self) -> f32 {
+        self.value.get()
+    }
+
+    pub fn label(&self) -> Option<Label> {
+        self.label.borrow().clone()
+    }
+
+    // the label if there is one, otherwise the kind of node
+    pub fn describe(&self) -> String {
+        match *self.label.borrow() {
+            Some(ref label) => format!("{} {}", self.type_.op().name(), label),
+            None => self.type_.op().name().to_string(),
+        }
+    }
+
+    // only meaningful for variables, other nodes overwrite it on forward
+    pub fn set_value(&self, value: f32) {
+        self.value.set(value)
+    }
+
+    pub fn var_name(&self) -> Option<&str> {
+        match self.type_ {
+            NodeType::Var(ref name) => Some(name),
+            _ => None,
+        }
+    }
+
+    // sets every variable below this node that `assignment` has a value for
+    pub fn assign(&self, assignment: &Assignment) {
+        for node in topological_order(&[self]) {
+            if let Some(&value) = node.var_name().and_then(|name| assignment.get(name)) {
+                node.value.set(value);
+            }
+        }
+    }
+
+    pub fn grads(&self) -> Ref<'_, GradMap> {
+        self.grads.borrow()
+    }
+
+    pub fn grad(&self, variable: &str) -> f32 {
+        self.grads.borrow().get(variable).unwrap_or(0f32)
+    }
+
+    // Reverse-mode gradient at the current variable values. Unlike
+    // `backward_ad`, this leaves the per-node gradients untouched.
+    pub fn gradients(&self, variables: &[&str]) -> Gradients {
+        let order = topological_order(&[self]);
+        let values = evaluate_all(&order);
+        let adjoints = adjoints(&order, &values, &[(self, 1f32)]);
+
+        variable_gradients(&order, &adjoints, variables)
+    }
+
+    pub fn children(&self) -> Vec<Node<'a>> {
+        use NodeType::*;
+
+        match self.type_ {
+            Const(_) | Var(_) => vec![],
+            Neg(value)
+            | Pow(value, _)
+            | Sin(value)
+            | Cos(value)
+            | ExpM1(value)
+            | Ln1p(value)
+            | StopGradient(value) => {
+                vec![value]
+            }
+            Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) | Div(lhs, rhs) => vec![lhs, rhs],
+            Custom(_, ref args) | AddN(ref args) | MulN(ref args) => args.clone(),
+            Select(cond, then, else_) => vec![cond, then, else_],
+        }
+    }
+
+    // value of this node given the values of its children
+    fn eval(&self, inputs: &[f32]) -> f32 {
+        match self.type_ {
+            NodeType::Var(_) => self.value.get(),
+            _ => self.type_.op().eval(inputs),
+        }
+    }
+
+    // partial derivatives with respect to each child given their values
+    fn partials(&self, inputs: &[f32]) -> Vec<f32> {
+        self.type_.op().partials(inputs)
+    }
+
     pub fn reset_grads(&self) {
         use NodeType::*;
 
         self.grads.borrow_mut().clear();
         match self.type_ {
-            Const(_) | Var(_) => {}
-            Neg(value) | Pow(value, _) | Sin(value) | Cos(value) => value.reset_grads(),
+            // backward_ad never fills the operand of StopGradient, and clearing
+            // it would wipe gradients of nodes shared with the rest of the graph
+            Const(_) | Var(_) | StopGradient(_) => {}
+            Neg(value) | Pow(value, _) | Sin(value) | Cos(value) | ExpM1(value) | Ln1p(value) => {
+                value.reset_grads()
+            }
             Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) | Div(lhs, rhs) => {
                 lhs.reset_grads();
                 rhs.reset_grads();
             }
+            Custom(_, ref args) | AddN(ref args) | MulN(ref args) => {
+                for arg in args {
+                    arg.reset_grads();
+                }
+            }
+            // like StopGradient, only the taken branch is filled by backward_ad
+            Select(cond, then, else_) => select_branch(cond, then, else_).reset_grads(),
         }
     }
 
@@ -100,10 +408,63 @@ impl<'a> NodeData<'a> {
 
                 value.value.get().cos()
             }
+            ExpM1(value) => {
+                value.forward();
+
+                value.value.get().exp_m1()
+            }
+            Ln1p(value) => {
+                value.forward();
+
+                value.value.get().ln_1p()
+            }
+            StopGradient(value) => {
+                value.forward();
+
+                value.value.get()
+            }
+            Custom(ref op, ref args) => {
+                for arg in args {
+                    arg.forward();
+                }
+
+                op.forward(&values(args))
+            }
+            Select(cond, then, else_) => {
+                cond.forward();
+                let branch = select_branch(cond, then, else_);
+                branch.forward();
+
+                branch.value.get()
+            }
+            AddN(ref args) => {
+                for arg in args {
+                    arg.forward();
+                }
+
+                values(args).iter().sum()
+            }
+            MulN(ref args) => {
+                for arg in args {
+                    arg.forward();
+                }
+
+                values(args).iter().product()
+            }
         })
     }
 
     pub fn backward_ad(&self, variables: &[&str]) {
+        // shared by the inline gradients of every node
+        let names: Option<Rc<[String]>> = if variables.len() <= grad_map::INLINE {
+            Some(variables.iter().map(|v| v.to_string()).collect())
+        } else {
+            None
+        };
+        self.backward_with(variables, names.as_ref());
+    }
+
+    fn backward_with(&self, variables: &[&str], names: Option<&Rc<[String]>>) {
         use NodeType::*;
 
         self.reset_grads();
@@ -112,74 +473,69 @@ impl<'a> NodeData<'a> {
         let mut grads = self.grads.borrow_mut();
 
         // we already set gradients
-        if grads.len() != 0 {
+        if !grads.is_empty() {
             return;
         }
+        *grads = GradMap::for_names(names);
 
         // we may be able to denote grad of x is 0 by just leaving grads[x] empty
         match self.type_ {
             Const(_) => {
                 for v in variables {
-                    grads.insert(v.to_string(), 0f32);
+                    grads.insert(v, 0f32);
                 }
             }
             Var(ref this) => {
                 for v in variables {
                     if this == v {
-                        grads.insert(v.to_string(), 1f32);
+                        grads.insert(v, 1f32);
                     } else {
-                        grads.insert(v.to_string(), 0f32);
+                        grads.insert(v, 0f32);
                     }
                 }
             }
             Neg(value) => {
-                value.backward_ad(variables);
+                value.backward_with(variables, names);
 
                 for v in variables {
-                    grads.insert(v.to_string(), -value.grads.borrow()[*v]);
+                    grads.insert(v, -value.grads.borrow()[*v]);
                 }
             }
             Add(lhs, rhs) => {
-                lhs.backward_ad(variables);
-                rhs.backward_ad(variables);
+                lhs.backward_with(variables, names);
+                rhs.backward_with(variables, names);
 
                 for v in variables {
-                    grads.insert(
-                        v.to_string(),
-                        lhs.grads.borrow()[*v] + rhs.grads.borrow()[*v],
-                    );
+                    grads.insert(v, lhs.grads.borrow()[*v] + rhs.grads.borrow()[*v]);
                 }
             }
             Sub(lhs, rhs) => {
-                lhs.backward_ad(variables);
-                rhs.backward_ad(variables);
+                lhs.backward_with(variables, names);
+                rhs.backward_with(variables, names);
 
                 for v in variables {
-                    grads.insert(
-                        v.to_string(),
-                        lhs.grads.borrow()[*v] - rhs.grads.borrow()[*v],
-                    );
+                    grads.insert(v, lhs.grads.borrow()[*v] - rhs.grads.borrow()[*v]);
                 }
             }
             Mul(lhs, rhs) => {
-                lhs.backward_ad(variables);
-                rhs.backward_ad(variables);
+                lhs.backward_with(variables, names);
+                rhs.backward_with(variables, names);
 
                 for v in variables {
                     grads.insert(
-                        v.to_string(),
+                        v,
                         lhs.grads.borrow()[*v] * rhs.value.get()
                             + lhs.value.get() * rhs.grads.borrow()[*v],
                     );
                 }
             }
             Div(lhs, rhs) => {
-                lhs.backward_ad(variables);
-                rhs.backward_ad(variables);
+                lhs.backward_with(variables, names);
+                rhs.backward_with(variables, names);
 
                 for v in variables {
                     grads.insert(
-                        v.to_string(),
+                        v,
                         (lhs.grads.borrow()[*v] * rhs.value.get()
                             - lhs.value.get() * rhs.grads.borrow()[*v])
                             / (rhs.value.get().powf(2f32)),
@@ -187,126 +543,1438 @@ impl<'a> NodeData<'a> {
                 }
             }
             Pow(lhs, rhs) => {
-                lhs.backward_ad(variables);
+                lhs.backward_with(variables, names);
 
                 for v in variables {
                     grads.insert(
-                        v.to_string(),
-                        rhs * self.value.get().powf(rhs - 1f32) * lhs.grads.borrow()[*v],
+                        v,
+                        rhs * lhs.value.get().powf(rhs - 1f32) * lhs.grads.borrow()[*v],
                     );
                 }
             }
             Sin(value) => {
-                value.backward_ad(variables);
+                value.backward_with(variables, names);
 
                 for v in variables {
-                    grads.insert(
-                        v.to_string(),
-                        self.value.get().cos() * value.grads.borrow()[*v],
-                    );
+                    grads.insert(v, value.value.get().cos() * value.grads.borrow()[*v]);
                 }
             }
             Cos(value) => {
-                value.backward_ad(variables);
+                value.backward_with(variables, names);
+
+                for v in variables {
+                    grads.insert(v, -value.value.get().sin() * value.grads.borrow()[*v]);
+                }
+            }
+            ExpM1(value) => {
+                value.backward_with(variables, names);
+
+                for v in variables {
+                    grads.insert(v, value.value.get().exp() * value.grads.borrow()[*v]);
+                }
+            }
+            Ln1p(value) => {
+                value.backward_with(variables, names);
+
+                for v in variables {
+                    grads.insert(v, value.grads.borrow()[*v] / (1f32 + value.value.get()));
+                }
+            }
+            // the operand is treated as a constant, so there is nothing to recurse into
+            StopGradient(_) => {
+                for v in variables {
+                    grads.insert(v, 0f32);
+                }
+            }
+            Custom(_, ref args) | AddN(ref args) | MulN(ref args) => {
+                for arg in args {
+                    arg.backward_with(variables, names);
+                }
 
+                let partials = self.partials(&values(args));
                 for v in variables {
                     grads.insert(
-                        v.to_string(),
-                        -self.value.get().sin() * value.grads.borrow()[*v],
+                        v,
+                        args.iter()
+                            .zip(&partials)
+                            .map(|(arg, partial)| partial * arg.grads.borrow()[*v])
+                            .sum(),
                     );
                 }
             }
+            // the condition only routes the gradient, it does not receive any
+            Select(cond, then, else_) => {
+                let branch = select_branch(cond, then, else_);
+                branch.backward_with(variables, names);
+
+                for v in variables {
+                    grads.insert(v, branch.grads.borrow()[*v]);
+                }
+            }
+        }
+    }
+
+    // Hessian with respect to `variables` at the values of the last `forward`
+    pub fn hessian(&self, variables: &[&str]) -> Vec<Vec<f32>> {
+        self.second_order(variables).1
+    }
+
+    // gradient and Hessian of every node below this one, children first, so
+    // that shared subexpressions are differentiated once
+    fn second_order(&self, variables: &[&str]) -> (Vec<f32>, Vec<Vec<f32>>) {
+        let mut memo: HashMap<*const NodeData, (Vec<f32>, Vec<Vec<f32>>)> = HashMap::new();
+        for node in topological_order(&[self]) {
+            let local = node.local_second_order(variables, &|child| memo[&node_key(child)].clone());
+            memo.insert(node_key(node), local);
+        }
+        memo.remove(&node_key(self)).unwrap()
+    }
+
+    // from the gradients and Hessians of the children, given by `of`
+    fn local_second_order(
+        &self,
+        variables: &[&str],
+        of: &dyn Fn(Node<'a>) -> (Vec<f32>, Vec<Vec<f32>>),
+    ) -> (Vec<f32>, Vec<Vec<f32>>) {
+        use NodeType::*;
+
+        let n = variables.len();
+        match self.type_ {
+            Const(_) | StopGradient(_) => (vec![0f32; n], vec![vec![0f32; n]; n]),
+            Var(ref this) => (
+                variables
+                    .iter()
+                    .map(|v| if this == v { 1f32 } else { 0f32 })
+                    .collect(),
+                vec![vec![0f32; n]; n],
+            ),
+            Neg(value) => chain_unary(of(value), -1f32, 0f32),
+            Add(lhs, rhs) => chain_binary(of(lhs), of(rhs), [1f32, 1f32], [0f32, 0f32, 0f32]),
+            Sub(lhs, rhs) => chain_binary(of(lhs), of(rhs), [1f32, -1f32], [0f32, 0f32, 0f32]),
+            Mul(lhs, rhs) => chain_binary(
+                of(lhs),
+                of(rhs),
+                [rhs.value.get(), lhs.value.get()],
+                [0f32, 1f32, 0f32],
+            ),
+            Div(lhs, rhs) => {
+                let (u, v) = (lhs.value.get(), rhs.value.get());
+                chain_binary(
+                    of(lhs),
+                    of(rhs),
+                    [1f32 / v, -u / v.powi(2)],
+                    [0f32, -1f32 / v.powi(2), 2f32 * u / v.powi(3)],
+                )
+            }
+            Pow(lhs, rhs) => {
+                let u = lhs.value.get();
+                chain_unary(
+                    of(lhs),
+                    rhs * u.powf(rhs - 1f32),
+                    rhs * (rhs - 1f32) * u.powf(rhs - 2f32),
+                )
+            }
+            Sin(value) => {
+                let u = value.value.get();
+                chain_unary(of(value), u.cos(), -u.sin())
+            }
+            Cos(value) => {
+                let u = value.value.get();
+                chain_unary(of(value), -u.sin(), -u.cos())
+            }
+            ExpM1(value) => {
+                let e = value.value.get().exp();
+                chain_unary(of(value), e, e)
+            }
+            Ln1p(value) => {
+                let r = 1f32 / (1f32 + value.value.get());
+                chain_unary(of(value), r, -r * r)
+            }
+            Custom(ref op, ref args) => {
+                let inputs = values(args);
+                let children = args.iter().map(|&arg| of(arg)).collect();
+                chain_nary(
+                    children,
+                    &op.backward(&inputs, 1f32),
+                    &custom_second_partials(&**op, &inputs),
+                )
+            }
+            Select(cond, then, else_) => of(select_branch(cond, then, else_)),
+            AddN(ref args) => {
+                let children = args.iter().map(|&arg| of(arg)).collect();
+                chain_nary(
+                    children,
+                    &vec![1f32; args.len()],
+                    &vec![vec![0f32; args.len()]; args.len()],
+                )
+            }
+            MulN(ref args) => {
+                let inputs = values(args);
+                let children = args.iter().map(|&arg| of(arg)).collect();
+                chain_nary(
+                    children,
+                    &self.partials(&inputs),
+                    &product_second_partials(&inputs),
+                )
+            }
         }
     }
 }
 
-type Node<'a> = &'a NodeData<'a>;
-type Arena<'a> = typed_arena::Arena<NodeData<'a>>;
+// branch taken by the last forward pass
+fn select_branch<'a>(cond: Node<'a>, then: Node<'a>, else_: Node<'a>) -> Node<'a> {
+    if cond.value.get() > 0f32 {
+        then
+    } else {
+        else_
+    }
+}
 
-pub fn constant<'a>(arena: &'a Arena<'a>, value: f32) -> Node<'a> {
-    arena.alloc(NodeType::Const(value).into())
+fn values(nodes: &[Node]) -> Vec<f32> {
+    nodes.iter().map(|node| node.value.get()).collect()
 }
 
-pub fn var<'a>(arena: &'a Arena<'a>, name: String) -> Node<'a> {
-    arena.alloc(NodeType::Var(name).into())
+fn node_key(node: Node) -> *const NodeData {
+    node as *const NodeData
 }
 
-pub fn add<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
-    arena.alloc(NodeType::Add(lhs, rhs).into())
+// Values of every node in `order` (as returned by `topological_order`),
+// reading variables from their cells.
+fn evaluate_all<'a>(order: &[Node<'a>]) -> HashMap<*const NodeData<'a>, f32> {
+    evaluate_observed(order, &mut ())
 }
 
-pub fn sub<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
-    arena.alloc(NodeType::Sub(lhs, rhs).into())
+fn evaluate_observed<'a>(
+    order: &[Node<'a>],
+    observer: &mut dyn Observer,
+) -> HashMap<*const NodeData<'a>, f32> {
+    let mut values = HashMap::with_capacity(order.len());
+    for &node in order {
+        let inputs: Vec<f32> = node
+            .children()
+            .iter()
+            .map(|child| values[&node_key(child)])
+            .collect();
+        observer.enter(node);
+        let value = node.eval(&inputs);
+        observer.forward(node, &inputs, value);
+        values.insert(node_key(node), value);
+    }
+    values
 }
 
-pub fn mul<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
-    arena.alloc(NodeType::Mul(lhs, rhs).into())
+// Like `evaluate_all`, but variables take their values from `assignment`
+// instead of their cells.
+fn evaluate_at<'a>(
+    order: &[Node<'a>],
+    assignment: &Assignment,
+) -> HashMap<*const NodeData<'a>, f32> {
+    let mut values = HashMap::with_capacity(order.len());
+    for &node in order {
+        let value = match node.type_ {
+            NodeType::Var(ref name) => assignment.lookup(name).unwrap_or_else(|e| panic!("{}", e)),
+            _ => {
+                let inputs: Vec<f32> = node
+                    .children()
+                    .iter()
+                    .map(|child| values[&node_key(child)])
+                    .collect();
+                node.eval(&inputs)
+            }
+        };
+        values.insert(node_key(node), value);
+    }
+    values
 }
 
-pub fn div<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
-    arena.alloc(NodeType::Div(lhs, rhs).into())
+// Sums the adjoints of the variable nodes by name.
+fn variable_gradients<'a>(
+    order: &[Node<'a>],
+    adjoints: &HashMap<*const NodeData<'a>, f32>,
+    variables: &[&str],
+) -> Gradients {
+    let mut grads = vec![0f32; variables.len()];
+    for node in order {
+        if let NodeType::Var(ref name) = node.type_ {
+            let adjoint = adjoints.get(&node_key(node)).cloned().unwrap_or(0f32);
+            for (grad, variable) in grads.iter_mut().zip(variables) {
+                if name == variable {
+                    *grad += adjoint;
+                }
+            }
+        }
+    }
+
+    Gradients::new(variables, grads)
 }
 
-pub fn pow<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: f32) -> Node<'a> {
-    arena.alloc(NodeType::Pow(lhs, rhs).into())
+// Reverse sweep over `order` starting from the given output adjoints.
+// Returns the adjoint of every node that received one.
+fn adjoints<'a>(
+    order: &[Node<'a>],
+    values: &HashMap<*const NodeData<'a>, f32>,
+    seeds: &[(Node<'a>, f32)],
+) -> HashMap<*const NodeData<'a>, f32> {
+    adjoints_observed(order, values, seeds, &mut ())
 }
 
-pub fn sin<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
-    arena.alloc(NodeType::Sin(value).into())
+fn adjoints_observed<'a>(
+    order: &[Node<'a>],
+    values: &HashMap<*const NodeData<'a>, f32>,
+    seeds: &[(Node<'a>, f32)],
+    observer: &mut dyn Observer,
+) -> HashMap<*const NodeData<'a>, f32> {
+    let mut adjoints = HashMap::new();
+    for &(node, seed) in seeds {
+        *adjoints.entry(node_key(node)).or_insert(0f32) += seed;
+    }
+
+    for node in order.iter().rev() {
+        let adjoint = match adjoints.get(&node_key(node)) {
+            Some(&adjoint) => adjoint,
+            None => continue,
+        };
+        observer.enter(node);
+        let children = node.children();
+        let inputs: Vec<f32> = children
+            .iter()
+            .map(|child| values[&node_key(child)])
+            .collect();
+        let partials = node.partials(&inputs);
+        observer.backward(node, &inputs, adjoint);
+        for (child, partial) in children.iter().zip(partials) {
+            *adjoints.entry(node_key(child)).or_insert(0f32) += adjoint * partial;
+        }
+    }
+
+    adjoints
 }
 
-pub fn cos<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
-    arena.alloc(NodeType::Cos(value).into())
+// Every node reachable from `roots` exactly once, children before parents.
+fn topological_order<'a>(roots: &[Node<'a>]) -> Vec<Node<'a>> {
+    let mut order = vec![];
+    let mut visited = HashSet::new();
+    // explicit stack so deep graphs do not overflow
+    let mut stack: Vec<(Node<'a>, bool)> = roots.iter().rev().map(|&root| (root, false)).collect();
+
+    while let Some((node, expanded)) = stack.pop() {
+        if expanded {
+            order.push(node);
+        } else if visited.insert(node_key(node)) {
+            stack.push((node, true));
+            for child in node.children().into_iter().rev() {
+                if !visited.contains(&node_key(child)) {
+                    stack.push((child, false));
+                }
+            }
+        }
+    }
+
+    order
 }
 
-#[test]
-fn basic_forward() {
-    let arena = Arena::new();
-    let arena = &arena;
+// f(u) with f' = d1 and f'' = d2
+fn chain_unary(
+    (grad, hess): (Vec<f32>, Vec<Vec<f32>>),
+    d1: f32,
+    d2: f32,
+) -> (Vec<f32>, Vec<Vec<f32>>) {
+    let hess = hess
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(j, h)| d2 * grad[i] * grad[j] + d1 * h)
+                .collect()
+        })
+        .collect();
+    let grad = grad.iter().map(|g| d1 * g).collect();
 
-    let x = var(arena, "x".to_string());
-    let y = var(arena, "y".to_string());
+    (grad, hess)
+}
 
-    let mul = mul(arena, x, y);
-    let div = div(arena, x, y);
-    let add = add(arena, mul, div);
-    let sub = sub(arena, mul, div);
+// f(u, v) with first partials [f_u, f_v] and second partials [f_uu, f_uv, f_vv]
+fn chain_binary(
+    (lgrad, lhess): (Vec<f32>, Vec<Vec<f32>>),
+    (rgrad, rhess): (Vec<f32>, Vec<Vec<f32>>),
+    [fu, fv]: [f32; 2],
+    [fuu, fuv, fvv]: [f32; 3],
+) -> (Vec<f32>, Vec<Vec<f32>>) {
+    let n = lgrad.len();
+    let hess = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    fu * lhess[i][j]
+                        + fv * rhess[i][j]
+                        + fuu * lgrad[i] * lgrad[j]
+                        + fuv * (lgrad[i] * rgrad[j] + rgrad[i] * lgrad[j])
+                        + fvv * rgrad[i] * rgrad[j]
+                })
+                .collect()
+        })
+        .collect();
+    let grad = lgrad
+        .iter()
+        .zip(&rgrad)
+        .map(|(l, r)| fu * l + fv * r)
+        .collect();
 
-    x.value.set(8f32);
-    y.value.set(4f32);
+    (grad, hess)
+}
 
-    add.forward();
-    sub.forward();
+pub type Node<'a> = &'a NodeData<'a>;
+pub type Arena<'a> = typed_arena::Arena<NodeData<'a>>;
 
-    assert_eq!(add.value.get(), 34f32);
-    assert_eq!(sub.value.get(), 30f32);
+// Rows are outputs and columns are variables, at the current variable values.
+pub fn jacobian(outputs: &[Node], variables: &[&str]) -> Vec<Vec<f32>> {
+    outputs
+        .iter()
+        .map(|output| {
+            output.forward();
+            output.backward_ad(variables);
+            variables.iter().map(|v| output.grad(v)).collect()
+        })
+        .collect()
 }
 
-#[test]
-fn basic_backward_ad() {
-    let arena = Arena::new();
-    let arena = &arena;
+// f(u_1, ..., u_k) with first partials f_i and second partials f_ij
+fn chain_nary(
+    children: Vec<(Vec<f32>, Vec<Vec<f32>>)>,
+    partials: &[f32],
+    second: &[Vec<f32>],
+) -> (Vec<f32>, Vec<Vec<f32>>) {
+    let n = children.first().map(|(grad, _)| grad.len()).unwrap_or(0);
+    let mut grad = vec![0f32; n];
+    let mut hess = vec![vec![0f32; n]; n];
 
-    let x = var(arena, "x".to_string());
-    let y = var(arena, "y".to_string());
+    for (i, (ref ci_grad, ref ci_hess)) in children.iter().enumerate() {
+        for a in 0..n {
+            grad[a] += partials[i] * ci_grad[a];
+            for b in 0..n {
+                hess[a][b] += partials[i] * ci_hess[a][b];
+            }
+        }
+        for (j, (ref cj_grad, _)) in children.iter().enumerate() {
+            for a in 0..n {
+                for b in 0..n {
+                    hess[a][b] += second[i][j] * ci_grad[a] * cj_grad[b];
+                }
+            }
+        }
+    }
 
-    let mul = mul(arena, x, y);
-    let div = div(arena, x, y);
-    let add = add(arena, mul, div);
-    let sub = sub(arena, mul, div);
+    (grad, hess)
+}
 
-    x.value.set(8f32);
-    y.value.set(4f32);
+// d^2 (x_1 * ... * x_k) / dx_i dx_j is the product of all the other inputs
+fn product_second_partials(inputs: &[f32]) -> Vec<Vec<f32>> {
+    let k = inputs.len();
+    (0..k)
+        .map(|i| {
+            (0..k)
+                .map(|j| {
+                    if i == j {
+                        0f32
+                    } else {
+                        (0..k)
+                            .filter(|&l| l != i && l != j)
+                            .map(|l| inputs[l])
+                            .product()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
 
-    add.forward();
-    sub.forward();
+// custom ops only provide first derivatives, so difference those
+fn custom_second_partials(op: &dyn CustomOp, inputs: &[f32]) -> Vec<Vec<f32>> {
+    let k = inputs.len();
+    let mut second = vec![vec![0f32; k]; k];
 
-    add.backward_ad(&["x", "y"]);
-    sub.backward_ad(&["x", "y"]);
+    for j in 0..k {
+        let h = 5e-3 * inputs[j].abs().max(1f32);
+        let mut shifted = inputs.to_vec();
+        shifted[j] = inputs[j] + h;
+        let plus = op.backward(&shifted, 1f32);
+        shifted[j] = inputs[j] - h;
+        let minus = op.backward(&shifted, 1f32);
 
-    assert_eq!(add.grads.borrow()["x"], 4.25);
-    assert_eq!(add.grads.borrow()["y"], 7.5);
+        for (i, row) in second.iter_mut().enumerate() {
+            row[j] = (plus[i] - minus[i]) / (2f32 * h);
+        }
+    }
 
-    assert_eq!(sub.grads.borrow()["x"], 3.75);
-    assert_eq!(sub.grads.borrow()["y"], 8.5);
+    second
+}
+
+// Value and gradient of `node` at `assignment`. All intermediate state is
+// local to the call: neither values nor gradients are written to the nodes,
+// so interleaved calls on the same graph do not disturb each other.
+pub fn eval_with_grad(node: Node, assignment: &Assignment, variables: &[&str]) -> (f32, Gradients) {
+    let order = topological_order(&[node]);
+    let values = evaluate_at(&order, assignment);
+    let adjoints = adjoints(&order, &values, &[(node, 1f32)]);
+
+    (
+        values[&node_key(node)],
+        variable_gradients(&order, &adjoints, variables),
+    )
+}
+
+// Like `eval_with_grad`, but reports the first unassigned variable instead
+// of panicking.
+pub fn try_eval_with_grad(
+    node: Node,
+    assignment: &Assignment,
+    variables: &[&str],
+) -> Result<(f32, Gradients), MissingVariable> {
+    for n in topological_order(&[node]) {
+        if let Some(name) = n.var_name() {
+            assignment.lookup(name)?;
+        }
+    }
+    Ok(eval_with_grad(node, assignment, variables))
+}
+
+// Values of several roots at `assignment`. Nodes shared between roots are
+// evaluated once, and like `eval_with_grad` nothing is written to the nodes.
+pub fn forward_multi(roots: &[Node], assignment: &Assignment) -> Vec<f32> {
+    let order = topological_order(roots);
+    let values = evaluate_at(&order, assignment);
+
+    roots.iter().map(|root| values[&node_key(root)]).collect()
+}
+
+// Value of `node` at the means of `inputs`, given as (mean, standard
+// deviation) per variable, and its standard deviation to first order,
+// treating the inputs as independent: sigma_f^2 = sum (df/dx_i)^2 sigma_i^2.
+pub fn forward_uncertain(node: Node, inputs: &HashMap<String, (f32, f32)>) -> (f32, f32) {
+    let means: Assignment = inputs
+        .iter()
+        .map(|(name, &(mean, _))| (name.clone(), mean))
+        .collect();
+    let names: Vec<&str> = inputs.keys().map(|name| name.as_str()).collect();
+    let (value, grads) = eval_with_grad(node, &means, &names);
+    let variance: f32 = grads
+        .iter()
+        .map(|(name, grad)| (grad * inputs[name].1).powi(2))
+        .sum();
+
+    (value, variance.sqrt())
+}
+
+// Derivatives of `output` with respect to the values of arbitrary nodes in
+// its graph, at the current variable values. A target that `output` does
+// not depend on gets 0.
+pub fn grad_wrt(output: Node, targets: &[Node]) -> Vec<f32> {
+    let order = topological_order(&[output]);
+    let values = evaluate_all(&order);
+    let adjoints = adjoints(&order, &values, &[(output, 1f32)]);
+
+    targets
+        .iter()
+        .map(|target| adjoints.get(&node_key(target)).cloned().unwrap_or(0f32))
+        .collect()
+}
+
+// Gradient of the weighted sum of `seeds` outputs, seed times output, by a
+// single reverse sweep at the current variable values. The sum itself is
+// never built.
+pub fn backward_seeded(seeds: &[(Node, f32)], variables: &[&str]) -> Gradients {
+    let outputs: Vec<Node> = seeds.iter().map(|&(output, _)| output).collect();
+    let order = topological_order(&outputs);
+    let values = evaluate_all(&order);
+    let adjoints = adjoints(&order, &values, seeds);
+
+    variable_gradients(&order, &adjoints, variables)
+}
+
+// The weighted sum of the `terms` outputs and its gradient, at the current
+// variable values. Changing the weights needs no new graph.
+pub fn grad_weighted(terms: &[(Node, f32)], variables: &[&str]) -> (f32, Gradients) {
+    let outputs: Vec<Node> = terms.iter().map(|&(output, _)| output).collect();
+    let order = topological_order(&outputs);
+    let values = evaluate_all(&order);
+    let adjoints = adjoints(&order, &values, terms);
+
+    let value = terms
+        .iter()
+        .map(|&(output, weight)| weight * values[&node_key(output)])
+        .sum();
+    (value, variable_gradients(&order, &adjoints, variables))
+}
+
+// Gradients of several outputs in one traversal of their shared graph, at
+// the current variable values. Unlike calling `backward_ad` on every output,
+// each node's gradient is computed once.
+pub fn backward_multi(outputs: &[Node], variables: &[&str]) -> Vec<Gradients> {
+    let order = topological_order(outputs);
+    let values = evaluate_all(&order);
+    let mut grads: HashMap<*const NodeData, Vec<f32>> = HashMap::with_capacity(order.len());
+
+    for &node in &order {
+        let grad = match node.type_ {
+            NodeType::Var(ref this) => variables
+                .iter()
+                .map(|v| if this == v { 1f32 } else { 0f32 })
+                .collect(),
+            _ => {
+                let children = node.children();
+                let inputs: Vec<f32> = children
+                    .iter()
+                    .map(|child| values[&node_key(child)])
+                    .collect();
+                let mut grad = vec![0f32; variables.len()];
+                for (child, partial) in children.iter().zip(node.partials(&inputs)) {
+                    for (g, c) in grad.iter_mut().zip(&grads[&node_key(child)]) {
+                        *g += partial * c;
+                    }
+                }
+                grad
+            }
+        };
+        grads.insert(node_key(node), grad);
+    }
+
+    outputs
+        .iter()
+        .map(|output| Gradients::new(variables, grads[&node_key(output)].clone()))
+        .collect()
+}
+
+pub fn constant<'a>(arena: &'a Arena<'a>, value: f32) -> Node<'a> {
+    arena.alloc(NodeType::Const(value).into())
+}
+
+pub fn var<'a>(arena: &'a Arena<'a>, name: String) -> Node<'a> {
+    arena.alloc(NodeType::Var(name).into())
+}
+
+pub fn neg<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Neg(value).into())
+}
+
+pub fn add<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Add(lhs, rhs).into())
+}
+
+pub fn sub<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Sub(lhs, rhs).into())
+}
+
+pub fn mul<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Mul(lhs, rhs).into())
+}
+
+pub fn div<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Div(lhs, rhs).into())
+}
+
+pub fn pow<'a>(arena: &'a Arena<'a>, lhs: Node<'a>, rhs: f32) -> Node<'a> {
+    arena.alloc(NodeType::Pow(lhs, rhs).into())
+}
+
+// Sum of `terms` as a balanced tree, so the depth grows logarithmically.
+// The empty sum is 0.
+pub fn sum<'a>(arena: &'a Arena<'a>, terms: &[Node<'a>]) -> Node<'a> {
+    reduce(arena, terms, add, 0f32)
+}
+
+// Product of `factors` as a balanced tree. The empty product is 1.
+pub fn prod<'a>(arena: &'a Arena<'a>, factors: &[Node<'a>]) -> Node<'a> {
+    reduce(arena, factors, mul, 1f32)
+}
+
+// Sum of the pairwise products, summed as a balanced tree.
+pub fn dot<'a>(arena: &'a Arena<'a>, lhs: &[Node<'a>], rhs: &[Node<'a>]) -> Node<'a> {
+    assert_eq!(lhs.len(), rhs.len(), "dot of slices of different lengths");
+    let products: Vec<Node<'a>> = lhs
+        .iter()
+        .zip(rhs)
+        .map(|(&l, &r)| mul(arena, l, r))
+        .collect();
+    sum(arena, &products)
+}
+
+// sum of weights[i] * inputs[i] plus bias. Zero weights and a zero bias add
+// no nodes, and unit weights add no multiplication.
+pub fn linear<'a>(
+    arena: &'a Arena<'a>,
+    inputs: &[Node<'a>],
+    weights: &[f32],
+    bias: f32,
+) -> Node<'a> {
+    assert_eq!(inputs.len(), weights.len(), "one weight per input");
+    let mut terms: Vec<Node<'a>> = inputs
+        .iter()
+        .zip(weights)
+        .filter(|&(_, &w)| w != 0f32)
+        .map(|(&input, &w)| {
+            if w == 1f32 {
+                input
+            } else {
+                mul(arena, constant(arena, w), input)
+            }
+        })
+        .collect();
+    if bias != 0f32 {
+        terms.push(constant(arena, bias));
+    }
+    sum(arena, &terms)
+}
+
+// coeffs[0] + coeffs[1] * x + coeffs[2] * x^2 + ... in Horner form.
+pub fn polynomial<'a>(arena: &'a Arena<'a>, x: Node<'a>, coeffs: &[f32]) -> Node<'a> {
+    let coeffs: Vec<Node<'a>> = coeffs.iter().map(|&c| constant(arena, c)).collect();
+    polynomial_nodes(arena, x, &coeffs)
+}
+
+// Like `polynomial`, with coefficients that are nodes themselves, so that
+// they can be fitted.
+pub fn polynomial_nodes<'a>(arena: &'a Arena<'a>, x: Node<'a>, coeffs: &[Node<'a>]) -> Node<'a> {
+    let mut coeffs = coeffs.iter().rev();
+    let mut poly = match coeffs.next() {
+        Some(&leading) => leading,
+        None => return constant(arena, 0f32),
+    };
+    for &coeff in coeffs {
+        poly = add(arena, coeff, mul(arena, x, poly));
+    }
+    poly
+}
+
+// Sum of `terms` as one node. Unlike `sum`, the graph depth does not grow
+// with the number of terms.
+pub fn add_n<'a>(arena: &'a Arena<'a>, terms: &[Node<'a>]) -> Node<'a> {
+    arena.alloc(NodeType::AddN(terms.to_vec()).into())
+}
+
+// Product of `factors` as one node.
+pub fn mul_n<'a>(arena: &'a Arena<'a>, factors: &[Node<'a>]) -> Node<'a> {
+    arena.alloc(NodeType::MulN(factors.to_vec()).into())
+}
+
+// a + t (b - a)
+pub fn lerp<'a>(arena: &'a Arena<'a>, a: Node<'a>, b: Node<'a>, t: Node<'a>) -> Node<'a> {
+    add(arena, a, mul(arena, t, sub(arena, b, a)))
+}
+
+// 3 s^2 - 2 s^3 for s = (x - edge0) / (edge1 - edge0) clamped to [0, 1].
+// Outside the edges the gradient is zero with respect to every argument.
+pub fn smoothstep<'a>(
+    arena: &'a Arena<'a>,
+    edge0: Node<'a>,
+    edge1: Node<'a>,
+    x: Node<'a>,
+) -> Node<'a> {
+    let s = div(arena, sub(arena, x, edge0), sub(arena, edge1, edge0));
+    let zero = constant(arena, 0f32);
+    let one = constant(arena, 1f32);
+    let s = select(arena, s, s, zero);
+    let s = select(arena, sub(arena, one, s), s, one);
+    polynomial_nodes(
+        arena,
+        s,
+        &[zero, zero, constant(arena, 3f32), constant(arena, -2f32)],
+    )
+}
+
+fn reduce<'a>(
+    arena: &'a Arena<'a>,
+    nodes: &[Node<'a>],
+    op: fn(&'a Arena<'a>, Node<'a>, Node<'a>) -> Node<'a>,
+    identity: f32,
+) -> Node<'a> {
+    match nodes.len() {
+        0 => constant(arena, identity),
+        1 => nodes[0],
+        n => {
+            let (lhs, rhs) = nodes.split_at(n / 2);
+            op(
+                arena,
+                reduce(arena, lhs, op, identity),
+                reduce(arena, rhs, op, identity),
+            )
+        }
+    }
+}
+
+pub fn sin<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Sin(value).into())
+}
+
+pub fn cos<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Cos(value).into())
+}
+
+pub fn exp_m1<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::ExpM1(value).into())
+}
+
+pub fn ln_1p<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::Ln1p(value).into())
+}
+
+pub fn custom<'a, O: CustomOp + 'static>(
+    arena: &'a Arena<'a>,
+    op: O,
+    args: &[Node<'a>],
+) -> Node<'a> {
+    arena.alloc(NodeType::Custom(Arc::new(op), args.to_vec()).into())
+}
+
+// `then` where `cond` is positive, `else_` elsewhere
+pub fn select<'a>(
+    arena: &'a Arena<'a>,
+    cond: Node<'a>,
+    then: Node<'a>,
+    else_: Node<'a>,
+) -> Node<'a> {
+    arena.alloc(NodeType::Select(cond, then, else_).into())
+}
+
+// The element of `values` at `index`, rounded to the nearest integer and
+// clamped into range. Only the selected element receives gradient. A
+// constant index picks the element directly; otherwise the choice is a
+// balanced tree of selects, so it works everywhere a select does.
+pub fn gather<'a>(arena: &'a Arena<'a>, values: &[Node<'a>], index: Node<'a>) -> Node<'a> {
+    assert!(!values.is_empty(), "gather from no values");
+    if let NodeType::Const(index) = index.type_ {
+        let i = index.round().max(0f32).min((values.len() - 1) as f32);
+        return values[i as usize];
+    }
+
+    // `values` starts at position `first`
+    fn pick<'a>(
+        arena: &'a Arena<'a>,
+        values: &[Node<'a>],
+        first: usize,
+        index: Node<'a>,
+    ) -> Node<'a> {
+        if values.len() == 1 {
+            return values[0];
+        }
+        let mid = values.len() / 2;
+        let boundary = constant(arena, (first + mid) as f32 - 0.5);
+        select(
+            arena,
+            sub(arena, index, boundary),
+            pick(arena, &values[mid..], first + mid, index),
+            pick(arena, &values[..mid], first, index),
+        )
+    }
+    pick(arena, values, 0, index)
+}
+
+// Names `node` for Debug output and error messages, recording the caller's
+// source location. Returns the same node.
+#[track_caller]
+pub fn labelled<'a>(node: Node<'a>, name: &str) -> Node<'a> {
+    *node.label.borrow_mut() = Some(Label {
+        name: name.to_string(),
+        location: Location::caller(),
+    });
+    node
+}
+
+// passes the value through but blocks gradients flowing into `value`
+pub fn stop_gradient<'a>(arena: &'a Arena<'a>, value: Node<'a>) -> Node<'a> {
+    arena.alloc(NodeType::StopGradient(value).into())
+}
+
+#[test]
+fn basic_forward() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    let mul = mul(arena, x, y);
+    let div = div(arena, x, y);
+    let add = add(arena, mul, div);
+    let sub = sub(arena, mul, div);
+
+    x.value.set(8f32);
+    y.value.set(4f32);
+
+    add.forward();
+    sub.forward();
+
+    assert_eq!(add.value.get(), 34f32);
+    assert_eq!(sub.value.get(), 30f32);
+}
+
+#[test]
+fn basic_backward_ad() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    let mul = mul(arena, x, y);
+    let div = div(arena, x, y);
+    let add = add(arena, mul, div);
+    let sub = sub(arena, mul, div);
+
+    x.value.set(8f32);
+    y.value.set(4f32);
+
+    add.forward();
+    sub.forward();
+
+    add.backward_ad(&["x", "y"]);
+    sub.backward_ad(&["x", "y"]);
+
+    assert_eq!(add.grads.borrow()["x"], 4.25);
+    assert_eq!(add.grads.borrow()["y"], 7.5);
+
+    assert_eq!(sub.grads.borrow()["x"], 3.75);
+    assert_eq!(sub.grads.borrow()["y"], 8.5);
+}
+
+#[test]
+fn negation() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let f = neg(arena, mul(arena, x, y));
+
+    x.value.set(8f32);
+    y.value.set(4f32);
+    f.forward();
+    assert_eq!(f.value(), -32f32);
+
+    f.backward_ad(&["x", "y"]);
+    assert_eq!(f.grad("x"), -4f32);
+    assert_eq!(f.grad("y"), -8f32);
+    assert_eq!(
+        f.hessian(&["x", "y"]),
+        vec![vec![0f32, -1f32], vec![-1f32, 0f32]]
+    );
+}
+
+#[test]
+fn basic_hessian() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    // x^3 * sin(y) / y
+    let cube = pow(arena, x, 3f32);
+    let sin = sin(arena, y);
+    let f = div(arena, mul(arena, cube, sin), y);
+
+    x.value.set(2f32);
+    y.value.set(1f32);
+
+    f.forward();
+    let hessian = f.hessian(&["x", "y"]);
+
+    let (s, c) = (1f32.sin(), 1f32.cos());
+    let expected = [
+        [6f32 * 2f32 * s, 3f32 * 4f32 * (c - s)],
+        [3f32 * 4f32 * (c - s), 8f32 * (-s - 2f32 * c + 2f32 * s)],
+    ];
+    for i in 0..2 {
+        for j in 0..2 {
+            assert!((hessian[i][j] - expected[i][j]).abs() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn hessian_of_shared_subexpressions() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    // 2^60 paths lead to x; each node has to be visited once
+    let x = var(arena, "x".to_string());
+    let mut f = mul(arena, x, x);
+    for _ in 0..60 {
+        f = add(arena, f, f);
+    }
+    // `forward` itself walks every path, so store the values directly
+    let order = topological_order(&[f]);
+    let values = evaluate_at(&order, &[("x", 0.5)].into());
+    for node in order {
+        node.value.set(values[&node_key(node)]);
+    }
+    assert_eq!(f.hessian(&["x"]), vec![vec![2f32.powi(61)]]);
+}
+
+#[test]
+fn pow_sin_cos_first_derivatives() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    // the derivatives are taken at the operand, not at the node's own value
+    let f = add(
+        arena,
+        add(arena, pow(arena, x, 3f32), sin(arena, x)),
+        cos(arena, x),
+    );
+
+    x.value.set(0.5);
+    f.forward();
+    f.backward_ad(&["x"]);
+
+    let expected = 3f32 * 0.25 + 0.5f32.cos() - 0.5f32.sin();
+    assert!((f.grad("x") - expected).abs() < 1e-6);
+}
+
+#[test]
+fn stop_gradient_blocks_backward() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    // x * stop_gradient(x * y)
+    let xy = mul(arena, x, y);
+    let f = mul(arena, x, stop_gradient(arena, xy));
+
+    x.value.set(3f32);
+    y.value.set(2f32);
+
+    f.forward();
+    f.backward_ad(&["x", "y"]);
+
+    assert_eq!(f.value.get(), 18f32);
+    assert_eq!(f.grads.borrow()["x"], 6f32);
+    assert_eq!(f.grads.borrow()["y"], 0f32);
+    assert_eq!(f.hessian(&["x", "y"]), vec![vec![0f32; 2]; 2]);
+}
+
+#[test]
+fn custom_op() {
+    struct Hypot;
+
+    impl CustomOp for Hypot {
+        fn name(&self) -> &str {
+            "hypot"
+        }
+
+        fn forward(&self, inputs: &[f32]) -> f32 {
+            inputs[0].hypot(inputs[1])
+        }
+
+        fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+            let r = inputs[0].hypot(inputs[1]);
+            vec![upstream * inputs[0] / r, upstream * inputs[1] / r]
+        }
+    }
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let f = custom(arena, Hypot, &[mul(arena, x, y), y]);
+
+    x.value.set(1f32);
+    y.value.set(3f32);
+
+    f.forward();
+    f.backward_ad(&["x", "y"]);
+
+    let r = 18f32.sqrt();
+    assert_eq!(f.value.get(), r);
+    assert!((f.grads.borrow()["x"] - 9f32 / r).abs() < 1e-6);
+    assert!((f.grads.borrow()["y"] - 6f32 / r).abs() < 1e-6);
+
+    // d^2/dx^2 sqrt(x^2 y^2 + y^2) = y^4 / r^3
+    let hessian = f.hessian(&["x", "y"]);
+    assert!((hessian[0][0] - 81f32 / r.powi(3)).abs() < 1e-3);
+    assert!((hessian[0][1] - hessian[1][0]).abs() < 1e-3);
+}
+
+#[test]
+fn grad_wrt_intermediate_nodes() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    // (x * y) + sin(x * y)
+    let xy = mul(arena, x, y);
+    let s = sin(arena, xy);
+    let f = add(arena, xy, s);
+    let unrelated = cos(arena, y);
+
+    x.value.set(2f32);
+    y.value.set(0.5);
+
+    let grads = grad_wrt(f, &[xy, s, x, unrelated]);
+    assert_eq!(grads[0], 1f32 + 1f32.cos());
+    assert_eq!(grads[1], 1f32);
+    assert_eq!(grads[2], 0.5 * (1f32 + 1f32.cos()));
+    assert_eq!(grads[3], 0f32);
+}
+
+#[test]
+fn backward_multi_matches_backward_ad() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    let shared = sin(arena, mul(arena, x, y));
+    let outputs = [
+        add(arena, shared, x),
+        div(arena, shared, y),
+        stop_gradient(arena, shared),
+    ];
+
+    x.value.set(1.5);
+    y.value.set(-0.5);
+
+    let grads = backward_multi(&outputs, &["x", "y"]);
+    for (output, grads) in outputs.iter().zip(&grads) {
+        output.forward();
+        output.backward_ad(&["x", "y"]);
+        assert_eq!(grads.clone().into_map(), output.grads().to_map());
+        for (v, g) in &output.gradients(&["x", "y"]) {
+            assert!((grads[v] - g).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn eval_with_grad_leaves_graph_untouched() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let f = add(arena, mul(arena, x, y), sin(arena, x));
+
+    let mut at = Assignment::new();
+    at.insert("x".to_string(), 2.0);
+    at.insert("y".to_string(), 3.0);
+    let (value, grads) = eval_with_grad(f, &at, &["x", "y"]);
+
+    assert_eq!(value, 6.0 + 2f32.sin());
+    assert!((grads["x"] - (3.0 + 2f32.cos())).abs() < 1e-6);
+    assert_eq!(grads["y"], 2.0);
+
+    // nothing was written back
+    assert_eq!(x.value(), 0.0);
+    assert_eq!(f.value(), 0.0);
+    assert!(f.grads().is_empty());
+}
+
+#[test]
+fn labels_show_up_in_descriptions() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let drag = labelled(mul(arena, x, x), "drag_term");
+
+    assert_eq!(x.describe(), "Var");
+    let label = drag.label().unwrap();
+    assert_eq!(label.name, "drag_term");
+    assert_eq!(label.location.file(), file!());
+    assert!(drag.describe().starts_with("Mul drag_term (src/lib.rs:"));
+    assert!(format!("{:?}", drag).contains("drag_term"));
+}
+
+#[test]
+fn backward_seeded_weights_outputs() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let first = mul(arena, x, y);
+    let second = sin(arena, x);
+    x.set_value(0.3);
+    y.set_value(2.0);
+
+    let grads = backward_seeded(&[(first, 0.5), (second, -2.0)], &["x", "y"]);
+    assert!((grads["x"] - (0.5 * 2.0 - 2.0 * 0.3f32.cos())).abs() < 1e-6);
+    assert!((grads["y"] - 0.5 * 0.3).abs() < 1e-6);
+}
+
+#[test]
+fn forward_multi_shares_nodes() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let shared = sin(arena, mul(arena, x, x));
+    let roots = [add(arena, shared, x), pow(arena, shared, 2.0), shared];
+
+    let mut at = Assignment::new();
+    at.insert("x".to_string(), 1.25);
+    let values = forward_multi(&roots, &at);
+
+    let s = (1.25f32 * 1.25).sin();
+    assert_eq!(values, vec![s + 1.25, s.powf(2.0), s]);
+}
+
+#[test]
+fn missing_variable_is_reported() {
+    let arena = Arena::new();
+    let speed = var(&arena, "speed".to_string());
+    let time = var(&arena, "time".to_string());
+    let distance = mul(&arena, speed, time);
+
+    let at: Assignment = [("sped", 2.0), ("time", 3.0)].into();
+    let missing = try_eval_with_grad(distance, &at, &["time"]).unwrap_err();
+    assert_eq!(missing.name, "speed");
+    assert_eq!(missing.suggestions, vec!["sped".to_string()]);
+
+    let at: Assignment = [("speed", 2.0), ("time", 3.0)].into();
+    assert_eq!(try_eval_with_grad(distance, &at, &["time"]).unwrap().0, 6.0);
+}
+
+#[test]
+fn uncertainty_propagation() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    // area of a rectangle, 2 +- 0.1 by 3 +- 0.2
+    let w = var(arena, "w".to_string());
+    let h = var(arena, "h".to_string());
+    let area = mul(arena, w, h);
+
+    let mut inputs = HashMap::new();
+    inputs.insert("w".to_string(), (2.0, 0.1));
+    inputs.insert("h".to_string(), (3.0, 0.2));
+    let (mean, std) = forward_uncertain(area, &inputs);
+    assert_eq!(mean, 6.0);
+    let expected = ((3.0f32 * 0.1).powi(2) + (2.0f32 * 0.2).powi(2)).sqrt();
+    assert!((std - expected).abs() < 1e-6);
+}
+
+#[test]
+fn balanced_sum_and_prod() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let xs: Vec<Node> = (1..9).map(|i| constant(arena, i as f32)).collect();
+    let total = sum(arena, &xs);
+    let product = prod(arena, &xs);
+    total.forward();
+    product.forward();
+
+    assert_eq!(total.value(), 36.0);
+    assert_eq!(product.value(), 40320.0);
+    assert_eq!(scan::depth(total), 3);
+
+    let empty = prod(arena, &[]);
+    empty.forward();
+    assert_eq!(empty.value(), 1.0);
+}
+
+#[test]
+fn dot_and_linear() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let z = var(arena, "z".to_string());
+    x.set_value(1.0);
+    y.set_value(2.0);
+    z.set_value(3.0);
+
+    let d = dot(arena, &[x, y], &[y, z]);
+    d.forward();
+    assert_eq!(d.value(), 8.0);
+
+    let l = linear(arena, &[x, y, z], &[2.0, 0.0, 1.0], 0.5);
+    l.forward();
+    assert_eq!(l.value(), 5.5);
+    l.backward_ad(&["x", "y", "z"]);
+    assert_eq!((l.grad("x"), l.grad("y"), l.grad("z")), (2.0, 0.0, 1.0));
+}
+
+#[test]
+fn polynomial_horner() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    x.set_value(2.0);
+
+    // 1 - 3x + 0.5x^3
+    let p = polynomial(arena, x, &[1.0, -3.0, 0.0, 0.5]);
+    p.forward();
+    p.backward_ad(&["x"]);
+    assert_eq!(p.value(), -1.0);
+    assert_eq!(p.grad("x"), 3.0);
+
+    let a = var(arena, "a".to_string());
+    a.set_value(4.0);
+    let q = polynomial_nodes(arena, x, &[a, a]);
+    q.forward();
+    q.backward_ad(&["a"]);
+    assert_eq!(q.value(), 12.0);
+    assert_eq!(q.grad("a"), 3.0);
+}
+
+#[test]
+fn n_ary_add_and_mul() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let z = var(arena, "z".to_string());
+    x.set_value(2.0);
+    y.set_value(3.0);
+    z.set_value(0.0);
+
+    let f = add_n(
+        arena,
+        &[mul_n(arena, &[x, y, z]), mul_n(arena, &[x, x, y]), y],
+    );
+    f.forward();
+    assert_eq!(f.value(), 15.0);
+
+    f.backward_ad(&["x", "y", "z"]);
+    assert_eq!((f.grad("x"), f.grad("y"), f.grad("z")), (12.0, 5.0, 6.0));
+    assert_eq!(f.gradients(&["x", "y", "z"]).values(), &[12.0, 5.0, 6.0]);
+
+    let h = f.hessian(&["x", "y", "z"]);
+    assert_eq!(
+        h,
+        vec![
+            vec![6.0, 4.0, 3.0],
+            vec![4.0, 0.0, 2.0],
+            vec![3.0, 2.0, 0.0]
+        ]
+    );
+}
+
+#[test]
+fn exp_m1_and_ln_1p_near_zero() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    x.set_value(1e-7);
+
+    let e = exp_m1(arena, x);
+    let l = ln_1p(arena, e);
+    l.forward();
+    assert_eq!(e.value(), 1e-7f32.exp_m1());
+    // exp(x) - 1 would have rounded to 1.1920929e-7
+    assert!((e.value() - 1e-7).abs() < 1e-12);
+    assert!((l.value() - 1e-7).abs() < 1e-12);
+
+    l.backward_ad(&["x"]);
+    assert!((l.grad("x") - 1.0).abs() < 1e-6);
+    assert!((e.hessian(&["x"])[0][0] - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn lerp_and_smoothstep() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let a = var(arena, "a".to_string());
+    let b = var(arena, "b".to_string());
+    let t = var(arena, "t".to_string());
+    a.set_value(1.0);
+    b.set_value(5.0);
+    t.set_value(0.25);
+
+    let l = lerp(arena, a, b, t);
+    l.forward();
+    l.backward_ad(&["a", "b", "t"]);
+    assert_eq!(l.value(), 2.0);
+    assert_eq!((l.grad("a"), l.grad("b"), l.grad("t")), (0.75, 0.25, 4.0));
+
+    // s = 0.25 between edges a and b at x = 2
+    let s = smoothstep(arena, a, b, l);
+    s.forward();
+    assert_eq!(s.value(), 3.0 * 0.0625 - 2.0 * 0.015625);
+    let grads = s.gradients(&["t"]);
+    // ds/dx = 6 s (1 - s) / (b - a), dx/dt = 4
+    assert!((grads["t"] - 6.0 * 0.25 * 0.75 / 4.0 * 4.0).abs() < 1e-6);
+
+    t.set_value(2.0);
+    s.forward();
+    assert_eq!(s.value(), 1.0);
+    assert_eq!(s.gradients(&["a", "b", "t"]).values(), &[0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn finite_difference_custom_op() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    // a black box: x^2 y + sin y
+    let op = FiniteDifference::new("black_box", |v: &[f32]| v[0] * v[0] * v[1] + v[1].sin());
+    let f = custom(arena, op, &[x, y]);
+    let f = mul(arena, f, constant(arena, 2f32));
+
+    let (value, grads) =
+        eval_with_grad(f, &Assignment::from([("x", 1.5), ("y", 0.5)]), &["x", "y"]);
+    assert!((value - 2f32 * (1.125 + 0.5f32.sin())).abs() < 1e-5);
+    assert!((grads["x"] - 2f32 * 1.5).abs() < 1e-2);
+    assert!((grads["y"] - 2f32 * (2.25 + 0.5f32.cos())).abs() < 1e-2);
+
+    let fine = FiniteDifference::new("fine", |v: &[f32]| v[0] * v[0]).step(1e-2);
+    let g = finite_difference_backward(&fine, &[100f32], 1f32, 1e-2);
+    assert!((g[0] - 200f32).abs() < 1e-2);
+}
+
+#[test]
+fn gather_selects_by_index() {
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let params: Vec<Node> = (0..5).map(|i| var(arena, format!("p{}", i))).collect();
+    let category = var(arena, "category".to_string());
+    let f = mul(
+        arena,
+        gather(arena, &params, category),
+        constant(arena, 3f32),
+    );
+    let names = ["p0", "p1", "p2", "p3", "p4", "category"];
+
+    for &(index, expected) in &[
+        (0f32, 0usize),
+        (2f32, 2),
+        (2.4, 2),
+        (3f32, 3),
+        (4f32, 4),
+        (9f32, 4),
+        (-1f32, 0),
+    ] {
+        let mut at: Assignment = (0..5)
+            .map(|i| (format!("p{}", i), 10f32 + i as f32))
+            .collect();
+        at.insert("category", index);
+        let (value, grads) = eval_with_grad(f, &at, &names);
+        assert_eq!(value, 3f32 * (10f32 + expected as f32));
+        for (i, name) in names[..5].iter().enumerate() {
+            assert_eq!(grads[*name], if i == expected { 3f32 } else { 0f32 });
+        }
+        assert_eq!(grads["category"], 0f32);
+    }
+
+    assert!(std::ptr::eq(
+        gather(arena, &params, constant(arena, 1f32)),
+        params[1]
+    ));
 }
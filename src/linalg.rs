@@ -0,0 +1,71 @@
+// Small dense solvers for the handful of variables the solvers deal with.
+
+// Solves `a x = b` for symmetric positive definite `a`, None if it is not.
+pub(crate) fn cholesky_solve(a: &[Vec<f32>], b: &[f32]) -> Option<Vec<f32>> {
+    let n = b.len();
+    let mut l = vec![vec![0f32; n]; n];
+
+    for i in 0..n {
+        for j in 0..i + 1 {
+            let sum: f32 = (0..j).map(|k| l[i][k] * l[j][k]).sum();
+            if i == j {
+                let diag = a[i][i] - sum;
+                if diag <= 0f32 || !diag.is_finite() {
+                    return None;
+                }
+                l[i][i] = diag.sqrt();
+            } else {
+                l[i][j] = (a[i][j] - sum) / l[j][j];
+            }
+        }
+    }
+
+    let mut y = vec![0f32; n];
+    for i in 0..n {
+        let sum: f32 = (0..i).map(|k| l[i][k] * y[k]).sum();
+        y[i] = (b[i] - sum) / l[i][i];
+    }
+
+    let mut x = vec![0f32; n];
+    for i in (0..n).rev() {
+        let sum: f32 = (i + 1..n).map(|k| l[k][i] * x[k]).sum();
+        x[i] = (y[i] - sum) / l[i][i];
+    }
+
+    Some(x)
+}
+
+// Solves `a x = b` by Gaussian elimination with partial pivoting, None if
+// `a` is singular.
+pub(crate) fn lu_solve(a: &[Vec<f32>], b: &[f32]) -> Option<Vec<f32>> {
+    let n = b.len();
+    let mut a = a.to_vec();
+    let mut b = b.to_vec();
+
+    for col in 0..n {
+        // a NaN entry sorts above every number and is rejected below
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= f32::EPSILON * 1e-3 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            let (pivot_rows, rest) = a.split_at_mut(row);
+            for (entry, pivot) in rest[0][col..].iter_mut().zip(&pivot_rows[col][col..]) {
+                *entry -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0f32; n];
+    for i in (0..n).rev() {
+        let sum: f32 = (i + 1..n).map(|k| a[i][k] * x[k]).sum();
+        x[i] = (b[i] - sum) / a[i][i];
+    }
+
+    Some(x)
+}
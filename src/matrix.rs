@@ -0,0 +1,146 @@
+// Expressions over small square matrices of nodes.
+
+use std::array;
+
+use {add_n, constant, div, mul, neg, Arena, Node};
+
+const MAX_SIZE: usize = 4;
+
+// Determinant by cofactor expansion, which stays a plain polynomial in the
+// entries so every entry gets its exact gradient (the cofactor).
+pub fn det<'a, const N: usize>(arena: &'a Arena<'a>, m: &[[Node<'a>; N]; N]) -> Node<'a> {
+    assert!(N <= MAX_SIZE, "determinants are expanded only up to 4x4");
+    let rows: Vec<&[Node<'a>]> = m.iter().map(|row| &row[..]).collect();
+    let cols: Vec<usize> = (0..N).collect();
+    expand(arena, &rows, &cols)
+}
+
+// determinant of the minor made of `rows` and the columns `cols`
+fn expand<'a>(arena: &'a Arena<'a>, rows: &[&[Node<'a>]], cols: &[usize]) -> Node<'a> {
+    match cols.len() {
+        0 => constant(arena, 1f32),
+        1 => rows[0][cols[0]],
+        2 => {
+            let main = mul(arena, rows[0][cols[0]], rows[1][cols[1]]);
+            let anti = mul(arena, rows[0][cols[1]], rows[1][cols[0]]);
+            add_n(arena, &[main, neg(arena, anti)])
+        }
+        _ => {
+            let terms: Vec<Node<'a>> = cols
+                .iter()
+                .enumerate()
+                .map(|(i, &col)| {
+                    let rest: Vec<usize> = cols.iter().cloned().filter(|&c| c != col).collect();
+                    let term = mul(arena, rows[0][col], expand(arena, &rows[1..], &rest));
+                    if i % 2 == 0 {
+                        term
+                    } else {
+                        neg(arena, term)
+                    }
+                })
+                .collect();
+            add_n(arena, &terms)
+        }
+    }
+}
+
+// Solution of `a x = b` by Cramer's rule, one quotient of determinants per
+// unknown. The denominator is built once and shared. Singular systems give
+// non-finite values rather than an error, as the graph cannot branch on
+// them.
+pub fn solve<'a, const N: usize>(
+    arena: &'a Arena<'a>,
+    a: &[[Node<'a>; N]; N],
+    b: &[Node<'a>; N],
+) -> [Node<'a>; N] {
+    let denominator = det(arena, a);
+    array::from_fn(|i| {
+        let mut replaced = *a;
+        for (row, &rhs) in replaced.iter_mut().zip(b) {
+            row[i] = rhs;
+        }
+        div(arena, det(arena, &replaced), denominator)
+    })
+}
+
+pub fn trace<'a, const N: usize>(arena: &'a Arena<'a>, m: &[[Node<'a>; N]; N]) -> Node<'a> {
+    let diagonal: Vec<Node<'a>> = (0..N).map(|i| m[i][i]).collect();
+    add_n(arena, &diagonal)
+}
+
+#[test]
+fn determinant_gradients_are_cofactors() {
+    use {eval_with_grad, forward_multi, var, Assignment};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let names: Vec<String> = (0..9).map(|i| format!("m{}", i)).collect();
+    let entries: Vec<Node> = names.iter().map(|name| var(arena, name.clone())).collect();
+    let m = [
+        [entries[0], entries[1], entries[2]],
+        [entries[3], entries[4], entries[5]],
+        [entries[6], entries[7], entries[8]],
+    ];
+    let values = [2f32, -1f32, 0f32, 1f32, 3f32, 2f32, 0f32, 1f32, 4f32];
+    let at: Assignment = names.iter().cloned().zip(values.iter().cloned()).collect();
+    let refs: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+
+    let (value, grads) = eval_with_grad(det(arena, &m), &at, &refs);
+    // 2 (12 - 2) + 1 (4 - 0) + 0
+    assert_eq!(value, 24f32);
+    // d det / d m_ij is the (i, j) cofactor
+    assert_eq!(grads["m0"], 10f32);
+    assert_eq!(grads["m1"], -4f32);
+    assert_eq!(grads["m4"], 8f32);
+    assert_eq!(grads["m7"], -4f32);
+
+    assert_eq!(forward_multi(&[trace(arena, &m)], &at)[0], 9f32);
+
+    // the identity has determinant 1 at every size
+    let one = constant(arena, 1f32);
+    let zero = constant(arena, 0f32);
+    let mut identity = [[zero; 4]; 4];
+    for (i, row) in identity.iter_mut().enumerate() {
+        row[i] = one;
+    }
+    assert_eq!(forward_multi(&[det(arena, &identity)], &at)[0], 1f32);
+}
+
+#[test]
+fn solve_small_systems() {
+    use {eval_with_grad, forward_multi, var, Assignment};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let p = var(arena, "p".to_string());
+    let c = |v: f32| constant(arena, v);
+    // [[p, 1, 0], [1, 3, 1], [0, 1, 2]] x = [1, 2, p]
+    let a = [
+        [p, c(1f32), c(0f32)],
+        [c(1f32), c(3f32), c(1f32)],
+        [c(0f32), c(1f32), c(2f32)],
+    ];
+    let b = [c(1f32), c(2f32), p];
+    let x = solve(arena, &a, &b);
+
+    let at: Assignment = [("p", 2f32)].into();
+    let values = forward_multi(&x, &at);
+    let a_values = [[2f32, 1f32, 0f32], [1f32, 3f32, 1f32], [0f32, 1f32, 2f32]];
+    for (row, rhs) in a_values.iter().zip(&[1f32, 2f32, 2f32]) {
+        let lhs: f32 = row.iter().zip(&values).map(|(a, x)| a * x).sum();
+        assert!((lhs - rhs).abs() < 1e-5);
+    }
+
+    // A dx/dp = db/dp - dA/dp x
+    let grads: Vec<f32> = x
+        .iter()
+        .map(|&xi| eval_with_grad(xi, &at, &["p"]).1["p"])
+        .collect();
+    let rhs = [-values[0], 0f32, 1f32];
+    for (row, rhs) in a_values.iter().zip(&rhs) {
+        let lhs: f32 = row.iter().zip(&grads).map(|(a, g)| a * g).sum();
+        assert!((lhs - rhs).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,63 @@
+// Estimates of the memory held by graphs, in bytes. Heap sizes are computed
+// from capacities and ignore allocator overhead.
+
+use std::mem::size_of;
+
+use {topological_order, Node, NodeData, NodeType};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    // the nodes themselves
+    pub nodes: usize,
+    // heap data owned by nodes: operand lists and variable names
+    pub operands: usize,
+    // per-node gradient maps
+    pub grads: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.nodes + self.operands + self.grads
+    }
+}
+
+// Memory of the arena nodes reachable from `root`.
+pub fn memory_usage(root: Node) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    for node in topological_order(&[root]) {
+        usage.nodes += size_of::<NodeData>();
+        usage.operands += match node.type_ {
+            NodeType::Var(ref name) => name.capacity(),
+            NodeType::Custom(_, ref args) | NodeType::AddN(ref args) | NodeType::MulN(ref args) => {
+                args.capacity() * size_of::<Node>()
+            }
+            _ => 0,
+        };
+        usage.grads += node.grads.borrow().heap_bytes();
+    }
+    usage
+}
+
+#[test]
+fn gradient_maps_are_accounted() {
+    use {add, mul, var, Arena};
+
+    let arena = Arena::with_capacity(3);
+    let x = var(&arena, "x".to_string());
+    let f = add(&arena, mul(&arena, x, x), x);
+
+    let before = memory_usage(f);
+    assert_eq!(before.nodes, 3 * size_of::<NodeData>());
+    assert_eq!(before.grads, 0);
+
+    f.forward();
+    // a few variables are stored inline
+    f.backward_ad(&["x"]);
+    assert_eq!(memory_usage(f).grads, 0);
+
+    let many = ["x", "a", "b", "c", "d", "e", "f", "g", "h"];
+    f.backward_ad(&many);
+    let after = memory_usage(f);
+    assert!(after.grads > 0);
+    assert_eq!(after.total(), before.total() + after.grads);
+}
@@ -0,0 +1,334 @@
+// Numbers that can be differentiated, so a function written once against
+// `AdNum` can be evaluated plainly (f32), in forward mode (`Dual`), into a
+// graph (`Expr`) or onto a tape (`TapeValue`).
+
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use expr::Expr;
+use tape::TapeValue;
+
+pub trait AdNum:
+    Copy
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + Add<f32, Output = Self>
+    + Sub<f32, Output = Self>
+    + Mul<f32, Output = Self>
+    + Div<f32, Output = Self>
+{
+    fn sin(self) -> Self;
+
+    fn cos(self) -> Self;
+
+    fn pow(self, exponent: f32) -> Self;
+
+    fn exp_m1(self) -> Self;
+
+    fn ln_1p(self) -> Self;
+
+    fn exp(self) -> Self {
+        self.exp_m1() + 1f32
+    }
+
+    // no default, as `ln_1p(x - 1)` loses everything for small x
+    fn ln(self) -> Self;
+}
+
+// ln for the graph types, which only have ln_1p: ln_1p(x - 1) from 1/2 up,
+// where x - 1 is exact, and -ln_1p(1/x - 1) below. `select(cond, then,
+// else_)` takes `then` where cond > 0. Each branch sees x clamped to its own
+// side, so the branch not taken stays finite and passes no NaN back.
+pub(crate) fn ln_by_select<T: AdNum, S: Fn(T, T, T) -> T>(x: T, half: T, select: S) -> T {
+    let above = x - 0.5;
+    let upper = select(above, x, half);
+    let lower = select(above, half, x);
+    select(
+        above,
+        (upper - 1f32).ln_1p(),
+        -(lower.pow(-1f32) - 1f32).ln_1p(),
+    )
+}
+
+impl AdNum for f32 {
+    fn sin(self) -> Self {
+        f32::sin(self)
+    }
+
+    fn cos(self) -> Self {
+        f32::cos(self)
+    }
+
+    fn pow(self, exponent: f32) -> Self {
+        self.powf(exponent)
+    }
+
+    fn exp_m1(self) -> Self {
+        f32::exp_m1(self)
+    }
+
+    fn ln_1p(self) -> Self {
+        f32::ln_1p(self)
+    }
+
+    fn exp(self) -> Self {
+        f32::exp(self)
+    }
+
+    fn ln(self) -> Self {
+        f32::ln(self)
+    }
+}
+
+impl<'a> AdNum for Expr<'a> {
+    fn sin(self) -> Self {
+        Expr::sin(self)
+    }
+
+    fn cos(self) -> Self {
+        Expr::cos(self)
+    }
+
+    fn pow(self, exponent: f32) -> Self {
+        Expr::pow(self, exponent)
+    }
+
+    fn exp_m1(self) -> Self {
+        Expr::exp_m1(self)
+    }
+
+    fn ln_1p(self) -> Self {
+        Expr::ln_1p(self)
+    }
+
+    fn ln(self) -> Self {
+        Expr::ln(self)
+    }
+}
+
+impl<'t> AdNum for TapeValue<'t> {
+    fn sin(self) -> Self {
+        TapeValue::sin(self)
+    }
+
+    fn cos(self) -> Self {
+        TapeValue::cos(self)
+    }
+
+    fn pow(self, exponent: f32) -> Self {
+        TapeValue::pow(self, exponent)
+    }
+
+    fn exp_m1(self) -> Self {
+        TapeValue::exp_m1(self)
+    }
+
+    fn ln_1p(self) -> Self {
+        TapeValue::ln_1p(self)
+    }
+
+    fn ln(self) -> Self {
+        TapeValue::ln(self)
+    }
+}
+
+// A value with its derivative along one direction (forward mode).
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Dual {
+    pub value: f32,
+    pub deriv: f32,
+}
+
+impl Dual {
+    pub fn new(value: f32, deriv: f32) -> Self {
+        Dual { value, deriv }
+    }
+
+    // the input being differentiated with respect to
+    pub fn var(value: f32) -> Self {
+        Dual::new(value, 1f32)
+    }
+
+    pub fn constant(value: f32) -> Self {
+        Dual::new(value, 0f32)
+    }
+
+    // value and derivative of a function through its local derivative
+    fn chain(self, value: f32, local: f32) -> Self {
+        Dual::new(value, local * self.deriv)
+    }
+}
+
+impl fmt::Debug for Dual {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} + {}e", self.value, self.deriv)
+    }
+}
+
+// Value and derivative of `f` at `x`.
+pub fn derivative<F: FnOnce(Dual) -> Dual>(f: F, x: f32) -> (f32, f32) {
+    let y = f(Dual::var(x));
+    (y.value, y.deriv)
+}
+
+impl Add for Dual {
+    type Output = Dual;
+
+    fn add(self, rhs: Dual) -> Dual {
+        Dual::new(self.value + rhs.value, self.deriv + rhs.deriv)
+    }
+}
+
+impl Sub for Dual {
+    type Output = Dual;
+
+    fn sub(self, rhs: Dual) -> Dual {
+        Dual::new(self.value - rhs.value, self.deriv - rhs.deriv)
+    }
+}
+
+impl Mul for Dual {
+    type Output = Dual;
+
+    fn mul(self, rhs: Dual) -> Dual {
+        Dual::new(
+            self.value * rhs.value,
+            self.deriv * rhs.value + self.value * rhs.deriv,
+        )
+    }
+}
+
+impl Div for Dual {
+    type Output = Dual;
+
+    fn div(self, rhs: Dual) -> Dual {
+        Dual::new(
+            self.value / rhs.value,
+            (self.deriv * rhs.value - self.value * rhs.deriv) / (rhs.value * rhs.value),
+        )
+    }
+}
+
+impl Neg for Dual {
+    type Output = Dual;
+
+    fn neg(self) -> Dual {
+        Dual::new(-self.value, -self.deriv)
+    }
+}
+
+macro_rules! scalar_op {
+    ($trait_:ident, $method:ident) => {
+        impl $trait_<f32> for Dual {
+            type Output = Dual;
+
+            fn $method(self, rhs: f32) -> Dual {
+                $trait_::$method(self, Dual::constant(rhs))
+            }
+        }
+
+        impl $trait_<Dual> for f32 {
+            type Output = Dual;
+
+            fn $method(self, rhs: Dual) -> Dual {
+                $trait_::$method(Dual::constant(self), rhs)
+            }
+        }
+    };
+}
+
+scalar_op!(Add, add);
+scalar_op!(Sub, sub);
+scalar_op!(Mul, mul);
+scalar_op!(Div, div);
+
+impl AdNum for Dual {
+    fn sin(self) -> Self {
+        self.chain(self.value.sin(), self.value.cos())
+    }
+
+    fn cos(self) -> Self {
+        self.chain(self.value.cos(), -self.value.sin())
+    }
+
+    fn pow(self, exponent: f32) -> Self {
+        self.chain(
+            self.value.powf(exponent),
+            exponent * self.value.powf(exponent - 1f32),
+        )
+    }
+
+    fn exp_m1(self) -> Self {
+        self.chain(self.value.exp_m1(), self.value.exp())
+    }
+
+    fn ln_1p(self) -> Self {
+        self.chain(self.value.ln_1p(), 1f32 / (1f32 + self.value))
+    }
+
+    fn exp(self) -> Self {
+        let exp = self.value.exp();
+        self.chain(exp, exp)
+    }
+
+    fn ln(self) -> Self {
+        self.chain(self.value.ln(), 1f32 / self.value)
+    }
+}
+
+#[test]
+fn one_function_every_mode() {
+    use tape::Tape;
+    use {eval_with_grad, forward_multi, Arena};
+
+    fn f<T: AdNum>(x: T, y: T) -> T {
+        (x * y).sin() + x.pow(3f32) / y - (x * 0.5).exp() + y.ln()
+    }
+    // ln(x y) for small x, where ln_1p(x y - 1) would give -inf
+    fn g<T: AdNum>(x: T, y: T) -> T {
+        (x * y).ln()
+    }
+    let (x, y) = (0.7f32, 1.3f32);
+    let dfdx = (x * y).cos() * y + 3f32 * x * x / y - 0.5 * (x * 0.5).exp();
+
+    let value = f(x, y);
+    let (dual_value, dual_dx) = derivative(|x| f(x, Dual::constant(y)), x);
+    assert!((dual_value - value).abs() < 1e-6);
+    assert!((dual_dx - dfdx).abs() < 1e-5);
+
+    let arena = Arena::new();
+    let graph = f(Expr::var(&arena, "x"), Expr::var(&arena, "y"));
+    let at = [("x", x), ("y", y)].into();
+    assert!((forward_multi(&[graph.node()], &at)[0] - value).abs() < 1e-5);
+
+    let tape = Tape::new();
+    let taped = f(tape.var("x", x), tape.var("y", y));
+    assert!((taped.value() - value).abs() < 1e-6);
+    assert!((tape.backward(taped)["x"] - dfdx).abs() < 1e-5);
+
+    let (x, y) = (1e-8f32, 2f32);
+    let value = g(x, y);
+    assert!((value - (-17.7275)).abs() < 1e-3);
+    assert!((derivative(|x| g(x, Dual::constant(y)), x).0 - value).abs() < 1e-5);
+    let graph = g(Expr::var(&arena, "x"), Expr::var(&arena, "y"));
+    let at = [("x", x), ("y", y)].into();
+    assert!((forward_multi(&[graph.node()], &at)[0] - value).abs() < 1e-5);
+    let (_, grads) = eval_with_grad(graph.node(), &at, &["x"]);
+    assert!((grads["x"] / 1e8 - 1f32).abs() < 1e-4);
+    let tape = Tape::new();
+    let taped = g(tape.var("x", x), tape.var("y", y));
+    assert!((taped.value() - value).abs() < 1e-5);
+    assert!((tape.backward(taped)["x"] / 1e8 - 1f32).abs() < 1e-4);
+    for &x in &[0.3f32, 0.5, 1.00001, 7.0, 1e20] {
+        let graph = Expr::var(&arena, "x").ln();
+        let at = [("x", x)].into();
+        let expected = x.ln();
+        assert!(
+            (forward_multi(&[graph.node()], &at)[0] - expected).abs()
+                <= 1e-6 * expected.abs().max(1f32)
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// Hooks called for every node during evaluation and differentiation, for
+// logging or for stopping at a suspicious value.
+
+use {adjoints_observed, evaluate_observed, topological_order, variable_gradients};
+use {Gradients, Node};
+
+pub trait Observer {
+    // about to evaluate `node`, or to propagate its adjoint
+    fn enter(&mut self, _node: Node) {}
+
+    // `node` evaluated to `output` from the values of its children
+    fn forward(&mut self, _node: Node, _inputs: &[f32], _output: f32) {}
+
+    // `node` received `adjoint`, its partials are computed and it is about
+    // to pass the adjoint to its children
+    fn backward(&mut self, _node: Node, _inputs: &[f32], _adjoint: f32) {}
+}
+
+// observes nothing
+impl Observer for () {}
+
+// Prints every event to stderr.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logger;
+
+impl Observer for Logger {
+    fn forward(&mut self, node: Node, inputs: &[f32], output: f32) {
+        eprintln!(
+            "forward  {:p} {} {:?} -> {}",
+            node,
+            node.describe(),
+            inputs,
+            output
+        );
+    }
+
+    fn backward(&mut self, node: Node, inputs: &[f32], adjoint: f32) {
+        eprintln!(
+            "backward {:p} {} {:?} <- {}",
+            node,
+            node.describe(),
+            inputs,
+            adjoint
+        );
+    }
+}
+
+// Like `NodeData::forward`, reporting every node to `observer`. Both
+// branches of a select are evaluated.
+pub fn forward_observed(node: Node, observer: &mut dyn Observer) {
+    let order = topological_order(&[node]);
+    let values = evaluate_observed(&order, observer);
+    for node in order {
+        node.set_value(values[&(node as *const _)]);
+    }
+}
+
+// Like `NodeData::gradients`, reporting the forward and the reverse sweep
+// to `observer`.
+pub fn gradients_observed(
+    node: Node,
+    variables: &[&str],
+    observer: &mut dyn Observer,
+) -> Gradients {
+    let order = topological_order(&[node]);
+    let values = evaluate_observed(&order, observer);
+    let adjoints = adjoints_observed(&order, &values, &[(node, 1f32)], observer);
+    variable_gradients(&order, &adjoints, variables)
+}
+
+#[test]
+fn observer_sees_every_node() {
+    use {mul, sin, var, Arena};
+
+    #[derive(Default)]
+    struct Record {
+        forward: Vec<(String, f32)>,
+        backward: Vec<(String, f32)>,
+    }
+
+    impl Observer for Record {
+        fn forward(&mut self, node: Node, _inputs: &[f32], output: f32) {
+            self.forward.push((node.describe(), output));
+        }
+
+        fn backward(&mut self, node: Node, _inputs: &[f32], adjoint: f32) {
+            self.backward.push((node.describe(), adjoint));
+        }
+    }
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let f = sin(&arena, mul(&arena, x, x));
+    x.set_value(0.5);
+
+    let mut record = Record::default();
+    forward_observed(f, &mut record);
+    assert_eq!(f.value(), 0.25f32.sin());
+    assert_eq!(
+        record.forward,
+        vec![
+            ("Var".to_string(), 0.5),
+            ("Mul".to_string(), 0.25),
+            ("Sin".to_string(), 0.25f32.sin()),
+        ]
+    );
+
+    let mut record = Record::default();
+    let grads = gradients_observed(f, &["x"], &mut record);
+    assert_eq!(grads["x"], 0.25f32.cos() * 2.0 * 0.5);
+    assert_eq!(
+        record.backward,
+        vec![
+            ("Sin".to_string(), 1.0),
+            ("Mul".to_string(), 0.25f32.cos()),
+            ("Var".to_string(), 0.25f32.cos() * 2.0 * 0.5),
+        ]
+    );
+}
@@ -0,0 +1,205 @@
+// Fixed-step integration of y' = f(t, y, p) with forward sensitivities.
+//
+// Next to the state, RK4 integrates S = dy/dp through S' = J_y S + J_p, with
+// the Jacobians of the right-hand sides taken by the backward pass at every
+// stage. The initial state is taken to be independent of the parameters.
+
+use {adjoints, evaluate_at, node_key, topological_order, variable_gradients, Assignment, Node};
+
+pub struct Ode<'a> {
+    rhs: Vec<Node<'a>>,
+    order: Vec<Node<'a>>,
+    states: Vec<String>,
+    params: Vec<String>,
+    time: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    pub times: Vec<f32>,
+    pub states: Vec<Vec<f32>>,
+    // sensitivities[k][i][j] = d states[k][i] / d params[j]
+    pub sensitivities: Vec<Vec<Vec<f32>>>,
+}
+
+impl<'a> Ode<'a> {
+    // `rhs[i]` is the derivative of the variable `states[i]`. Variables that
+    // are neither states, parameters nor time keep their current values.
+    pub fn new(rhs: &[Node<'a>], states: &[&str], params: &[&str]) -> Self {
+        assert_eq!(rhs.len(), states.len(), "one right-hand side per state");
+        Ode {
+            rhs: rhs.to_vec(),
+            order: topological_order(rhs),
+            states: states.iter().map(|s| s.to_string()).collect(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+            time: None,
+        }
+    }
+
+    // the variable holding the time, for non-autonomous systems
+    pub fn time(mut self, name: &str) -> Self {
+        self.time = Some(name.to_string());
+        self
+    }
+
+    // f, J_y and J_p at (t, y, p)
+    fn derivatives(
+        &self,
+        at: &mut Assignment,
+        t: f32,
+        y: &[f32],
+    ) -> (Vec<f32>, Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        if let Some(ref time) = self.time {
+            at.insert(time.clone(), t);
+        }
+        for (name, &value) in self.states.iter().zip(y) {
+            at.insert(name.clone(), value);
+        }
+        let values = evaluate_at(&self.order, at);
+
+        let states: Vec<&str> = self.states.iter().map(|s| s.as_str()).collect();
+        let params: Vec<&str> = self.params.iter().map(|p| p.as_str()).collect();
+        let mut f = vec![];
+        let mut jy = vec![];
+        let mut jp = vec![];
+        for &output in &self.rhs {
+            let adjoints = adjoints(&self.order, &values, &[(output, 1f32)]);
+            f.push(values[&node_key(output)]);
+            jy.push(variable_gradients(&self.order, &adjoints, &states).to_vec(&states));
+            jp.push(variable_gradients(&self.order, &adjoints, &params).to_vec(&params));
+        }
+        (f, jy, jp)
+    }
+
+    // (y', S') of the augmented system
+    fn augmented(
+        &self,
+        at: &mut Assignment,
+        t: f32,
+        y: &[f32],
+        s: &[Vec<f32>],
+    ) -> (Vec<f32>, Vec<Vec<f32>>) {
+        let (f, jy, jp) = self.derivatives(at, t, y);
+        let ds = (0..y.len())
+            .map(|i| {
+                (0..self.params.len())
+                    .map(|j| jp[i][j] + (0..y.len()).map(|k| jy[i][k] * s[k][j]).sum::<f32>())
+                    .collect()
+            })
+            .collect();
+        (f, ds)
+    }
+
+    // Integrates from `t0` to `t1` in `steps` RK4 steps, starting at `y0`
+    // with the parameters at `p`. The trajectory includes both ends.
+    pub fn solve(&self, y0: &[f32], p: &[f32], t0: f32, t1: f32, steps: usize) -> Trajectory {
+        assert_eq!(y0.len(), self.states.len());
+        assert_eq!(p.len(), self.params.len());
+
+        let mut at = Assignment::new();
+        for node in &self.order {
+            if let Some(name) = node.var_name() {
+                at.insert(name.to_string(), node.value());
+            }
+        }
+        for (name, &value) in self.params.iter().zip(p) {
+            at.insert(name.clone(), value);
+        }
+
+        let n = y0.len();
+        let m = p.len();
+        let h = (t1 - t0) / steps as f32;
+        let mut t = t0;
+        let mut y = y0.to_vec();
+        let mut s = vec![vec![0f32; m]; n];
+        let mut trajectory = Trajectory {
+            times: vec![t],
+            states: vec![y.clone()],
+            sensitivities: vec![s.clone()],
+        };
+
+        // y + c k, and likewise for S
+        let shift = |y: &[f32], k: &[f32], c: f32| -> Vec<f32> {
+            y.iter().zip(k).map(|(y, k)| y + c * k).collect()
+        };
+        let shift_s = |s: &[Vec<f32>], k: &[Vec<f32>], c: f32| -> Vec<Vec<f32>> {
+            s.iter().zip(k).map(|(s, k)| shift(s, k, c)).collect()
+        };
+
+        for step in 0..steps {
+            let (k1, l1) = self.augmented(&mut at, t, &y, &s);
+            let (k2, l2) = self.augmented(
+                &mut at,
+                t + h / 2f32,
+                &shift(&y, &k1, h / 2f32),
+                &shift_s(&s, &l1, h / 2f32),
+            );
+            let (k3, l3) = self.augmented(
+                &mut at,
+                t + h / 2f32,
+                &shift(&y, &k2, h / 2f32),
+                &shift_s(&s, &l2, h / 2f32),
+            );
+            let (k4, l4) = self.augmented(&mut at, t + h, &shift(&y, &k3, h), &shift_s(&s, &l3, h));
+
+            for i in 0..n {
+                y[i] += h / 6f32 * (k1[i] + 2f32 * k2[i] + 2f32 * k3[i] + k4[i]);
+                for j in 0..m {
+                    s[i][j] += h / 6f32 * (l1[i][j] + 2f32 * l2[i][j] + 2f32 * l3[i][j] + l4[i][j]);
+                }
+            }
+            // no drift from accumulating h
+            t = t0 + h * (step + 1) as f32;
+
+            trajectory.times.push(t);
+            trajectory.states.push(y.clone());
+            trajectory.sensitivities.push(s.clone());
+        }
+
+        trajectory
+    }
+}
+
+#[test]
+fn exponential_decay_sensitivity() {
+    use {constant, mul, var, Arena};
+
+    let arena = Arena::new();
+    // y' = -k y, y(t) = y0 exp(-k t), dy/dk = -t y
+    let y = var(&arena, "y".to_string());
+    let k = var(&arena, "k".to_string());
+    let rhs = mul(&arena, constant(&arena, -1.0), mul(&arena, k, y));
+
+    let ode = Ode::new(&[rhs], &["y"], &["k"]);
+    let trajectory = ode.solve(&[2.0], &[0.7], 0.0, 1.5, 30);
+    assert_eq!(trajectory.times.len(), 31);
+    assert_eq!(trajectory.times[30], 1.5);
+
+    let exact = 2.0 * (-0.7f32 * 1.5).exp();
+    assert!((trajectory.states[30][0] - exact).abs() < 1e-5);
+    assert!((trajectory.sensitivities[30][0][0] + 1.5 * exact).abs() < 1e-4);
+}
+
+#[test]
+fn forced_oscillator_against_finite_differences() {
+    use {cos, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    // x'' = -w x + cos(t) as a first-order system
+    let x = var(arena, "x".to_string());
+    let v = var(arena, "v".to_string());
+    let w = var(arena, "w".to_string());
+    let t = var(arena, "t".to_string());
+    let rhs = [v, sub(arena, cos(arena, t), mul(arena, w, x))];
+    let ode = Ode::new(&rhs, &["x", "v"], &["w"]).time("t");
+
+    let solve = |w: f32| ode.solve(&[1.0, 0.0], &[w], 0.0, 2.0, 200);
+    let trajectory = solve(3.0);
+    let eps = 1e-2;
+    let (plus, minus) = (solve(3.0 + eps), solve(3.0 - eps));
+    for i in 0..2 {
+        let fd = (plus.states[200][i] - minus.states[200][i]) / (2.0 * eps);
+        assert!((trajectory.sensitivities[200][i][0] - fd).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,379 @@
+// Fused operations implemented as custom ops, for formulas whose naive
+// composition is numerically poor.
+
+use {constant, custom, div, select, sub, Arena, CustomOp, Node};
+
+// numerically stable logistic function
+fn sigmoid(z: f32) -> f32 {
+    if z >= 0f32 {
+        1f32 / (1f32 + (-z).exp())
+    } else {
+        let e = z.exp();
+        e / (1f32 + e)
+    }
+}
+
+// Binary cross entropy of sigmoid(logit) against target,
+// max(z, 0) - z t + ln(1 + exp(-|z|)), which is finite for any logit.
+#[derive(Debug, Clone, Copy)]
+pub struct BceWithLogits;
+
+impl CustomOp for BceWithLogits {
+    fn name(&self) -> &str {
+        "BceWithLogits"
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        let (z, t) = (inputs[0], inputs[1]);
+        z.max(0f32) - z * t + (-z.abs()).exp().ln_1p()
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        let (z, t) = (inputs[0], inputs[1]);
+        vec![upstream * (sigmoid(z) - t), -upstream * z]
+    }
+}
+
+pub fn bce_with_logits<'a>(arena: &'a Arena<'a>, logit: Node<'a>, target: Node<'a>) -> Node<'a> {
+    custom(arena, BceWithLogits, &[logit, target])
+}
+
+// complementary error function, with relative error below 1.2e-7
+// (Numerical Recipes' erfcc)
+fn erfc(x: f64) -> f64 {
+    let t = 1f64 / (1f64 + 0.5 * x.abs());
+    let poly = -1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let ans = t * (-x * x + poly).exp();
+    if x >= 0f64 {
+        ans
+    } else {
+        2f64 - ans
+    }
+}
+
+// standardized value and the log density of N(mu, sigma^2) at x
+fn standardize(inputs: &[f32]) -> (f64, f64, f64) {
+    let (x, mu, sigma) = (inputs[0] as f64, inputs[1] as f64, inputs[2] as f64);
+    let z = (x - mu) / sigma;
+    let logpdf = -0.5 * z * z - sigma.ln() - 0.5 * (2f64 * ::std::f64::consts::PI).ln();
+    (z, sigma, logpdf)
+}
+
+// Density of the normal distribution, with inputs (x, mu, sigma).
+#[derive(Debug, Clone, Copy)]
+pub struct NormalPdf;
+
+impl CustomOp for NormalPdf {
+    fn name(&self) -> &str {
+        "NormalPdf"
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        standardize(inputs).2.exp() as f32
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        let (z, sigma, logpdf) = standardize(inputs);
+        let scale = upstream as f64 * logpdf.exp() / sigma;
+        vec![
+            (-z * scale) as f32,
+            (z * scale) as f32,
+            ((z * z - 1f64) * scale) as f32,
+        ]
+    }
+}
+
+// Log density of the normal distribution, with inputs (x, mu, sigma).
+#[derive(Debug, Clone, Copy)]
+pub struct NormalLogPdf;
+
+impl CustomOp for NormalLogPdf {
+    fn name(&self) -> &str {
+        "NormalLogPdf"
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        standardize(inputs).2 as f32
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        let (z, sigma, _) = standardize(inputs);
+        let scale = upstream as f64 / sigma;
+        vec![
+            (-z * scale) as f32,
+            (z * scale) as f32,
+            ((z * z - 1f64) * scale) as f32,
+        ]
+    }
+}
+
+// Distribution function of the normal distribution, with inputs
+// (x, mu, sigma).
+#[derive(Debug, Clone, Copy)]
+pub struct NormalCdf;
+
+impl CustomOp for NormalCdf {
+    fn name(&self) -> &str {
+        "NormalCdf"
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        let (z, _, _) = standardize(inputs);
+        (0.5 * erfc(-z / 2f64.sqrt())) as f32
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        let (z, _, logpdf) = standardize(inputs);
+        let pdf = upstream as f64 * logpdf.exp();
+        vec![pdf as f32, -pdf as f32, (-pdf * z) as f32]
+    }
+}
+
+pub fn normal_pdf<'a>(
+    arena: &'a Arena<'a>,
+    x: Node<'a>,
+    mu: Node<'a>,
+    sigma: Node<'a>,
+) -> Node<'a> {
+    custom(arena, NormalPdf, &[x, mu, sigma])
+}
+
+pub fn normal_logpdf<'a>(
+    arena: &'a Arena<'a>,
+    x: Node<'a>,
+    mu: Node<'a>,
+    sigma: Node<'a>,
+) -> Node<'a> {
+    custom(arena, NormalLogPdf, &[x, mu, sigma])
+}
+
+pub fn normal_cdf<'a>(
+    arena: &'a Arena<'a>,
+    x: Node<'a>,
+    mu: Node<'a>,
+    sigma: Node<'a>,
+) -> Node<'a> {
+    custom(arena, NormalCdf, &[x, mu, sigma])
+}
+
+// Euclidean norm of the inputs. The gradient is x / max(norm, eps), so it
+// stays finite (and is zero) at the origin.
+#[derive(Debug, Clone, Copy)]
+pub struct L2Norm {
+    pub eps: f32,
+}
+
+impl CustomOp for L2Norm {
+    fn name(&self) -> &str {
+        "L2Norm"
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        inputs.iter().map(|x| x * x).sum::<f32>().sqrt()
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        let scale = upstream / self.forward(inputs).max(self.eps);
+        inputs.iter().map(|x| x * scale).collect()
+    }
+
+    // the cone's apex
+    fn is_kink(&self, inputs: &[f32], eps: f32) -> bool {
+        inputs.iter().all(|x| x.abs() < eps)
+    }
+}
+
+pub fn l2_norm<'a>(arena: &'a Arena<'a>, xs: &[Node<'a>], eps: f32) -> Node<'a> {
+    custom(arena, L2Norm { eps }, xs)
+}
+
+// xs / max(|xs|, eps), which maps the origin to itself instead of NaN.
+pub fn normalize<'a>(arena: &'a Arena<'a>, xs: &[Node<'a>], eps: f32) -> Vec<Node<'a>> {
+    let norm = l2_norm(arena, xs, eps);
+    let floor = constant(arena, eps);
+    let norm = select(arena, sub(arena, norm, floor), norm, floor);
+    xs.iter().map(|&x| div(arena, x, norm)).collect()
+}
+
+// Gradient used in place of the true derivative of a step, which is zero
+// everywhere except at the jump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Surrogate {
+    // the true derivative away from the jump
+    Zero,
+    // derivative of sigmoid(x / temperature)
+    Sigmoid { temperature: f32 },
+    // passes the upstream gradient through unchanged
+    StraightThrough,
+}
+
+// 1 for positive inputs and 0 otherwise, differentiated with a surrogate.
+#[derive(Debug, Clone, Copy)]
+pub struct Heaviside {
+    pub surrogate: Surrogate,
+}
+
+impl CustomOp for Heaviside {
+    fn name(&self) -> &str {
+        "Heaviside"
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        if inputs[0] > 0f32 {
+            1f32
+        } else {
+            0f32
+        }
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        let partial = match self.surrogate {
+            Surrogate::Zero => 0f32,
+            Surrogate::Sigmoid { temperature } => {
+                let s = sigmoid(inputs[0] / temperature);
+                s * (1f32 - s) / temperature
+            }
+            Surrogate::StraightThrough => 1f32,
+        };
+        vec![upstream * partial]
+    }
+
+    fn is_kink(&self, inputs: &[f32], eps: f32) -> bool {
+        inputs[0].abs() < eps
+    }
+}
+
+pub fn heaviside<'a>(arena: &'a Arena<'a>, x: Node<'a>, surrogate: Surrogate) -> Node<'a> {
+    custom(arena, Heaviside { surrogate }, &[x])
+}
+
+#[test]
+fn bce_with_large_logits() {
+    use {constant, var};
+
+    let arena = Arena::new();
+    let z = var(&arena, "z".to_string());
+    let loss = bce_with_logits(&arena, z, constant(&arena, 1f32));
+
+    for &(logit, value, grad) in &[
+        (100f32, 0f32, 0f32),
+        (-100f32, 100f32, -1f32),
+        (0f32, 2f32.ln(), -0.5f32),
+    ] {
+        z.set_value(logit);
+        loss.forward();
+        loss.backward_ad(&["z"]);
+        assert!((loss.value() - value).abs() < 1e-6);
+        assert!((loss.grad("z") - grad).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn normal_distribution() {
+    use var;
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let mu = var(&arena, "mu".to_string());
+    let sigma = var(&arena, "sigma".to_string());
+    x.set_value(1.5);
+    mu.set_value(0.5);
+    sigma.set_value(2.0);
+
+    let pdf = normal_pdf(&arena, x, mu, sigma);
+    let logpdf = normal_logpdf(&arena, x, mu, sigma);
+    let cdf = normal_cdf(&arena, x, mu, sigma);
+    let variables = ["x", "mu", "sigma"];
+
+    // N(0.5, 4) at 1.5: z = 0.5
+    pdf.forward();
+    assert!((pdf.value() - 0.176_032_66).abs() < 1e-6);
+    logpdf.forward();
+    assert!((logpdf.value() - 0.176_032_66f32.ln()).abs() < 1e-5);
+    cdf.forward();
+    assert!((cdf.value() - 0.691_462_5).abs() < 1e-6);
+
+    // every partial agrees with central differences
+    for &node in &[pdf, logpdf, cdf] {
+        node.forward();
+        node.backward_ad(&variables);
+        for (v, input) in variables.iter().zip(&[x, mu, sigma]) {
+            let at = input.value();
+            let h = 1e-2;
+            input.set_value(at + h);
+            node.forward();
+            let plus = node.value();
+            input.set_value(at - h);
+            node.forward();
+            let minus = node.value();
+            input.set_value(at);
+            assert!((node.grad(v) - (plus - minus) / (2.0 * h)).abs() < 1e-3);
+        }
+    }
+}
+
+#[test]
+fn norm_is_safe_at_origin() {
+    use var;
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let y = var(&arena, "y".to_string());
+    let norm = l2_norm(&arena, &[x, y], 1e-6);
+    let unit = normalize(&arena, &[x, y], 1e-6);
+
+    x.set_value(3.0);
+    y.set_value(-4.0);
+    norm.forward();
+    norm.backward_ad(&["x", "y"]);
+    assert_eq!(norm.value(), 5.0);
+    assert_eq!((norm.grad("x"), norm.grad("y")), (0.6, -0.8));
+    unit[0].forward();
+    assert_eq!(unit[0].value(), 0.6);
+    // d(x / |v|)/dx = (|v|^2 - x^2) / |v|^3
+    assert!((unit[0].gradients(&["x"])["x"] - 16.0 / 125.0).abs() < 1e-6);
+
+    x.set_value(0.0);
+    y.set_value(0.0);
+    norm.forward();
+    norm.backward_ad(&["x", "y"]);
+    assert_eq!(norm.value(), 0.0);
+    assert_eq!((norm.grad("x"), norm.grad("y")), (0.0, 0.0));
+    for u in &unit {
+        u.forward();
+        assert_eq!(u.value(), 0.0);
+        assert!(u
+            .gradients(&["x", "y"])
+            .values()
+            .iter()
+            .all(|g| g.is_finite()));
+    }
+}
+
+#[test]
+fn heaviside_surrogates() {
+    use var;
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    x.set_value(0.0);
+
+    for &(surrogate, grad) in &[
+        (Surrogate::Zero, 0f32),
+        (Surrogate::Sigmoid { temperature: 0.5 }, 0.5f32),
+        (Surrogate::StraightThrough, 1f32),
+    ] {
+        let step = heaviside(&arena, x, surrogate);
+        step.forward();
+        step.backward_ad(&["x"]);
+        assert_eq!(step.value(), 0.0);
+        assert_eq!(step.grad("x"), grad);
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use super::clip::clipped;
+use super::state::{Reader, Writer, ADAM};
+use super::{param_name, GradClip, Optimizer, Persistent, StateError};
+use {GradientValues, Node};
+
+#[derive(Debug, Clone, Default)]
+struct Moments {
+    first: f32,
+    second: f32,
+}
+
+// Adam with optional weight decay. With `decoupled_weight_decay` the decay
+// is applied directly to the parameter (AdamW) instead of being folded into
+// the gradient.
+#[derive(Debug, Clone)]
+pub struct Adam {
+    pub lr: f32,
+    pub beta1: f32,
+    pub beta2: f32,
+    pub eps: f32,
+    pub weight_decay: f32,
+    pub decoupled_weight_decay: bool,
+    pub clip: Option<GradClip>,
+    steps: i32,
+    moments: HashMap<String, Moments>,
+}
+
+impl Adam {
+    pub fn new(lr: f32) -> Self {
+        Adam {
+            lr,
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            weight_decay: 0f32,
+            decoupled_weight_decay: false,
+            clip: None,
+            steps: 0,
+            moments: HashMap::new(),
+        }
+    }
+
+    pub fn adamw(lr: f32, weight_decay: f32) -> Self {
+        Adam {
+            weight_decay,
+            decoupled_weight_decay: true,
+            ..Adam::new(lr)
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self, params: &[Node], grads: &dyn GradientValues) {
+        let clipped = clipped(self.clip, grads);
+        let grads = clipped.as_ref().map_or(grads, |g| g as &dyn GradientValues);
+        self.steps += 1;
+        let bias1 = 1f32 - self.beta1.powi(self.steps);
+        let bias2 = 1f32 - self.beta2.powi(self.steps);
+
+        for param in params {
+            let name = param_name(param);
+            let mut value = param.value();
+            let mut grad = grads.get(name).unwrap_or(0f32);
+
+            if self.decoupled_weight_decay {
+                value -= self.lr * self.weight_decay * value;
+            } else {
+                grad += self.weight_decay * value;
+            }
+
+            let moments = self.moments.entry(name.to_string()).or_default();
+            moments.first = self.beta1 * moments.first + (1f32 - self.beta1) * grad;
+            moments.second = self.beta2 * moments.second + (1f32 - self.beta2) * grad * grad;
+
+            let first = moments.first / bias1;
+            let second = moments.second / bias2;
+            param.set_value(value - self.lr * first / (second.sqrt() + self.eps));
+        }
+    }
+
+    fn learning_rate(&self) -> f32 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f32) {
+        self.lr = lr;
+    }
+}
+
+impl Persistent for Adam {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new(ADAM);
+        for &value in &[self.lr, self.beta1, self.beta2, self.eps, self.weight_decay] {
+            writer.f32(value);
+        }
+        writer.u32(self.decoupled_weight_decay as u32);
+        writer.clip(self.clip);
+        writer.u32(self.steps as u32);
+        writer.map(&self.moments, |writer, moments| {
+            writer.f32(moments.first);
+            writer.f32(moments.second);
+        });
+        writer.finish()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut reader = Reader::new(bytes, ADAM)?;
+        let adam = Adam {
+            lr: reader.f32()?,
+            beta1: reader.f32()?,
+            beta2: reader.f32()?,
+            eps: reader.f32()?,
+            weight_decay: reader.f32()?,
+            decoupled_weight_decay: reader.u32()? != 0,
+            clip: reader.clip()?,
+            steps: reader.u32()? as i32,
+            moments: reader.map(|reader| {
+                Ok(Moments {
+                    first: reader.f32()?,
+                    second: reader.f32()?,
+                })
+            })?,
+        };
+        reader.finish(adam)
+    }
+}
+
+#[test]
+fn adam_minimizes_quadratic() {
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let dx = sub(arena, x, constant(arena, 3f32));
+    let dy = sub(arena, y, constant(arena, -1f32));
+    let loss = add(arena, mul(arena, dx, dx), mul(arena, dy, dy));
+
+    for adam in &mut [Adam::new(0.05), Adam::adamw(0.05, 1e-4)] {
+        x.set_value(0f32);
+        y.set_value(0f32);
+        for _ in 0..1000 {
+            loss.forward();
+            loss.backward_ad(&["x", "y"]);
+            adam.step(&[x, y], &*loss.grads());
+        }
+
+        assert!((x.value() - 3f32).abs() < 1e-2);
+        assert!((y.value() + 1f32).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt::Write;
+
+// The state after an iteration of a minimizer, passed to callbacks. Step 0
+// is the starting point.
+#[derive(Debug, Clone, Copy)]
+pub struct Iteration<'p> {
+    pub step: usize,
+    pub objective: f32,
+    pub grad_norm: f32,
+    pub params: &'p [f32],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub step: usize,
+    pub objective: f32,
+    pub grad_norm: f32,
+    pub params: Vec<f32>,
+}
+
+// Iterations kept for plotting convergence curves, e.g. with
+// `|iteration| log.record(iteration)` as the callback.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrajectoryLog {
+    pub records: Vec<Record>,
+}
+
+impl TrajectoryLog {
+    pub fn new() -> Self {
+        TrajectoryLog::default()
+    }
+
+    pub fn record(&mut self, iteration: &Iteration) {
+        self.records.push(Record {
+            step: iteration.step,
+            objective: iteration.objective,
+            grad_norm: iteration.grad_norm,
+            params: iteration.params.to_vec(),
+        });
+    }
+
+    // one row per iteration: step, objective, gradient norm, then the
+    // parameters in order
+    pub fn to_csv(&self) -> String {
+        let n = self.records.first().map_or(0, |r| r.params.len());
+        let mut csv = String::from("step,objective,grad_norm");
+        for i in 0..n {
+            write!(csv, ",p{}", i).unwrap();
+        }
+        csv.push('\n');
+        for r in &self.records {
+            write!(csv, "{},{},{}", r.step, r.objective, r.grad_norm).unwrap();
+            for p in &r.params {
+                write!(csv, ",{}", p).unwrap();
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    // an array of objects, with non-finite numbers as null
+    pub fn to_json(&self) -> String {
+        fn number(v: f32) -> String {
+            if v.is_finite() {
+                format!("{}", v)
+            } else {
+                "null".to_string()
+            }
+        }
+
+        let records: Vec<String> = self
+            .records
+            .iter()
+            .map(|r| {
+                let params: Vec<String> = r.params.iter().map(|&p| number(p)).collect();
+                format!(
+                    "{{\"step\":{},\"objective\":{},\"grad_norm\":{},\"params\":[{}]}}",
+                    r.step,
+                    number(r.objective),
+                    number(r.grad_norm),
+                    params.join(",")
+                )
+            })
+            .collect();
+        format!("[{}]", records.join(","))
+    }
+}
+
+#[test]
+fn trajectory_formats() {
+    let mut log = TrajectoryLog::new();
+    log.record(&Iteration {
+        step: 0,
+        objective: 2.5,
+        grad_norm: 1.0,
+        params: &[1.0, -2.0],
+    });
+    log.record(&Iteration {
+        step: 1,
+        objective: f32::NAN,
+        grad_norm: 0.5,
+        params: &[0.5, -1.0],
+    });
+
+    assert_eq!(
+        log.to_csv(),
+        "step,objective,grad_norm,p0,p1\n0,2.5,1,1,-2\n1,NaN,0.5,0.5,-1\n"
+    );
+    assert_eq!(
+        log.to_json(),
+        "[{\"step\":0,\"objective\":2.5,\"grad_norm\":1,\"params\":[1,-2]},\
+         {\"step\":1,\"objective\":null,\"grad_norm\":0.5,\"params\":[0.5,-1]}]"
+    );
+}
@@ -0,0 +1,74 @@
+use {GradientValues, Gradients};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradClip {
+    Norm(f32),
+    Value(f32),
+}
+
+impl GradClip {
+    pub fn apply<G: GradientValues + ?Sized>(&self, grads: &mut G) {
+        match *self {
+            GradClip::Norm(max_norm) => {
+                clip_grads_by_norm(grads, max_norm);
+            }
+            GradClip::Value(limit) => clip_by_value(grads, limit),
+        }
+    }
+}
+
+// Rescales `grads` so that their joint L2 norm is at most `max_norm` and
+// returns the norm before clipping.
+pub fn clip_grads_by_norm<G: GradientValues + ?Sized>(grads: &mut G, max_norm: f32) -> f32 {
+    let norm = grads.iter().map(|(_, g)| g * g).sum::<f32>().sqrt();
+    if norm > max_norm {
+        let scale = max_norm / norm;
+        for grad in grads.values_mut() {
+            *grad *= scale;
+        }
+    }
+    norm
+}
+
+// Clamps every gradient into [-limit, limit].
+pub fn clip_by_value<G: GradientValues + ?Sized>(grads: &mut G, limit: f32) {
+    for grad in grads.values_mut() {
+        *grad = grad.max(-limit).min(limit);
+    }
+}
+
+// a clipped copy of `grads`, if there is anything to clip
+pub(super) fn clipped(clip: Option<GradClip>, grads: &dyn GradientValues) -> Option<Gradients> {
+    clip.map(|clip| {
+        let (names, values): (Vec<&str>, Vec<f32>) = grads.iter().unzip();
+        let mut grads = Gradients::new(&names, values);
+        clip.apply(&mut grads);
+        grads
+    })
+}
+
+#[test]
+fn clipping() {
+    use std::collections::HashMap;
+
+    let mut grads = HashMap::new();
+    grads.insert("x".to_string(), 3f32);
+    grads.insert("y".to_string(), -4f32);
+
+    let mut by_norm = grads.clone();
+    assert_eq!(clip_grads_by_norm(&mut by_norm, 1f32), 5f32);
+    assert!((by_norm["x"] - 0.6).abs() < 1e-6);
+    assert!((by_norm["y"] + 0.8).abs() < 1e-6);
+
+    let mut by_value = grads.clone();
+    clip_by_value(&mut by_value, 3.5);
+    assert_eq!(by_value["x"], 3f32);
+    assert_eq!(by_value["y"], -3.5);
+
+    // the gradients returned by value clip the same way
+    let mut returned = Gradients::new(&["x", "y"], vec![3f32, -4f32]);
+    assert_eq!(clip_grads_by_norm(&mut returned, 1f32), 5f32);
+    assert!((returned["x"] - 0.6).abs() < 1e-6);
+    clip_by_value(&mut returned, 0.7);
+    assert_eq!(returned.values(), &[0.6f32, -0.7f32][..]);
+}
@@ -0,0 +1,238 @@
+use super::{value_and_grad, Lbfgs, MinimizeOptions};
+use Node;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Constraint<'a> {
+    // node == 0
+    Eq(Node<'a>),
+    // node <= 0
+    Ineq(Node<'a>),
+}
+
+impl<'a> Constraint<'a> {
+    fn node(&self) -> Node<'a> {
+        match *self {
+            Constraint::Eq(node) | Constraint::Ineq(node) => node,
+        }
+    }
+
+    fn violation(&self, value: f32) -> f32 {
+        match *self {
+            Constraint::Eq(_) => value.abs(),
+            Constraint::Ineq(_) => value.max(0f32),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstrainedOptions {
+    // options of every unconstrained subproblem
+    pub inner: MinimizeOptions,
+    pub max_outer_iters: usize,
+    pub initial_penalty: f32,
+    pub penalty_growth: f32,
+    // largest constraint violation accepted as feasible
+    pub constraint_tol: f32,
+}
+
+impl Default for ConstrainedOptions {
+    fn default() -> Self {
+        ConstrainedOptions {
+            inner: MinimizeOptions::default(),
+            max_outer_iters: 30,
+            initial_penalty: 10f32,
+            penalty_growth: 10f32,
+            constraint_tol: 1e-4,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstrainedResult {
+    pub x: Vec<f32>,
+    pub value: f32,
+    pub max_violation: f32,
+    // Lagrange multiplier estimates, one per constraint
+    pub multipliers: Vec<f32>,
+    pub outer_iterations: usize,
+    pub converged: bool,
+}
+
+// Minimizes `node` subject to `constraints` with the augmented Lagrangian
+// method, solving each subproblem with L-BFGS.
+pub fn minimize_constrained<'a>(
+    node: Node<'a>,
+    vars: &[Node<'a>],
+    initial_guess: &[f32],
+    constraints: &[Constraint<'a>],
+    opts: &ConstrainedOptions,
+) -> ConstrainedResult {
+    let mut objective = value_and_grad(node, vars);
+    let mut constraint_fns: Vec<_> = constraints
+        .iter()
+        .map(|constraint| value_and_grad(constraint.node(), vars))
+        .collect();
+
+    let lbfgs = Lbfgs {
+        max_iters: opts.inner.max_iters,
+        grad_tol: opts.inner.grad_tol,
+        value_tol: opts.inner.value_tol,
+        time_budget: opts.inner.time_budget,
+        ..Lbfgs::new()
+    };
+
+    let mut x = initial_guess.to_vec();
+    let mut multipliers = vec![0f32; constraints.len()];
+    let mut penalty = opts.initial_penalty;
+    let mut last_violation = f32::INFINITY;
+
+    for outer in 0..opts.max_outer_iters {
+        let result = {
+            let multipliers = &multipliers;
+            let constraint_fns = &mut constraint_fns;
+            let objective = &mut objective;
+            let augmented = move |values: &[f32]| {
+                let (mut value, mut grad) = objective(values);
+                for ((constraint, f), lambda) in constraints
+                    .iter()
+                    .zip(constraint_fns.iter_mut())
+                    .zip(multipliers)
+                {
+                    let (c, c_grad) = f(values);
+                    let (term, scale) = match *constraint {
+                        Constraint::Eq(_) => {
+                            (lambda * c + penalty / 2f32 * c * c, lambda + penalty * c)
+                        }
+                        Constraint::Ineq(_) => {
+                            let shifted = (lambda + penalty * c).max(0f32);
+                            (
+                                (shifted * shifted - lambda * lambda) / (2f32 * penalty),
+                                shifted,
+                            )
+                        }
+                    };
+                    value += term;
+                    for (g, cg) in grad.iter_mut().zip(&c_grad) {
+                        *g += scale * cg;
+                    }
+                }
+                (value, grad)
+            };
+            lbfgs.minimize(augmented, &x)
+        };
+        x = result.x;
+
+        let mut max_violation = 0f32;
+        for ((constraint, f), lambda) in constraints
+            .iter()
+            .zip(constraint_fns.iter_mut())
+            .zip(multipliers.iter_mut())
+        {
+            let c = f(&x).0;
+            max_violation = max_violation.max(constraint.violation(c));
+            *lambda = match *constraint {
+                Constraint::Eq(_) => *lambda + penalty * c,
+                Constraint::Ineq(_) => (*lambda + penalty * c).max(0f32),
+            };
+        }
+
+        if max_violation <= opts.constraint_tol && result.converged {
+            let value = objective(&x).0;
+            return ConstrainedResult {
+                x,
+                value,
+                max_violation,
+                multipliers,
+                outer_iterations: outer + 1,
+                converged: true,
+            };
+        }
+
+        if max_violation > 0.25 * last_violation {
+            penalty *= opts.penalty_growth;
+        }
+        last_violation = max_violation;
+    }
+
+    let value = objective(&x).0;
+    let max_violation = constraints
+        .iter()
+        .zip(constraint_fns.iter_mut())
+        .map(|(constraint, f)| constraint.violation(f(&x).0))
+        .fold(0f32, f32::max);
+    ConstrainedResult {
+        x,
+        value,
+        max_violation,
+        multipliers,
+        outer_iterations: opts.max_outer_iters,
+        converged: false,
+    }
+}
+
+#[test]
+fn sum_to_one_and_upper_bound() {
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let one = constant(arena, 1f32);
+
+    // min x^2 + 2 y^2 s.t. x + y = 1 and x <= 0.5
+    let f = add(
+        arena,
+        mul(arena, x, x),
+        mul(arena, constant(arena, 2f32), mul(arena, y, y)),
+    );
+    let sum_to_one = sub(arena, add(arena, x, y), one);
+    let upper = sub(arena, x, constant(arena, 0.5));
+
+    let result = minimize_constrained(
+        f,
+        &[x, y],
+        &[0f32, 0f32],
+        &[Constraint::Eq(sum_to_one), Constraint::Ineq(upper)],
+        &Default::default(),
+    );
+
+    assert!(result.converged);
+    assert!((result.x[0] - 0.5).abs() < 1e-3);
+    assert!((result.x[1] - 0.5).abs() < 1e-3);
+    assert!(result.multipliers[1] > 0f32);
+}
+
+#[test]
+fn inner_time_budget_applies() {
+    use std::time::Duration;
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let f = add(arena, mul(arena, x, x), mul(arena, y, y));
+    let sum_to_one = sub(arena, add(arena, x, y), constant(arena, 1f32));
+
+    let opts = ConstrainedOptions {
+        inner: MinimizeOptions {
+            time_budget: Some(Duration::from_secs(0)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let result = minimize_constrained(
+        f,
+        &[x, y],
+        &[0f32, 0f32],
+        &[Constraint::Eq(sum_to_one)],
+        &opts,
+    );
+
+    // every subproblem stops before its first step
+    assert_eq!(result.x, vec![0f32, 0f32]);
+    assert!(!result.converged);
+}
@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{param_name, Optimizer};
+use {GradientValues, Node};
+
+// Parameters split into groups, each stepped by its own optimizer so that
+// groups can have different learning rates or weight decay. Parameters in
+// no group use the default optimizer. Frozen parameters keep their values
+// (they still take part in the forward pass) but are never updated.
+#[derive(Debug, Clone)]
+pub struct ParamGroups<O> {
+    default: O,
+    groups: Vec<O>,
+    membership: HashMap<String, usize>,
+    frozen: HashSet<String>,
+}
+
+impl<O: Optimizer> ParamGroups<O> {
+    pub fn new(default: O) -> Self {
+        ParamGroups {
+            default,
+            groups: vec![],
+            membership: HashMap::new(),
+            frozen: HashSet::new(),
+        }
+    }
+
+    // Moves the named parameters into a new group and returns its index.
+    pub fn add_group(&mut self, names: &[&str], optimizer: O) -> usize {
+        let index = self.groups.len();
+        self.groups.push(optimizer);
+        for name in names {
+            self.membership.insert(name.to_string(), index);
+        }
+        index
+    }
+
+    pub fn group(&self, index: usize) -> &O {
+        &self.groups[index]
+    }
+
+    pub fn group_mut(&mut self, index: usize) -> &mut O {
+        &mut self.groups[index]
+    }
+
+    pub fn freeze(&mut self, names: &[&str]) {
+        self.frozen
+            .extend(names.iter().map(|name| name.to_string()));
+    }
+
+    pub fn unfreeze(&mut self, names: &[&str]) {
+        for name in names {
+            self.frozen.remove(*name);
+        }
+    }
+
+    // freezes every parameter of the group
+    pub fn freeze_group(&mut self, index: usize) {
+        let names = self.names_in(index);
+        self.frozen.extend(names);
+    }
+
+    pub fn unfreeze_group(&mut self, index: usize) {
+        for name in self.names_in(index) {
+            self.frozen.remove(&name);
+        }
+    }
+
+    pub fn is_frozen(&self, name: &str) -> bool {
+        self.frozen.contains(name)
+    }
+
+    fn names_in(&self, index: usize) -> Vec<String> {
+        self.membership
+            .iter()
+            .filter(|&(_, &group)| group == index)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+impl<O: Optimizer> Optimizer for ParamGroups<O> {
+    fn step(&mut self, params: &[Node], grads: &dyn GradientValues) {
+        let mut default = vec![];
+        let mut grouped = vec![vec![]; self.groups.len()];
+        for &param in params {
+            let name = param_name(param);
+            if self.frozen.contains(name) {
+                continue;
+            }
+            match self.membership.get(name) {
+                Some(&index) => grouped[index].push(param),
+                None => default.push(param),
+            }
+        }
+
+        // optimizers still see empty steps so that their step counts agree
+        self.default.step(&default, grads);
+        for (optimizer, params) in self.groups.iter_mut().zip(&grouped) {
+            optimizer.step(params, grads);
+        }
+    }
+
+    fn learning_rate(&self) -> f32 {
+        self.default.learning_rate()
+    }
+
+    // scales every group by the same factor as the default rate, keeping
+    // the ratios between groups
+    fn set_learning_rate(&mut self, lr: f32) {
+        let scale = lr / self.default.learning_rate();
+        for optimizer in &mut self.groups {
+            let group_lr = optimizer.learning_rate() * scale;
+            optimizer.set_learning_rate(group_lr);
+        }
+        self.default.set_learning_rate(lr);
+    }
+}
+
+#[test]
+fn staged_fitting() {
+    use super::Sgd;
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let a = var(arena, "a".to_string());
+    let b = var(arena, "b".to_string());
+    let da = sub(arena, a, constant(arena, 1f32));
+    let db = sub(arena, b, constant(arena, 2f32));
+    let loss = add(arena, mul(arena, da, da), mul(arena, db, db));
+    let train = |optimizer: &mut ParamGroups<Sgd>| {
+        for _ in 0..200 {
+            loss.forward();
+            loss.backward_ad(&["a", "b"]);
+            optimizer.step(&[a, b], &*loss.grads());
+        }
+    };
+
+    a.set_value(0f32);
+    b.set_value(0f32);
+    let mut optimizer = ParamGroups::new(Sgd::new(0.1, 0f32));
+    let slow = optimizer.add_group(&["b"], Sgd::new(0.01, 0f32));
+
+    // fit a alone, then b
+    optimizer.freeze_group(slow);
+    train(&mut optimizer);
+    assert!((a.value() - 1f32).abs() < 1e-4);
+    assert_eq!(b.value(), 0f32);
+
+    optimizer.unfreeze_group(slow);
+    optimizer.freeze(&["a"]);
+    assert!(optimizer.is_frozen("a") && !optimizer.is_frozen("b"));
+    let fitted_a = a.value();
+    train(&mut optimizer);
+    assert_eq!(a.value(), fitted_a);
+    assert!((b.value() - 2f32).abs() < 0.1);
+
+    optimizer.set_learning_rate(0.2);
+    assert_eq!(optimizer.learning_rate(), 0.2);
+    assert!((optimizer.group(slow).learning_rate() - 0.02).abs() < 1e-7);
+}
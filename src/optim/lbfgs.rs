@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::state::{Reader, Writer, LBFGS};
+use super::stopping::Deadline;
+use super::{dot, norm, strong_wolfe, Iteration, Persistent, StateError, StopReason, StrongWolfe};
+
+#[derive(Debug, Clone)]
+pub struct Lbfgs {
+    pub history_size: usize,
+    pub max_iters: usize,
+    // stop when the gradient norm drops below this
+    pub grad_tol: f32,
+    // stop when the objective changes less than this (relative)
+    pub value_tol: f32,
+    // stop after this much wall-clock time
+    pub time_budget: Option<Duration>,
+    pub line_search: StrongWolfe,
+}
+
+#[derive(Debug, Clone)]
+pub struct LbfgsResult {
+    pub x: Vec<f32>,
+    pub value: f32,
+    pub grad_norm: f32,
+    pub iterations: usize,
+    pub converged: bool,
+    pub stopped_by: StopReason,
+    // curvature pairs at `x`, for continuing with `resume`
+    pub history: LbfgsHistory,
+}
+
+impl Default for Lbfgs {
+    fn default() -> Self {
+        Lbfgs {
+            history_size: 10,
+            max_iters: 100,
+            grad_tol: 1e-5,
+            value_tol: 1e-7,
+            time_budget: None,
+            line_search: StrongWolfe::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Correction {
+    s: Vec<f32>,
+    y: Vec<f32>,
+    rho: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LbfgsHistory {
+    corrections: VecDeque<Correction>,
+}
+
+impl LbfgsHistory {
+    pub fn len(&self) -> usize {
+        self.corrections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.corrections.is_empty()
+    }
+}
+
+impl Persistent for LbfgsHistory {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new(LBFGS);
+        writer.u32(self.corrections.len() as u32);
+        for correction in &self.corrections {
+            writer.floats(&correction.s);
+            writer.floats(&correction.y);
+            writer.f32(correction.rho);
+        }
+        writer.finish()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut reader = Reader::new(bytes, LBFGS)?;
+        let corrections = (0..reader.u32()?)
+            .map(|_| {
+                Ok(Correction {
+                    s: reader.floats()?,
+                    y: reader.floats()?,
+                    rho: reader.f32()?,
+                })
+            })
+            .collect::<Result<_, StateError>>()?;
+        reader.finish(LbfgsHistory { corrections })
+    }
+}
+
+impl Lbfgs {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn minimize<F>(&self, f: F, x0: &[f32]) -> LbfgsResult
+    where
+        F: FnMut(&[f32]) -> (f32, Vec<f32>),
+    {
+        self.minimize_with_callback(f, x0, &mut |_| {})
+    }
+
+    // Like `minimize`, calling `callback` with the starting point and after
+    // every iteration.
+    pub fn minimize_with_callback<F>(
+        &self,
+        f: F,
+        x0: &[f32],
+        callback: &mut dyn FnMut(&Iteration),
+    ) -> LbfgsResult
+    where
+        F: FnMut(&[f32]) -> (f32, Vec<f32>),
+    {
+        self.run(f, x0, LbfgsHistory::default(), callback)
+    }
+
+    // Continues from `x0` with the curvature pairs of an earlier run, e.g.
+    // `LbfgsResult::history` restored from bytes.
+    pub fn resume<F>(&self, f: F, x0: &[f32], history: LbfgsHistory) -> LbfgsResult
+    where
+        F: FnMut(&[f32]) -> (f32, Vec<f32>),
+    {
+        self.run(f, x0, history, &mut |_| {})
+    }
+
+    fn run<F>(
+        &self,
+        mut f: F,
+        x0: &[f32],
+        history: LbfgsHistory,
+        callback: &mut dyn FnMut(&Iteration),
+    ) -> LbfgsResult
+    where
+        F: FnMut(&[f32]) -> (f32, Vec<f32>),
+    {
+        let deadline = Deadline::after(self.time_budget);
+        let mut x = x0.to_vec();
+        let (mut value, mut grad) = f(&x);
+        callback(&Iteration {
+            step: 0,
+            objective: value,
+            grad_norm: norm(&grad),
+            params: &x,
+        });
+        let mut history = history.corrections;
+        while history.len() > self.history_size {
+            history.pop_front();
+        }
+
+        for iteration in 0..self.max_iters {
+            if norm(&grad) <= self.grad_tol {
+                return self.result(
+                    x,
+                    value,
+                    &grad,
+                    history,
+                    iteration,
+                    StopReason::GradientNorm,
+                );
+            }
+            if deadline.passed() {
+                return self.result(x, value, &grad, history, iteration, StopReason::TimeBudget);
+            }
+
+            let mut direction = self.direction(&grad, &history);
+            if dot(&grad, &direction) >= 0f32 {
+                // the curvature information went bad, restart from steepest descent
+                history.clear();
+                direction = grad.iter().map(|g| -g).collect();
+            }
+
+            let (next_x, next_value, next_grad) =
+                match strong_wolfe(&mut f, &x, &direction, value, &grad, &self.line_search) {
+                    Some(point) => (point.x, point.value, point.grad),
+                    None => {
+                        return self.result(
+                            x,
+                            value,
+                            &grad,
+                            history,
+                            iteration,
+                            StopReason::Stalled,
+                        )
+                    }
+                };
+
+            let s: Vec<f32> = next_x.iter().zip(&x).map(|(n, o)| n - o).collect();
+            let y: Vec<f32> = next_grad.iter().zip(&grad).map(|(n, o)| n - o).collect();
+            let sy = dot(&s, &y);
+            if sy > f32::EPSILON * dot(&y, &y) {
+                // with no history this is gradient descent with a line search
+                if self.history_size > 0 {
+                    if history.len() >= self.history_size {
+                        history.pop_front();
+                    }
+                    history.push_back(Correction {
+                        s,
+                        y,
+                        rho: 1f32 / sy,
+                    });
+                }
+            } else {
+                // only possible when the line search gave up on the curvature condition
+                history.clear();
+            }
+
+            let change = (value - next_value).abs();
+            x = next_x;
+            value = next_value;
+            grad = next_grad;
+            callback(&Iteration {
+                step: iteration + 1,
+                objective: value,
+                grad_norm: norm(&grad),
+                params: &x,
+            });
+
+            if change <= self.value_tol * value.abs().max(1f32) {
+                return self.result(
+                    x,
+                    value,
+                    &grad,
+                    history,
+                    iteration + 1,
+                    StopReason::ObjectiveChange,
+                );
+            }
+        }
+
+        let reason = if norm(&grad) <= self.grad_tol {
+            StopReason::GradientNorm
+        } else {
+            StopReason::MaxIterations
+        };
+        self.result(x, value, &grad, history, self.max_iters, reason)
+    }
+
+    // two-loop recursion approximating -H^{-1} grad
+    fn direction(&self, grad: &[f32], history: &VecDeque<Correction>) -> Vec<f32> {
+        let mut q = grad.to_vec();
+        let mut alphas = Vec::with_capacity(history.len());
+
+        for correction in history.iter().rev() {
+            let alpha = correction.rho * dot(&correction.s, &q);
+            for (q, y) in q.iter_mut().zip(&correction.y) {
+                *q -= alpha * y;
+            }
+            alphas.push(alpha);
+        }
+
+        if let Some(last) = history.back() {
+            let gamma = dot(&last.s, &last.y) / dot(&last.y, &last.y);
+            for q in q.iter_mut() {
+                *q *= gamma;
+            }
+        }
+
+        for (correction, alpha) in history.iter().zip(alphas.into_iter().rev()) {
+            let beta = correction.rho * dot(&correction.y, &q);
+            for (q, s) in q.iter_mut().zip(&correction.s) {
+                *q += s * (alpha - beta);
+            }
+        }
+
+        q.into_iter().map(|q| -q).collect()
+    }
+
+    fn result(
+        &self,
+        x: Vec<f32>,
+        value: f32,
+        grad: &[f32],
+        corrections: VecDeque<Correction>,
+        iterations: usize,
+        stopped_by: StopReason,
+    ) -> LbfgsResult {
+        LbfgsResult {
+            x,
+            value,
+            grad_norm: norm(grad),
+            iterations,
+            converged: stopped_by.is_converged(),
+            stopped_by,
+            history: LbfgsHistory { corrections },
+        }
+    }
+}
+
+#[test]
+fn lbfgs_minimizes_rosenbrock() {
+    use super::value_and_grad;
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let a = sub(arena, constant(arena, 1f32), x);
+    let b = sub(arena, y, mul(arena, x, x));
+    let rosenbrock = add(
+        arena,
+        mul(arena, a, a),
+        mul(arena, constant(arena, 100f32), mul(arena, b, b)),
+    );
+
+    let lbfgs = Lbfgs {
+        max_iters: 500,
+        ..Lbfgs::new()
+    };
+    let result = lbfgs.minimize(value_and_grad(rosenbrock, &[x, y]), &[-1.2, 1.0]);
+
+    assert!(result.converged);
+    assert!((result.x[0] - 1f32).abs() < 1e-2);
+    assert!((result.x[1] - 1f32).abs() < 1e-2);
+}
+
+#[test]
+fn lbfgs_without_history() {
+    use super::value_and_grad;
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let dx = sub(arena, x, constant(arena, 1f32));
+    let dy = sub(arena, y, constant(arena, 2f32));
+    let f = add(
+        arena,
+        mul(arena, dx, dx),
+        mul(arena, constant(arena, 4f32), mul(arena, dy, dy)),
+    );
+
+    let lbfgs = Lbfgs {
+        history_size: 0,
+        ..Lbfgs::new()
+    };
+    let result = lbfgs.minimize(value_and_grad(f, &[x, y]), &[0f32, 0f32]);
+
+    assert!(result.converged);
+    assert!(result.history.is_empty());
+    assert!((result.x[0] - 1f32).abs() < 1e-3);
+    assert!((result.x[1] - 2f32).abs() < 1e-3);
+}
+
+#[test]
+fn lbfgs_resumes_from_saved_history() {
+    use super::value_and_grad;
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let a = sub(arena, constant(arena, 1f32), x);
+    let b = sub(arena, y, mul(arena, x, x));
+    let rosenbrock = add(
+        arena,
+        mul(arena, a, a),
+        mul(arena, constant(arena, 100f32), mul(arena, b, b)),
+    );
+
+    let short = Lbfgs {
+        max_iters: 10,
+        ..Lbfgs::new()
+    };
+    let first = short.minimize(value_and_grad(rosenbrock, &[x, y]), &[-1.2, 1.0]);
+    assert!(!first.history.is_empty());
+
+    let history = LbfgsHistory::from_bytes(&first.history.to_bytes()).unwrap();
+    assert_eq!(history, first.history);
+    let lbfgs = Lbfgs {
+        max_iters: 500,
+        ..Lbfgs::new()
+    };
+    let resumed = lbfgs.resume(value_and_grad(rosenbrock, &[x, y]), &first.x, history);
+
+    assert!(resumed.converged);
+    assert!((resumed.x[0] - 1f32).abs() < 1e-2);
+    assert!((resumed.x[1] - 1f32).abs() < 1e-2);
+}
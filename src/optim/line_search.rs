@@ -0,0 +1,263 @@
+use super::dot;
+
+#[derive(Debug, Clone)]
+pub struct LineSearchResult {
+    pub step: f32,
+    pub x: Vec<f32>,
+    pub value: f32,
+    pub grad: Vec<f32>,
+    pub evaluations: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Backtracking {
+    pub initial_step: f32,
+    // factor the step is multiplied with after every rejection
+    pub shrink: f32,
+    // sufficient decrease (Armijo) constant
+    pub c1: f32,
+    pub min_step: f32,
+}
+
+impl Default for Backtracking {
+    fn default() -> Self {
+        Backtracking {
+            initial_step: 1f32,
+            shrink: 0.5,
+            c1: 1e-4,
+            min_step: 1e-10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StrongWolfe {
+    pub initial_step: f32,
+    // sufficient decrease constant
+    pub c1: f32,
+    // curvature constant
+    pub c2: f32,
+    pub max_step: f32,
+    pub max_evals: usize,
+}
+
+impl Default for StrongWolfe {
+    fn default() -> Self {
+        StrongWolfe {
+            initial_step: 1f32,
+            c1: 1e-4,
+            c2: 0.9,
+            max_step: 1e10,
+            max_evals: 30,
+        }
+    }
+}
+
+struct Probe<'f, F: 'f> {
+    f: &'f mut F,
+    x: &'f [f32],
+    direction: &'f [f32],
+    evaluations: usize,
+}
+
+impl<'f, F> Probe<'f, F>
+where
+    F: FnMut(&[f32]) -> (f32, Vec<f32>),
+{
+    fn at(&mut self, step: f32) -> LineSearchResult {
+        let x: Vec<f32> = self
+            .x
+            .iter()
+            .zip(self.direction)
+            .map(|(x, d)| x + step * d)
+            .collect();
+        let (value, grad) = (self.f)(&x);
+        self.evaluations += 1;
+
+        LineSearchResult {
+            step,
+            x,
+            value,
+            grad,
+            evaluations: self.evaluations,
+        }
+    }
+
+    fn slope(&self, point: &LineSearchResult) -> f32 {
+        dot(&point.grad, self.direction)
+    }
+}
+
+// Shrinks the step along `direction` from `x` until the Armijo condition
+// holds. `value` and `grad` are the objective and gradient at `x`.
+pub fn backtracking<F>(
+    f: &mut F,
+    x: &[f32],
+    direction: &[f32],
+    value: f32,
+    grad: &[f32],
+    opts: &Backtracking,
+) -> Option<LineSearchResult>
+where
+    F: FnMut(&[f32]) -> (f32, Vec<f32>),
+{
+    let slope = dot(grad, direction);
+    let mut probe = Probe {
+        f,
+        x,
+        direction,
+        evaluations: 0,
+    };
+
+    let mut step = opts.initial_step;
+    while step >= opts.min_step {
+        let point = probe.at(step);
+        if point.value <= value + opts.c1 * step * slope {
+            return Some(point);
+        }
+        step *= opts.shrink;
+    }
+
+    None
+}
+
+// Finds a step satisfying the strong Wolfe conditions (Nocedal & Wright,
+// algorithms 3.5 and 3.6). `direction` must be a descent direction.
+pub fn strong_wolfe<F>(
+    f: &mut F,
+    x: &[f32],
+    direction: &[f32],
+    value: f32,
+    grad: &[f32],
+    opts: &StrongWolfe,
+) -> Option<LineSearchResult>
+where
+    F: FnMut(&[f32]) -> (f32, Vec<f32>),
+{
+    let slope0 = dot(grad, direction);
+    if slope0 >= 0f32 {
+        return None;
+    }
+
+    let mut probe = Probe {
+        f,
+        x,
+        direction,
+        evaluations: 0,
+    };
+    let sufficient =
+        |point: &LineSearchResult| point.value <= value + opts.c1 * point.step * slope0;
+
+    let mut previous = LineSearchResult {
+        step: 0f32,
+        x: x.to_vec(),
+        value,
+        grad: grad.to_vec(),
+        evaluations: 0,
+    };
+    let mut step = opts.initial_step;
+
+    while probe.evaluations < opts.max_evals {
+        let point = probe.at(step);
+        if !sufficient(&point) || (previous.step > 0f32 && point.value >= previous.value) {
+            return zoom(&mut probe, previous, point, value, slope0, opts);
+        }
+
+        let slope = probe.slope(&point);
+        if slope.abs() <= -opts.c2 * slope0 {
+            return Some(point);
+        }
+        if slope >= 0f32 {
+            return zoom(&mut probe, point, previous, value, slope0, opts);
+        }
+
+        if step >= opts.max_step {
+            return Some(point);
+        }
+        step = (2f32 * step).min(opts.max_step);
+        previous = point;
+    }
+
+    None
+}
+
+fn zoom<F>(
+    probe: &mut Probe<F>,
+    mut lo: LineSearchResult,
+    mut hi: LineSearchResult,
+    value: f32,
+    slope0: f32,
+    opts: &StrongWolfe,
+) -> Option<LineSearchResult>
+where
+    F: FnMut(&[f32]) -> (f32, Vec<f32>),
+{
+    while probe.evaluations < opts.max_evals {
+        // minimizer of the quadratic through lo (value and slope) and hi,
+        // kept away from the interval ends
+        let width = hi.step - lo.step;
+        let lo_slope = probe.slope(&lo);
+        let denominator = 2f32 * (hi.value - lo.value - lo_slope * width);
+        let mut step = if denominator > 0f32 {
+            lo.step - lo_slope * width * width / denominator
+        } else {
+            lo.step + width / 2f32
+        };
+        let (left, right) = if lo.step < hi.step {
+            (lo.step, hi.step)
+        } else {
+            (hi.step, lo.step)
+        };
+        let margin = 0.1 * (right - left);
+        if !step.is_finite() || step < left + margin || step > right - margin {
+            step = (left + right) / 2f32;
+        }
+        if right - left <= f32::EPSILON * right.max(1f32) {
+            return if lo.step > 0f32 { Some(lo) } else { None };
+        }
+
+        let point = probe.at(step);
+        if point.value > value + opts.c1 * step * slope0 || point.value >= lo.value {
+            hi = point;
+        } else {
+            let slope = probe.slope(&point);
+            if slope.abs() <= -opts.c2 * slope0 {
+                return Some(point);
+            }
+            if slope * (hi.step - lo.step) >= 0f32 {
+                hi = lo;
+            }
+            lo = point;
+        }
+    }
+
+    // out of evaluations, settle for sufficient decrease
+    if lo.step > 0f32 {
+        Some(lo)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn line_searches_on_quadratic() {
+    // f(x) = (x0 - 3)^2 + 10 x1^2
+    let mut f = |x: &[f32]| {
+        (
+            (x[0] - 3f32).powi(2) + 10f32 * x[1] * x[1],
+            vec![2f32 * (x[0] - 3f32), 20f32 * x[1]],
+        )
+    };
+    let x = [0f32, 1f32];
+    let (value, grad) = f(&x);
+    let direction: Vec<f32> = grad.iter().map(|g| -g).collect();
+
+    let armijo = backtracking(&mut f, &x, &direction, value, &grad, &Default::default()).unwrap();
+    assert!(armijo.value < value);
+
+    let opts = StrongWolfe::default();
+    let wolfe = strong_wolfe(&mut f, &x, &direction, value, &grad, &opts).unwrap();
+    let slope0 = dot(&grad, &direction);
+    assert!(wolfe.value <= value + opts.c1 * wolfe.step * slope0);
+    assert!(dot(&wolfe.grad, &direction).abs() <= -opts.c2 * slope0);
+}
@@ -0,0 +1,266 @@
+use std::time::Duration;
+
+use super::stopping::Deadline;
+use super::{
+    newton_minimize_with_callback, norm, param_name, value_and_grad, Adam, Iteration, Lbfgs,
+    NewtonOptions, Optimizer, StopReason,
+};
+use Node;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Lbfgs,
+    Newton,
+    Adam { lr: f32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct MinimizeOptions {
+    pub max_iters: usize,
+    pub grad_tol: f32,
+    pub value_tol: f32,
+    // wall-clock limit, checked once per iteration
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for MinimizeOptions {
+    fn default() -> Self {
+        MinimizeOptions {
+            max_iters: 1000,
+            grad_tol: 1e-5,
+            value_tol: 1e-7,
+            time_budget: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MinimizeResult {
+    pub x: Vec<f32>,
+    pub value: f32,
+    pub grad_norm: f32,
+    pub iterations: usize,
+    pub converged: bool,
+    pub stopped_by: StopReason,
+}
+
+// Minimizes `node` over the variables `vars` starting from `initial_guess`.
+// The variables are left at the returned minimizer.
+pub fn minimize(
+    node: Node,
+    vars: &[Node],
+    initial_guess: &[f32],
+    method: Method,
+    opts: &MinimizeOptions,
+) -> MinimizeResult {
+    minimize_with_callback(node, vars, initial_guess, method, opts, &mut |_| {})
+}
+
+// Like `minimize`, calling `callback` with the starting point and after
+// every iteration.
+pub fn minimize_with_callback(
+    node: Node,
+    vars: &[Node],
+    initial_guess: &[f32],
+    method: Method,
+    opts: &MinimizeOptions,
+    callback: &mut dyn FnMut(&Iteration),
+) -> MinimizeResult {
+    assert_eq!(vars.len(), initial_guess.len());
+
+    let result = match method {
+        Method::Lbfgs => {
+            let lbfgs = Lbfgs {
+                max_iters: opts.max_iters,
+                grad_tol: opts.grad_tol,
+                value_tol: opts.value_tol,
+                time_budget: opts.time_budget,
+                ..Lbfgs::new()
+            };
+            let result =
+                lbfgs.minimize_with_callback(value_and_grad(node, vars), initial_guess, callback);
+            MinimizeResult {
+                x: result.x,
+                value: result.value,
+                grad_norm: result.grad_norm,
+                iterations: result.iterations,
+                converged: result.converged,
+                stopped_by: result.stopped_by,
+            }
+        }
+        Method::Newton => {
+            let newton = NewtonOptions {
+                max_iters: opts.max_iters,
+                grad_tol: opts.grad_tol,
+                value_tol: opts.value_tol,
+                time_budget: opts.time_budget,
+                ..Default::default()
+            };
+            let result =
+                newton_minimize_with_callback(node, vars, initial_guess, &newton, callback);
+            MinimizeResult {
+                x: result.x,
+                value: result.value,
+                grad_norm: result.grad_norm,
+                iterations: result.iterations,
+                converged: result.converged,
+                stopped_by: result.stopped_by,
+            }
+        }
+        Method::Adam { lr } => {
+            first_order(node, vars, initial_guess, Adam::new(lr), opts, callback)
+        }
+    };
+
+    for (var, value) in vars.iter().zip(&result.x) {
+        var.set_value(*value);
+    }
+    node.forward();
+
+    result
+}
+
+fn first_order<O: Optimizer>(
+    node: Node,
+    vars: &[Node],
+    initial_guess: &[f32],
+    mut optimizer: O,
+    opts: &MinimizeOptions,
+    callback: &mut dyn FnMut(&Iteration),
+) -> MinimizeResult {
+    let names: Vec<&str> = vars.iter().map(|var| param_name(var)).collect();
+    for (var, value) in vars.iter().zip(initial_guess) {
+        var.set_value(*value);
+    }
+
+    let deadline = Deadline::after(opts.time_budget);
+    let mut previous = None;
+    let mut iterations = 0;
+    let stopped_by = loop {
+        node.forward();
+        node.backward_ad(&names);
+
+        let value = node.value();
+        let grad: Vec<f32> = names.iter().map(|name| node.grad(name)).collect();
+        let params: Vec<f32> = vars.iter().map(|var| var.value()).collect();
+        callback(&Iteration {
+            step: iterations,
+            objective: value,
+            grad_norm: norm(&grad),
+            params: &params,
+        });
+        if norm(&grad) <= opts.grad_tol {
+            break StopReason::GradientNorm;
+        }
+        if let Some(previous) = previous {
+            let change: f32 = value - previous;
+            if change.abs() <= opts.value_tol * value.abs().max(1f32) {
+                break StopReason::ObjectiveChange;
+            }
+        }
+        if iterations == opts.max_iters {
+            break StopReason::MaxIterations;
+        }
+        if deadline.passed() {
+            break StopReason::TimeBudget;
+        }
+
+        optimizer.step(vars, &*node.grads());
+        previous = Some(value);
+        iterations += 1;
+    };
+
+    let grad: Vec<f32> = names.iter().map(|name| node.grad(name)).collect();
+    MinimizeResult {
+        x: vars.iter().map(|var| var.value()).collect(),
+        value: node.value(),
+        grad_norm: norm(&grad),
+        iterations,
+        converged: stopped_by.is_converged(),
+        stopped_by,
+    }
+}
+
+#[test]
+fn minimize_with_every_method() {
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let dx = sub(arena, x, constant(arena, 2f32));
+    let dy = sub(arena, y, x);
+    let f = add(arena, mul(arena, dx, dx), mul(arena, dy, dy));
+
+    for &method in &[Method::Lbfgs, Method::Newton, Method::Adam { lr: 0.05 }] {
+        let result = minimize(f, &[x, y], &[0f32, 0f32], method, &Default::default());
+
+        assert!(result.converged, "{:?}", method);
+        assert!((result.x[0] - 2f32).abs() < 1e-2, "{:?}", method);
+        assert!((result.x[1] - 2f32).abs() < 1e-2, "{:?}", method);
+        assert_eq!(x.value(), result.x[0]);
+    }
+}
+
+#[test]
+fn stopping_rules_are_reported() {
+    use {add, constant, cos, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let dx = sub(arena, x, constant(arena, 1f32));
+    let f = add(arena, mul(arena, dx, dx), cos(arena, mul(arena, x, x)));
+
+    let few = MinimizeOptions {
+        max_iters: 3,
+        ..Default::default()
+    };
+    let result = minimize(f, &[x], &[3f32], Method::Adam { lr: 1e-3 }, &few);
+    assert_eq!(result.stopped_by, StopReason::MaxIterations);
+    assert!(!result.converged);
+
+    let no_time = MinimizeOptions {
+        time_budget: Some(Duration::from_secs(0)),
+        ..Default::default()
+    };
+    for &method in &[Method::Lbfgs, Method::Newton, Method::Adam { lr: 1e-3 }] {
+        let result = minimize(f, &[x], &[3f32], method, &no_time);
+        assert_eq!(result.stopped_by, StopReason::TimeBudget, "{:?}", method);
+        assert_eq!(result.iterations, 0);
+    }
+
+    let result = minimize(f, &[x], &[3f32], Method::Lbfgs, &Default::default());
+    assert!(result.stopped_by.is_converged());
+}
+
+#[test]
+fn callbacks_see_every_iteration() {
+    use super::TrajectoryLog;
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let dx = sub(arena, x, constant(arena, 2f32));
+    let f = add(arena, mul(arena, dx, dx), x);
+
+    for &method in &[Method::Lbfgs, Method::Newton, Method::Adam { lr: 0.1 }] {
+        let mut log = TrajectoryLog::new();
+        let result =
+            minimize_with_callback(f, &[x], &[0f32], method, &Default::default(), &mut |it| {
+                log.record(it)
+            });
+
+        assert_eq!(log.records.len(), result.iterations + 1, "{:?}", method);
+        assert_eq!(log.records[0].params, vec![0f32]);
+        assert_eq!(log.records[0].objective, 4f32);
+        let last = log.records.last().unwrap();
+        assert_eq!(last.step, result.iterations);
+        assert_eq!(last.params, result.x);
+    }
+}
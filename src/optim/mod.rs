@@ -0,0 +1,85 @@
+mod adam;
+mod callback;
+mod clip;
+mod constrained;
+mod groups;
+mod lbfgs;
+mod line_search;
+mod minimize;
+mod newton;
+mod regularize;
+mod schedule;
+mod sgd;
+mod state;
+mod stopping;
+
+pub use self::adam::Adam;
+pub use self::callback::{Iteration, Record, TrajectoryLog};
+pub use self::clip::{clip_by_value, clip_grads_by_norm, GradClip};
+pub use self::constrained::{
+    minimize_constrained, ConstrainedOptions, ConstrainedResult, Constraint,
+};
+pub use self::groups::ParamGroups;
+pub use self::lbfgs::{Lbfgs, LbfgsHistory, LbfgsResult};
+pub use self::line_search::{
+    backtracking, strong_wolfe, Backtracking, LineSearchResult, StrongWolfe,
+};
+pub use self::minimize::{
+    minimize, minimize_with_callback, Method, MinimizeOptions, MinimizeResult,
+};
+pub use self::newton::{
+    newton_minimize, newton_minimize_with_callback, NewtonOptions, NewtonResult,
+};
+pub use self::regularize::Regularizer;
+pub use self::schedule::{Constant, Cosine, Exponential, Scheduled, Scheduler, StepDecay, Warmup};
+pub use self::sgd::Sgd;
+pub use self::state::{restore_params, save_params, Persistent, StateError};
+pub use self::stopping::StopReason;
+
+use {GradientValues, Node};
+
+pub trait Optimizer {
+    // grads are usually the ones left in the loss node by `backward_ad`
+    fn step(&mut self, params: &[Node], grads: &dyn GradientValues);
+
+    fn learning_rate(&self) -> f32;
+
+    fn set_learning_rate(&mut self, lr: f32);
+}
+
+// parameters of an optimizer are the variable nodes themselves
+fn param_name<'a>(param: Node<'a>) -> &'a str {
+    param
+        .var_name()
+        .expect("optimizer parameters must be variable nodes")
+}
+
+// Turns `node` into the value-and-gradient closure used by the
+// quasi-Newton solvers. `params` are written before every evaluation.
+pub fn value_and_grad<'a>(
+    node: Node<'a>,
+    params: &[Node<'a>],
+) -> impl FnMut(&[f32]) -> (f32, Vec<f32>) + 'a {
+    let params = params.to_vec();
+    let names: Vec<&'a str> = params.iter().map(|param| param_name(param)).collect();
+
+    move |values: &[f32]| {
+        for (param, value) in params.iter().zip(values) {
+            param.set_value(*value);
+        }
+
+        node.forward();
+        node.backward_ad(&names);
+
+        let grads = names.iter().map(|name| node.grad(name)).collect();
+        (node.value(), grads)
+    }
+}
+
+fn dot(lhs: &[f32], rhs: &[f32]) -> f32 {
+    lhs.iter().zip(rhs).map(|(l, r)| l * r).sum()
+}
+
+fn norm(values: &[f32]) -> f32 {
+    dot(values, values).sqrt()
+}
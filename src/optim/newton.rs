@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use super::stopping::Deadline;
+use super::{dot, norm, param_name, Iteration, StopReason};
+use linalg::cholesky_solve;
+use Node;
+
+#[derive(Debug, Clone)]
+pub struct NewtonOptions {
+    pub max_iters: usize,
+    pub grad_tol: f32,
+    // stop when the objective changes less than this (relative); zero
+    // only stops on no change at all
+    pub value_tol: f32,
+    // Levenberg-style damping added to the Hessian diagonal when it is not
+    // positive definite or the full step does not decrease the objective
+    pub initial_damping: f32,
+    pub max_damping: f32,
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for NewtonOptions {
+    fn default() -> Self {
+        NewtonOptions {
+            max_iters: 50,
+            grad_tol: 1e-5,
+            value_tol: 0f32,
+            initial_damping: 1e-3,
+            max_damping: 1e8,
+            time_budget: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewtonResult {
+    pub x: Vec<f32>,
+    pub value: f32,
+    pub grad_norm: f32,
+    pub iterations: usize,
+    pub converged: bool,
+    pub stopped_by: StopReason,
+}
+
+pub fn newton_minimize(
+    node: Node,
+    vars: &[Node],
+    init: &[f32],
+    opts: &NewtonOptions,
+) -> NewtonResult {
+    newton_minimize_with_callback(node, vars, init, opts, &mut |_| {})
+}
+
+// Like `newton_minimize`, calling `callback` with the starting point and
+// after every iteration.
+pub fn newton_minimize_with_callback(
+    node: Node,
+    vars: &[Node],
+    init: &[f32],
+    opts: &NewtonOptions,
+    callback: &mut dyn FnMut(&Iteration),
+) -> NewtonResult {
+    let names: Vec<&str> = vars.iter().map(|var| param_name(var)).collect();
+    let evaluate = |values: &[f32]| {
+        for (var, value) in vars.iter().zip(values) {
+            var.set_value(*value);
+        }
+        node.forward();
+        node.value()
+    };
+    let derivatives = || {
+        node.backward_ad(&names);
+        let grad: Vec<f32> = names.iter().map(|name| node.grad(name)).collect();
+        (grad, node.hessian(&names))
+    };
+
+    let mut x = init.to_vec();
+    let mut value = evaluate(&x);
+    let (mut grad, mut hessian) = derivatives();
+    let mut damping = 0f32;
+    let mut iterations = 0;
+    let deadline = Deadline::after(opts.time_budget);
+    callback(&Iteration {
+        step: 0,
+        objective: value,
+        grad_norm: norm(&grad),
+        params: &x,
+    });
+
+    let stopped_by = loop {
+        if norm(&grad) <= opts.grad_tol {
+            break StopReason::GradientNorm;
+        }
+        if iterations == opts.max_iters {
+            break StopReason::MaxIterations;
+        }
+        if deadline.passed() {
+            break StopReason::TimeBudget;
+        }
+
+        let neg_grad: Vec<f32> = grad.iter().map(|g| -g).collect();
+        let mut accepted = None;
+        while damping <= opts.max_damping {
+            let mut damped = hessian.clone();
+            for (i, row) in damped.iter_mut().enumerate() {
+                row[i] += damping;
+            }
+
+            if let Some(step) = cholesky_solve(&damped, &neg_grad) {
+                let candidate: Vec<f32> = x.iter().zip(&step).map(|(x, s)| x + s).collect();
+                let candidate_value = evaluate(&candidate);
+                // positive definiteness makes this a descent direction
+                if candidate_value <= value + 1e-4 * dot(&grad, &step) {
+                    accepted = Some((candidate, candidate_value));
+                    break;
+                }
+            }
+
+            damping = (damping * 10f32).max(opts.initial_damping);
+        }
+
+        let (candidate, candidate_value) = match accepted {
+            Some(accepted) => accepted,
+            None => break StopReason::Stalled,
+        };
+        let change = (value - candidate_value).abs();
+        x = candidate;
+        value = candidate_value;
+        // `evaluate` left the graph at the accepted point
+        let next = derivatives();
+        grad = next.0;
+        hessian = next.1;
+        damping /= 10f32;
+        if damping < opts.initial_damping {
+            damping = 0f32;
+        }
+        iterations += 1;
+        callback(&Iteration {
+            step: iterations,
+            objective: value,
+            grad_norm: norm(&grad),
+            params: &x,
+        });
+        if change <= opts.value_tol * value.abs().max(1f32) {
+            break StopReason::ObjectiveChange;
+        }
+    };
+
+    // leave the variables at the reported point
+    value = evaluate(&x);
+    let grad_norm = norm(&grad);
+    NewtonResult {
+        x,
+        value,
+        grad_norm,
+        iterations,
+        converged: stopped_by.is_converged(),
+        stopped_by,
+    }
+}
+
+#[test]
+fn newton_minimizes_in_few_steps() {
+    use {add, constant, cos, mul, pow, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    // (x - 1)^4 + (x - 1)^2 + 2 (y + 2)^2 - cos(y + 2)
+    let dx = sub(arena, x, constant(arena, 1f32));
+    let dy = add(arena, y, constant(arena, 2f32));
+    let f = sub(
+        arena,
+        add(
+            arena,
+            add(arena, pow(arena, dx, 4f32), mul(arena, dx, dx)),
+            mul(arena, constant(arena, 2f32), mul(arena, dy, dy)),
+        ),
+        cos(arena, dy),
+    );
+
+    let result = newton_minimize(f, &[x, y], &[3f32, 1f32], &NewtonOptions::default());
+
+    assert!(result.converged);
+    assert!(result.iterations < 15);
+    assert!((result.x[0] - 1f32).abs() < 1e-2);
+    assert!((result.x[1] + 2f32).abs() < 1e-3);
+}
+
+#[test]
+fn newton_stops_on_small_objective_change() {
+    use {constant, pow, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    // (x - 1)^4 converges only linearly, so the objective change rule fires
+    // long before the gradient one
+    let x = var(arena, "x".to_string());
+    let dx = sub(arena, x, constant(arena, 1f32));
+    let f = pow(arena, dx, 4f32);
+
+    let opts = NewtonOptions {
+        grad_tol: 0f32,
+        value_tol: 1e-3,
+        ..Default::default()
+    };
+    let result = newton_minimize(f, &[x], &[3f32], &opts);
+
+    assert_eq!(result.stopped_by, StopReason::ObjectiveChange);
+    assert!(result.iterations < 20);
+    assert!((result.x[0] - 1f32).abs() < 0.5);
+}
@@ -0,0 +1,109 @@
+use {add, add_n, constant, custom, mul, Arena, CustomOp, Node};
+
+// sum of |x| over the inputs, with the subgradient 0 at 0 so that
+// parameters sitting exactly at zero stay there
+#[derive(Debug, Clone, Copy)]
+struct L1Norm;
+
+impl CustomOp for L1Norm {
+    fn name(&self) -> &str {
+        "L1Norm"
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        inputs.iter().map(|x| x.abs()).sum()
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        inputs
+            .iter()
+            .map(|&x| {
+                if x == 0f32 {
+                    0f32
+                } else {
+                    upstream * x.signum()
+                }
+            })
+            .collect()
+    }
+
+    fn is_kink(&self, inputs: &[f32], eps: f32) -> bool {
+        inputs.iter().any(|x| x.abs() < eps)
+    }
+}
+
+// Penalties on parameter values. The L2 penalty is `strength * sum x^2`,
+// without the conventional 1/2.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Regularizer {
+    L1(f32),
+    L2(f32),
+    ElasticNet { l1: f32, l2: f32 },
+}
+
+impl Regularizer {
+    pub fn penalty<'a>(&self, arena: &'a Arena<'a>, params: &[Node<'a>]) -> Node<'a> {
+        let (l1, l2) = match *self {
+            Regularizer::L1(l1) => (l1, 0f32),
+            Regularizer::L2(l2) => (0f32, l2),
+            Regularizer::ElasticNet { l1, l2 } => (l1, l2),
+        };
+
+        let mut terms = vec![];
+        if l1 != 0f32 && !params.is_empty() {
+            let norm = custom(arena, L1Norm, params);
+            terms.push(mul(arena, constant(arena, l1), norm));
+        }
+        if l2 != 0f32 && !params.is_empty() {
+            let squares: Vec<Node<'a>> = params.iter().map(|&x| mul(arena, x, x)).collect();
+            terms.push(mul(arena, constant(arena, l2), add_n(arena, &squares)));
+        }
+        match terms.len() {
+            0 => constant(arena, 0f32),
+            1 => terms[0],
+            _ => add(arena, terms[0], terms[1]),
+        }
+    }
+
+    // `objective` plus the penalty on `params`. Regularizers compose by
+    // applying them one after another, e.g. to different parameter sets.
+    pub fn apply<'a>(
+        &self,
+        arena: &'a Arena<'a>,
+        objective: Node<'a>,
+        params: &[Node<'a>],
+    ) -> Node<'a> {
+        add(arena, objective, self.penalty(arena, params))
+    }
+}
+
+#[test]
+fn penalties_and_subgradients() {
+    use {var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let z = var(arena, "z".to_string());
+    let objective = constant(arena, 1f32);
+    let f = Regularizer::ElasticNet { l1: 0.5, l2: 2f32 }.apply(arena, objective, &[x, y]);
+    let f = Regularizer::L1(3f32).apply(arena, f, &[z]);
+
+    x.set_value(2f32);
+    y.set_value(-1f32);
+    z.set_value(0f32);
+    f.forward();
+    // 1 + 0.5 (2 + 1) + 2 (4 + 1) + 3 * 0
+    assert_eq!(f.value(), 12.5);
+
+    f.backward_ad(&["x", "y", "z"]);
+    assert_eq!(f.grad("x"), 0.5 + 2f32 * 2f32 * 2f32);
+    assert_eq!(f.grad("y"), -0.5 - 2f32 * 2f32);
+    assert_eq!(f.grad("z"), 0f32);
+
+    let none = Regularizer::L2(0f32).penalty(arena, &[x]);
+    none.forward();
+    assert_eq!(none.value(), 0f32);
+}
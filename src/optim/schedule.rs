@@ -0,0 +1,177 @@
+use std::f32::consts::PI;
+
+use super::Optimizer;
+use {GradientValues, Node};
+
+pub trait Scheduler {
+    // learning rate to use for the `step`-th (0-based) optimizer step
+    fn learning_rate(&self, step: usize, base_lr: f32) -> f32;
+}
+
+// multiplies the rate by `gamma` every `step_size` steps; a step size of
+// zero never decays
+#[derive(Debug, Clone)]
+pub struct StepDecay {
+    pub step_size: usize,
+    pub gamma: f32,
+}
+
+impl Scheduler for StepDecay {
+    fn learning_rate(&self, step: usize, base_lr: f32) -> f32 {
+        match step.checked_div(self.step_size) {
+            Some(decays) => base_lr * self.gamma.powi(decays as i32),
+            None => base_lr,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Exponential {
+    pub gamma: f32,
+}
+
+impl Scheduler for Exponential {
+    fn learning_rate(&self, step: usize, base_lr: f32) -> f32 {
+        base_lr * self.gamma.powi(step as i32)
+    }
+}
+
+// anneals from the base rate down to `min_lr` over `total_steps`; zero
+// total steps never anneals
+#[derive(Debug, Clone)]
+pub struct Cosine {
+    pub total_steps: usize,
+    pub min_lr: f32,
+}
+
+impl Scheduler for Cosine {
+    fn learning_rate(&self, step: usize, base_lr: f32) -> f32 {
+        if self.total_steps == 0 {
+            return base_lr;
+        }
+        let progress = step.min(self.total_steps) as f32 / self.total_steps as f32;
+        self.min_lr + (base_lr - self.min_lr) * (1f32 + (PI * progress).cos()) / 2f32
+    }
+}
+
+// ramps up linearly for `warmup_steps`, then hands over to `after`
+#[derive(Debug, Clone)]
+pub struct Warmup<S> {
+    pub warmup_steps: usize,
+    pub after: S,
+}
+
+impl<S: Scheduler> Scheduler for Warmup<S> {
+    fn learning_rate(&self, step: usize, base_lr: f32) -> f32 {
+        if step < self.warmup_steps {
+            base_lr * (step + 1) as f32 / self.warmup_steps as f32
+        } else {
+            self.after.learning_rate(step - self.warmup_steps, base_lr)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Constant;
+
+impl Scheduler for Constant {
+    fn learning_rate(&self, _step: usize, base_lr: f32) -> f32 {
+        base_lr
+    }
+}
+
+// An optimizer whose learning rate is driven by a scheduler.
+#[derive(Debug, Clone)]
+pub struct Scheduled<O, S> {
+    pub optimizer: O,
+    pub scheduler: S,
+    base_lr: f32,
+    steps: usize,
+}
+
+impl<O: Optimizer, S: Scheduler> Scheduled<O, S> {
+    pub fn new(optimizer: O, scheduler: S) -> Self {
+        Scheduled {
+            base_lr: optimizer.learning_rate(),
+            optimizer,
+            scheduler,
+            steps: 0,
+        }
+    }
+}
+
+impl<O: Optimizer, S: Scheduler> Optimizer for Scheduled<O, S> {
+    fn step(&mut self, params: &[Node], grads: &dyn GradientValues) {
+        let lr = self.scheduler.learning_rate(self.steps, self.base_lr);
+        self.optimizer.set_learning_rate(lr);
+        self.optimizer.step(params, grads);
+        self.steps += 1;
+    }
+
+    fn learning_rate(&self) -> f32 {
+        self.optimizer.learning_rate()
+    }
+
+    // changes the base rate the schedule is computed from
+    fn set_learning_rate(&mut self, lr: f32) {
+        self.base_lr = lr;
+    }
+}
+
+#[test]
+fn schedules() {
+    let step = StepDecay {
+        step_size: 10,
+        gamma: 0.5,
+    };
+    assert_eq!(step.learning_rate(9, 1f32), 1f32);
+    assert_eq!(step.learning_rate(25, 1f32), 0.25);
+
+    let cosine = Cosine {
+        total_steps: 100,
+        min_lr: 0.1,
+    };
+    assert_eq!(cosine.learning_rate(0, 1f32), 1f32);
+    assert!((cosine.learning_rate(50, 1f32) - 0.55).abs() < 1e-6);
+    assert!((cosine.learning_rate(200, 1f32) - 0.1).abs() < 1e-6);
+
+    // zero-length schedules keep the base rate instead of dividing by zero
+    let step = StepDecay {
+        step_size: 0,
+        gamma: 0.5,
+    };
+    assert_eq!(step.learning_rate(3, 1f32), 1f32);
+    let cosine = Cosine {
+        total_steps: 0,
+        min_lr: 0.1,
+    };
+    assert_eq!(cosine.learning_rate(0, 1f32), 1f32);
+    assert_eq!(cosine.learning_rate(5, 1f32), 1f32);
+
+    let warmup = Warmup {
+        warmup_steps: 4,
+        after: Exponential { gamma: 0.5 },
+    };
+    assert_eq!(warmup.learning_rate(0, 1f32), 0.25);
+    assert_eq!(warmup.learning_rate(4, 1f32), 1f32);
+    assert_eq!(warmup.learning_rate(5, 1f32), 0.5);
+}
+
+#[test]
+fn scheduled_optimizer_updates_learning_rate() {
+    use super::Sgd;
+    use std::collections::HashMap;
+    use {var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let mut grads = HashMap::new();
+    grads.insert("x".to_string(), 1f32);
+
+    let mut sgd = Scheduled::new(Sgd::new(1f32, 0f32), Exponential { gamma: 0.5 });
+    sgd.step(&[x], &grads);
+    sgd.step(&[x], &grads);
+
+    assert_eq!(x.value(), -1.5);
+    assert_eq!(sgd.learning_rate(), 0.5);
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use super::clip::clipped;
+use super::state::{Reader, Writer, SGD};
+use super::{param_name, GradClip, Optimizer, Persistent, StateError};
+use {GradientValues, Node};
+
+#[derive(Debug, Clone)]
+pub struct Sgd {
+    pub lr: f32,
+    pub momentum: f32,
+    pub clip: Option<GradClip>,
+    velocity: HashMap<String, f32>,
+}
+
+impl Sgd {
+    pub fn new(lr: f32, momentum: f32) -> Self {
+        Sgd {
+            lr,
+            momentum,
+            clip: None,
+            velocity: HashMap::new(),
+        }
+    }
+}
+
+impl Optimizer for Sgd {
+    fn step(&mut self, params: &[Node], grads: &dyn GradientValues) {
+        let clipped = clipped(self.clip, grads);
+        let grads = clipped.as_ref().map_or(grads, |g| g as &dyn GradientValues);
+        for param in params {
+            let name = param_name(param);
+            let grad = grads.get(name).unwrap_or(0f32);
+
+            let velocity = self.velocity.entry(name.to_string()).or_insert(0f32);
+            *velocity = self.momentum * *velocity + grad;
+
+            param.set_value(param.value() - self.lr * *velocity);
+        }
+    }
+
+    fn learning_rate(&self) -> f32 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f32) {
+        self.lr = lr;
+    }
+}
+
+impl Persistent for Sgd {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new(SGD);
+        writer.f32(self.lr);
+        writer.f32(self.momentum);
+        writer.clip(self.clip);
+        writer.map(&self.velocity, |writer, &velocity| writer.f32(velocity));
+        writer.finish()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut reader = Reader::new(bytes, SGD)?;
+        let sgd = Sgd {
+            lr: reader.f32()?,
+            momentum: reader.f32()?,
+            clip: reader.clip()?,
+            velocity: reader.map(|reader| reader.f32())?,
+        };
+        reader.finish(sgd)
+    }
+}
+
+#[test]
+fn sgd_minimizes_quadratic() {
+    use {constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let three = constant(arena, 3f32);
+    let diff = sub(arena, x, three);
+    let loss = mul(arena, diff, diff);
+
+    let mut sgd = Sgd::new(0.1, 0.5);
+    for _ in 0..100 {
+        loss.forward();
+        loss.backward_ad(&["x"]);
+        sgd.step(&[x], &*loss.grads());
+    }
+
+    assert!((x.value() - 3f32).abs() < 1e-4);
+}
+
+#[test]
+fn sgd_clips_gradients() {
+    use {eval_with_grad, mul, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let loss = mul(arena, x, x);
+    x.set_value(100f32);
+
+    let mut sgd = Sgd::new(0.5, 0f32);
+    sgd.clip = Some(GradClip::Norm(1f32));
+    loss.forward();
+    loss.backward_ad(&["x"]);
+    sgd.step(&[x], &*loss.grads());
+
+    assert_eq!(x.value(), 99.5);
+
+    // gradients returned by value work the same
+    let (_, grads) = eval_with_grad(loss, &[("x", 99.5)].into(), &["x"]);
+    sgd.step(&[x], &grads);
+    assert_eq!(x.value(), 99.0);
+}
@@ -0,0 +1,272 @@
+// Binary snapshots of optimizer state and parameter values, so that long
+// runs can be checkpointed and resumed in another process.
+//
+// Parameters are identified by their variable names, which are the only
+// identifiers that are stable across runs.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use super::{param_name, GradClip};
+use reader::{self, Malformed};
+use Node;
+
+const MAGIC: &[u8] = b"ADOS";
+pub const STATE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    UnsupportedVersion(u8),
+    // the bytes were not written by `to_bytes` of the same type
+    Malformed,
+    // a parameter being restored has no saved value
+    MissingParameter(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported optimizer state version {}", version)
+            }
+            StateError::Malformed => write!(f, "malformed optimizer state"),
+            StateError::MissingParameter(ref name) => {
+                write!(f, "no saved value for parameter {}", name)
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl From<Malformed> for StateError {
+    fn from(_: Malformed) -> Self {
+        StateError::Malformed
+    }
+}
+
+pub trait Persistent: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, StateError>;
+}
+
+// what a snapshot holds, written after the magic and the version
+pub(super) const PARAMS: u8 = 0;
+pub(super) const SGD: u8 = 1;
+pub(super) const ADAM: u8 = 2;
+pub(super) const LBFGS: u8 = 3;
+
+pub(super) struct Writer(Vec<u8>);
+
+impl Writer {
+    pub(super) fn new(kind: u8) -> Self {
+        let mut out = MAGIC.to_vec();
+        out.push(STATE_VERSION);
+        out.push(kind);
+        Writer(out)
+    }
+
+    pub(super) fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    pub(super) fn floats(&mut self, values: &[f32]) {
+        self.u32(values.len() as u32);
+        for &value in values {
+            self.f32(value);
+        }
+    }
+
+    pub(super) fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    pub(super) fn clip(&mut self, clip: Option<GradClip>) {
+        match clip {
+            None => self.u32(0),
+            Some(GradClip::Norm(max_norm)) => {
+                self.u32(1);
+                self.f32(max_norm);
+            }
+            Some(GradClip::Value(limit)) => {
+                self.u32(2);
+                self.f32(limit);
+            }
+        }
+    }
+
+    // sorted by name so that equal maps give equal bytes
+    pub(super) fn map<T, F>(&mut self, map: &HashMap<String, T>, mut write: F)
+    where
+        F: FnMut(&mut Writer, &T),
+    {
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort_by(|l, r| l.0.cmp(r.0));
+        self.u32(entries.len() as u32);
+        for (name, value) in entries {
+            self.str(name);
+            write(self, value);
+        }
+    }
+
+    pub(super) fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+pub(super) struct Reader<'b>(reader::Reader<'b>);
+
+impl<'b> Reader<'b> {
+    pub(super) fn new(bytes: &'b [u8], kind: u8) -> Result<Self, StateError> {
+        let mut reader = reader::Reader::new(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(StateError::Malformed);
+        }
+        let version = reader.byte()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if reader.byte()? != kind {
+            return Err(StateError::Malformed);
+        }
+        Ok(Reader(reader))
+    }
+
+    pub(super) fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(self.0.u32()?)
+    }
+
+    pub(super) fn f32(&mut self) -> Result<f32, StateError> {
+        Ok(self.0.f32()?)
+    }
+
+    pub(super) fn floats(&mut self) -> Result<Vec<f32>, StateError> {
+        (0..self.u32()?).map(|_| self.f32()).collect()
+    }
+
+    pub(super) fn string(&mut self) -> Result<String, StateError> {
+        let len = self.u32()? as usize;
+        Ok(self.0.utf8(len)?)
+    }
+
+    pub(super) fn clip(&mut self) -> Result<Option<GradClip>, StateError> {
+        Ok(match self.u32()? {
+            0 => None,
+            1 => Some(GradClip::Norm(self.f32()?)),
+            2 => Some(GradClip::Value(self.f32()?)),
+            _ => return Err(StateError::Malformed),
+        })
+    }
+
+    pub(super) fn map<T, F>(&mut self, mut read: F) -> Result<HashMap<String, T>, StateError>
+    where
+        F: FnMut(&mut Reader<'b>) -> Result<T, StateError>,
+    {
+        (0..self.u32()?)
+            .map(|_| {
+                let name = self.string()?;
+                Ok((name, read(self)?))
+            })
+            .collect()
+    }
+
+    // trailing bytes mean the snapshot came from something else
+    pub(super) fn finish<T>(self, value: T) -> Result<T, StateError> {
+        if self.0.is_at_end() {
+            Ok(value)
+        } else {
+            Err(StateError::Malformed)
+        }
+    }
+}
+
+// Snapshot of the current values of `params`, keyed by variable name.
+pub fn save_params(params: &[Node]) -> Vec<u8> {
+    let values: HashMap<String, f32> = params
+        .iter()
+        .map(|param| (param_name(param).to_string(), param.value()))
+        .collect();
+    let mut writer = Writer::new(PARAMS);
+    writer.map(&values, |writer, &value| writer.f32(value));
+    writer.finish()
+}
+
+// Sets `params` to the values saved by `save_params`. Saved values of
+// other variables are ignored, so parameters can be added between runs as
+// long as they are initialized separately.
+pub fn restore_params(params: &[Node], bytes: &[u8]) -> Result<(), StateError> {
+    let mut reader = Reader::new(bytes, PARAMS)?;
+    let values = reader.map(|reader| reader.f32())?;
+    let values = reader.finish(values)?;
+
+    for param in params {
+        let name = param_name(param);
+        match values.get(name) {
+            Some(&value) => param.set_value(value),
+            None => return Err(StateError::MissingParameter(name.to_string())),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn resume_training_from_snapshot() {
+    use super::{Adam, Optimizer};
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let dx = sub(arena, x, constant(arena, 3f32));
+    let loss = add(arena, mul(arena, dx, dx), mul(arena, y, y));
+    let train = |adam: &mut Adam, steps: usize| {
+        for _ in 0..steps {
+            loss.forward();
+            loss.backward_ad(&["x", "y"]);
+            adam.step(&[x, y], &*loss.grads());
+        }
+    };
+
+    x.set_value(0f32);
+    y.set_value(1f32);
+    let mut adam = Adam::new(0.05);
+    train(&mut adam, 20);
+    let params = save_params(&[x, y]);
+    let state = adam.to_bytes();
+    train(&mut adam, 20);
+    let expected = (x.value(), y.value());
+
+    // a fresh process: new values, new optimizer
+    x.set_value(0f32);
+    y.set_value(0f32);
+    restore_params(&[x, y], &params).unwrap();
+    let mut resumed = Adam::from_bytes(&state).unwrap();
+    train(&mut resumed, 20);
+    assert_eq!((x.value(), y.value()), expected);
+
+    let z = var(arena, "z".to_string());
+    assert_eq!(
+        restore_params(&[z], &params),
+        Err(StateError::MissingParameter("z".to_string()))
+    );
+    assert_eq!(Adam::from_bytes(&params).err(), Some(StateError::Malformed));
+    assert_eq!(
+        Adam::from_bytes(&state[..state.len() - 1]).err(),
+        Some(StateError::Malformed)
+    );
+    let mut future = state.clone();
+    future[4] = STATE_VERSION + 1;
+    assert_eq!(
+        Adam::from_bytes(&future).err(),
+        Some(StateError::UnsupportedVersion(STATE_VERSION + 1))
+    );
+}
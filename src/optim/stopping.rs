@@ -0,0 +1,33 @@
+use std::time::{Duration, Instant};
+
+// The rule that ended a minimization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    GradientNorm,
+    // relative change of the objective between iterations
+    ObjectiveChange,
+    MaxIterations,
+    TimeBudget,
+    // no step that decreases the objective could be found
+    Stalled,
+}
+
+impl StopReason {
+    pub fn is_converged(self) -> bool {
+        matches!(self, StopReason::GradientNorm | StopReason::ObjectiveChange)
+    }
+}
+
+// Point in time after which a budgeted minimization stops.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub(crate) fn after(budget: Option<Duration>) -> Self {
+        Deadline(budget.map(|budget| Instant::now() + budget))
+    }
+
+    pub(crate) fn passed(&self) -> bool {
+        self.0.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
@@ -0,0 +1,108 @@
+// Piecewise expressions built from Select nodes. A case applies where its
+// condition node is positive.
+
+use std::error::Error;
+use std::fmt;
+
+use {select, Arena, Node};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PiecewiseError {
+    // both conditions hold at the current values
+    Overlap { first: usize, second: usize },
+    // no condition holds and there is no default to fall back on
+    NoMatch,
+}
+
+impl fmt::Display for PiecewiseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PiecewiseError::Overlap { first, second } => {
+                write!(f, "cases {} and {} overlap", first, second)
+            }
+            PiecewiseError::NoMatch => write!(f, "no case matches"),
+        }
+    }
+}
+
+impl Error for PiecewiseError {}
+
+// The expression of the first case whose condition is positive, or
+// `default` if there is none.
+pub fn piecewise<'a>(
+    arena: &'a Arena<'a>,
+    cases: &[(Node<'a>, Node<'a>)],
+    default: Node<'a>,
+) -> Node<'a> {
+    cases.iter().rev().fold(default, |else_, &(cond, then)| {
+        select(arena, cond, then, else_)
+    })
+}
+
+// Checks that exactly one condition holds at the current variable values
+// and returns its index.
+pub fn check_cases(cases: &[(Node, Node)]) -> Result<usize, PiecewiseError> {
+    let mut matched = None;
+    for (i, &(cond, _)) in cases.iter().enumerate() {
+        cond.forward();
+        if cond.value() > 0f32 {
+            if let Some(first) = matched {
+                return Err(PiecewiseError::Overlap { first, second: i });
+            }
+            matched = Some(i);
+        }
+    }
+
+    matched.ok_or(PiecewiseError::NoMatch)
+}
+
+#[test]
+fn tax_brackets() {
+    use {add, constant, mul, sub, var};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let income = var(arena, "income".to_string());
+    let c = |v: f32| constant(arena, v);
+
+    // 0% up to 10, 20% up to 50, 40% above
+    let above_10 = sub(arena, income, c(10f32));
+    let above_50 = sub(arena, income, c(50f32));
+    let low = sub(arena, c(10f32), income);
+    // positive strictly between 10 and 50
+    let middle = mul(arena, above_10, sub(arena, c(50f32), income));
+    let tax = piecewise(
+        arena,
+        &[
+            (above_50, add(arena, c(8f32), mul(arena, c(0.4), above_50))),
+            (above_10, mul(arena, c(0.2), above_10)),
+        ],
+        c(0f32),
+    );
+
+    for &(value, expected_tax, expected_grad) in
+        &[(5f32, 0f32, 0f32), (30f32, 4f32, 0.2), (60f32, 12f32, 0.4)]
+    {
+        income.set_value(value);
+        tax.forward();
+        tax.backward_ad(&["income"]);
+        assert!((tax.value() - expected_tax).abs() < 1e-5);
+        assert_eq!(tax.grad("income"), expected_grad);
+    }
+
+    income.set_value(30f32);
+    assert_eq!(check_cases(&[(low, c(0f32)), (middle, c(0f32))]), Ok(1));
+    assert_eq!(
+        check_cases(&[(above_10, c(0f32)), (middle, c(0f32))]),
+        Err(PiecewiseError::Overlap {
+            first: 0,
+            second: 1
+        })
+    );
+    income.set_value(60f32);
+    assert_eq!(
+        check_cases(&[(low, c(0f32)), (middle, c(0f32))]),
+        Err(PiecewiseError::NoMatch)
+    );
+}
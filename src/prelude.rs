@@ -0,0 +1,32 @@
+// The commonly used types and functions, for `use ad::prelude::*;`.
+
+pub use assignment::{Assignment, MissingVariable};
+pub use expr::Expr;
+pub use gradients::Gradients;
+pub use graph::{Graph, NodeId, VarHandle};
+pub use num::{derivative, AdNum, Dual};
+pub use ops::{
+    bce_with_logits, heaviside, l2_norm, normal_cdf, normal_logpdf, normal_pdf, normalize,
+    Surrogate,
+};
+pub use optim::{minimize, Adam, Method, MinimizeOptions, Optimizer, Sgd};
+pub use typed::Variable;
+pub use {
+    add, add_n, backward_multi, backward_seeded, constant, cos, custom, div, dot, eval_with_grad,
+    exp_m1, finite_difference_backward, forward_multi, gather, grad_weighted, grad_wrt, jacobian,
+    labelled, lerp, linear, ln_1p, mul, mul_n, neg, polynomial, pow, prod, select, sin, smoothstep,
+    stop_gradient, sub, sum, try_eval_with_grad, var, Arena, CustomOp, FiniteDifference, Node,
+    NodeData, NodeType,
+};
+
+#[test]
+fn one_import_is_enough() {
+    let arena = Arena::new();
+    let x = Expr::var(&arena, "x");
+    let f = sum(&arena, &[(x * x).node(), sin(&arena, x.node())]);
+
+    let at: Assignment = [("x", 0.5)].into();
+    let (value, grads): (f32, Gradients) = eval_with_grad(f, &at, &["x"]);
+    assert_eq!(value, 0.25 + 0.5f32.sin());
+    assert_eq!(grads["x"], 1.0 + 0.5f32.cos());
+}
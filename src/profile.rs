@@ -0,0 +1,174 @@
+// Evaluation counts and time per kind of node and per labelled subgraph,
+// collected by observing `forward_observed` and `gradients_observed`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use observe::Observer;
+use {topological_order, Node};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub evaluations: usize,
+    pub forward_time: Duration,
+    pub backward_steps: usize,
+    pub backward_time: Duration,
+}
+
+impl Stats {
+    fn merge(&mut self, other: &Stats) {
+        self.evaluations += other.evaluations;
+        self.forward_time += other.forward_time;
+        self.backward_steps += other.backward_steps;
+        self.backward_time += other.backward_time;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    nodes: HashMap<*const (), (String, Stats)>,
+    started: Option<Instant>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+
+    // Totals for the nodes of `root`. A labelled node accounts for itself
+    // and everything below it.
+    pub fn report(&self, root: Node) -> Profile {
+        let mut profile = Profile::default();
+        let order = topological_order(&[root]);
+        for &node in &order {
+            if let Some((op, stats)) = self.nodes.get(&key(node)) {
+                profile.by_op.entry(op.clone()).or_default().merge(stats);
+            }
+        }
+
+        for node in order {
+            if let Some(label) = node.label() {
+                let total = profile.by_label.entry(label.name).or_default();
+                for child in topological_order(&[node]) {
+                    if let Some((_, stats)) = self.nodes.get(&key(child)) {
+                        total.merge(stats);
+                    }
+                }
+            }
+        }
+
+        profile
+    }
+
+    fn record(&mut self, node: Node) -> (&mut Stats, Duration) {
+        let elapsed = self
+            .started
+            .take()
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        let entry = self
+            .nodes
+            .entry(key(node))
+            .or_insert_with(|| (node.type_.op().name().to_string(), Stats::default()));
+        (&mut entry.1, elapsed)
+    }
+}
+
+fn key(node: Node) -> *const () {
+    node as *const _ as *const ()
+}
+
+impl Observer for Profiler {
+    fn enter(&mut self, _node: Node) {
+        self.started = Some(Instant::now());
+    }
+
+    fn forward(&mut self, node: Node, _inputs: &[f32], _output: f32) {
+        let (stats, elapsed) = self.record(node);
+        stats.evaluations += 1;
+        stats.forward_time += elapsed;
+    }
+
+    fn backward(&mut self, node: Node, _inputs: &[f32], _adjoint: f32) {
+        let (stats, elapsed) = self.record(node);
+        stats.backward_steps += 1;
+        stats.backward_time += elapsed;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub by_op: BTreeMap<String, Stats>,
+    pub by_label: BTreeMap<String, Stats>,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self
+            .by_op
+            .iter()
+            .map(|(name, stats)| ("op", name, stats))
+            .chain(
+                self.by_label
+                    .iter()
+                    .map(|(name, stats)| ("label", name, stats)),
+            );
+        writeln!(
+            f,
+            "{:<6} {:<20} {:>8} {:>12} {:>8} {:>12}",
+            "", "name", "evals", "forward", "steps", "backward"
+        )?;
+        for (kind, name, stats) in rows {
+            writeln!(
+                f,
+                "{:<6} {:<20} {:>8} {:>12?} {:>8} {:>12?}",
+                kind,
+                name,
+                stats.evaluations,
+                stats.forward_time,
+                stats.backward_steps,
+                stats.backward_time
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn profile_counts_by_op_and_label() {
+    use observe::{forward_observed, gradients_observed};
+    use {add, labelled, mul, pow, sin, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let y = var(&arena, "y".to_string());
+    let term = labelled(pow(&arena, mul(&arena, x, y), 2.0), "term");
+    let f = add(&arena, term, pow(&arena, x, 3.0));
+
+    let mut profiler = Profiler::new();
+    forward_observed(f, &mut profiler);
+    gradients_observed(f, &["x", "y"], &mut profiler);
+    let profile = profiler.report(f);
+
+    // two forward sweeps, one reverse sweep
+    assert_eq!(profile.by_op["Pow"].evaluations, 4);
+    assert_eq!(profile.by_op["Pow"].backward_steps, 2);
+    assert_eq!(profile.by_op["Var"].evaluations, 4);
+    assert_eq!(profile.by_op["Add"].evaluations, 2);
+    // term = (x * y)^2 covers one Pow, one Mul and both variables
+    assert_eq!(profile.by_label["term"].evaluations, 8);
+    assert!(format!("{}", profile).contains("term"));
+
+    // other graphs observed by the same profiler are left out
+    let g = sin(&arena, var(&arena, "z".to_string()));
+    forward_observed(g, &mut profiler);
+    let profile = profiler.report(f);
+    assert!(!profile.by_op.contains_key("Sin"));
+    assert_eq!(profile.by_op["Var"].evaluations, 4);
+    assert_eq!(profiler.report(g).by_op["Var"].evaluations, 1);
+}
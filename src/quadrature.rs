@@ -0,0 +1,151 @@
+// Definite integrals lowered into the graph as Gauss-Legendre quadrature,
+// so derivatives with respect to parameters of the integrand and to the
+// limits come out of the ordinary backward pass.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use graph::Graph;
+use {add, constant, mul, sub, sum, topological_order, Arena, Node};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    // exact for polynomials up to degree 2 points - 1
+    GaussLegendre { points: usize },
+    // the same rule on each of `intervals` equal subintervals
+    Composite { points: usize, intervals: usize },
+}
+
+// nodes and weights of the `n`-point rule on [-1, 1]
+pub fn gauss_legendre(n: usize) -> (Vec<f32>, Vec<f32>) {
+    assert!(n > 0, "quadrature needs at least one point");
+
+    let mut nodes = vec![0f32; n];
+    let mut weights = vec![0f32; n];
+    for i in 0..n.div_ceil(2) {
+        let mut x = (PI * (i as f64 + 0.75) / (n as f64 + 0.5)).cos();
+        let mut derivative = 0f64;
+        for _ in 0..100 {
+            // P_n(x) and P_n'(x) by the three-term recurrence
+            let (mut p0, mut p1) = (1f64, x);
+            for k in 2..n + 1 {
+                let p2 = ((2 * k - 1) as f64 * x * p1 - (k - 1) as f64 * p0) / k as f64;
+                p0 = p1;
+                p1 = p2;
+            }
+            derivative = n as f64 * (x * p1 - p0) / (x * x - 1f64);
+            let step = p1 / derivative;
+            x -= step;
+            if step.abs() < 1e-15 {
+                break;
+            }
+        }
+        let weight = 2f64 / ((1f64 - x * x) * derivative * derivative);
+        nodes[i] = -x as f32;
+        nodes[n - 1 - i] = x as f32;
+        weights[i] = weight as f32;
+        weights[n - 1 - i] = weight as f32;
+    }
+    (nodes, weights)
+}
+
+// The integral of `integrand` over `var` from `a` to `b`. The integrand is
+// copied once per quadrature point with `var` replaced; its other variables
+// stay the same nodes.
+pub fn integrate<'a>(
+    arena: &'a Arena<'a>,
+    integrand: Node<'a>,
+    var: &str,
+    a: Node<'a>,
+    b: Node<'a>,
+    scheme: Scheme,
+) -> Node<'a> {
+    let (points, intervals) = match scheme {
+        Scheme::GaussLegendre { points } => (points, 1),
+        Scheme::Composite { points, intervals } => (points, intervals),
+    };
+    assert!(intervals > 0, "quadrature needs at least one interval");
+    let (nodes, weights) = gauss_legendre(points);
+
+    let (graph, root) = Graph::from_node(integrand);
+    let mut bindings: HashMap<&str, Node<'a>> = HashMap::new();
+    for node in topological_order(&[integrand]) {
+        if let Some(name) = node.var_name() {
+            bindings.entry(name).or_insert(node);
+        }
+    }
+
+    // every subinterval maps [-1, 1] onto [lo, lo + 2 half]
+    let width = sub(arena, b, a);
+    let half = mul(arena, width, constant(arena, 0.5 / intervals as f32));
+    let mut terms = vec![];
+    for k in 0..intervals {
+        for (&t, &w) in nodes.iter().zip(&weights) {
+            let offset = constant(arena, (2 * k + 1) as f32 + t);
+            let x = add(arena, a, mul(arena, half, offset));
+            bindings.insert(var, x);
+            let fx = graph.build_in(arena, root, &bindings);
+            terms.push(mul(arena, constant(arena, w), fx));
+        }
+    }
+
+    mul(arena, half, sum(arena, &terms))
+}
+
+#[test]
+fn gauss_legendre_rules() {
+    let (nodes, weights) = gauss_legendre(3);
+    let expected = (0.6f32).sqrt();
+    assert!((nodes[0] + expected).abs() < 1e-6 && nodes[1].abs() < 1e-7);
+    assert!((weights[0] - 5.0 / 9.0).abs() < 1e-6);
+    assert!((weights[1] - 8.0 / 9.0).abs() < 1e-6);
+
+    // exact for degree 2n - 1
+    let (nodes, weights) = gauss_legendre(4);
+    let integral: f32 = nodes.iter().zip(&weights).map(|(x, w)| w * x.powi(6)).sum();
+    assert!((integral - 2.0 / 7.0).abs() < 1e-6);
+
+    let (nodes, weights) = gauss_legendre(1);
+    assert_eq!((nodes, weights), (vec![0.0], vec![2.0]));
+}
+
+#[test]
+fn integral_derivatives() {
+    use {sin, var};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    // F(k, a, b) = integral of sin(k t) dt from a to b
+    //            = (cos(k a) - cos(k b)) / k
+    let t = var(arena, "t".to_string());
+    let k = var(arena, "k".to_string());
+    let a = var(arena, "a".to_string());
+    let b = var(arena, "b".to_string());
+    let integrand = sin(arena, mul(arena, k, t));
+    let f = integrate(
+        arena,
+        integrand,
+        "t",
+        a,
+        b,
+        Scheme::Composite {
+            points: 5,
+            intervals: 2,
+        },
+    );
+
+    let (kv, av, bv) = (1.5f32, 0.2f32, 1.7f32);
+    k.set_value(kv);
+    a.set_value(av);
+    b.set_value(bv);
+    f.forward();
+    let exact = ((kv * av).cos() - (kv * bv).cos()) / kv;
+    assert!((f.value() - exact).abs() < 1e-5);
+
+    let grads = f.gradients(&["k", "a", "b"]);
+    // Leibniz: dF/da = -f(a), dF/db = f(b)
+    assert!((grads["a"] + (kv * av).sin()).abs() < 1e-5);
+    assert!((grads["b"] - (kv * bv).sin()).abs() < 1e-5);
+    let dk = (-av * (kv * av).sin() + bv * (kv * bv).sin()) / kv - exact / kv;
+    assert!((grads["k"] - dk).abs() < 1e-4);
+}
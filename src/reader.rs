@@ -0,0 +1,58 @@
+// Reading the crate's little-endian binary formats. Every failure is a
+// `Malformed`, which the formats turn into their own errors.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Malformed;
+
+pub(crate) struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Reader<'b> {
+    pub(crate) fn new(bytes: &'b [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'b [u8], Malformed> {
+        if self.bytes.len() - self.pos < n {
+            return Err(Malformed);
+        }
+        self.pos += n;
+        Ok(&self.bytes[self.pos - n..self.pos])
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, Malformed> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Malformed> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32, Malformed> {
+        self.u32().map(f32::from_bits)
+    }
+
+    // LEB128
+    pub(crate) fn varint(&mut self) -> Result<u64, Malformed> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Malformed)
+    }
+
+    pub(crate) fn utf8(&mut self, len: usize) -> Result<String, Malformed> {
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| Malformed)
+    }
+
+    pub(crate) fn is_at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+}
@@ -0,0 +1,270 @@
+// Named expressions that may refer to each other, spreadsheet style.
+//
+// A variable of a definition whose name is itself defined refers to that
+// definition; any other variable is an input. Values are cached, and
+// redefining an expression or setting an input drops the cached values of
+// everything depending on it.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use graph::{Graph, NodeId};
+use {eval_with_grad, topological_order, Arena, Assignment, Gradients, Node};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryError {
+    UnknownName(String),
+    UnassignedInput(String),
+    // the definition would make the names refer to each other in a loop
+    Cycle(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegistryError::UnknownName(ref name) => write!(f, "{} is not defined", name),
+            RegistryError::UnassignedInput(ref name) => write!(f, "input {} is not assigned", name),
+            RegistryError::Cycle(ref name) => write!(f, "{} would depend on itself", name),
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    graph: Graph,
+    root: NodeId,
+    // every variable name in the expression
+    names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    defs: HashMap<String, Definition>,
+    inputs: Assignment,
+    values: HashMap<String, f32>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.defs.contains_key(name)
+    }
+
+    // Defines or redefines `name` as `node`. Returns the names whose values
+    // are invalidated, `name` included, in sorted order.
+    pub fn define(&mut self, name: &str, node: Node) -> Result<Vec<String>, RegistryError> {
+        let mut names: Vec<String> = topological_order(&[node])
+            .iter()
+            .filter_map(|node| node.var_name().map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        // the new expression must not reach `name` through other
+        // definitions, including ones that only refer to `name` because it
+        // is about to be defined
+        let mut stack: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        let mut seen = BTreeSet::new();
+        while let Some(next) = stack.pop() {
+            if next == name {
+                return Err(RegistryError::Cycle(name.to_string()));
+            }
+            if seen.insert(next) {
+                if let Some(def) = self.defs.get(next) {
+                    stack.extend(def.names.iter().map(|n| n.as_str()));
+                }
+            }
+        }
+
+        let (graph, root) = Graph::from_node(node);
+        self.defs
+            .insert(name.to_string(), Definition { graph, root, names });
+        Ok(self.invalidate(name))
+    }
+
+    // Removes a definition; expressions referring to it see an input of that
+    // name from now on. Returns the invalidated names.
+    pub fn remove(&mut self, name: &str) -> Result<Vec<String>, RegistryError> {
+        let invalidated = self.invalidate(name);
+        match self.defs.remove(name) {
+            Some(_) => Ok(invalidated),
+            None => Err(RegistryError::UnknownName(name.to_string())),
+        }
+    }
+
+    // Returns the definitions whose values are invalidated.
+    pub fn set_input(&mut self, name: &str, value: f32) -> Vec<String> {
+        self.inputs.insert(name.to_string(), value);
+        self.invalidate(name)
+    }
+
+    // names of the definitions `name` refers to directly
+    pub fn references(&self, name: &str) -> Vec<&str> {
+        match self.defs.get(name) {
+            Some(def) => def
+                .names
+                .iter()
+                .filter(|n| self.defs.contains_key(n.as_str()))
+                .map(|n| n.as_str())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // definitions depending on `name`, directly or not, in sorted order
+    pub fn dependents(&self, name: &str) -> Vec<String> {
+        let mut found = BTreeSet::new();
+        let mut stack = vec![name.to_string()];
+        while let Some(next) = stack.pop() {
+            for (other, def) in &self.defs {
+                if def.names.contains(&next) && found.insert(other.clone()) {
+                    stack.push(other.clone());
+                }
+            }
+        }
+        found.into_iter().collect()
+    }
+
+    fn invalidate(&mut self, name: &str) -> Vec<String> {
+        let mut invalidated = self.dependents(name);
+        if self.defs.contains_key(name) && !invalidated.iter().any(|n| n == name) {
+            invalidated.push(name.to_string());
+            invalidated.sort();
+        }
+        for stale in &invalidated {
+            self.values.remove(stale);
+        }
+        invalidated
+    }
+
+    // The value of `name`, recomputing only what was invalidated.
+    pub fn value(&mut self, name: &str) -> Result<f32, RegistryError> {
+        if let Some(&value) = self.values.get(name) {
+            return Ok(value);
+        }
+        let (names, root) = match self.defs.get(name) {
+            Some(def) => (def.names.clone(), def.root),
+            None => return Err(RegistryError::UnknownName(name.to_string())),
+        };
+
+        let mut at = Assignment::new();
+        for n in names {
+            let value = if self.defs.contains_key(&n) {
+                self.value(&n)?
+            } else {
+                match self.inputs.get(&n) {
+                    Some(&value) => value,
+                    None => return Err(RegistryError::UnassignedInput(n)),
+                }
+            };
+            at.insert(n, value);
+        }
+        let value = self.defs[name].graph.eval(root, &at);
+        self.values.insert(name.to_string(), value);
+        Ok(value)
+    }
+
+    // Gradient of `name` with respect to inputs, through the definitions it
+    // refers to.
+    pub fn gradient(&mut self, name: &str, inputs: &[&str]) -> Result<Gradients, RegistryError> {
+        let arena = Arena::new();
+        let mut built = HashMap::new();
+        let node = self.build(&arena, name, &mut built)?;
+        for input in topological_order(&[node])
+            .iter()
+            .filter_map(|node| node.var_name())
+        {
+            if !self.inputs.contains_key(input) {
+                return Err(RegistryError::UnassignedInput(input.to_string()));
+            }
+        }
+        let (value, grads) = eval_with_grad(node, &self.inputs, inputs);
+        self.values.insert(name.to_string(), value);
+        Ok(grads)
+    }
+
+    // `name` with every referenced definition expanded in place
+    fn build<'a>(
+        &self,
+        arena: &'a Arena<'a>,
+        name: &str,
+        built: &mut HashMap<String, Node<'a>>,
+    ) -> Result<Node<'a>, RegistryError> {
+        if let Some(&node) = built.get(name) {
+            return Ok(node);
+        }
+        let def = match self.defs.get(name) {
+            Some(def) => def,
+            None => return Err(RegistryError::UnknownName(name.to_string())),
+        };
+        let mut bindings = HashMap::new();
+        for reference in self.references(name) {
+            bindings.insert(reference, self.build(arena, reference, built)?);
+        }
+        let node = def.graph.build_in(arena, def.root, &bindings);
+        built.insert(name.to_string(), node);
+        Ok(node)
+    }
+}
+
+#[test]
+fn definitions_track_dependencies() {
+    use {add, constant, mul, var};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let price = var(arena, "price".to_string());
+    let qty = var(arena, "qty".to_string());
+    let revenue = var(arena, "revenue".to_string());
+    let tax = var(arena, "tax".to_string());
+
+    let mut registry = Registry::new();
+    registry.define("revenue", mul(arena, price, qty)).unwrap();
+    registry
+        .define("total", add(arena, revenue, mul(arena, revenue, tax)))
+        .unwrap();
+    assert_eq!(registry.references("total"), vec!["revenue"]);
+
+    assert_eq!(
+        registry.value("total"),
+        Err(RegistryError::UnassignedInput("price".to_string()))
+    );
+    registry.set_input("price", 2.0);
+    registry.set_input("qty", 10.0);
+    assert_eq!(registry.set_input("tax", 0.5), vec!["total".to_string()]);
+    assert_eq!(registry.value("total"), Ok(30.0));
+
+    // redefining revenue invalidates total
+    let invalidated = registry.define("revenue", add(arena, price, qty)).unwrap();
+    assert_eq!(
+        invalidated,
+        vec!["revenue".to_string(), "total".to_string()]
+    );
+    assert_eq!(registry.value("total"), Ok(18.0));
+
+    let grads = registry.gradient("total", &["price", "tax"]).unwrap();
+    assert_eq!(grads["price"], 1.5);
+    assert_eq!(grads["tax"], 12.0);
+
+    assert_eq!(
+        registry.define("revenue", mul(arena, var(arena, "total".to_string()), qty)),
+        Err(RegistryError::Cycle("revenue".to_string()))
+    );
+    assert_eq!(registry.value("total"), Ok(18.0));
+
+    // b refers to a before a is defined
+    let one = constant(arena, 1.0);
+    registry
+        .define("b", add(arena, var(arena, "a".to_string()), one))
+        .unwrap();
+    assert_eq!(
+        registry.define("a", add(arena, var(arena, "b".to_string()), one)),
+        Err(RegistryError::Cycle("a".to_string()))
+    );
+}
@@ -0,0 +1,279 @@
+use std::error::Error;
+use std::fmt;
+
+use linalg::lu_solve;
+use {jacobian, Node};
+
+const MAX_ITERS: usize = 100;
+const VALUE_TOL: f32 = 1e-6;
+const STEP_TOL: f32 = 1e-7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RootMethod {
+    Newton,
+    Bisection { lo: f32, hi: f32 },
+    // Newton steps, falling back to bisection whenever a step leaves the bracket
+    Bracketed { lo: f32, hi: f32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootError {
+    // f(lo) and f(hi) have the same sign
+    InvalidBracket { lo: f32, hi: f32 },
+    ZeroDerivative { at: f32 },
+    SingularJacobian { at: Vec<f32> },
+    NoConvergence { last: Vec<f32> },
+}
+
+impl fmt::Display for RootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RootError::InvalidBracket { lo, hi } => {
+                write!(f, "[{}, {}] does not bracket a root", lo, hi)
+            }
+            RootError::ZeroDerivative { at } => write!(f, "zero derivative at {}", at),
+            RootError::SingularJacobian { ref at } => write!(f, "singular Jacobian at {:?}", at),
+            RootError::NoConvergence { ref last } => {
+                write!(
+                    f,
+                    "no convergence after {} iterations, last iterate {:?}",
+                    MAX_ITERS, last
+                )
+            }
+        }
+    }
+}
+
+impl Error for RootError {}
+
+// Solves node(var) = 0. The variable is left at the root.
+pub fn find_root(
+    node: Node,
+    var: Node,
+    initial: f32,
+    method: RootMethod,
+) -> Result<f32, RootError> {
+    let name = var
+        .var_name()
+        .expect("root variable must be a variable node");
+    let evaluate = |x: f32| {
+        var.set_value(x);
+        node.forward();
+        node.backward_ad(&[name]);
+        (node.value(), node.grad(name))
+    };
+
+    let bracket = match method {
+        RootMethod::Newton => None,
+        RootMethod::Bisection { lo, hi } | RootMethod::Bracketed { lo, hi } => {
+            let (f_lo, f_hi) = (evaluate(lo).0, evaluate(hi).0);
+            if f_lo == 0f32 {
+                return Ok(lo);
+            }
+            if f_hi == 0f32 {
+                return Ok(hi);
+            }
+            if f_lo.signum() == f_hi.signum() {
+                return Err(RootError::InvalidBracket { lo, hi });
+            }
+            // keep the bracket oriented so that f(lo) < 0 < f(hi)
+            Some(if f_lo < 0f32 { (lo, hi) } else { (hi, lo) })
+        }
+    };
+
+    let mut x = match (method, bracket) {
+        (RootMethod::Bisection { .. }, Some((lo, hi))) => (lo + hi) / 2f32,
+        (_, Some((lo, hi))) if !between(initial, lo, hi) => (lo + hi) / 2f32,
+        _ => initial,
+    };
+    let mut bracket = bracket;
+
+    for _ in 0..MAX_ITERS {
+        let (value, derivative) = evaluate(x);
+        if value.abs() <= VALUE_TOL {
+            return Ok(x);
+        }
+
+        if let Some((ref mut lo, ref mut hi)) = bracket {
+            if value < 0f32 {
+                *lo = x;
+            } else {
+                *hi = x;
+            }
+        }
+
+        let newton = x - value / derivative;
+        let next = match (method, bracket) {
+            (RootMethod::Newton, _) => {
+                if derivative == 0f32 {
+                    return Err(RootError::ZeroDerivative { at: x });
+                }
+                newton
+            }
+            (RootMethod::Bracketed { .. }, Some((lo, hi)))
+                if newton.is_finite() && between(newton, lo, hi) =>
+            {
+                newton
+            }
+            (_, Some((lo, hi))) => (lo + hi) / 2f32,
+            (_, None) => unreachable!(),
+        };
+
+        if (next - x).abs() <= STEP_TOL * x.abs().max(1f32) {
+            var.set_value(next);
+            return Ok(next);
+        }
+        x = next;
+    }
+
+    Err(RootError::NoConvergence { last: vec![x] })
+}
+
+fn between(x: f32, a: f32, b: f32) -> bool {
+    a.min(b) <= x && x <= a.max(b)
+}
+
+// Solves the square system equations(vars) = 0 with damped Newton steps on
+// the Jacobian. The variables are left at the root.
+pub fn find_root_multi(
+    equations: &[Node],
+    vars: &[Node],
+    initial: &[f32],
+) -> Result<Vec<f32>, RootError> {
+    assert_eq!(equations.len(), vars.len(), "the system must be square");
+    let names: Vec<&str> = vars
+        .iter()
+        .map(|var| {
+            var.var_name()
+                .expect("root variables must be variable nodes")
+        })
+        .collect();
+    let residual = |x: &[f32]| -> Vec<f32> {
+        for (var, value) in vars.iter().zip(x) {
+            var.set_value(*value);
+        }
+        equations
+            .iter()
+            .map(|equation| {
+                equation.forward();
+                equation.value()
+            })
+            .collect()
+    };
+    let sq_norm = |r: &[f32]| r.iter().map(|r| r * r).sum::<f32>();
+
+    let mut x = initial.to_vec();
+    let mut r = residual(&x);
+    for _ in 0..MAX_ITERS {
+        if r.iter().all(|r| r.abs() <= VALUE_TOL) {
+            return Ok(x);
+        }
+
+        let neg_r: Vec<f32> = r.iter().map(|r| -r).collect();
+        let step = match lu_solve(&jacobian(equations, &names), &neg_r) {
+            Some(step) => step,
+            None => return Err(RootError::SingularJacobian { at: x }),
+        };
+
+        // halve the step until the residual shrinks
+        let mut scale = 1f32;
+        let (next, next_r) = loop {
+            let candidate: Vec<f32> = x.iter().zip(&step).map(|(x, s)| x + scale * s).collect();
+            let candidate_r = residual(&candidate);
+            if sq_norm(&candidate_r) < sq_norm(&r) || scale < 1e-4 {
+                break (candidate, candidate_r);
+            }
+            scale /= 2f32;
+        };
+
+        let moved = next
+            .iter()
+            .zip(&x)
+            .any(|(n, x)| (n - x).abs() > STEP_TOL * x.abs().max(1f32));
+        x = next;
+        r = next_r;
+        if !moved && r.iter().all(|r| r.abs() <= VALUE_TOL.sqrt()) {
+            return Ok(x);
+        }
+    }
+
+    Err(RootError::NoConvergence { last: x })
+}
+
+#[test]
+fn scalar_roots() {
+    use {constant, cos, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    // x^2 - 2
+    let f = sub(arena, mul(arena, x, x), constant(arena, 2f32));
+    let sqrt2 = 2f32.sqrt();
+
+    let newton = find_root(f, x, 1f32, RootMethod::Newton).unwrap();
+    assert!((newton - sqrt2).abs() < 1e-5);
+
+    let bisection = find_root(f, x, 0f32, RootMethod::Bisection { lo: 0f32, hi: 2f32 }).unwrap();
+    assert!((bisection - sqrt2).abs() < 1e-5);
+
+    // Newton from 0 would divide by zero; the bracket rescues it
+    let bracketed = find_root(f, x, 0f32, RootMethod::Bracketed { lo: 0f32, hi: 2f32 }).unwrap();
+    assert!((bracketed - sqrt2).abs() < 1e-5);
+    assert_eq!(
+        find_root(f, x, 0f32, RootMethod::Newton),
+        Err(RootError::ZeroDerivative { at: 0f32 })
+    );
+
+    // cos(x) - x
+    let g = sub(arena, cos(arena, x), x);
+    assert_eq!(
+        find_root(g, x, 0f32, RootMethod::Bisection { lo: 1f32, hi: 2f32 }),
+        Err(RootError::InvalidBracket { lo: 1f32, hi: 2f32 })
+    );
+}
+
+#[test]
+fn system_root() {
+    use {add, constant, mul, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    // x^2 + y^2 = 4, x = y
+    let circle = sub(
+        arena,
+        add(arena, mul(arena, x, x), mul(arena, y, y)),
+        constant(arena, 4f32),
+    );
+    let diagonal = sub(arena, x, y);
+
+    let root = find_root_multi(&[circle, diagonal], &[x, y], &[1f32, 0.5]).unwrap();
+    assert!((root[0] - 2f32.sqrt()).abs() < 1e-4);
+    assert!((root[1] - 2f32.sqrt()).abs() < 1e-4);
+}
+
+#[test]
+fn system_root_waits_for_every_component() {
+    use {constant, mul, pow, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    // x is exact after one step while y is still converging
+    let linear = sub(arena, x, constant(arena, 1f32));
+    let square = sub(arena, mul(arena, y, y), constant(arena, 2f32));
+
+    let root = find_root_multi(&[linear, square], &[x, y], &[0f32, 1f32]).unwrap();
+    assert_eq!(root[0], 1f32);
+    assert!((root[1] * root[1] - 2f32).abs() <= VALUE_TOL);
+
+    // a NaN in the Jacobian is an error rather than a panic
+    let root = sub(arena, pow(arena, x, 0.5), constant(arena, 1f32));
+    assert!(find_root_multi(&[root, linear], &[x, y], &[-2f32, 0f32]).is_err());
+}
@@ -0,0 +1,185 @@
+// Graphs from expressions in reverse Polish notation, in the token shape of
+// meval's `to_rpn` output, so applications that already parse expressions
+// with such a crate can differentiate them by mapping tokens one to one.
+//
+// The crate does not depend on meval or evalexpr, so there is no feature
+// converting their ASTs directly; that mapping is the caller's few lines.
+
+use std::error::Error;
+use std::fmt;
+
+use {add, constant, cos, div, exp_m1, ln_1p, mul, neg, pow, sin, sub, var, Arena, Node, NodeType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Plus,
+    Minus,
+    Times,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(f64),
+    Var(String),
+    Binary(Operation),
+    Unary(Operation),
+    // function name and number of arguments
+    Func(String, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpnError {
+    // an operator found fewer operands than it takes
+    MissingOperand(usize),
+    // more than one value left at the end, or none
+    Unbalanced(usize),
+    UnknownFunction(String),
+    UnsupportedOperation(Operation),
+}
+
+impl fmt::Display for RpnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RpnError::MissingOperand(at) => write!(f, "missing operand for token {}", at),
+            RpnError::Unbalanced(left) => {
+                write!(f, "expression leaves {} values instead of one", left)
+            }
+            RpnError::UnknownFunction(ref name) => write!(f, "unknown function {}", name),
+            RpnError::UnsupportedOperation(op) => write!(f, "unsupported operation {:?}", op),
+        }
+    }
+}
+
+impl Error for RpnError {}
+
+fn function<'a>(arena: &'a Arena<'a>, name: &str, args: &[Node<'a>]) -> Option<Node<'a>> {
+    let one = || constant(arena, 1f32);
+    let node = match (name, args) {
+        ("sin", &[x]) => sin(arena, x),
+        ("cos", &[x]) => cos(arena, x),
+        ("tan", &[x]) => div(arena, sin(arena, x), cos(arena, x)),
+        ("exp", &[x]) => add(arena, exp_m1(arena, x), one()),
+        ("exp_m1", &[x]) => exp_m1(arena, x),
+        ("ln", &[x]) => ln_1p(arena, sub(arena, x, one())),
+        ("ln_1p", &[x]) => ln_1p(arena, x),
+        ("sqrt", &[x]) => pow(arena, x, 0.5),
+        _ => return None,
+    };
+    Some(node)
+}
+
+// x^y, with a constant exponent kept as a power node
+fn power<'a>(arena: &'a Arena<'a>, x: Node<'a>, y: Node<'a>) -> Node<'a> {
+    if let NodeType::Const(p) = y.type_ {
+        return pow(arena, x, p);
+    }
+    // exp(y ln x)
+    let ln_x = ln_1p(arena, sub(arena, x, constant(arena, 1f32)));
+    add(
+        arena,
+        exp_m1(arena, mul(arena, y, ln_x)),
+        constant(arena, 1f32),
+    )
+}
+
+// Builds the expression of `tokens`. Variables are created by name, once per
+// occurrence, and start at zero like `var`.
+pub fn from_rpn<'a>(arena: &'a Arena<'a>, tokens: &[Token]) -> Result<Node<'a>, RpnError> {
+    let mut stack: Vec<Node<'a>> = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        let node = match *token {
+            Token::Number(v) => constant(arena, v as f32),
+            Token::Var(ref name) => var(arena, name.clone()),
+            Token::Unary(op) => {
+                let x = stack.pop().ok_or(RpnError::MissingOperand(i))?;
+                match op {
+                    Operation::Plus => x,
+                    Operation::Minus => neg(arena, x),
+                    _ => return Err(RpnError::UnsupportedOperation(op)),
+                }
+            }
+            Token::Binary(op) => {
+                if stack.len() < 2 {
+                    return Err(RpnError::MissingOperand(i));
+                }
+                let rhs = stack.pop().unwrap();
+                let lhs = stack.pop().unwrap();
+                match op {
+                    Operation::Plus => add(arena, lhs, rhs),
+                    Operation::Minus => sub(arena, lhs, rhs),
+                    Operation::Times => mul(arena, lhs, rhs),
+                    Operation::Div => div(arena, lhs, rhs),
+                    Operation::Pow => power(arena, lhs, rhs),
+                }
+            }
+            Token::Func(ref name, n) => {
+                if stack.len() < n {
+                    return Err(RpnError::MissingOperand(i));
+                }
+                let args = stack.split_off(stack.len() - n);
+                function(arena, name, &args)
+                    .ok_or_else(|| RpnError::UnknownFunction(name.clone()))?
+            }
+        };
+        stack.push(node);
+    }
+
+    match stack.len() {
+        1 => Ok(stack[0]),
+        n => Err(RpnError::Unbalanced(n)),
+    }
+}
+
+#[test]
+fn rpn_expressions() {
+    use self::Operation::*;
+    use self::Token::*;
+    use {eval_with_grad, forward_multi, Assignment};
+
+    let arena = Arena::new();
+    // sin(x) * y + x^2
+    let tokens = vec![
+        Var("x".to_string()),
+        Func("sin".to_string(), 1),
+        Var("y".to_string()),
+        Binary(Times),
+        Var("x".to_string()),
+        Number(2.0),
+        Binary(Pow),
+        Binary(Plus),
+    ];
+    let f = from_rpn(&arena, &tokens).unwrap();
+    let (x, y) = (0.5f32, 3.0f32);
+    let grads = {
+        let mut at = Assignment::new();
+        at.insert("x".to_string(), x);
+        at.insert("y".to_string(), y);
+        eval_with_grad(f, &at, &["x", "y"])
+    };
+    assert_eq!(grads.0, x.sin() * y + x * x);
+    assert!((grads.1["x"] - (x.cos() * y + 2.0 * x)).abs() < 1e-6);
+    assert_eq!(grads.1["y"], x.sin());
+
+    // x^y with a variable exponent
+    let tokens = vec![Var("x".to_string()), Var("y".to_string()), Binary(Pow)];
+    let mut at = Assignment::new();
+    at.insert("x".to_string(), 2.0);
+    at.insert("y".to_string(), 3.0);
+    let value = forward_multi(&[from_rpn(&arena, &tokens).unwrap()], &at)[0];
+    assert!((value - 8.0).abs() < 1e-5);
+
+    assert_eq!(
+        from_rpn(&arena, &[Number(1.0), Binary(Plus)]).err(),
+        Some(RpnError::MissingOperand(1))
+    );
+    assert_eq!(
+        from_rpn(&arena, &[Number(1.0), Func("gamma".to_string(), 1)]).err(),
+        Some(RpnError::UnknownFunction("gamma".to_string()))
+    );
+    assert_eq!(
+        from_rpn(&arena, &[Number(1.0), Number(2.0)]).err(),
+        Some(RpnError::Unbalanced(2))
+    );
+}
@@ -0,0 +1,94 @@
+// Point series of an expression along one variable, e.g. for plotting.
+
+use std::ops::Range;
+
+use {adjoints, evaluate_at, node_key, topological_order, variable_gradients, Assignment, Node};
+
+// `n` evenly spaced points from `range.start` to `range.end`, both included
+fn grid(range: &Range<f32>, n: usize) -> Vec<f32> {
+    match n {
+        0 => vec![],
+        1 => vec![range.start],
+        _ => {
+            let step = (range.end - range.start) / (n - 1) as f32;
+            (0..n)
+                .map(|i| {
+                    if i == n - 1 {
+                        range.end
+                    } else {
+                        range.start + step * i as f32
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+// (x, f(x)) at `n` points of `range`, with the other variables taken from
+// `base`. Nothing is written to the nodes.
+pub fn sample(
+    node: Node,
+    var: &str,
+    range: Range<f32>,
+    n: usize,
+    base: &Assignment,
+) -> Vec<(f32, f32)> {
+    let order = topological_order(&[node]);
+    let mut at = base.clone();
+    grid(&range, n)
+        .into_iter()
+        .map(|x| {
+            at.insert(var.to_string(), x);
+            (x, evaluate_at(&order, &at)[&node_key(node)])
+        })
+        .collect()
+}
+
+// (x, df/dx(x)) at the same points as `sample`.
+pub fn sample_derivative(
+    node: Node,
+    var: &str,
+    range: Range<f32>,
+    n: usize,
+    base: &Assignment,
+) -> Vec<(f32, f32)> {
+    let order = topological_order(&[node]);
+    let mut at = base.clone();
+    grid(&range, n)
+        .into_iter()
+        .map(|x| {
+            at.insert(var.to_string(), x);
+            let values = evaluate_at(&order, &at);
+            let adjoints = adjoints(&order, &values, &[(node, 1f32)]);
+            (x, variable_gradients(&order, &adjoints, &[var])[0])
+        })
+        .collect()
+}
+
+#[test]
+fn sample_value_and_derivative() {
+    use {mul, var, Arena};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let a = var(&arena, "a".to_string());
+    let f = mul(&arena, a, mul(&arena, x, x));
+
+    let mut base = Assignment::new();
+    base.insert("a".to_string(), 3.0);
+    let values = sample(f, "x", -1.0..1.0, 5, &base);
+    assert_eq!(
+        values,
+        vec![
+            (-1.0, 3.0),
+            (-0.5, 0.75),
+            (0.0, 0.0),
+            (0.5, 0.75),
+            (1.0, 3.0)
+        ]
+    );
+    let slopes = sample_derivative(f, "x", -1.0..1.0, 5, &base);
+    assert_eq!(slopes[0], (-1.0, -6.0));
+    assert_eq!(slopes[4], (1.0, 6.0));
+    assert!(sample(f, "x", 0.0..1.0, 0, &base).is_empty());
+}
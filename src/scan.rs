@@ -0,0 +1,177 @@
+// Unrolling iterative updates into the graph.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use {node_key, stop_gradient, topological_order, Arena, Node, NodeData};
+
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    // cut gradients flowing through the state every this many steps
+    // (truncated backpropagation through time)
+    pub truncate_every: Option<usize>,
+    // fail instead of building a state deeper than this
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanError {
+    TooDeep { step: usize, depth: usize },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScanError::TooDeep { step, depth } => {
+                write!(f, "state reached depth {} at step {}", depth, step)
+            }
+        }
+    }
+}
+
+impl Error for ScanError {}
+
+// Applies `body` to the state `n_steps` times and returns the state after
+// every step. Each step only references the previous state's nodes, so the
+// unrolled graph grows linearly with `n_steps`.
+pub fn scan<'a, F>(
+    arena: &'a Arena<'a>,
+    init: &[Node<'a>],
+    n_steps: usize,
+    body: F,
+) -> Vec<Vec<Node<'a>>>
+where
+    F: FnMut(&[Node<'a>]) -> Vec<Node<'a>>,
+{
+    scan_with(arena, init, n_steps, &ScanOptions::default(), body)
+        .expect("scan without a depth limit cannot fail")
+}
+
+pub fn scan_with<'a, F>(
+    arena: &'a Arena<'a>,
+    init: &[Node<'a>],
+    n_steps: usize,
+    opts: &ScanOptions,
+    mut body: F,
+) -> Result<Vec<Vec<Node<'a>>>, ScanError>
+where
+    F: FnMut(&[Node<'a>]) -> Vec<Node<'a>>,
+{
+    let mut depths = HashMap::new();
+    let mut states = Vec::with_capacity(n_steps);
+    let mut state = init.to_vec();
+
+    for step in 1..n_steps + 1 {
+        let mut next = body(&state);
+        assert_eq!(
+            next.len(),
+            state.len(),
+            "scan body must preserve the state size"
+        );
+
+        if let Some(max_depth) = opts.max_depth {
+            let depth = next
+                .iter()
+                .map(|&node| depth_memo(node, &mut depths))
+                .max()
+                .unwrap_or(0);
+            if depth > max_depth {
+                return Err(ScanError::TooDeep { step, depth });
+            }
+        }
+
+        if let Some(every) = opts.truncate_every {
+            if step.is_multiple_of(every) {
+                next = next
+                    .into_iter()
+                    .map(|node| stop_gradient(arena, node))
+                    .collect();
+            }
+        }
+
+        states.push(next.clone());
+        state = next;
+    }
+
+    Ok(states)
+}
+
+// Length of the longest path from `node` down to a leaf.
+pub fn depth(node: Node) -> usize {
+    depth_memo(node, &mut HashMap::new())
+}
+
+fn depth_memo<'a>(node: Node<'a>, depths: &mut HashMap<*const NodeData<'a>, usize>) -> usize {
+    if let Some(&depth) = depths.get(&node_key(node)) {
+        return depth;
+    }
+
+    // only walks the part of the graph not seen before
+    for node in topological_order(&[node]) {
+        if depths.contains_key(&node_key(node)) {
+            continue;
+        }
+        let depth = node
+            .children()
+            .iter()
+            .map(|child| depths[&node_key(child)] + 1)
+            .max()
+            .unwrap_or(0);
+        depths.insert(node_key(node), depth);
+    }
+
+    depths[&node_key(node)]
+}
+
+#[test]
+fn scan_unrolls_recurrence() {
+    use {add, constant, mul, var};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let a = var(arena, "a".to_string());
+    let x0 = constant(arena, 1f32);
+
+    // x <- a * x + 1
+    let states = scan(arena, &[x0], 5, |state| {
+        vec![add(arena, mul(arena, a, state[0]), constant(arena, 1f32))]
+    });
+    let last = states[4][0];
+
+    a.set_value(2f32);
+    last.forward();
+    last.backward_ad(&["a"]);
+
+    assert_eq!(states.len(), 5);
+    assert_eq!(last.value(), 63f32);
+    // d/da of a^5 + a^4 + a^3 + a^2 + a + 1
+    assert_eq!(last.grad("a"), 129f32);
+    assert_eq!(depth(last), 10);
+
+    let opts = ScanOptions {
+        max_depth: Some(6),
+        ..Default::default()
+    };
+    let err = scan_with(arena, &[x0], 5, &opts, |state| {
+        vec![add(arena, mul(arena, a, state[0]), constant(arena, 1f32))]
+    });
+    assert_eq!(err.err(), Some(ScanError::TooDeep { step: 4, depth: 8 }));
+
+    let opts = ScanOptions {
+        truncate_every: Some(3),
+        ..Default::default()
+    };
+    let truncated = scan_with(arena, &[x0], 5, &opts, |state| {
+        vec![add(arena, mul(arena, a, state[0]), constant(arena, 1f32))]
+    })
+    .unwrap();
+    let last = truncated[4][0];
+    last.forward();
+    last.backward_ad(&["a"]);
+
+    assert_eq!(last.value(), 63f32);
+    // only the last two steps contribute: d/da (a^2 x3 + a + 1) with x3 = 15
+    assert_eq!(last.grad("a"), 61f32);
+}
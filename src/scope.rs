@@ -0,0 +1,281 @@
+// A per-thread default graph for short programs.
+//
+// Inside `scope`, the free functions of this module and the operators on
+// `Value` add nodes to a graph owned by the scope, so no arena or graph has
+// to be passed around:
+//
+//     let dfdx = scope(|g| {
+//         let x = var("x");
+//         let f = sin(x) * x + 1.0;
+//         g.grad(f, &[("x", 0.5)].into(), &["x"])["x"]
+//     });
+//
+// Scopes nest; values belong to the innermost scope at the time they were
+// built and must not be used after it ends.
+
+use std::cell::{Cell, RefCell};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use graph::{Graph, NodeId};
+use num::{ln_by_select, AdNum};
+use {Assignment, Gradients};
+
+thread_local! {
+    // the graphs of the enclosing scopes, innermost last, by scope number
+    static GRAPHS: RefCell<Vec<(usize, Graph)>> = const { RefCell::new(vec![]) };
+    static NEXT_SCOPE: Cell<usize> = const { Cell::new(0) };
+}
+
+fn with_graph<R, F: FnOnce(&mut Graph) -> R>(scope: usize, f: F) -> R {
+    GRAPHS.with(|graphs| match graphs.borrow_mut().last_mut() {
+        Some(&mut (current, ref mut graph)) if current == scope => f(graph),
+        _ => panic!("value used outside of its scope"),
+    })
+}
+
+fn current_scope() -> usize {
+    GRAPHS.with(|graphs| match graphs.borrow().last() {
+        Some(&(current, _)) => current,
+        None => panic!("used outside of `scope`"),
+    })
+}
+
+// A node of the graph of the scope it was built in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    id: NodeId,
+    scope: usize,
+}
+
+impl Value {
+    fn build<F: FnOnce(&mut Graph) -> NodeId>(scope: usize, f: F) -> Self {
+        Value {
+            id: with_graph(scope, f),
+            scope,
+        }
+    }
+
+    fn map<F: FnOnce(&mut Graph, NodeId) -> NodeId>(self, f: F) -> Self {
+        let id = self.id;
+        Value::build(self.scope, |graph| f(graph, id))
+    }
+
+    // the node in the graph of `Scope::graph`
+    pub fn id(self) -> NodeId {
+        self.id
+    }
+
+    pub fn pow(self, rhs: f32) -> Self {
+        self.map(|graph, id| graph.pow(id, rhs))
+    }
+
+    pub fn sin(self) -> Self {
+        self.map(Graph::sin)
+    }
+
+    pub fn cos(self) -> Self {
+        self.map(Graph::cos)
+    }
+
+    pub fn exp_m1(self) -> Self {
+        self.map(Graph::exp_m1)
+    }
+
+    pub fn ln_1p(self) -> Self {
+        self.map(Graph::ln_1p)
+    }
+
+    pub fn ln(self) -> Self {
+        let scope = self.scope;
+        let half = Value::build(scope, |graph| graph.constant(0.5));
+        ln_by_select(self, half, |cond, then, else_| {
+            Value::build(scope, |graph| graph.select(cond.id, then.id, else_.id))
+        })
+    }
+}
+
+// The scope's side of the graph: evaluation and differentiation.
+pub struct Scope {
+    scope: usize,
+}
+
+impl Scope {
+    // a copy of the graph built so far
+    pub fn graph(&self) -> Graph {
+        with_graph(self.scope, |graph| graph.clone())
+    }
+
+    pub fn eval(&self, value: Value, assignment: &Assignment) -> f32 {
+        with_graph(self.scope, |graph| graph.eval(value.id, assignment))
+    }
+
+    pub fn grad(&self, value: Value, assignment: &Assignment, variables: &[&str]) -> Gradients {
+        with_graph(self.scope, |graph| {
+            graph.grad(value.id, assignment, variables)
+        })
+    }
+
+    pub fn eval_with_grad(
+        &self,
+        value: Value,
+        assignment: &Assignment,
+        variables: &[&str],
+    ) -> (f32, Gradients) {
+        with_graph(self.scope, |graph| {
+            graph.eval_with_grad(value.id, assignment, variables)
+        })
+    }
+}
+
+// pops the scope's graph even when `f` panics
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        GRAPHS.with(|graphs| graphs.borrow_mut().pop());
+    }
+}
+
+// Runs `f` with a fresh graph as the thread's default.
+pub fn scope<R, F: FnOnce(&Scope) -> R>(f: F) -> R {
+    let scope = NEXT_SCOPE.with(|next| next.replace(next.get() + 1));
+    GRAPHS.with(|graphs| graphs.borrow_mut().push((scope, Graph::new())));
+    let _guard = Guard;
+    f(&Scope { scope })
+}
+
+pub fn var(name: &str) -> Value {
+    Value::build(current_scope(), |graph| graph.var(name).id())
+}
+
+pub fn constant(value: f32) -> Value {
+    Value::build(current_scope(), |graph| graph.constant(value))
+}
+
+pub fn pow(value: Value, rhs: f32) -> Value {
+    value.pow(rhs)
+}
+
+pub fn sin(value: Value) -> Value {
+    value.sin()
+}
+
+pub fn cos(value: Value) -> Value {
+    value.cos()
+}
+
+pub fn exp_m1(value: Value) -> Value {
+    value.exp_m1()
+}
+
+pub fn ln_1p(value: Value) -> Value {
+    value.ln_1p()
+}
+
+macro_rules! binary_op {
+    ($trait_:ident, $method:ident) => {
+        impl $trait_ for Value {
+            type Output = Value;
+
+            fn $method(self, rhs: Value) -> Value {
+                assert_eq!(self.scope, rhs.scope, "values from different scopes");
+                self.map(|graph, lhs| graph.$method(lhs, rhs.id))
+            }
+        }
+
+        impl $trait_<f32> for Value {
+            type Output = Value;
+
+            fn $method(self, rhs: f32) -> Value {
+                self.map(|graph, lhs| {
+                    let rhs = graph.constant(rhs);
+                    graph.$method(lhs, rhs)
+                })
+            }
+        }
+
+        impl $trait_<Value> for f32 {
+            type Output = Value;
+
+            fn $method(self, rhs: Value) -> Value {
+                rhs.map(|graph, rhs| {
+                    let lhs = graph.constant(self);
+                    graph.$method(lhs, rhs)
+                })
+            }
+        }
+    };
+}
+
+binary_op!(Add, add);
+binary_op!(Sub, sub);
+binary_op!(Mul, mul);
+binary_op!(Div, div);
+
+impl Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        self.map(Graph::neg)
+    }
+}
+
+impl AdNum for Value {
+    fn sin(self) -> Self {
+        Value::sin(self)
+    }
+
+    fn cos(self) -> Self {
+        Value::cos(self)
+    }
+
+    fn pow(self, exponent: f32) -> Self {
+        Value::pow(self, exponent)
+    }
+
+    fn exp_m1(self) -> Self {
+        Value::exp_m1(self)
+    }
+
+    fn ln_1p(self) -> Self {
+        Value::ln_1p(self)
+    }
+
+    fn ln(self) -> Self {
+        Value::ln(self)
+    }
+}
+
+#[test]
+fn scoped_expressions() {
+    let at: Assignment = [("x", 0.5), ("y", 3.0)].into();
+    let (value, grads) = scope(|g| {
+        let x = var("x");
+        let y = var("y");
+        let f = sin(x) * y + x.pow(2.0) - 1.0 / y;
+        g.eval_with_grad(f, &at, &["x", "y"])
+    });
+    let (x, y) = (0.5f32, 3f32);
+    assert!((value - (x.sin() * y + x * x - 1.0 / y)).abs() < 1e-6);
+    assert!((grads["x"] - (x.cos() * y + 2.0 * x)).abs() < 1e-6);
+    assert!((grads["y"] - (x.sin() + 1.0 / (y * y))).abs() < 1e-6);
+
+    // an inner scope gets its own graph and leaves the outer one alone
+    scope(|outer| {
+        let x = var("x");
+        let inner = scope(|g| {
+            let x = var("x");
+            g.eval(x * x, &at)
+        });
+        assert_eq!(inner, 0.25);
+        assert_eq!(outer.graph().len(), 1);
+        assert_eq!(outer.eval(-x, &at), -0.5);
+    });
+}
+
+#[test]
+#[should_panic(expected = "outside of its scope")]
+fn values_do_not_outlive_their_scope() {
+    let x = scope(|_| var("x"));
+    scope(|_| x + 1.0);
+}
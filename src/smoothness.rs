@@ -0,0 +1,236 @@
+// Finds the nodes that are evaluated at or near a point where they are not
+// differentiable or not defined, where backward silently produces a
+// one-sided, infinite or NaN gradient.
+
+use std::fmt;
+
+use sympy::to_sympy;
+use {evaluate_at, node_key, topological_order, Assignment, Node};
+
+// how close to a bad point counts as at it
+pub const DEFAULT_EPSILON: f32 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hazard {
+    // a select condition or the input of an absolute value or step is at
+    // zero, so the gradient depends on which side is taken
+    Kink,
+    SmallDivisor,
+    // a fractional power of a negative number
+    NegativeBase,
+    // a power below one of a base near zero, whose derivative is unbounded
+    SingularPower,
+    // ln_1p at or below -1
+    LogDomain,
+    // a finite input gave an infinite or NaN value
+    NonFinite,
+}
+
+impl fmt::Display for Hazard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match *self {
+            Hazard::Kink => "non-differentiable point",
+            Hazard::SmallDivisor => "division by a number near zero",
+            Hazard::NegativeBase => "fractional power of a negative number",
+            Hazard::SingularPower => "power below one of a number near zero",
+            Hazard::LogDomain => "logarithm of a non-positive number",
+            Hazard::NonFinite => "non-finite value",
+        };
+        f.write_str(text)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding<'a> {
+    pub hazard: Hazard,
+    pub node: Node<'a>,
+    // the values of the node's operands at the assignment
+    pub operands: Vec<f32>,
+}
+
+impl<'a> fmt::Display for Finding<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}: {} with operands {:?}",
+            self.hazard,
+            self.node.describe(),
+            to_sympy(self.node),
+            self.operands
+        )
+    }
+}
+
+fn hazard(node: Node, inputs: &[f32], output: f32, eps: f32) -> Option<Hazard> {
+    use NodeType::*;
+
+    let near_zero = |x: f32| x.abs() < eps;
+    let found = match node.type_ {
+        Div(..) => near_zero(inputs[1]),
+        Pow(_, p) if inputs[0] < 0f32 && p.fract() != 0f32 => return Some(Hazard::NegativeBase),
+        Pow(_, p) => near_zero(inputs[0]) && p < 1f32 && p != 0f32,
+        Ln1p(_) => inputs[0] < -1f32 + eps,
+        Select(..) => near_zero(inputs[0]),
+        Custom(ref op, _) => op.is_kink(inputs, eps),
+        _ => false,
+    };
+    if found {
+        return Some(match node.type_ {
+            Div(..) => Hazard::SmallDivisor,
+            Pow(..) => Hazard::SingularPower,
+            Ln1p(_) => Hazard::LogDomain,
+            _ => Hazard::Kink,
+        });
+    }
+    // only report where a non-finite value first appears
+    if !output.is_finite() && inputs.iter().all(|x| x.is_finite()) {
+        return Some(Hazard::NonFinite);
+    }
+    None
+}
+
+// Like `analyze_smoothness_with` at `DEFAULT_EPSILON`.
+pub fn analyze_smoothness<'a>(node: Node<'a>, assignment: &Assignment) -> Vec<Finding<'a>> {
+    analyze_smoothness_with(node, assignment, DEFAULT_EPSILON)
+}
+
+// The hazards below `node` at `assignment`, children first. Both branches
+// of a select are checked, as both are evaluated.
+pub fn analyze_smoothness_with<'a>(
+    node: Node<'a>,
+    assignment: &Assignment,
+    eps: f32,
+) -> Vec<Finding<'a>> {
+    let order = topological_order(&[node]);
+    let values = evaluate_at(&order, assignment);
+
+    let mut findings = vec![];
+    for node in order {
+        let inputs: Vec<f32> = node
+            .children()
+            .iter()
+            .map(|child| values[&node_key(child)])
+            .collect();
+        if let Some(hazard) = hazard(node, &inputs, values[&node_key(node)], eps) {
+            findings.push(Finding {
+                hazard,
+                node,
+                operands: inputs,
+            });
+        }
+    }
+    findings
+}
+
+#[test]
+fn hazards_are_reported_with_operands() {
+    use ops::l2_norm;
+    use {add_n, constant, div, exp_m1, labelled, ln_1p, mul, neg, pow, select, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let z = var(arena, "z".to_string());
+
+    // 1 / (x - 1) + y^0.5 + |z| + ln_1p(-y) + exp_m1(100 x) + |(z, z)|
+    let one = constant(arena, 1f32);
+    let ratio = labelled(div(arena, one, sub(arena, x, one)), "ratio");
+    let abs_z = select(arena, z, z, neg(arena, z));
+    let f = add_n(
+        arena,
+        &[
+            ratio,
+            pow(arena, y, 0.5),
+            abs_z,
+            ln_1p(arena, neg(arena, y)),
+            exp_m1(arena, mul(arena, constant(arena, 100f32), x)),
+            l2_norm(arena, &[z, z], 1e-6),
+        ],
+    );
+
+    let smooth: Assignment = [("x", 0.5), ("y", 0.25), ("z", 2.0)].into();
+    assert!(analyze_smoothness(f, &smooth).is_empty());
+
+    let at: Assignment = [("x", 1.0), ("y", 4.0), ("z", 0.0)].into();
+    let findings = analyze_smoothness(f, &at);
+    let hazards: Vec<Hazard> = findings.iter().map(|finding| finding.hazard).collect();
+    // the select and the norm are both kinks at z = 0
+    assert_eq!(hazards.len(), 5, "{:?}", hazards);
+    for hazard in &[Hazard::SmallDivisor, Hazard::Kink, Hazard::LogDomain] {
+        assert!(hazards.contains(hazard), "{:?}", hazards);
+    }
+    // only exp_m1(100) overflows by itself; 1 / 0 is reported for its divisor
+    let overflow: Vec<&Finding> = findings
+        .iter()
+        .filter(|finding| finding.hazard == Hazard::NonFinite)
+        .collect();
+    assert_eq!(overflow.len(), 1);
+    assert_eq!(overflow[0].operands, vec![100f32]);
+    let division = &findings[hazards
+        .iter()
+        .position(|&h| h == Hazard::SmallDivisor)
+        .unwrap()];
+    assert_eq!(division.operands, vec![1f32, 0f32]);
+    let text = division.to_string();
+    assert!(text.starts_with("division by a number near zero at Div ratio (src/smoothness.rs:"));
+    assert!(text.ends_with(": 1/(x - 1) with operands [1.0, 0.0]"));
+
+    let y_negative: Assignment = [("x", 0.0), ("y", -4.0), ("z", 1.0)].into();
+    let hazards: Vec<Hazard> = analyze_smoothness(f, &y_negative)
+        .iter()
+        .map(|finding| finding.hazard)
+        .collect();
+    assert_eq!(hazards, vec![Hazard::NegativeBase]);
+}
+
+#[test]
+fn custom_ops_report_their_own_kinks() {
+    use {custom, var, Arena, CustomOp};
+
+    // smooth, whatever it is called
+    struct Square;
+
+    impl CustomOp for Square {
+        fn name(&self) -> &str {
+            "L1Norm"
+        }
+
+        fn forward(&self, inputs: &[f32]) -> f32 {
+            inputs[0] * inputs[0]
+        }
+
+        fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+            vec![upstream * 2f32 * inputs[0]]
+        }
+    }
+
+    struct Relu;
+
+    impl CustomOp for Relu {
+        fn name(&self) -> &str {
+            "Relu"
+        }
+
+        fn forward(&self, inputs: &[f32]) -> f32 {
+            inputs[0].max(0f32)
+        }
+
+        fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+            vec![if inputs[0] > 0f32 { upstream } else { 0f32 }]
+        }
+
+        fn is_kink(&self, inputs: &[f32], eps: f32) -> bool {
+            inputs[0].abs() < eps
+        }
+    }
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let at: Assignment = [("x", 0.0)].into();
+
+    assert!(analyze_smoothness(custom(&arena, Square, &[x]), &at).is_empty());
+    let findings = analyze_smoothness(custom(&arena, Relu, &[x]), &at);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].hazard, Hazard::Kink);
+}
@@ -0,0 +1,171 @@
+// Jacobians of many outputs whose rows only depend on a few variables.
+//
+// Variables that never appear in the same row are grouped by a greedy
+// coloring of the column intersection graph, and one forward-mode sweep
+// per color recovers all of their columns at once.
+
+use std::collections::{BTreeSet, HashMap};
+
+use {evaluate_all, node_key, topological_order, Node, NodeData, NodeType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseJacobian {
+    pub rows: usize,
+    pub cols: usize,
+    // (row, column, value) triplets sorted by row then column
+    pub entries: Vec<(usize, usize, f32)>,
+    // number of forward sweeps the computation took
+    pub colors: usize,
+}
+
+impl SparseJacobian {
+    pub fn to_dense(&self) -> Vec<Vec<f32>> {
+        let mut dense = vec![vec![0f32; self.cols]; self.rows];
+        for &(row, col, value) in &self.entries {
+            dense[row][col] = value;
+        }
+        dense
+    }
+}
+
+// For every output, the indices of the variables its gradient can depend on.
+pub fn sparsity_pattern(outputs: &[Node], variables: &[&str]) -> Vec<BTreeSet<usize>> {
+    let columns: HashMap<&str, usize> =
+        variables.iter().enumerate().map(|(i, &v)| (v, i)).collect();
+    let mut deps: HashMap<*const NodeData, BTreeSet<usize>> = HashMap::new();
+
+    for node in topological_order(outputs) {
+        let set = match node.type_ {
+            NodeType::Var(ref name) => columns.get(name.as_str()).cloned().into_iter().collect(),
+            NodeType::StopGradient(_) => BTreeSet::new(),
+            _ => node
+                .children()
+                .iter()
+                .flat_map(|child| deps[&node_key(child)].iter().cloned())
+                .collect(),
+        };
+        deps.insert(node_key(node), set);
+    }
+
+    outputs
+        .iter()
+        .map(|output| deps[&node_key(output)].clone())
+        .collect()
+}
+
+// Greedy coloring of the columns such that no row has two columns of the
+// same color. Returns the color of every column.
+fn color_columns(pattern: &[BTreeSet<usize>], cols: usize) -> Vec<usize> {
+    let mut neighbors = vec![BTreeSet::new(); cols];
+    for row in pattern {
+        for &i in row {
+            for &j in row {
+                if i != j {
+                    neighbors[i].insert(j);
+                }
+            }
+        }
+    }
+
+    let mut by_degree: Vec<usize> = (0..cols).collect();
+    by_degree.sort_by_key(|&col| ::std::cmp::Reverse(neighbors[col].len()));
+
+    let mut colors: Vec<Option<usize>> = vec![None; cols];
+    for col in by_degree {
+        let taken: BTreeSet<usize> = neighbors[col].iter().filter_map(|&n| colors[n]).collect();
+        colors[col] = (0..).find(|c| !taken.contains(c));
+    }
+
+    colors.into_iter().map(|c| c.unwrap()).collect()
+}
+
+// Jacobian of `outputs` with respect to `variables` at the current variable
+// values, returned as triplets of its structurally nonzero entries.
+pub fn sparse_jacobian(outputs: &[Node], variables: &[&str]) -> SparseJacobian {
+    let pattern = sparsity_pattern(outputs, variables);
+    let colors = color_columns(&pattern, variables.len());
+    let n_colors = colors.iter().map(|c| c + 1).max().unwrap_or(0);
+
+    let order = topological_order(outputs);
+    let values = evaluate_all(&order);
+
+    let column_color: HashMap<&str, usize> = variables
+        .iter()
+        .zip(&colors)
+        .map(|(&v, &c)| (v, c))
+        .collect();
+    let mut entries = vec![];
+    for color in 0..n_colors {
+        // directional derivative along the sum of this color's columns
+        let mut tangents: HashMap<*const NodeData, f32> = HashMap::new();
+        for &node in &order {
+            let tangent = match node.var_name() {
+                Some(name) => {
+                    if column_color.get(name) == Some(&color) {
+                        1f32
+                    } else {
+                        0f32
+                    }
+                }
+                None => {
+                    let children = node.children();
+                    let inputs: Vec<f32> = children
+                        .iter()
+                        .map(|child| values[&node_key(child)])
+                        .collect();
+                    children
+                        .iter()
+                        .zip(node.partials(&inputs))
+                        .map(|(child, partial)| partial * tangents[&node_key(child)])
+                        .sum()
+                }
+            };
+            tangents.insert(node_key(node), tangent);
+        }
+
+        for (row, (output, cols)) in outputs.iter().zip(&pattern).enumerate() {
+            if let Some(&col) = cols.iter().find(|&&col| colors[col] == color) {
+                entries.push((row, col, tangents[&node_key(output)]));
+            }
+        }
+    }
+    entries.sort_by_key(|&(row, col, _)| (row, col));
+
+    SparseJacobian {
+        rows: outputs.len(),
+        cols: variables.len(),
+        entries,
+        colors: n_colors,
+    }
+}
+
+#[test]
+fn banded_jacobian() {
+    use {jacobian, mul, sin, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    // residuals r_i = x_i * x_{i+1} - sin(x_i)
+    let names: Vec<String> = (0..10).map(|i| format!("x{}", i)).collect();
+    let xs: Vec<_> = names.iter().map(|name| var(arena, name.clone())).collect();
+    for (i, x) in xs.iter().enumerate() {
+        x.set_value(0.1 * i as f32 + 0.3);
+    }
+    let residuals: Vec<_> = xs
+        .windows(2)
+        .map(|pair| sub(arena, mul(arena, pair[0], pair[1]), sin(arena, pair[0])))
+        .collect();
+    let variables: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+
+    let sparse = sparse_jacobian(&residuals, &variables);
+    assert_eq!(sparse.colors, 2);
+    assert_eq!(sparse.entries.len(), 18);
+
+    let dense = jacobian(&residuals, &variables);
+    for (row, expected) in sparse.to_dense().iter().zip(&dense) {
+        for (actual, expected) in row.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+// Values and gradients over a grid of scenarios around a base assignment.
+//
+// The base point is evaluated once. Every scenario then recomputes only the
+// nodes depending on a shocked variable and reuses the base values for the
+// rest, before a reverse sweep for the gradient.
+
+use std::collections::HashMap;
+
+use {node_key, topological_order, Assignment, Gradients, Node, NodeType};
+
+#[derive(Debug, Clone)]
+pub struct SweepResult {
+    // shocked variables, in the order they were given
+    pub shocked: Vec<String>,
+    // variables of the graph the gradients are taken with respect to, sorted
+    pub variables: Vec<String>,
+    // the shock applied to each shocked variable, one row per scenario
+    pub shocks: Vec<Vec<f32>>,
+    pub values: Vec<f32>,
+    pub gradients: Vec<Gradients>,
+}
+
+impl SweepResult {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+// Evaluates `node` and its gradient with respect to all of its variables for
+// every combination of `shocks`. A shock is added to the variable's value in
+// `base`; the last shocked variable varies fastest. Panics if a variable of
+// the graph is missing from `base`.
+pub fn sweep(node: Node, base: &Assignment, shocks: &[(&str, &[f32])]) -> SweepResult {
+    let order = topological_order(&[node]);
+    let positions: HashMap<_, _> = order
+        .iter()
+        .enumerate()
+        .map(|(i, &node)| (node_key(node), i))
+        .collect();
+    let args: Vec<Vec<usize>> = order
+        .iter()
+        .map(|node| {
+            node.children()
+                .iter()
+                .map(|&child| positions[&node_key(child)])
+                .collect()
+        })
+        .collect();
+    let base_value = |name: &str| base.lookup(name).unwrap_or_else(|e| panic!("{}", e));
+
+    let mut variables: Vec<&str> = order.iter().filter_map(|node| node.var_name()).collect();
+    variables.sort();
+    variables.dedup();
+
+    // which shock, if any, each node reads, and whether it depends on one
+    let shock_of: Vec<Option<usize>> = order
+        .iter()
+        .map(|node| {
+            node.var_name()
+                .and_then(|name| shocks.iter().position(|&(shocked, _)| shocked == name))
+        })
+        .collect();
+    let mut dirty = vec![false; order.len()];
+    for i in 0..order.len() {
+        dirty[i] = shock_of[i].is_some() || args[i].iter().any(|&arg| dirty[arg]);
+    }
+
+    let mut base_values = vec![0f32; order.len()];
+    let mut inputs = vec![];
+    for (i, node) in order.iter().enumerate() {
+        base_values[i] = match node.type_ {
+            NodeType::Var(ref name) => base_value(name),
+            _ => {
+                inputs.clear();
+                inputs.extend(args[i].iter().map(|&arg| base_values[arg]));
+                node.eval(&inputs)
+            }
+        };
+    }
+
+    let total = shocks.iter().map(|&(_, levels)| levels.len()).product();
+    let mut result = SweepResult {
+        shocked: shocks.iter().map(|&(name, _)| name.to_string()).collect(),
+        variables: variables.iter().map(|name| name.to_string()).collect(),
+        shocks: Vec::with_capacity(total),
+        values: Vec::with_capacity(total),
+        gradients: Vec::with_capacity(total),
+    };
+
+    let root = order.len() - 1;
+    let mut values = base_values.clone();
+    let mut adjoints = vec![0f32; order.len()];
+    let mut reached = vec![false; order.len()];
+    // mixed-radix counter over the grid
+    let mut index = vec![0usize; shocks.len()];
+    for _ in 0..total {
+        let point: Vec<f32> = index
+            .iter()
+            .zip(shocks)
+            .map(|(&i, &(_, levels))| levels[i])
+            .collect();
+
+        for (i, node) in order.iter().enumerate() {
+            if !dirty[i] {
+                continue;
+            }
+            values[i] = match shock_of[i] {
+                Some(shock) => base_values[i] + point[shock],
+                None => {
+                    inputs.clear();
+                    inputs.extend(args[i].iter().map(|&arg| values[arg]));
+                    node.eval(&inputs)
+                }
+            };
+        }
+
+        for i in 0..order.len() {
+            adjoints[i] = 0f32;
+            reached[i] = false;
+        }
+        adjoints[root] = 1f32;
+        reached[root] = true;
+        for (i, node) in order.iter().enumerate().rev() {
+            if !reached[i] {
+                continue;
+            }
+            inputs.clear();
+            inputs.extend(args[i].iter().map(|&arg| values[arg]));
+            for (&arg, partial) in args[i].iter().zip(node.partials(&inputs)) {
+                adjoints[arg] += adjoints[i] * partial;
+                reached[arg] = true;
+            }
+        }
+
+        let mut grads = vec![0f32; variables.len()];
+        for (i, node) in order.iter().enumerate() {
+            if let Some(name) = node.var_name() {
+                let j = variables.binary_search(&name).unwrap();
+                grads[j] += adjoints[i];
+            }
+        }
+
+        result.shocks.push(point);
+        result.values.push(values[root]);
+        result.gradients.push(Gradients::new(&variables, grads));
+
+        for k in (0..index.len()).rev() {
+            index[k] += 1;
+            if index[k] < shocks[k].1.len() {
+                break;
+            }
+            index[k] = 0;
+        }
+    }
+
+    result
+}
+
+#[test]
+fn sweep_matches_independent_evaluations() {
+    use {add, eval_with_grad, mul, sin, var, Arena};
+
+    let arena = Arena::new();
+    let spot = var(&arena, "spot".to_string());
+    let vol = var(&arena, "vol".to_string());
+    let rate = var(&arena, "rate".to_string());
+    let f = add(
+        &arena,
+        mul(&arena, spot, sin(&arena, vol)),
+        mul(&arena, rate, rate),
+    );
+
+    let mut base = Assignment::new();
+    base.insert("spot".to_string(), 100.0);
+    base.insert("vol".to_string(), 0.2);
+    base.insert("rate".to_string(), 0.05);
+
+    let spot_shocks = [-10.0, 0.0, 10.0];
+    let vol_shocks = [-0.05, 0.05];
+    let result = sweep(f, &base, &[("spot", &spot_shocks), ("vol", &vol_shocks)]);
+    assert_eq!(result.len(), 6);
+    assert_eq!(result.variables, ["rate", "spot", "vol"]);
+    assert_eq!(result.shocks[1], [-10.0, 0.05]);
+
+    for i in 0..result.len() {
+        let mut at = base.clone();
+        *at.get_mut("spot").unwrap() += result.shocks[i][0];
+        *at.get_mut("vol").unwrap() += result.shocks[i][1];
+        let (value, grads) = eval_with_grad(f, &at, &["rate", "spot", "vol"]);
+        assert_eq!(result.values[i], value);
+        assert_eq!(result.gradients[i], grads);
+    }
+}
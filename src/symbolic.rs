@@ -0,0 +1,205 @@
+// Gradients as graphs.
+//
+// `grad_graph` runs reverse mode over the structure of a graph instead of
+// its values, building a node for every adjoint. The resulting gradient
+// nodes are ordinary nodes, so they can be evaluated at any point and
+// differentiated again, e.g. by the Newton root finder.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use {
+    add, constant, cos, custom, div, finite_difference_backward, mul, mul_n, neg, node_key, pow,
+    select, sin, topological_order, Arena, CustomOp, Node, NodeData,
+};
+
+// One partial derivative of a custom op, whose own derivatives are taken by
+// finite differences since custom ops only provide first derivatives.
+struct CustomPartial {
+    op: Arc<dyn CustomOp>,
+    index: usize,
+    name: String,
+}
+
+impl CustomOp for CustomPartial {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        self.op.backward(inputs, 1f32)[self.index]
+    }
+
+    fn backward(&self, inputs: &[f32], upstream: f32) -> Vec<f32> {
+        finite_difference_backward(self, inputs, upstream, 5e-3)
+    }
+}
+
+// Nodes computing the derivatives of `node` with respect to each of the
+// variable nodes `vars`, matched by name.
+pub fn grad_graph<'a>(arena: &'a Arena<'a>, node: Node<'a>, vars: &[Node<'a>]) -> Vec<Node<'a>> {
+    use NodeType::*;
+
+    let order = topological_order(&[node]);
+    let mut adjoints: HashMap<*const NodeData<'a>, Node<'a>> = HashMap::new();
+    adjoints.insert(node_key(node), constant(arena, 1f32));
+
+    let mut by_name: HashMap<&'a str, Node<'a>> = HashMap::new();
+    let one = constant(arena, 1f32);
+    let zero = constant(arena, 0f32);
+    for &current in order.iter().rev() {
+        let adjoint = match adjoints.get(&node_key(current)) {
+            Some(&adjoint) => adjoint,
+            None => continue,
+        };
+
+        let mut contributions = vec![];
+        let mut accumulate = |target: Node<'a>, adjoint: Node<'a>| {
+            contributions.push((target, adjoint));
+        };
+        match current.type_ {
+            Const(_) | StopGradient(_) => {}
+            Var(ref name) => {
+                let total = match by_name.get(name.as_str()) {
+                    Some(&previous) => add(arena, previous, adjoint),
+                    None => adjoint,
+                };
+                by_name.insert(name, total);
+            }
+            Neg(x) => accumulate(x, neg(arena, adjoint)),
+            Add(l, r) => {
+                accumulate(l, adjoint);
+                accumulate(r, adjoint);
+            }
+            Sub(l, r) => {
+                accumulate(l, adjoint);
+                accumulate(r, neg(arena, adjoint));
+            }
+            Mul(l, r) => {
+                accumulate(l, mul(arena, adjoint, r));
+                accumulate(r, mul(arena, adjoint, l));
+            }
+            Div(l, r) => {
+                accumulate(l, div(arena, adjoint, r));
+                let quotient = div(arena, mul(arena, adjoint, current), r);
+                accumulate(r, neg(arena, quotient));
+            }
+            Pow(x, p) => {
+                let local = if p == 2f32 {
+                    mul(arena, constant(arena, p), x)
+                } else {
+                    mul(arena, constant(arena, p), pow(arena, x, p - 1f32))
+                };
+                accumulate(x, mul(arena, adjoint, local));
+            }
+            Sin(x) => accumulate(x, mul(arena, adjoint, cos(arena, x))),
+            Cos(x) => accumulate(x, neg(arena, mul(arena, adjoint, sin(arena, x)))),
+            // exp(x) is the node plus one
+            ExpM1(x) => accumulate(x, mul(arena, adjoint, add(arena, current, one))),
+            Ln1p(x) => accumulate(x, div(arena, adjoint, add(arena, one, x))),
+            Custom(ref op, ref args) => {
+                for (index, &arg) in args.iter().enumerate() {
+                    let partial = CustomPartial {
+                        op: op.clone(),
+                        index,
+                        name: format!("d{}/d{}", op.name(), index),
+                    };
+                    accumulate(arg, mul(arena, adjoint, custom(arena, partial, args)));
+                }
+            }
+            Select(cond, then, else_) => {
+                accumulate(then, select(arena, cond, adjoint, zero));
+                accumulate(else_, select(arena, cond, zero, adjoint));
+            }
+            AddN(ref terms) => {
+                for &term in terms {
+                    accumulate(term, adjoint);
+                }
+            }
+            MulN(ref factors) => {
+                for i in 0..factors.len() {
+                    let others: Vec<Node<'a>> = factors
+                        .iter()
+                        .enumerate()
+                        .filter(|&(j, _)| j != i)
+                        .map(|(_, &factor)| factor)
+                        .collect();
+                    accumulate(factors[i], mul(arena, adjoint, mul_n(arena, &others)));
+                }
+            }
+        }
+
+        for (target, adjoint) in contributions {
+            let sum = match adjoints.get(&node_key(target)) {
+                Some(&previous) => add(arena, previous, adjoint),
+                None => adjoint,
+            };
+            adjoints.insert(node_key(target), sum);
+        }
+    }
+
+    vars.iter()
+        .map(|var| {
+            let name = var
+                .var_name()
+                .expect("gradients are taken for variable nodes");
+            by_name.get(name).cloned().unwrap_or(zero)
+        })
+        .collect()
+}
+
+#[test]
+fn gradient_graphs_match_reverse_mode() {
+    use ops::bce_with_logits;
+    use {
+        add_n, eval_with_grad, exp_m1, forward_multi, ln_1p, stop_gradient, sub, var, Assignment,
+    };
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let one = constant(arena, 1f32);
+    let f = add_n(
+        arena,
+        &[
+            div(
+                arena,
+                sin(arena, mul(arena, x, y)),
+                add(arena, one, pow(arena, y, 2f32)),
+            ),
+            mul(arena, cos(arena, x), exp_m1(arena, y)),
+            neg(arena, ln_1p(arena, mul(arena, x, x))),
+            mul_n(arena, &[x, y, x]),
+            select(
+                arena,
+                sub(arena, x, y),
+                pow(arena, x, 3f32),
+                mul(arena, y, y),
+            ),
+            mul(arena, stop_gradient(arena, x), y),
+            bce_with_logits(arena, x, y),
+        ],
+    );
+    let grads = grad_graph(arena, f, &[x, y]);
+
+    for &(xv, yv) in &[(0.7f32, 0.4f32), (-0.3, 1.2)] {
+        let at = Assignment::from([("x", xv), ("y", yv)]);
+        let (_, expected) = eval_with_grad(f, &at, &["x", "y"]);
+        let symbolic = forward_multi(&grads, &at);
+        assert!((symbolic[0] - expected["x"]).abs() < 1e-5);
+        assert!((symbolic[1] - expected["y"]).abs() < 1e-5);
+
+        // second derivatives through the gradient graph
+        let hessian = forward_multi(&grad_graph(arena, grads[0], &[x]), &at)[0];
+        let h = 1e-2;
+        let shifted = |dx: f32| forward_multi(&grads, &[("x", xv + dx), ("y", yv)].into())[0];
+        let expected_hessian = (shifted(h) - shifted(-h)) / (2f32 * h);
+        assert!(
+            (hessian - expected_hessian).abs() < 1e-2,
+            "{} vs {}",
+            hessian,
+            expected_hessian
+        );
+    }
+}
@@ -0,0 +1,136 @@
+// Expressions as SymPy source, e.g. `sin(x)*y + x**2`, for checking
+// derivatives against SymPy. Shared subexpressions are written out at every
+// use.
+
+use std::collections::HashMap;
+
+use {node_key, topological_order, Node, NodeData};
+
+// binding strength, loosest first
+const SUM: u8 = 1;
+const PRODUCT: u8 = 2;
+const UNARY: u8 = 3;
+const POWER: u8 = 4;
+const ATOM: u8 = 5;
+
+fn number(v: f32) -> (String, u8) {
+    if v.is_nan() {
+        ("nan".to_string(), ATOM)
+    } else if v.is_infinite() {
+        if v > 0f32 {
+            ("oo".to_string(), ATOM)
+        } else {
+            ("-oo".to_string(), UNARY)
+        }
+    } else if v.fract() == 0f32 && v.abs() < 1e15 {
+        // integral values as integers, so that powers stay exact
+        let s = format!("{}", v as i64);
+        let prec = if v < 0f32 { UNARY } else { ATOM };
+        (s, prec)
+    } else {
+        // shortest representation that reads back as the same f32
+        let prec = if v < 0f32 { UNARY } else { ATOM };
+        (format!("{:?}", v), prec)
+    }
+}
+
+fn wrap(expr: &(String, u8), at_least: u8) -> String {
+    if expr.1 >= at_least {
+        expr.0.clone()
+    } else {
+        format!("({})", expr.0)
+    }
+}
+
+pub fn to_sympy<'a>(node: Node<'a>) -> String {
+    use NodeType::*;
+
+    let mut exprs: HashMap<*const NodeData<'a>, (String, u8)> = HashMap::new();
+    for n in topological_order(&[node]) {
+        let expr = {
+            let of = |child: Node<'a>| &exprs[&node_key(child)];
+            let call = |f: &str, arg: Node<'a>| (format!("{}({})", f, of(arg).0), ATOM);
+            let join = |args: &[Node<'a>], sep: &str, prec: u8| {
+                args.iter()
+                    .map(|&arg| wrap(of(arg), prec))
+                    .collect::<Vec<_>>()
+                    .join(sep)
+            };
+            match n.type_ {
+                Const(v) => number(v),
+                Var(ref name) => (name.clone(), ATOM),
+                Neg(x) => (format!("-{}", wrap(of(x), UNARY)), UNARY),
+                Add(l, r) => (format!("{} + {}", wrap(of(l), SUM), wrap(of(r), SUM)), SUM),
+                Sub(l, r) => (
+                    format!("{} - {}", wrap(of(l), SUM), wrap(of(r), PRODUCT)),
+                    SUM,
+                ),
+                Mul(l, r) => (
+                    format!("{}*{}", wrap(of(l), PRODUCT), wrap(of(r), UNARY)),
+                    PRODUCT,
+                ),
+                Div(l, r) => (
+                    format!("{}/{}", wrap(of(l), PRODUCT), wrap(of(r), UNARY)),
+                    PRODUCT,
+                ),
+                // ** binds tighter than a unary minus on its left
+                Pow(x, p) => (
+                    format!("{}**{}", wrap(of(x), ATOM), wrap(&number(p), UNARY)),
+                    POWER,
+                ),
+                Sin(x) => call("sin", x),
+                Cos(x) => call("cos", x),
+                ExpM1(x) => (format!("exp({}) - 1", of(x).0), SUM),
+                Ln1p(x) => (format!("log(1 + {})", of(x).0), ATOM),
+                // only the value survives the export
+                StopGradient(x) => of(x).clone(),
+                // an undefined function of SymPy
+                Custom(ref op, ref args) => {
+                    (format!("{}({})", op.name(), join(args, ", ", SUM)), ATOM)
+                }
+                Select(c, t, e) => (
+                    format!(
+                        "Piecewise(({}, {} > 0), ({}, True))",
+                        of(t).0,
+                        of(c).0,
+                        of(e).0
+                    ),
+                    ATOM,
+                ),
+                AddN(ref args) if args.is_empty() => ("0".to_string(), ATOM),
+                MulN(ref args) if args.is_empty() => ("1".to_string(), ATOM),
+                AddN(ref args) => (join(args, " + ", SUM), SUM),
+                MulN(ref args) => (join(args, "*", UNARY), PRODUCT),
+            }
+        };
+        exprs.insert(node_key(n), expr);
+    }
+
+    exprs.remove(&node_key(node)).unwrap().0
+}
+
+#[test]
+fn sympy_source() {
+    use {add, constant, div, exp_m1, mul, pow, select, sin, sub, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+
+    let f = add(arena, mul(arena, sin(arena, x), y), pow(arena, x, 2.0));
+    assert_eq!(to_sympy(f), "sin(x)*y + x**2");
+
+    // parentheses only where the tree needs them
+    let g = div(arena, sub(arena, x, add(arena, y, x)), mul(arena, x, y));
+    assert_eq!(to_sympy(g), "(x - (y + x))/(x*y)");
+    let h = pow(arena, sub(arena, constant(arena, -0.5), x), -1.5);
+    assert_eq!(to_sympy(h), "(-0.5 - x)**-1.5");
+    assert_eq!(
+        to_sympy(exp_m1(arena, mul(arena, x, exp_m1(arena, y)))),
+        "exp(x*(exp(y) - 1)) - 1"
+    );
+
+    let s = select(arena, x, y, constant(arena, 0.1));
+    assert_eq!(to_sympy(s), "Piecewise((y, x > 0), (0.1, True))");
+}
@@ -0,0 +1,250 @@
+// Eager reverse mode.
+//
+// Operations on `TapeValue`s are computed immediately and recorded on a
+// `Tape` together with their local partial derivatives, so ordinary Rust
+// control flow decides what gets recorded. `Tape::backward` then sweeps the
+// recording in reverse.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::sync::Arc;
+
+use graph::Op;
+use {CustomOp, Gradients};
+
+struct Record {
+    args: Vec<usize>,
+    partials: Vec<f32>,
+}
+
+#[derive(Default)]
+pub struct Tape {
+    records: RefCell<Vec<Record>>,
+    // variable names and their records, in order of creation
+    vars: RefCell<Vec<(String, usize)>>,
+}
+
+#[derive(Clone, Copy)]
+pub struct TapeValue<'t> {
+    tape: &'t Tape,
+    index: usize,
+    value: f32,
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Tape::default()
+    }
+
+    // number of recorded operations, variables and constants included
+    pub fn len(&self) -> usize {
+        self.records.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.borrow().is_empty()
+    }
+
+    pub fn var(&self, name: &str, value: f32) -> TapeValue<'_> {
+        let value = self.push(vec![], vec![], value);
+        self.vars.borrow_mut().push((name.to_string(), value.index));
+        value
+    }
+
+    pub fn constant(&self, value: f32) -> TapeValue<'_> {
+        self.push(vec![], vec![], value)
+    }
+
+    // Applies `op` to `args` now, recording its partials.
+    pub fn apply<'t>(&'t self, op: &Op, args: &[TapeValue<'t>]) -> TapeValue<'t> {
+        let inputs: Vec<f32> = args.iter().map(|arg| arg.value).collect();
+        let value = op.eval(&inputs);
+        let partials = op.partials(&inputs);
+        self.push(args.iter().map(|arg| arg.index).collect(), partials, value)
+    }
+
+    pub fn custom<'t, O: CustomOp + 'static>(
+        &'t self,
+        op: O,
+        args: &[TapeValue<'t>],
+    ) -> TapeValue<'t> {
+        self.apply(&Op::Custom(Arc::new(op)), args)
+    }
+
+    pub fn sum<'t>(&'t self, terms: &[TapeValue<'t>]) -> TapeValue<'t> {
+        self.apply(&Op::AddN, terms)
+    }
+
+    pub fn product<'t>(&'t self, factors: &[TapeValue<'t>]) -> TapeValue<'t> {
+        self.apply(&Op::MulN, factors)
+    }
+
+    // Gradients of `result` with respect to every variable on the tape.
+    // The tape can keep recording afterwards.
+    pub fn backward(&self, result: TapeValue) -> Gradients {
+        assert!(
+            ::std::ptr::eq(self, result.tape),
+            "value was recorded on another tape"
+        );
+
+        let records = self.records.borrow();
+        let mut adjoints = vec![0f32; result.index + 1];
+        adjoints[result.index] = 1f32;
+        for index in (0..=result.index).rev() {
+            let adjoint = adjoints[index];
+            if adjoint == 0f32 {
+                continue;
+            }
+            let record = &records[index];
+            for (&arg, partial) in record.args.iter().zip(&record.partials) {
+                adjoints[arg] += adjoint * partial;
+            }
+        }
+
+        let vars = self.vars.borrow();
+        let names: Vec<&str> = vars.iter().map(|(name, _)| name.as_str()).collect();
+        let values = vars
+            .iter()
+            .map(|&(_, index)| adjoints.get(index).cloned().unwrap_or(0f32))
+            .collect();
+        Gradients::new(&names, values)
+    }
+
+    fn push(&self, args: Vec<usize>, partials: Vec<f32>, value: f32) -> TapeValue<'_> {
+        let mut records = self.records.borrow_mut();
+        records.push(Record { args, partials });
+        TapeValue {
+            tape: self,
+            index: records.len() - 1,
+            value,
+        }
+    }
+}
+
+impl fmt::Debug for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tape({} records)", self.len())
+    }
+}
+
+impl<'t> TapeValue<'t> {
+    pub fn value(self) -> f32 {
+        self.value
+    }
+
+    fn unary(self, op: Op) -> Self {
+        self.tape.apply(&op, &[self])
+    }
+
+    pub fn pow(self, rhs: f32) -> Self {
+        self.unary(Op::Pow(rhs))
+    }
+
+    pub fn sin(self) -> Self {
+        self.unary(Op::Sin)
+    }
+
+    pub fn cos(self) -> Self {
+        self.unary(Op::Cos)
+    }
+
+    pub fn exp_m1(self) -> Self {
+        self.unary(Op::ExpM1)
+    }
+
+    pub fn ln_1p(self) -> Self {
+        self.unary(Op::Ln1p)
+    }
+
+    // recorded directly, as the graph ops only have ln_1p
+    pub fn ln(self) -> Self {
+        self.tape
+            .push(vec![self.index], vec![1f32 / self.value], self.value.ln())
+    }
+
+    pub fn stop_gradient(self) -> Self {
+        self.unary(Op::StopGradient)
+    }
+}
+
+impl<'t> fmt::Debug for TapeValue<'t> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TapeValue(#{} = {})", self.index, self.value)
+    }
+}
+
+macro_rules! binary_op {
+    ($trait_:ident, $method:ident, $op:ident) => {
+        impl<'t> $trait_ for TapeValue<'t> {
+            type Output = TapeValue<'t>;
+
+            fn $method(self, rhs: TapeValue<'t>) -> TapeValue<'t> {
+                self.tape.apply(&Op::$op, &[self, rhs])
+            }
+        }
+
+        impl<'t> $trait_<f32> for TapeValue<'t> {
+            type Output = TapeValue<'t>;
+
+            fn $method(self, rhs: f32) -> TapeValue<'t> {
+                let rhs = self.tape.constant(rhs);
+                self.tape.apply(&Op::$op, &[self, rhs])
+            }
+        }
+
+        impl<'t> $trait_<TapeValue<'t>> for f32 {
+            type Output = TapeValue<'t>;
+
+            fn $method(self, rhs: TapeValue<'t>) -> TapeValue<'t> {
+                let lhs = rhs.tape.constant(self);
+                rhs.tape.apply(&Op::$op, &[lhs, rhs])
+            }
+        }
+    };
+}
+
+binary_op!(Add, add, Add);
+binary_op!(Sub, sub, Sub);
+binary_op!(Mul, mul, Mul);
+binary_op!(Div, div, Div);
+
+impl<'t> Neg for TapeValue<'t> {
+    type Output = TapeValue<'t>;
+
+    fn neg(self) -> TapeValue<'t> {
+        self.unary(Op::Neg)
+    }
+}
+
+#[test]
+fn records_rust_control_flow() {
+    let tape = Tape::new();
+    let x = tape.var("x", 1.5);
+    let y = tape.var("y", -2.0);
+
+    // a loop and a branch the static graph would need unrolled
+    let mut acc = tape.constant(0.0);
+    for i in 1..4 {
+        acc = if acc.value() < 2.0 {
+            acc + x * i as f32
+        } else {
+            acc * y
+        };
+    }
+    // 0 + 1.5 = 1.5, 1.5 + 3 = 4.5, 4.5 * -2 = -9
+    assert_eq!(acc.value(), -9.0);
+
+    let result = acc.sin() + 1.0;
+    let grads = tape.backward(result);
+    let dacc = acc.value().cos();
+    assert!((grads["x"] - dacc * 3.0 * y.value()).abs() < 1e-5);
+    assert!((grads["y"] - dacc * 4.5).abs() < 1e-5);
+
+    // recording can continue, and unused variables get zero
+    let z = tape.var("z", 3.0);
+    let grads = tape.backward(-(x / 2.0).pow(2.0));
+    assert_eq!(grads["x"], -0.75);
+    assert_eq!(grads["z"], 0.0);
+    assert_eq!(z.stop_gradient().value(), 3.0);
+}
@@ -0,0 +1,271 @@
+// Truncated Taylor polynomials by propagating normalized derivatives
+// (f^(k)(c) / k!) through the graph.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use {constant, node_key, polynomial, sub, topological_order, Arena, Node, NodeData};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaylorError {
+    // custom ops only provide first derivatives
+    UnsupportedOp(String),
+    // a fractional or negative power of zero has no Taylor expansion
+    PowAtZero,
+    // neither has a logarithm at or below zero
+    LogAtZero,
+}
+
+impl fmt::Display for TaylorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TaylorError::UnsupportedOp(ref name) => {
+                write!(f, "cannot expand custom op {} beyond first order", name)
+            }
+            TaylorError::PowAtZero => write!(f, "power of zero in Taylor expansion"),
+            TaylorError::LogAtZero => write!(f, "logarithm of zero in Taylor expansion"),
+        }
+    }
+}
+
+impl Error for TaylorError {}
+
+// Taylor polynomial of `node` in the variable node `x` around `center` up to
+// `order`, as a new graph over `x`. Other variables are fixed at their
+// current values.
+pub fn taylor<'a>(
+    arena: &'a Arena<'a>,
+    node: Node<'a>,
+    x: Node<'a>,
+    center: f32,
+    order: usize,
+) -> Result<Node<'a>, TaylorError> {
+    let var_name = x
+        .var_name()
+        .expect("expansion variable must be a variable node");
+    let coeffs = taylor_coefficients(node, var_name, center, order)?;
+
+    let shifted = sub(arena, x, constant(arena, center));
+    let poly = polynomial(arena, shifted, &coeffs);
+
+    Ok(poly)
+}
+
+// f^(k)(center) / k! for k = 0..=order
+pub fn taylor_coefficients<'a>(
+    node: Node<'a>,
+    var_name: &str,
+    center: f32,
+    order: usize,
+) -> Result<Vec<f32>, TaylorError> {
+    use NodeType::*;
+
+    let n = order + 1;
+    let constant_series = |v: f32| {
+        let mut series = vec![0f32; n];
+        series[0] = v;
+        series
+    };
+
+    let mut series: HashMap<*const NodeData<'a>, Vec<f32>> = HashMap::new();
+    for node in topological_order(&[node]) {
+        let s = {
+            let of = |child: Node<'a>| &series[&node_key(child)];
+            match node.type_ {
+                Const(v) => constant_series(v),
+                Var(ref name) if name == var_name => {
+                    let mut s = constant_series(center);
+                    if n > 1 {
+                        s[1] = 1f32;
+                    }
+                    s
+                }
+                Var(_) => constant_series(node.value.get()),
+                Neg(value) => of(value).iter().map(|v| -v).collect(),
+                Add(lhs, rhs) => of(lhs).iter().zip(of(rhs)).map(|(l, r)| l + r).collect(),
+                Sub(lhs, rhs) => of(lhs).iter().zip(of(rhs)).map(|(l, r)| l - r).collect(),
+                Mul(lhs, rhs) => cauchy_product(of(lhs), of(rhs)),
+                Div(lhs, rhs) => {
+                    let (u, v) = (of(lhs), of(rhs));
+                    let mut w = vec![0f32; n];
+                    for k in 0..n {
+                        let sum: f32 = (1..k + 1).map(|j| v[j] * w[k - j]).sum();
+                        w[k] = (u[k] - sum) / v[0];
+                    }
+                    w
+                }
+                Pow(lhs, p) => {
+                    let u = of(lhs);
+                    if u[0] == 0f32 {
+                        if p < 0f32 || p.fract() != 0f32 {
+                            return Err(TaylorError::PowAtZero);
+                        }
+                        // The recurrence divides by u[0], so multiply out. u^p
+                        // has no terms below degree p, so n factors suffice.
+                        (0..(p as usize).min(n))
+                            .fold(constant_series(1f32), |w, _| cauchy_product(&w, u))
+                    } else {
+                        let mut w = vec![0f32; n];
+                        w[0] = u[0].powf(p);
+                        for k in 1..n {
+                            let sum: f32 = (1..k + 1)
+                                .map(|j| (p * j as f32 - (k - j) as f32) * u[j] * w[k - j])
+                                .sum();
+                            w[k] = sum / (k as f32 * u[0]);
+                        }
+                        w
+                    }
+                }
+                Sin(value) => sin_cos(of(value)).0,
+                Cos(value) => sin_cos(of(value)).1,
+                ExpM1(value) => {
+                    let u = of(value);
+                    let mut w = exp_series(u);
+                    w[0] = u[0].exp_m1();
+                    w
+                }
+                Ln1p(value) => {
+                    let u = of(value);
+                    if u[0] <= -1f32 {
+                        return Err(TaylorError::LogAtZero);
+                    }
+                    // w' = u' / (1 + u)
+                    let mut w = vec![0f32; n];
+                    w[0] = u[0].ln_1p();
+                    for k in 1..n {
+                        let sum: f32 = (1..k).map(|j| j as f32 * w[j] * u[k - j]).sum();
+                        w[k] = (u[k] - sum / k as f32) / (1f32 + u[0]);
+                    }
+                    w
+                }
+                StopGradient(value) => constant_series(of(value)[0]),
+                Custom(_, _) => return Err(TaylorError::UnsupportedOp(node.describe())),
+                Select(cond, then, else_) => {
+                    if of(cond)[0] > 0f32 {
+                        of(then).clone()
+                    } else {
+                        of(else_).clone()
+                    }
+                }
+                AddN(ref args) => args.iter().fold(constant_series(0f32), |acc, &arg| {
+                    acc.iter().zip(of(arg)).map(|(a, b)| a + b).collect()
+                }),
+                MulN(ref args) => args.iter().fold(constant_series(1f32), |acc, &arg| {
+                    cauchy_product(&acc, of(arg))
+                }),
+            }
+        };
+        series.insert(node_key(node), s);
+    }
+
+    Ok(series.remove(&node_key(node)).unwrap())
+}
+
+fn cauchy_product(u: &[f32], v: &[f32]) -> Vec<f32> {
+    (0..u.len())
+        .map(|k| (0..k + 1).map(|j| u[j] * v[k - j]).sum())
+        .collect()
+}
+
+// exp of a series via w' = u' w
+fn exp_series(u: &[f32]) -> Vec<f32> {
+    let n = u.len();
+    let mut w = vec![0f32; n];
+    w[0] = u[0].exp();
+    for k in 1..n {
+        let sum: f32 = (1..k + 1).map(|j| j as f32 * u[j] * w[k - j]).sum();
+        w[k] = sum / k as f32;
+    }
+    w
+}
+
+fn sin_cos(u: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    let n = u.len();
+    let mut s = vec![0f32; n];
+    let mut c = vec![0f32; n];
+    s[0] = u[0].sin();
+    c[0] = u[0].cos();
+    for k in 1..n {
+        let ds: f32 = (1..k + 1).map(|j| j as f32 * u[j] * c[k - j]).sum();
+        let dc: f32 = (1..k + 1).map(|j| j as f32 * u[j] * s[k - j]).sum();
+        s[k] = ds / k as f32;
+        c[k] = -dc / k as f32;
+    }
+    (s, c)
+}
+
+#[test]
+fn taylor_polynomials() {
+    use {add, div, exp_m1, ln_1p, mul, pow, sin, var};
+
+    let arena = Arena::new();
+    let arena = &arena;
+
+    let x = var(arena, "x".to_string());
+    let a = var(arena, "a".to_string());
+    a.set_value(2f32);
+
+    // sin(a x) / (1 + x)^0.5 around 0.3
+    let one = constant(arena, 1f32);
+    let f = div(
+        arena,
+        sin(arena, mul(arena, a, x)),
+        pow(arena, add(arena, one, x), 0.5),
+    );
+    let approx = taylor(arena, f, x, 0.3, 6).unwrap();
+
+    for &point in &[0.25f32, 0.3, 0.4] {
+        x.set_value(point);
+        f.forward();
+        approx.forward();
+        assert!((f.value() - approx.value()).abs() < 1e-4);
+    }
+
+    let coeffs = taylor_coefficients(sin(arena, x), "x", 0f32, 5).unwrap();
+    let expected = [0f32, 1f32, 0f32, -1f32 / 6f32, 0f32, 1f32 / 120f32];
+    for (c, e) in coeffs.iter().zip(&expected) {
+        assert!((c - e).abs() < 1e-7);
+    }
+
+    // ln(1 + (exp(x) - 1)) = x
+    let identity = ln_1p(arena, exp_m1(arena, x));
+    let coeffs = taylor_coefficients(identity, "x", 0.5, 4).unwrap();
+    for (c, e) in coeffs.iter().zip(&[0.5f32, 1f32, 0f32, 0f32, 0f32]) {
+        assert!((c - e).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn integer_powers_expand_around_zero() {
+    use {add, constant, pow, var, Arena};
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+
+    let square = pow(arena, x, 2f32);
+    assert_eq!(
+        taylor_coefficients(square, "x", 0f32, 3),
+        Ok(vec![0f32, 0f32, 1f32, 0f32])
+    );
+
+    let cube = pow(arena, add(arena, x, constant(arena, 0f32)), 3f32);
+    assert_eq!(
+        taylor_coefficients(cube, "x", 0f32, 4),
+        Ok(vec![0f32, 0f32, 0f32, 1f32, 0f32])
+    );
+    assert_eq!(
+        taylor_coefficients(pow(arena, x, 0f32), "x", 0f32, 2),
+        Ok(vec![1f32, 0f32, 0f32])
+    );
+
+    assert_eq!(
+        taylor_coefficients(pow(arena, x, 0.5), "x", 0f32, 2),
+        Err(TaylorError::PowAtZero)
+    );
+    assert_eq!(
+        taylor_coefficients(pow(arena, x, -1f32), "x", 0f32, 2),
+        Err(TaylorError::PowAtZero)
+    );
+}
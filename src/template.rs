@@ -0,0 +1,122 @@
+// Reusable sub-expressions.
+//
+// A `FnDef` is built once over formal input variables and then expanded any
+// number of times, each time with the formals replaced by argument nodes.
+
+use std::collections::HashMap;
+
+use graph::{Graph, NodeId};
+use {Arena, Node};
+
+#[derive(Debug, Clone)]
+pub struct FnDef {
+    name: String,
+    inputs: Vec<String>,
+    body: Graph,
+    output: NodeId,
+}
+
+impl FnDef {
+    // `build` receives the body graph and one variable node per input.
+    // Variables of the body that are not inputs stay free and are shared by
+    // every call.
+    pub fn new<F>(name: &str, inputs: &[&str], build: F) -> Self
+    where
+        F: FnOnce(&mut Graph, &[NodeId]) -> NodeId,
+    {
+        let mut body = Graph::new();
+        let formals: Vec<NodeId> = inputs.iter().map(|input| body.var(*input).id()).collect();
+        let output = build(&mut body, &formals);
+
+        FnDef {
+            name: name.to_string(),
+            inputs: inputs.iter().map(|input| input.to_string()).collect(),
+            body,
+            output,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    // Expands the body into `arena` with the inputs bound to `args`.
+    pub fn call<'a>(&self, arena: &'a Arena<'a>, args: &[Node<'a>]) -> Node<'a> {
+        self.check_arity(args.len());
+        let bindings: HashMap<&str, Node<'a>> = self
+            .inputs
+            .iter()
+            .map(|input| input.as_str())
+            .zip(args.iter().cloned())
+            .collect();
+        self.body.build_in(arena, self.output, &bindings)
+    }
+
+    // Same as `call`, for owned graphs.
+    pub fn call_in(&self, graph: &mut Graph, args: &[NodeId]) -> NodeId {
+        self.check_arity(args.len());
+        let bindings: HashMap<&str, NodeId> = self
+            .inputs
+            .iter()
+            .map(|input| input.as_str())
+            .zip(args.iter().cloned())
+            .collect();
+        self.body.copy_into(graph, self.output, &bindings)
+    }
+
+    fn check_arity(&self, args: usize) {
+        assert_eq!(
+            args,
+            self.inputs.len(),
+            "{} takes {} arguments",
+            self.name,
+            self.inputs.len()
+        );
+    }
+}
+
+#[test]
+fn call_template_twice() {
+    use {add, var, Assignment};
+
+    // k(s, t) = s * t + sin(s) * rate
+    let kernel = FnDef::new("kernel", &["s", "t"], |g, args| {
+        let st = g.mul(args[0], args[1]);
+        let sin = g.sin(args[0]);
+        let rate = g.var("rate").id();
+        let scaled = g.mul(sin, rate);
+        g.add(st, scaled)
+    });
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let y = var(&arena, "y".to_string());
+    let first = kernel.call(&arena, &[x, y]);
+    let second = kernel.call(&arena, &[y, x]);
+    let total = add(&arena, first, second);
+
+    let mut assignment = Assignment::new();
+    assignment.insert("x".to_string(), 0.5);
+    assignment.insert("y".to_string(), 2.0);
+    assignment.insert("rate".to_string(), 3.0);
+    total.assign(&assignment);
+    total.forward();
+    total.backward_ad(&["x", "rate"]);
+
+    let expected = 2.0 * 0.5 * 2.0 + 3.0 * (0.5f32.sin() + 2.0f32.sin());
+    assert!((total.value() - expected).abs() < 1e-5);
+    assert!((total.grad("x") - (2.0 * 2.0 + 3.0 * 0.5f32.cos())).abs() < 1e-5);
+    assert!((total.grad("rate") - (0.5f32.sin() + 2.0f32.sin())).abs() < 1e-5);
+
+    let mut graph = Graph::new();
+    let x = graph.var("x").id();
+    let y = graph.var("y").id();
+    let first = kernel.call_in(&mut graph, &[x, y]);
+    let second = kernel.call_in(&mut graph, &[y, x]);
+    let total = graph.add(first, second);
+    assert!((graph.eval(total, &assignment) - expected).abs() < 1e-5);
+}
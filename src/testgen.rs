@@ -0,0 +1,358 @@
+// Reproducible random expressions for benchmarks, fuzzing and property
+// tests.
+//
+// Cases are keyed by a `u64` seed rather than exposed as proptest
+// strategies, since the crate does not depend on proptest: a harness maps
+// its own seed strategy through `arbitrary_case`, and a failing seed
+// reproduces without it.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use init::Rng;
+use sympy::to_sympy;
+use {
+    add, constant, cos, div, eval_with_grad, forward_multi, mul, pow, sin, sub, var, Arena,
+    Assignment, Node,
+};
+
+// Relative frequencies of the kinds of node. `leaf` covers variables and
+// constants, and `share` is the weight of reusing an already generated
+// subexpression, which turns the tree into a DAG.
+#[derive(Debug, Clone)]
+pub struct OpWeights {
+    pub leaf: f32,
+    pub share: f32,
+    pub add: f32,
+    pub sub: f32,
+    pub mul: f32,
+    pub div: f32,
+    pub pow: f32,
+    pub sin: f32,
+    pub cos: f32,
+}
+
+impl Default for OpWeights {
+    fn default() -> Self {
+        OpWeights {
+            leaf: 1f32,
+            share: 1f32,
+            add: 2f32,
+            sub: 1f32,
+            mul: 2f32,
+            div: 0.5,
+            pow: 0.5,
+            sin: 1f32,
+            cos: 1f32,
+        }
+    }
+}
+
+// names of the variables used by `generate_random`
+pub fn variable_names(n_vars: usize) -> Vec<String> {
+    (0..n_vars).map(|i| format!("x{}", i)).collect()
+}
+
+struct Generator<'a, 'w> {
+    arena: &'a Arena<'a>,
+    rng: Rng,
+    weights: &'w OpWeights,
+    vars: Vec<Node<'a>>,
+    // generated nodes by the depth they were allowed, so that `pool[d]` are
+    // the nodes that can stand in for one of depth `d` or more
+    pool: Vec<Vec<Node<'a>>>,
+}
+
+impl<'a, 'w> Generator<'a, 'w> {
+    fn leaf(&mut self) -> Node<'a> {
+        if self.vars.is_empty() || self.rng.uniform() < 0.25 {
+            let value = (self.rng.uniform() * 4f32 - 2f32 + 0.5).round() * 0.5;
+            constant(self.arena, value)
+        } else {
+            let i = self.rng.below(self.vars.len());
+            self.vars[i]
+        }
+    }
+
+    fn node(&mut self, depth: usize) -> Node<'a> {
+        if depth == 0 {
+            return self.leaf();
+        }
+
+        let w = self.weights;
+        // only nodes that keep the result within `depth` can be shared
+        let shareable: usize = self.pool[..=depth].iter().map(Vec::len).sum();
+        let share = if shareable == 0 { 0f32 } else { w.share };
+        let choices = [
+            w.leaf, share, w.add, w.sub, w.mul, w.div, w.pow, w.sin, w.cos,
+        ];
+        let total: f32 = choices.iter().sum();
+        let mut pick = self.rng.uniform() * total;
+        let mut kind = choices.len() - 1;
+        for (i, &weight) in choices.iter().enumerate() {
+            if pick < weight {
+                kind = i;
+                break;
+            }
+            pick -= weight;
+        }
+
+        let arena = self.arena;
+        let node = match kind {
+            0 => return self.leaf(),
+            1 => {
+                let mut i = self.rng.below(shareable);
+                for bucket in &self.pool[..=depth] {
+                    if i < bucket.len() {
+                        return bucket[i];
+                    }
+                    i -= bucket.len();
+                }
+                unreachable!()
+            }
+            2 => add(arena, self.node(depth - 1), self.node(depth - 1)),
+            3 => sub(arena, self.node(depth - 1), self.node(depth - 1)),
+            4 => mul(arena, self.node(depth - 1), self.node(depth - 1)),
+            5 => div(arena, self.node(depth - 1), self.node(depth - 1)),
+            6 => {
+                let exponent = 2f32 + self.rng.below(2) as f32;
+                pow(arena, self.node(depth - 1), exponent)
+            }
+            7 => sin(arena, self.node(depth - 1)),
+            _ => cos(arena, self.node(depth - 1)),
+        };
+        self.pool[depth].push(node);
+        node
+    }
+}
+
+// A random expression over the variables `variable_names(n_vars)` at most
+// `depth` operations deep. The same seed and weights always give the same
+// graph.
+pub fn generate_random<'a>(
+    arena: &'a Arena<'a>,
+    seed: u64,
+    depth: usize,
+    n_vars: usize,
+    weights: &OpWeights,
+) -> Node<'a> {
+    let vars = variable_names(n_vars)
+        .into_iter()
+        .map(|name| var(arena, name))
+        .collect();
+    let mut generator = Generator {
+        arena,
+        rng: Rng::new(seed),
+        weights,
+        vars,
+        pool: vec![vec![]; depth + 1],
+    };
+    generator.node(depth)
+}
+
+// What `arbitrary_case` generates.
+#[derive(Debug, Clone)]
+pub struct CaseConfig {
+    pub depth: usize,
+    pub n_vars: usize,
+    pub weights: OpWeights,
+    // variables are drawn uniformly from this range
+    pub range: (f32, f32),
+    // cases whose value or gradient is larger than this, or whose gradient
+    // changes by more than a tenth of it a hundredth away, are redrawn. This
+    // keeps them away from poles and wild oscillations, where finite
+    // differences are meaningless.
+    pub max_magnitude: f32,
+}
+
+impl Default for CaseConfig {
+    fn default() -> Self {
+        CaseConfig {
+            depth: 6,
+            n_vars: 3,
+            weights: OpWeights::default(),
+            range: (-2f32, 2f32),
+            max_magnitude: 100f32,
+        }
+    }
+}
+
+// An expression together with a point to evaluate it at.
+#[derive(Debug, Clone)]
+pub struct Case<'a> {
+    pub seed: u64,
+    pub node: Node<'a>,
+    pub assignment: Assignment,
+}
+
+// A random case with a finite value and gradient. The same seed always
+// gives the same case, so a failing seed from a property test harness
+// reproduces on its own.
+pub fn arbitrary_case<'a>(arena: &'a Arena<'a>, seed: u64, config: &CaseConfig) -> Case<'a> {
+    let names = variable_names(config.n_vars);
+    let refs: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    let (lo, hi) = config.range;
+    let mut rng = Rng::new(seed);
+
+    for _ in 0..1000 {
+        let node = generate_random(
+            arena,
+            rng.next_u64(),
+            config.depth,
+            config.n_vars,
+            &config.weights,
+        );
+        let assignment: Assignment = names
+            .iter()
+            .map(|name| (name.clone(), lo + (hi - lo) * rng.uniform()))
+            .collect();
+        let (value, grads) = eval_with_grad(node, &assignment, &refs);
+        let nearby: Assignment = assignment
+            .iter()
+            .map(|(name, &x)| (name.clone(), x + 1e-2 * x.abs().max(1f32)))
+            .collect();
+        let (_, nearby_grads) = eval_with_grad(node, &nearby, &refs);
+
+        let tame = |v: f32| v.is_finite() && v.abs() <= config.max_magnitude;
+        let smooth = grads
+            .values()
+            .iter()
+            .zip(nearby_grads.values())
+            .all(|(&g, &n)| (g - n).abs() <= 0.1 * g.abs().max(1f32));
+        if tame(value) && grads.values().iter().all(|&g| tame(g)) && smooth {
+            return Case {
+                seed,
+                node,
+                assignment,
+            };
+        }
+    }
+    panic!("no tame case found for seed {}", seed)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradientMismatch {
+    pub variable: String,
+    pub ad: f32,
+    pub finite_difference: f32,
+}
+
+impl fmt::Display for GradientMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "gradient with respect to {} is {}, but finite differences give {}",
+            self.variable, self.ad, self.finite_difference
+        )
+    }
+}
+
+impl Error for GradientMismatch {}
+
+// Compares reverse-mode gradients of `node` at `at` with central
+// differences, relative to the larger of 1 and the gradient.
+pub fn check_gradients(node: Node, at: &Assignment, tol: f32) -> Result<(), GradientMismatch> {
+    let names: Vec<&str> = at.keys().map(|name| name.as_str()).collect();
+    let (_, grads) = eval_with_grad(node, at, &names);
+
+    for name in names {
+        let x = at[name];
+        let h = 1e-3 * x.abs().max(1f32);
+        let mut shifted = at.clone();
+        shifted.insert(name, x + h);
+        let above = forward_multi(&[node], &shifted)[0];
+        shifted.insert(name, x - h);
+        let below = forward_multi(&[node], &shifted)[0];
+        let finite_difference = (above - below) / ((x + h) - (x - h));
+
+        let ad = grads[name];
+        if (ad - finite_difference).abs() > tol * ad.abs().max(1f32) {
+            return Err(GradientMismatch {
+                variable: name.to_string(),
+                ad,
+                finite_difference,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Checks the gradients of the case of every seed, panicking with the seed
+// and the expression of the first mismatch.
+pub fn assert_gradients_match(seeds: Range<u64>, config: &CaseConfig, tol: f32) {
+    for seed in seeds {
+        let arena = Arena::new();
+        let case = arbitrary_case(&arena, seed, config);
+        if let Err(mismatch) = check_gradients(case.node, &case.assignment, tol) {
+            panic!(
+                "seed {}: {}\n{} at {:?}",
+                seed,
+                mismatch,
+                to_sympy(case.node),
+                case.assignment
+            );
+        }
+    }
+}
+
+#[test]
+fn generation_is_reproducible() {
+    use scan::depth;
+    use {forward_multi, Assignment};
+
+    let weights = OpWeights {
+        div: 0f32,
+        ..OpWeights::default()
+    };
+    let mut at = Assignment::new();
+    for (i, name) in variable_names(3).into_iter().enumerate() {
+        at.insert(name, 0.1 * (i + 1) as f32);
+    }
+
+    let arena = Arena::new();
+    let first = generate_random(&arena, 42, 8, 3, &weights);
+    let second = generate_random(&arena, 42, 8, 3, &weights);
+    let other = generate_random(&arena, 43, 8, 3, &weights);
+
+    let values = forward_multi(&[first, second, other], &at);
+    assert_eq!(values[0], values[1]);
+    assert_ne!(values[0], values[2]);
+    for seed in 0..20 {
+        assert!(depth(generate_random(&arena, seed, 6, 3, &weights)) <= 6);
+    }
+}
+
+#[test]
+fn generated_gradients_match_finite_differences() {
+    let config = CaseConfig::default();
+    assert_gradients_match(0..200, &config, 1e-2);
+
+    let arena = Arena::new();
+    let first = arbitrary_case(&arena, 7, &config);
+    let second = arbitrary_case(&arena, 7, &config);
+    assert_eq!(first.assignment, second.assignment);
+
+    // a wrong derivative is caught
+    struct Broken;
+    impl ::CustomOp for Broken {
+        fn name(&self) -> &str {
+            "Broken"
+        }
+
+        fn forward(&self, inputs: &[f32]) -> f32 {
+            2f32 * inputs[0]
+        }
+
+        fn backward(&self, _inputs: &[f32], upstream: f32) -> Vec<f32> {
+            vec![3f32 * upstream]
+        }
+    }
+    let x = var(&arena, "x".to_string());
+    let at: Assignment = [("x", 1f32)].into();
+    let broken = ::custom(&arena, Broken, &[x]);
+    let mismatch = check_gradients(broken, &at, 1e-3).unwrap_err();
+    assert_eq!(mismatch.variable, "x");
+    assert_eq!(mismatch.ad, 3f32);
+    assert!((mismatch.finite_difference - 2f32).abs() < 1e-3);
+}
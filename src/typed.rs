@@ -0,0 +1,79 @@
+// Variables named by types instead of strings.
+//
+// `vars! { X = "x", Y = "y" }` declares the unit structs `X` and `Y` standing
+// for the variables "x" and "y"; a bare `vars! { T }` stands for "T". A typo
+// in `grads[X]` is then a compile error, and indexing gradients by a typed
+// variable that was not requested panics instead of reading 0.
+
+use std::ops::Index;
+
+use {var, Arena, Gradients, Node};
+
+pub trait Variable: Copy {
+    const NAME: &'static str;
+
+    fn name(self) -> &'static str {
+        Self::NAME
+    }
+
+    fn var<'a>(self, arena: &'a Arena<'a>) -> Node<'a> {
+        var(arena, Self::NAME.to_string())
+    }
+}
+
+#[macro_export]
+macro_rules! vars {
+    (@one $name:ident = $var:expr) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct $name;
+
+        impl $crate::typed::Variable for $name {
+            const NAME: &'static str = $var;
+        }
+    };
+    (@one $name:ident) => {
+        $crate::vars!(@one $name = stringify!($name));
+    };
+    ($($name:ident $(= $var:expr)?),* $(,)?) => {
+        $($crate::vars!(@one $name $(= $var)?);)*
+    };
+}
+
+impl<V: Variable> Index<V> for Gradients {
+    type Output = f32;
+
+    fn index(&self, _: V) -> &f32 {
+        match self.names().iter().position(|n| n == V::NAME) {
+            Some(i) => &self.values()[i],
+            None => panic!("gradient of {} was not requested", V::NAME),
+        }
+    }
+}
+
+#[test]
+fn typed_variables() {
+    use {eval_with_grad, mul, sin, Assignment};
+
+    vars! { X = "x", Y = "y", Scale }
+
+    let arena = Arena::new();
+    let x = X.var(&arena);
+    let y = Y.var(&arena);
+    let s = Scale.var(&arena);
+    let f = mul(&arena, s, mul(&arena, sin(&arena, x), y));
+
+    let at: Assignment = [(X.name(), 0.5), (Y.name(), 3.0), (Scale.name(), 2.0)].into();
+    let (_, grads) = eval_with_grad(f, &at, &[X::NAME, Y::NAME]);
+    assert_eq!(grads[X], 2.0 * 0.5f32.cos() * 3.0);
+    assert_eq!(grads[Y], 2.0 * 0.5f32.sin());
+    assert_eq!(Scale::NAME, "Scale");
+}
+
+#[test]
+#[should_panic(expected = "gradient of Scale was not requested")]
+fn unrequested_typed_variable_panics() {
+    vars! { Scale }
+
+    let grads = Gradients::new(&["x"], vec![1.0]);
+    let _ = grads[Scale];
+}
@@ -0,0 +1,271 @@
+// Dimensional analysis of expressions.
+//
+// Variables and constants can be tagged with a `Dimension`, the exponents of
+// the SI base units. `check_units` infers the dimension of every node and
+// rejects sums of different dimensions, transcendental functions of
+// dimensioned values and the like. Untagged leaves are dimensionless.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Div, Mul};
+
+use {node_key, topological_order, Node, NodeData};
+
+const SYMBOLS: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+// exponents of metre, kilogram, second, ampere, kelvin, mole and candela
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Dimension(pub [i8; 7]);
+
+impl Dimension {
+    pub const NONE: Dimension = Dimension([0; 7]);
+    pub const LENGTH: Dimension = Dimension([1, 0, 0, 0, 0, 0, 0]);
+    pub const MASS: Dimension = Dimension([0, 1, 0, 0, 0, 0, 0]);
+    pub const TIME: Dimension = Dimension([0, 0, 1, 0, 0, 0, 0]);
+    pub const CURRENT: Dimension = Dimension([0, 0, 0, 1, 0, 0, 0]);
+    pub const TEMPERATURE: Dimension = Dimension([0, 0, 0, 0, 1, 0, 0]);
+    pub const AMOUNT: Dimension = Dimension([0, 0, 0, 0, 0, 1, 0]);
+    pub const LUMINOSITY: Dimension = Dimension([0, 0, 0, 0, 0, 0, 1]);
+
+    pub fn is_dimensionless(self) -> bool {
+        self == Dimension::NONE
+    }
+
+    pub fn powi(self, n: i8) -> Dimension {
+        let mut exponents = self.0;
+        for e in &mut exponents {
+            *e *= n;
+        }
+        Dimension(exponents)
+    }
+
+    // None unless every exponent times `p` is an integer
+    pub fn powf(self, p: f32) -> Option<Dimension> {
+        let mut exponents = self.0;
+        for e in &mut exponents {
+            let scaled = *e as f32 * p;
+            if scaled.fract() != 0f32 {
+                return None;
+            }
+            *e = scaled as i8;
+        }
+        Some(Dimension(exponents))
+    }
+}
+
+impl Mul for Dimension {
+    type Output = Dimension;
+
+    // units multiply by adding exponents
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn mul(self, rhs: Dimension) -> Dimension {
+        let mut exponents = self.0;
+        for (e, r) in exponents.iter_mut().zip(&rhs.0) {
+            *e += r;
+        }
+        Dimension(exponents)
+    }
+}
+
+impl Div for Dimension {
+    type Output = Dimension;
+
+    fn div(self, rhs: Dimension) -> Dimension {
+        self * rhs.powi(-1)
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_dimensionless() {
+            return write!(f, "1");
+        }
+        let units: Vec<String> = SYMBOLS
+            .iter()
+            .zip(&self.0)
+            .filter(|&(_, &e)| e != 0)
+            .map(|(symbol, &e)| {
+                if e == 1 {
+                    symbol.to_string()
+                } else {
+                    format!("{}^{}", symbol, e)
+                }
+            })
+            .collect();
+        write!(f, "{}", units.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitError {
+    // operands of a sum, difference or select disagree
+    Mismatch {
+        node: String,
+        lhs: Dimension,
+        rhs: Dimension,
+    },
+    // argument of sin, cos, exp_m1 or ln_1p
+    NotDimensionless {
+        node: String,
+        found: Dimension,
+    },
+    FractionalPower {
+        node: String,
+        base: Dimension,
+        exponent: f32,
+    },
+    // custom ops do not declare how they transform units
+    CustomOp(String),
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnitError::Mismatch { ref node, lhs, rhs } => {
+                write!(f, "{} combines {} with {}", node, lhs, rhs)
+            }
+            UnitError::NotDimensionless { ref node, found } => {
+                write!(
+                    f,
+                    "{} needs a dimensionless argument, found {}",
+                    node, found
+                )
+            }
+            UnitError::FractionalPower {
+                ref node,
+                base,
+                exponent,
+            } => write!(f, "{} raises {} to the power {}", node, base, exponent),
+            UnitError::CustomOp(ref node) => {
+                write!(f, "{} has dimensioned arguments of unknown effect", node)
+            }
+        }
+    }
+}
+
+impl Error for UnitError {}
+
+// Tags a variable or constant with a dimension. Returns the same node.
+pub fn with_unit<'a>(node: Node<'a>, unit: Dimension) -> Node<'a> {
+    node.unit.set(Some(unit));
+    node
+}
+
+pub fn unit_of(node: Node) -> Option<Dimension> {
+    node.unit.get()
+}
+
+// Dimension of `node`, or the first inconsistency found from the leaves up.
+pub fn check_units(node: Node) -> Result<Dimension, UnitError> {
+    use NodeType::*;
+
+    let mut dims: HashMap<*const NodeData, Dimension> = HashMap::new();
+    for n in topological_order(&[node]) {
+        let dim = {
+            let of = |child: Node| dims[&node_key(child)];
+            let same = |lhs: Node, rhs: Node| {
+                if of(lhs) == of(rhs) {
+                    Ok(of(lhs))
+                } else {
+                    Err(UnitError::Mismatch {
+                        node: n.describe(),
+                        lhs: of(lhs),
+                        rhs: of(rhs),
+                    })
+                }
+            };
+            let dimensionless = |arg: Node| {
+                if of(arg).is_dimensionless() {
+                    Ok(Dimension::NONE)
+                } else {
+                    Err(UnitError::NotDimensionless {
+                        node: n.describe(),
+                        found: of(arg),
+                    })
+                }
+            };
+            match n.type_ {
+                Const(_) | Var(_) => n.unit.get().unwrap_or(Dimension::NONE),
+                Neg(x) | StopGradient(x) => of(x),
+                Add(l, r) | Sub(l, r) | Select(_, l, r) => same(l, r)?,
+                Mul(l, r) => of(l) * of(r),
+                Div(l, r) => of(l) / of(r),
+                Pow(x, p) => match of(x).powf(p) {
+                    Some(dim) => dim,
+                    None => {
+                        return Err(UnitError::FractionalPower {
+                            node: n.describe(),
+                            base: of(x),
+                            exponent: p,
+                        })
+                    }
+                },
+                Sin(x) | Cos(x) | ExpM1(x) | Ln1p(x) => dimensionless(x)?,
+                Custom(_, ref args) => {
+                    if args.iter().any(|&arg| !of(arg).is_dimensionless()) {
+                        return Err(UnitError::CustomOp(n.describe()));
+                    }
+                    Dimension::NONE
+                }
+                AddN(ref args) => match args.split_first() {
+                    Some((&first, rest)) => {
+                        for &arg in rest {
+                            same(first, arg)?;
+                        }
+                        of(first)
+                    }
+                    None => Dimension::NONE,
+                },
+                MulN(ref args) => args.iter().fold(Dimension::NONE, |acc, &arg| acc * of(arg)),
+            }
+        };
+        dims.insert(node_key(n), dim);
+    }
+
+    Ok(dims[&node_key(node)])
+}
+
+#[test]
+fn units_are_checked() {
+    use {add, constant, div, labelled, mul, pow, sin, var, Arena};
+
+    let arena = Arena::new();
+    let metre = Dimension::LENGTH;
+    let second = Dimension::TIME;
+
+    // x + v t + a t^2 / 2
+    let x = with_unit(var(&arena, "x".to_string()), metre);
+    let v = with_unit(var(&arena, "v".to_string()), metre / second);
+    let a = with_unit(var(&arena, "a".to_string()), metre / second.powi(2));
+    let t = with_unit(var(&arena, "t".to_string()), second);
+    let half = constant(&arena, 0.5);
+    let position = add(
+        &arena,
+        add(&arena, x, mul(&arena, v, t)),
+        mul(&arena, half, mul(&arena, a, pow(&arena, t, 2.0))),
+    );
+    assert_eq!(check_units(position), Ok(metre));
+    assert_eq!(format!("{}", metre / second.powi(2)), "m s^-2");
+    assert_eq!(
+        check_units(pow(&arena, pow(&arena, x, 2.0), 0.5)),
+        Ok(metre)
+    );
+
+    let wrong = labelled(add(&arena, x, t), "x + t");
+    match check_units(wrong) {
+        Err(UnitError::Mismatch { node, lhs, rhs }) => {
+            assert!(node.contains("x + t"));
+            assert_eq!((lhs, rhs), (metre, second));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+        check_units(sin(&arena, div(&arena, x, t))),
+        Err(UnitError::NotDimensionless {
+            node: "Sin".to_string(),
+            found: metre / second,
+        })
+    );
+    assert!(check_units(pow(&arena, x, 0.5)).is_err());
+}
@@ -0,0 +1,395 @@
+// WGSL compute shaders evaluating an expression, and optionally its
+// gradient, for every row of an input buffer.
+//
+// The generated shader binds the inputs at group 0 binding 0, row-major with
+// `inputs_per_row` values per row in the order of the variables, and writes
+// `outputs_per_row` values per row (the value followed by the gradient) to
+// binding 1. One invocation handles one row, in workgroups of
+// `WORKGROUP_SIZE`.
+//
+// This crate only generates the source: there is no `wgpu` feature and no
+// `forward_gpu`/`grad_gpu` yet, so creating the device, validating the module
+// (e.g. with naga) and dispatching are left to the caller's wgpu setup. A
+// batched runner belongs behind that feature once the crate can depend on
+// wgpu.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+use {node_key, topological_order, Node, NodeData};
+
+pub const WORKGROUP_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WgslError {
+    // custom ops only exist on the CPU
+    UnsupportedOp(String),
+}
+
+impl fmt::Display for WgslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WgslError::UnsupportedOp(ref name) => write!(f, "{} cannot be compiled to WGSL", name),
+        }
+    }
+}
+
+impl Error for WgslError {}
+
+#[derive(Debug, Clone)]
+pub struct Shader {
+    pub source: String,
+    pub inputs_per_row: usize,
+    pub outputs_per_row: usize,
+}
+
+impl Shader {
+    // number of workgroups to dispatch for `rows` rows
+    pub fn workgroups(&self, rows: usize) -> u32 {
+        rows.div_ceil(WORKGROUP_SIZE) as u32
+    }
+}
+
+fn literal(v: f32) -> String {
+    if v.is_finite() {
+        format!("{:?}", v)
+    } else {
+        format!("bitcast<f32>({}u)", v.to_bits())
+    }
+}
+
+// x^p, by multiplication for small integer powers since WGSL's `pow` is
+// undefined for negative bases
+fn power(x: &str, p: f32) -> String {
+    if p.fract() == 0f32 && p.abs() <= 4f32 {
+        let n = p.abs() as usize;
+        let product = if n == 0 {
+            "1.0".to_string()
+        } else {
+            vec![x; n].join(" * ")
+        };
+        if p < 0f32 {
+            format!("(1.0 / ({}))", product)
+        } else {
+            format!("({})", product)
+        }
+    } else {
+        format!("pow({}, {})", x, literal(p))
+    }
+}
+
+// Compiles `node` with `variables` read from the input rows. Other variables
+// are baked in at their current values. With `with_grad`, the gradient with
+// respect to `variables` follows the value in every output row.
+pub fn compile(node: Node, variables: &[&str], with_grad: bool) -> Result<Shader, WgslError> {
+    use NodeType::*;
+
+    let order = topological_order(&[node]);
+    let names: HashMap<*const NodeData, String> = order
+        .iter()
+        .enumerate()
+        .map(|(i, &node)| (node_key(node), format!("v{}", i)))
+        .collect();
+    let name = |node: Node| names[&node_key(node)].clone();
+    let inputs_per_row = variables.len();
+    let outputs_per_row = if with_grad { 1 + variables.len() } else { 1 };
+
+    let mut body = String::new();
+    for &n in &order {
+        let expr = match n.type_ {
+            Const(v) => literal(v),
+            Var(ref var) => match variables.iter().position(|v| v == var) {
+                Some(i) => format!("inputs[row * {}u + {}u]", inputs_per_row, i),
+                None => literal(n.value()),
+            },
+            Neg(x) => format!("-{}", name(x)),
+            Add(l, r) => format!("{} + {}", name(l), name(r)),
+            Sub(l, r) => format!("{} - {}", name(l), name(r)),
+            Mul(l, r) => format!("{} * {}", name(l), name(r)),
+            Div(l, r) => format!("{} / {}", name(l), name(r)),
+            Pow(x, p) => power(&name(x), p),
+            Sin(x) => format!("sin({})", name(x)),
+            Cos(x) => format!("cos({})", name(x)),
+            ExpM1(x) => format!("exp({}) - 1.0", name(x)),
+            Ln1p(x) => format!("log(1.0 + {})", name(x)),
+            StopGradient(x) => name(x),
+            Custom(ref op, _) => return Err(WgslError::UnsupportedOp(op.name().to_string())),
+            Select(c, t, e) => format!("select({}, {}, {} > 0.0)", name(e), name(t), name(c)),
+            AddN(ref args) | MulN(ref args) if args.is_empty() => {
+                if let AddN(_) = n.type_ {
+                    "0.0".to_string()
+                } else {
+                    "1.0".to_string()
+                }
+            }
+            AddN(ref args) => args
+                .iter()
+                .map(|&a| name(a))
+                .collect::<Vec<_>>()
+                .join(" + "),
+            MulN(ref args) => args
+                .iter()
+                .map(|&a| name(a))
+                .collect::<Vec<_>>()
+                .join(" * "),
+        };
+        writeln!(body, "    let {} = {};", name(n), expr).unwrap();
+    }
+    writeln!(
+        body,
+        "    outputs[row * {}u] = {};",
+        outputs_per_row,
+        name(node)
+    )
+    .unwrap();
+
+    if with_grad {
+        // straight-line reverse sweep
+        let adjoint = |node: Node| format!("a{}", &names[&node_key(node)][1..]);
+        for &n in &order {
+            writeln!(body, "    var {} = 0.0;", adjoint(n)).unwrap();
+        }
+        writeln!(body, "    {} = 1.0;", adjoint(node)).unwrap();
+        for &n in order.iter().rev() {
+            let a = adjoint(n);
+            let mut flow = |child: Node, partial: String| {
+                writeln!(body, "    {} += {} * ({});", adjoint(child), a, partial).unwrap();
+            };
+            match n.type_ {
+                Const(_) | Var(_) | StopGradient(_) | Custom(_, _) => {}
+                Neg(x) => flow(x, "-1.0".to_string()),
+                Add(l, r) => {
+                    flow(l, "1.0".to_string());
+                    flow(r, "1.0".to_string());
+                }
+                Sub(l, r) => {
+                    flow(l, "1.0".to_string());
+                    flow(r, "-1.0".to_string());
+                }
+                Mul(l, r) => {
+                    flow(l, name(r));
+                    flow(r, name(l));
+                }
+                Div(l, r) => {
+                    flow(l, format!("1.0 / {}", name(r)));
+                    flow(r, format!("-{} / ({} * {})", name(l), name(r), name(r)));
+                }
+                Pow(x, p) => flow(x, format!("{} * {}", literal(p), power(&name(x), p - 1f32))),
+                Sin(x) => flow(x, format!("cos({})", name(x))),
+                Cos(x) => flow(x, format!("-sin({})", name(x))),
+                ExpM1(x) => flow(x, format!("exp({})", name(x))),
+                Ln1p(x) => flow(x, format!("1.0 / (1.0 + {})", name(x))),
+                Select(c, t, e) => {
+                    flow(t, format!("select(0.0, 1.0, {} > 0.0)", name(c)));
+                    flow(e, format!("select(1.0, 0.0, {} > 0.0)", name(c)));
+                }
+                AddN(ref args) => {
+                    for &arg in args {
+                        flow(arg, "1.0".to_string());
+                    }
+                }
+                MulN(ref args) => {
+                    for (i, &arg) in args.iter().enumerate() {
+                        let others: Vec<String> = args
+                            .iter()
+                            .enumerate()
+                            .filter(|&(j, _)| j != i)
+                            .map(|(_, &other)| name(other))
+                            .collect();
+                        let others = if others.is_empty() {
+                            "1.0".to_string()
+                        } else {
+                            others.join(" * ")
+                        };
+                        flow(arg, others);
+                    }
+                }
+            }
+        }
+        // variables of the same name share a gradient
+        for (i, variable) in variables.iter().enumerate() {
+            let terms: Vec<String> = order
+                .iter()
+                .filter(|n| n.var_name() == Some(variable))
+                .map(|&n| adjoint(n))
+                .collect();
+            let sum = if terms.is_empty() {
+                "0.0".to_string()
+            } else {
+                terms.join(" + ")
+            };
+            writeln!(
+                body,
+                "    outputs[row * {}u + {}u] = {};",
+                outputs_per_row,
+                i + 1,
+                sum
+            )
+            .unwrap();
+        }
+    }
+
+    let mut source = String::new();
+    writeln!(
+        source,
+        "@group(0) @binding(0) var<storage, read> inputs: array<f32>;"
+    )
+    .unwrap();
+    writeln!(
+        source,
+        "@group(0) @binding(1) var<storage, read_write> outputs: array<f32>;"
+    )
+    .unwrap();
+    writeln!(source).unwrap();
+    writeln!(source, "@compute @workgroup_size({})", WORKGROUP_SIZE).unwrap();
+    writeln!(
+        source,
+        "fn main(@builtin(global_invocation_id) id: vec3<u32>) {{"
+    )
+    .unwrap();
+    writeln!(source, "    let row = id.x;").unwrap();
+    writeln!(
+        source,
+        "    if (row >= arrayLength(&outputs) / {}u) {{\n        return;\n    }}",
+        outputs_per_row
+    )
+    .unwrap();
+    source.push_str(&body);
+    writeln!(source, "}}").unwrap();
+
+    Ok(Shader {
+        source,
+        inputs_per_row,
+        outputs_per_row,
+    })
+}
+
+#[test]
+fn shader_for_value_and_gradient() {
+    use {add, constant, custom, mul, pow, sin, var, Arena, CustomOp};
+
+    let arena = Arena::new();
+    let x = var(&arena, "x".to_string());
+    let c = var(&arena, "c".to_string());
+    c.set_value(2.5);
+    let f = add(&arena, mul(&arena, c, sin(&arena, x)), pow(&arena, x, 2.0));
+
+    let shader = compile(f, &["x"], true).unwrap();
+    assert_eq!((shader.inputs_per_row, shader.outputs_per_row), (1, 2));
+    assert_eq!(shader.workgroups(65), 2);
+    let source = &shader.source;
+    assert!(source.contains("inputs[row * 1u + 0u]"));
+    // c is baked in
+    assert!(source.contains("= 2.5;"));
+    assert!(source.contains("sin(v"));
+    assert!(source.contains("outputs[row * 2u + 1u] = a"));
+
+    struct Opaque;
+    impl CustomOp for Opaque {
+        fn name(&self) -> &str {
+            "Opaque"
+        }
+        fn forward(&self, inputs: &[f32]) -> f32 {
+            inputs[0]
+        }
+        fn backward(&self, _: &[f32], upstream: f32) -> Vec<f32> {
+            vec![upstream]
+        }
+    }
+    let opaque = custom(&arena, Opaque, &[constant(&arena, 1.0)]);
+    assert_eq!(
+        compile(opaque, &[], false).err(),
+        Some(WgslError::UnsupportedOp("Opaque".to_string()))
+    );
+}
+
+// A structural check of generated source, standing in for a WGSL validator:
+// brackets balance, statements end in `;` and every `vN`/`aN` is declared
+// before it is used.
+#[cfg(test)]
+fn check_source(source: &str) {
+    use std::collections::HashSet;
+
+    let mut open = vec![];
+    for c in source.chars() {
+        match c {
+            '(' | '[' | '{' => open.push(c),
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                assert_eq!(
+                    open.pop(),
+                    Some(expected),
+                    "unbalanced {} in\n{}",
+                    c,
+                    source
+                );
+            }
+            _ => {}
+        }
+    }
+    assert!(open.is_empty(), "unclosed {:?} in\n{}", open, source);
+
+    let mut declared = HashSet::new();
+    for line in source.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('@') || line.ends_with('{') || line == "}" {
+            continue;
+        }
+        assert!(line.ends_with(';'), "unterminated statement {:?}", line);
+        let (defined, rest) = match line.find(" = ") {
+            Some(at) if line.starts_with("let ") || line.starts_with("var ") => {
+                (Some(&line[4..at]), &line[at..])
+            }
+            _ => (None, line),
+        };
+        let words = rest.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'));
+        for word in words {
+            let local = (word.starts_with('v') || word.starts_with('a'))
+                && word.len() > 1
+                && word[1..].bytes().all(|b| b.is_ascii_digit());
+            assert!(
+                !local || declared.contains(word),
+                "{} used before its declaration",
+                word
+            );
+        }
+        declared.extend(defined);
+    }
+}
+
+#[test]
+fn shader_source_is_well_formed() {
+    use {
+        add_n, cos, div, exp_m1, ln_1p, mul_n, neg, pow, select, sin, stop_gradient, sub, var,
+        Arena,
+    };
+
+    let arena = Arena::new();
+    let arena = &arena;
+    let x = var(arena, "x".to_string());
+    let y = var(arena, "y".to_string());
+    let f = add_n(
+        arena,
+        &[
+            div(arena, sin(arena, x), cos(arena, y)),
+            neg(arena, pow(arena, x, -2.0)),
+            pow(arena, y, 0.5),
+            mul_n(arena, &[x, y, exp_m1(arena, x)]),
+            select(
+                arena,
+                sub(arena, x, y),
+                ln_1p(arena, y),
+                stop_gradient(arena, x),
+            ),
+            add_n(arena, &[]),
+        ],
+    );
+    for &with_grad in &[false, true] {
+        check_source(&compile(f, &["x", "y"], with_grad).unwrap().source);
+    }
+}